@@ -0,0 +1,16 @@
+CREATE TABLE audio_mix_files (
+    id BIGSERIAL PRIMARY KEY,
+    file_name TEXT NOT NULL UNIQUE,
+    guild_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    year INT NOT NULL,
+    month INT NOT NULL,
+    start_ts BIGINT NOT NULL,
+    end_ts BIGINT NULL,
+    recording_owner_instance_id TEXT NULL REFERENCES bot_instances(instance_id) ON DELETE SET NULL,
+    recording_heartbeat_at TIMESTAMPTZ NULL,
+    reaped BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX audio_mix_files_guild_channel_start_idx
+    ON audio_mix_files (guild_id, channel_id, start_ts);
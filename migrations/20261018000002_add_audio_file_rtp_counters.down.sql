@@ -0,0 +1,4 @@
+ALTER TABLE audio_files
+    DROP COLUMN reordered_packets,
+    DROP COLUMN concealed_frames,
+    DROP COLUMN lost_packets;
@@ -0,0 +1,4 @@
+ALTER TABLE audio_files
+    ADD COLUMN lost_packets BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN concealed_frames BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN reordered_packets BIGINT NOT NULL DEFAULT 0;
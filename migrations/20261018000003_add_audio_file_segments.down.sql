@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS audio_files_previous_file_id_idx;
+
+ALTER TABLE audio_files
+    DROP COLUMN IF EXISTS segment_index,
+    DROP COLUMN IF EXISTS previous_file_id;
@@ -0,0 +1,8 @@
+-- Long recordings are rotated into consecutive segments. Each segment
+-- after the first points at the one it continues; state_enter/state_leave
+-- use 4 (ROTATED) at segment boundaries.
+ALTER TABLE audio_files
+    ADD COLUMN previous_file_id BIGINT REFERENCES audio_files(id) ON DELETE SET NULL,
+    ADD COLUMN segment_index INT NOT NULL DEFAULT 0;
+
+CREATE INDEX audio_files_previous_file_id_idx ON audio_files (previous_file_id);
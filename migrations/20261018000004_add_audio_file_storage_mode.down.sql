@@ -0,0 +1,2 @@
+ALTER TABLE audio_files
+    DROP COLUMN storage_mode;
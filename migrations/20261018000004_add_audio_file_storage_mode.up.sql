@@ -0,0 +1,5 @@
+-- 0 = padded (silence written into the file), 1 = speech-only: the .ogg
+-- holds only speech packets and a <stem>.idx sidecar maps them onto the
+-- recording timeline.
+ALTER TABLE audio_files
+    ADD COLUMN storage_mode SMALLINT NOT NULL DEFAULT 0;
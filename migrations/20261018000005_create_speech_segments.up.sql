@@ -0,0 +1,12 @@
+-- Who talked when, per recording file. start_ms/end_ms are wall-clock
+-- epoch milliseconds; peak_level is 0.0-1.0 of full scale.
+CREATE TABLE speech_segments (
+    id BIGSERIAL PRIMARY KEY,
+    audio_file_id BIGINT NOT NULL REFERENCES audio_files(id) ON DELETE CASCADE,
+    start_ms BIGINT NOT NULL,
+    end_ms BIGINT NOT NULL,
+    peak_level REAL NOT NULL
+);
+
+CREATE INDEX speech_segments_audio_file_id_start_ms_idx
+    ON speech_segments (audio_file_id, start_ms);
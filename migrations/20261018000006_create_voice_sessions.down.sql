@@ -0,0 +1,9 @@
+DROP INDEX IF EXISTS stamps_session_id_idx;
+DROP INDEX IF EXISTS audio_files_session_id_idx;
+
+ALTER TABLE stamps DROP COLUMN IF EXISTS session_id;
+ALTER TABLE voice_state_events DROP COLUMN IF EXISTS session_id;
+ALTER TABLE audio_mix_files DROP COLUMN IF EXISTS session_id;
+ALTER TABLE audio_files DROP COLUMN IF EXISTS session_id;
+
+DROP TABLE IF EXISTS voice_sessions;
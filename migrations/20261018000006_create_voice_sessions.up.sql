@@ -0,0 +1,28 @@
+-- One row per continuous recording of a channel: opened when the first
+-- human is recorded, closed once the last writer is finalized. Recoverable
+-- driver disconnects and user rejoins stay inside the same session.
+CREATE TABLE voice_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    channel_id BIGINT NOT NULL,
+    start_ts BIGINT NOT NULL,
+    end_ts BIGINT NULL,
+    recording_owner_instance_id TEXT NULL REFERENCES bot_instances(instance_id) ON DELETE SET NULL,
+    participant_count INT NOT NULL DEFAULT 0,
+    reaped BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX voice_sessions_guild_channel_start_idx
+    ON voice_sessions (guild_id, channel_id, start_ts);
+
+ALTER TABLE audio_files
+    ADD COLUMN session_id BIGINT REFERENCES voice_sessions(id) ON DELETE SET NULL;
+ALTER TABLE audio_mix_files
+    ADD COLUMN session_id BIGINT REFERENCES voice_sessions(id) ON DELETE SET NULL;
+ALTER TABLE voice_state_events
+    ADD COLUMN session_id BIGINT REFERENCES voice_sessions(id) ON DELETE SET NULL;
+ALTER TABLE stamps
+    ADD COLUMN session_id BIGINT REFERENCES voice_sessions(id) ON DELETE SET NULL;
+
+CREATE INDEX audio_files_session_id_idx ON audio_files (session_id);
+CREATE INDEX stamps_session_id_idx ON stamps (session_id);
@@ -0,0 +1,3 @@
+ALTER TABLE audio_files DROP COLUMN IF EXISTS is_bot_track;
+
+DROP TABLE IF EXISTS guild_recording_settings;
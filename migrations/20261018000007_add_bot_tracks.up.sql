@@ -0,0 +1,12 @@
+-- Per-guild recording options. A guild without a row uses the defaults.
+CREATE TABLE guild_recording_settings (
+    guild_id BIGINT PRIMARY KEY,
+    -- Record other bots (music, TTS) to their own files.
+    record_bots BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Record clips we play ourselves as a track of the session.
+    record_playback BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE audio_files
+    ADD COLUMN is_bot_track BOOLEAN NOT NULL DEFAULT FALSE;
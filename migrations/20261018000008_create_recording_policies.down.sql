@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS recording_user_opt_outs;
+DROP TABLE IF EXISTS recording_channel_policies;
+DROP TABLE IF EXISTS recording_guild_policies;
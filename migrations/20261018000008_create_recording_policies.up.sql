@@ -0,0 +1,28 @@
+-- Admin-level recording policy. A guild without a row records everywhere.
+CREATE TABLE recording_guild_policies (
+    guild_id BIGINT PRIMARY KEY,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Whether channels without an override are recorded (FALSE = allowlist).
+    channels_default_allowed BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Whether entries in recording_user_opt_outs are honoured.
+    allow_user_opt_out BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_by BIGINT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE recording_channel_policies (
+    channel_id BIGINT PRIMARY KEY,
+    guild_id BIGINT NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    updated_by BIGINT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX recording_channel_policies_guild_idx ON recording_channel_policies (guild_id);
+
+CREATE TABLE recording_user_opt_outs (
+    guild_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (guild_id, user_id)
+);
@@ -0,0 +1,9 @@
+-- Member-controlled consent, independent of any guild's recording policy.
+-- Timestamps are unix milliseconds of the last change in each direction.
+CREATE TABLE user_consent (
+    user_id BIGINT PRIMARY KEY,
+    recording_opt_out BOOLEAN NOT NULL DEFAULT FALSE,
+    opted_out_ts BIGINT NULL,
+    opted_in_ts BIGINT NULL,
+    updated_ts BIGINT NOT NULL
+);
@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS clips_source_audio_file_id_idx;
+ALTER TABLE clips DROP COLUMN IF EXISTS source_audio_file_id;
+ALTER TABLE audio_mix_files DROP COLUMN IF EXISTS pruned_at;
+ALTER TABLE audio_files DROP COLUMN IF EXISTS pruned_at;
+
+DROP TABLE IF EXISTS recording_retention_rules;
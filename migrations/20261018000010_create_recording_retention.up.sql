@@ -0,0 +1,31 @@
+-- Retention rules for finished recordings. The most specific rule wins:
+-- channel, then guild (channel_id NULL), then global (both NULL).
+CREATE TABLE recording_retention_rules (
+    id BIGSERIAL PRIMARY KEY,
+    guild_id BIGINT NULL,
+    channel_id BIGINT NULL,
+    -- Prune recordings that started more than this many days ago.
+    max_age_days INT NULL CHECK (max_age_days > 0),
+    -- Prune the oldest recordings once a guild holds more than this.
+    -- Read from the guild or global rule only.
+    max_guild_bytes BIGINT NULL CHECK (max_guild_bytes > 0),
+    keep_stamped BOOLEAN NOT NULL DEFAULT TRUE,
+    keep_clipped BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK (channel_id IS NULL OR guild_id IS NOT NULL)
+);
+
+CREATE UNIQUE INDEX recording_retention_rules_scope_idx
+    ON recording_retention_rules (COALESCE(guild_id, 0), COALESCE(channel_id, 0));
+
+-- Set when retention deleted the files but kept the row.
+ALTER TABLE audio_files
+    ADD COLUMN pruned_at TIMESTAMPTZ NULL;
+ALTER TABLE audio_mix_files
+    ADD COLUMN pruned_at TIMESTAMPTZ NULL;
+
+-- Recording a clip was cut from; retention can keep it.
+ALTER TABLE clips
+    ADD COLUMN source_audio_file_id BIGINT NULL REFERENCES audio_files(id) ON DELETE SET NULL;
+
+CREATE INDEX clips_source_audio_file_id_idx ON clips (source_audio_file_id);
@@ -0,0 +1,18 @@
+DROP INDEX IF EXISTS audio_mix_files_pending_upload_idx;
+DROP INDEX IF EXISTS audio_files_pending_upload_idx;
+
+ALTER TABLE audio_mix_files
+    DROP COLUMN IF EXISTS upload_error,
+    DROP COLUMN IF EXISTS upload_attempts,
+    DROP COLUMN IF EXISTS uploaded_at,
+    DROP COLUMN IF EXISTS object_size_bytes,
+    DROP COLUMN IF EXISTS object_sha256,
+    DROP COLUMN IF EXISTS storage_backend;
+
+ALTER TABLE audio_files
+    DROP COLUMN IF EXISTS upload_error,
+    DROP COLUMN IF EXISTS upload_attempts,
+    DROP COLUMN IF EXISTS uploaded_at,
+    DROP COLUMN IF EXISTS object_size_bytes,
+    DROP COLUMN IF EXISTS object_sha256,
+    DROP COLUMN IF EXISTS storage_backend;
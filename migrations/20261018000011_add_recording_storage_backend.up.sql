@@ -0,0 +1,24 @@
+-- Where a finished recording's files live. 'local' rows are still only
+-- under RECORDING_ROOT; others were uploaded and verified.
+ALTER TABLE audio_files
+    ADD COLUMN storage_backend TEXT NOT NULL DEFAULT 'local',
+    -- SHA-256 of the uploaded .ogg, lowercase hex.
+    ADD COLUMN object_sha256 TEXT NULL,
+    -- Bytes uploaded for the .ogg and its sidecars.
+    ADD COLUMN object_size_bytes BIGINT NULL,
+    ADD COLUMN uploaded_at TIMESTAMPTZ NULL,
+    ADD COLUMN upload_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN upload_error TEXT NULL;
+
+ALTER TABLE audio_mix_files
+    ADD COLUMN storage_backend TEXT NOT NULL DEFAULT 'local',
+    ADD COLUMN object_sha256 TEXT NULL,
+    ADD COLUMN object_size_bytes BIGINT NULL,
+    ADD COLUMN uploaded_at TIMESTAMPTZ NULL,
+    ADD COLUMN upload_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN upload_error TEXT NULL;
+
+CREATE INDEX audio_files_pending_upload_idx ON audio_files (id)
+    WHERE storage_backend = 'local' AND end_ts IS NOT NULL AND pruned_at IS NULL;
+CREATE INDEX audio_mix_files_pending_upload_idx ON audio_mix_files (id)
+    WHERE storage_backend = 'local' AND end_ts IS NOT NULL AND pruned_at IS NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE clips
+    DROP COLUMN IF EXISTS source_user_id,
+    DROP COLUMN IF EXISTS created_by_user_id;
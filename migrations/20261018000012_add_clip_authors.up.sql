@@ -0,0 +1,5 @@
+-- Clips the bot cuts itself (/clip) record who asked for them and whose
+-- voice they hold. Clips made elsewhere leave both NULL.
+ALTER TABLE clips
+    ADD COLUMN created_by_user_id BIGINT NULL,
+    ADD COLUMN source_user_id BIGINT NULL;
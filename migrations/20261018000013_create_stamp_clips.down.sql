@@ -0,0 +1,14 @@
+ALTER TABLE guild_recording_settings
+    DROP COLUMN IF EXISTS stamp_clip_channel_id;
+
+DROP INDEX IF EXISTS clips_stamp_id_idx;
+ALTER TABLE clips
+    DROP COLUMN IF EXISTS stamp_id;
+
+DROP INDEX IF EXISTS stamps_pending_clip_idx;
+ALTER TABLE stamps
+    DROP COLUMN IF EXISTS clip_message_id,
+    DROP COLUMN IF EXISTS clip_next_attempt_at,
+    DROP COLUMN IF EXISTS clip_error,
+    DROP COLUMN IF EXISTS clip_attempts,
+    DROP COLUMN IF EXISTS clip_state;
@@ -0,0 +1,24 @@
+-- Automatic clips rendered from stamps. Stamps made before this migration
+-- are marked skipped (3) so only new ones are rendered.
+-- 0 = pending, 1 = done, 2 = failed (gave up), 3 = skipped.
+ALTER TABLE stamps
+    ADD COLUMN clip_state SMALLINT NOT NULL DEFAULT 3,
+    ADD COLUMN clip_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN clip_error TEXT NULL,
+    ADD COLUMN clip_next_attempt_at TIMESTAMPTZ NULL,
+    -- Message the clip was posted as, when the guild has a channel set.
+    ADD COLUMN clip_message_id BIGINT NULL;
+ALTER TABLE stamps
+    ALTER COLUMN clip_state SET DEFAULT 0;
+
+CREATE INDEX stamps_pending_clip_idx ON stamps (id) WHERE clip_state = 0;
+
+-- Stamp a clip was rendered for.
+ALTER TABLE clips
+    ADD COLUMN stamp_id BIGINT NULL REFERENCES stamps(id) ON DELETE SET NULL;
+
+CREATE UNIQUE INDEX clips_stamp_id_idx ON clips (stamp_id) WHERE stamp_id IS NOT NULL;
+
+-- Text channel stamp clips are posted to. NULL: not posted.
+ALTER TABLE guild_recording_settings
+    ADD COLUMN stamp_clip_channel_id BIGINT NULL;
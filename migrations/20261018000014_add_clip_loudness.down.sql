@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS guild_playback_settings;
+
+DROP INDEX IF EXISTS clips_unmeasured_idx;
+ALTER TABLE clips
+    DROP COLUMN IF EXISTS loudness_error,
+    DROP COLUMN IF EXISTS loudness_attempts,
+    DROP COLUMN IF EXISTS loudness_measured_at,
+    DROP COLUMN IF EXISTS peak_dbfs,
+    DROP COLUMN IF EXISTS loudness_lufs;
@@ -0,0 +1,20 @@
+-- Clip loudness for playback gain, measured when a clip is added or by the
+-- backfill. loudness_lufs stays NULL for clips too quiet or short to gate.
+ALTER TABLE clips
+    ADD COLUMN loudness_lufs DOUBLE PRECISION NULL,
+    ADD COLUMN peak_dbfs DOUBLE PRECISION NULL,
+    ADD COLUMN loudness_measured_at TIMESTAMPTZ NULL,
+    ADD COLUMN loudness_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN loudness_error TEXT NULL;
+
+CREATE INDEX clips_unmeasured_idx ON clips (clip_id)
+    WHERE loudness_measured_at IS NULL AND deleted_at IS NULL;
+
+-- Per-guild clip playback loudness. NULL columns use CLIP_TARGET_LUFS and
+-- CLIP_PEAK_CEILING_DBFS.
+CREATE TABLE guild_playback_settings (
+    guild_id BIGINT PRIMARY KEY,
+    target_lufs DOUBLE PRECISION NULL CHECK (target_lufs BETWEEN -70 AND 0),
+    ceiling_dbfs DOUBLE PRECISION NULL CHECK (ceiling_dbfs <= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
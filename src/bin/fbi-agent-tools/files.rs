@@ -0,0 +1,112 @@
+//! Recordings found on disk under `RECORDING_ROOT`.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, Utc};
+use fbi_agent::events::session_mix;
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+
+/// Which table a file on disk belongs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    /// `audio_files`, one speaker.
+    User(i64),
+    /// `audio_mix_files`.
+    Mix,
+}
+
+pub struct OnDisk {
+    pub key: RecordingKey,
+    pub file_name: String,
+    pub owner: Owner,
+    pub guild_id: i64,
+    pub channel_id: i64,
+    pub start_ms: i64,
+}
+
+/// Every `.ogg` below `root`, in path order.
+pub fn recordings(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|e| e == "ogg") {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// `(year, month)` a recording started at `start_ms` is filed under.
+fn filed_under(start_ms: i64) -> Option<(i32, u32)> {
+    let start = DateTime::<Utc>::from_timestamp_millis(start_ms)?;
+    Some((start.year(), start.month()))
+}
+
+/// Work out what the recording at `path` is from where it sits. Names are
+/// only trusted when rebuilding the key from them gives back `path`.
+pub fn identify(path: &Path) -> Option<OnDisk> {
+    let relative = path.strip_prefix(RECORDING_ROOT).ok()?;
+    let mut parts = relative.iter().filter_map(|part| part.to_str());
+    let guild_id: i64 = parts.next()?.parse().ok()?;
+    let channel_id: i64 = parts.next()?.parse().ok()?;
+    let stem = path.file_stem()?.to_str()?;
+
+    let mut candidates = Vec::new();
+    if let Some((ms, _)) = session_mix::parse_stem(stem) {
+        candidates.push((ms, Owner::Mix));
+    }
+    let numbers: Vec<i64> = stem.split('-').filter_map(|n| n.parse().ok()).collect();
+    if let [a, b] = numbers[..] {
+        for (ms, user) in [(a, b), (b, a)] {
+            if RecordingKey::stem_for(ms, user) == stem {
+                candidates.push((ms, Owner::User(user)));
+            }
+        }
+    }
+
+    candidates.into_iter().find_map(|(start_ms, owner)| {
+        let (year, month) = filed_under(start_ms)?;
+        let key = RecordingKey::new(guild_id, channel_id, year, month, stem.to_string());
+        (key.recording_path(RECORDING_ROOT) == path).then(|| OnDisk {
+            key,
+            file_name: stem.to_string(),
+            owner,
+            guild_id,
+            channel_id,
+            start_ms,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_recordings_from_their_paths() {
+        let start_ms = 1_760_000_000_000;
+        let user = RecordingKey::new(1, 2, 2025, 10, RecordingKey::stem_for(start_ms, 42));
+        let found = identify(&user.recording_path(RECORDING_ROOT));
+        assert_eq!(found.map(|f| f.owner), Some(Owner::User(42)));
+
+        let mix = RecordingKey::new(1, 2, 2025, 10, session_mix::stem_for(start_ms, 0));
+        let found = identify(&mix.recording_path(RECORDING_ROOT));
+        assert_eq!(
+            found.map(|f| (f.owner, f.start_ms)),
+            Some((Owner::Mix, start_ms))
+        );
+        let reopened = RecordingKey::new(1, 2, 2025, 10, session_mix::stem_for(start_ms, 1));
+        let found = identify(&reopened.recording_path(RECORDING_ROOT));
+        assert_eq!(found.map(|f| f.start_ms), Some(start_ms));
+
+        assert!(identify(Path::new("/elsewhere/1/2/x.ogg")).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+//! `inspect`: what is in a recording file, page by page.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use fbi_agent::events::sparse_index;
+use fbi_agent::repair;
+
+use crate::ToolResult;
+
+/// Opus frames are 20 ms, 960 granules each.
+const SAMPLES_PER_PACKET: u64 = 960;
+
+#[derive(Debug, Default)]
+struct Inspection {
+    pages: u64,
+    packets: u64,
+    granule: u64,
+    /// Pages whose granule moved by more than their packets account for.
+    gaps: Vec<Gap>,
+    eos: bool,
+    /// Why reading stopped early, if it did.
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct Gap {
+    page: u64,
+    missing_ms: u64,
+}
+
+fn inspect_packets(path: &Path) -> std::io::Result<Inspection> {
+    let input = BufReader::new(fbi_agent::encryption::open_media(File::open(path)?)?);
+    let mut reader = ogg::PacketReader::new(input);
+    let mut found = Inspection::default();
+    let mut page_packets = 0u64;
+    let mut headers = 0;
+    loop {
+        let packet = match reader.read_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => {
+                found.error = Some(e.to_string());
+                break;
+            }
+        };
+        if headers < 2 {
+            headers += 1;
+        } else if !packet.data.is_empty() {
+            found.packets += 1;
+            page_packets += 1;
+        }
+        if packet.last_in_page() {
+            found.pages += 1;
+            let granule = packet.absgp_page();
+            let expected = found.granule + page_packets * SAMPLES_PER_PACKET;
+            if granule > expected {
+                found.gaps.push(Gap {
+                    page: found.pages,
+                    missing_ms: (granule - expected) / 48,
+                });
+            }
+            if granule != u64::MAX {
+                found.granule = granule;
+            }
+            page_packets = 0;
+        }
+        if packet.last_in_stream() {
+            found.eos = true;
+        }
+    }
+    Ok(found)
+}
+
+fn inspect_file(path: &Path) -> std::io::Result<()> {
+    let encrypted = repair::is_encrypted_file(path)?;
+    let (scan, torn_chunk) = repair::scan_file(path)?;
+    let found = inspect_packets(path)?;
+
+    println!("{}", path.display());
+    println!("  encrypted      {}", encrypted);
+    println!("  pages          {}", found.pages);
+    println!("  packets        {}", found.packets);
+    println!(
+        "  granule        {} ({} ms)",
+        found.granule,
+        found.granule / 48
+    );
+    println!("  eos            {}", found.eos);
+    println!("  valid bytes    {} of {}", scan.valid_len, scan.total_len);
+    if torn_chunk {
+        println!("  torn chunk     yes");
+    }
+    if let Some(error) = &found.error {
+        println!("  stopped at     {}", error);
+    }
+    for gap in &found.gaps {
+        println!("  gap            page {}: {} ms", gap.page, gap.missing_ms);
+    }
+
+    let index = path.with_extension("idx");
+    if index.exists() {
+        let parsed = sparse_index::parse_index(&fbi_agent::encryption::read_file(&index)?)?;
+        let indexed: u64 = parsed.runs.iter().map(|run| run.frames as u64).sum();
+        println!(
+            "  index          {} runs, {} packets, {} ms logical{}",
+            parsed.runs.len(),
+            indexed,
+            parsed.logical_frames() * 20,
+            if parsed.logical_end.is_some() {
+                ""
+            } else {
+                ", no trailer"
+            }
+        );
+        if indexed != found.packets {
+            println!(
+                "  index mismatch {} packets in the file, {} indexed",
+                found.packets, indexed
+            );
+        }
+    }
+    if scan.needs_repair() || torn_chunk {
+        println!("  needs repair");
+    }
+    Ok(())
+}
+
+pub fn run(args: &[String]) -> ToolResult {
+    if args.is_empty() {
+        return Err("usage: inspect <recording.ogg>...".into());
+    }
+    let mut failed = 0;
+    for path in args {
+        if let Err(e) = inspect_file(Path::new(path)) {
+            eprintln!("{}: {}", path, e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} files could not be read", failed).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+//! Offline inspection and repair of recordings.
+//!
+//! ```text
+//! fbi-agent-tools inspect <recording.ogg>...
+//! fbi-agent-tools verify [--guild <id>] [--tolerance-ms <ms>]
+//! fbi-agent-tools repair [--dry-run] [--repage] <recording.ogg>...
+//! fbi-agent-tools reindex [--dry-run]
+//! ```
+//!
+//! `verify` and `reindex` connect to `DATABASE_URL` and walk
+//! `RECORDING_ROOT`. Encrypted recordings are read with the
+//! `RECORDING_ENCRYPTION_*` keys the bot uses.
+
+use std::error::Error;
+use std::path::Path;
+
+use fbi_agent::repair;
+
+mod files;
+mod inspect;
+mod reindex;
+mod verify;
+
+type ToolResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+const USAGE: &str = "usage: fbi-agent-tools <inspect|verify|repair|reindex> [args]";
+
+#[tokio::main]
+async fn main() -> ToolResult {
+    dotenvy::dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+    fbi_agent::encryption::init()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some((command, rest)) = args.split_first() else {
+        return Err(USAGE.into());
+    };
+    match command.as_str() {
+        "inspect" => inspect::run(rest),
+        "verify" => verify::run(rest).await,
+        "repair" => repair_files(rest),
+        "reindex" => reindex::run(rest).await,
+        _ => Err(USAGE.into()),
+    }
+}
+
+async fn connect() -> Result<sqlx::Pool<sqlx::Postgres>, Box<dyn Error + Send + Sync>> {
+    Ok(sqlx::postgres::PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&fbi_agent::config::db_url()?)
+        .await?)
+}
+
+/// Cut torn files back to their last whole page and close them, optionally
+/// rewriting every page first.
+fn repair_files(args: &[String]) -> ToolResult {
+    let dry_run = args.iter().any(|a| a == "--dry-run");
+    let repage = args.iter().any(|a| a == "--repage");
+    let paths: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    if paths.is_empty() {
+        return Err("usage: repair [--dry-run] [--repage] <recording.ogg>...".into());
+    }
+
+    let mut failed = 0;
+    for path in paths {
+        let path = Path::new(path);
+        if repage && !dry_run {
+            match repair::repage_file(path) {
+                Ok(packets) => println!("{}: repaged {} packets", path.display(), packets),
+                Err(e) => {
+                    eprintln!("{}: repage failed: {}", path.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            }
+        }
+        let sparse = path.with_extension("idx").exists();
+        match repair::repair_recording(path, sparse, dry_run) {
+            Ok(report) if report.unchanged && !dry_run => {
+                println!("{}: ok ({} ms)", path.display(), report.duration_ms);
+            }
+            Ok(report) => println!(
+                "{}: {}dropped {} bytes, eos {}, index {}, {} ms",
+                path.display(),
+                if dry_run { "would have " } else { "" },
+                report.dropped_bytes,
+                if report.eos_added { "added" } else { "present" },
+                if report.index_closed {
+                    "closed"
+                } else {
+                    "unchanged"
+                },
+                report.duration_ms
+            ),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} recordings not repaired", failed).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+//! `reindex`: recreate rows for recordings that exist only on disk.
+//!
+//! Every `.ogg` under `RECORDING_ROOT` whose name no row has is identified
+//! from its path (guild, channel, start, and speaker or session mix),
+//! scanned for its duration and inserted into `audio_files` or
+//! `audio_mix_files`. Torn files are reported and skipped; run `repair` on
+//! them first.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, Utc};
+use sakiot_paths::RECORDING_ROOT;
+use sqlx::{Pool, Postgres};
+
+use crate::ToolResult;
+use crate::files::{self, OnDisk, Owner};
+
+struct Found {
+    file: OnDisk,
+    duration_ms: i64,
+    sparse: bool,
+}
+
+fn examine(file: OnDisk) -> Result<Found, String> {
+    let path = file.key.recording_path(RECORDING_ROOT);
+    let (scan, torn_chunk) = fbi_agent::repair::scan_file(&path).map_err(|e| e.to_string())?;
+    if scan.needs_repair() || torn_chunk {
+        return Err("torn; run repair first".to_string());
+    }
+    let index = path.with_extension("idx");
+    let sparse = matches!(file.owner, Owner::User(_)) && index.exists();
+    let duration_ms = if sparse {
+        let bytes = fbi_agent::encryption::read_file(&index).map_err(|e| e.to_string())?;
+        fbi_agent::events::sparse_index::parse_index(&bytes)
+            .map_err(|e| e.to_string())?
+            .logical_frames() as i64
+            * 20
+    } else {
+        scan.duration_ms()
+    };
+    Ok(Found {
+        file,
+        duration_ms,
+        sparse,
+    })
+}
+
+async fn insert(pool: &Pool<Postgres>, found: &Found) -> Result<u64, sqlx::Error> {
+    let file = &found.file;
+    let start = DateTime::<Utc>::from_timestamp_millis(file.start_ms).unwrap_or_default();
+    let end_ts = file.start_ms + found.duration_ms;
+    let result = match file.owner {
+        Owner::User(user_id) => {
+            sqlx::query!(
+                "INSERT INTO audio_files
+                    (file_name, guild_id, channel_id, user_id, year, month, start_ts, end_ts,
+                     state_enter, state_leave, storage_mode)
+                 SELECT $1::TEXT, $2::BIGINT, $3::BIGINT, $4::BIGINT, $5::INT, $6::INT,
+                        $7::BIGINT, $8::BIGINT, 2, 2, $9::SMALLINT
+                  WHERE NOT EXISTS (SELECT 1 FROM audio_files WHERE file_name = $1)",
+                file.file_name,
+                file.guild_id,
+                file.channel_id,
+                user_id,
+                start.year(),
+                start.month() as i32,
+                file.start_ms,
+                end_ts,
+                found.sparse as i16
+            )
+            .execute(pool)
+            .await?
+        }
+        Owner::Mix => {
+            sqlx::query!(
+                "INSERT INTO audio_mix_files
+                    (file_name, guild_id, channel_id, year, month, start_ts, end_ts)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7)
+                 ON CONFLICT (file_name) DO NOTHING",
+                file.file_name,
+                file.guild_id,
+                file.channel_id,
+                start.year(),
+                start.month() as i32,
+                file.start_ms,
+                end_ts
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(result.rows_affected())
+}
+
+pub async fn run(args: &[String]) -> ToolResult {
+    let dry_run = match args {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => return Err("usage: reindex [--dry-run]".into()),
+    };
+
+    let pool = crate::connect().await?;
+    let known: HashSet<String> = sqlx::query_scalar!(
+        r#"SELECT file_name AS "file_name!" FROM audio_files
+           UNION ALL SELECT file_name FROM audio_mix_files"#
+    )
+    .fetch_all(&pool)
+    .await?
+    .into_iter()
+    .collect();
+
+    let mut inserted = 0;
+    let mut skipped = 0;
+    for path in files::recordings(std::path::Path::new(RECORDING_ROOT))? {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        if known.contains(stem) {
+            continue;
+        }
+        let Some(file) = files::identify(&path) else {
+            println!("skipped {}: name not recognised", path.display());
+            skipped += 1;
+            continue;
+        };
+        let found = match tokio::task::spawn_blocking(move || examine(file)).await? {
+            Ok(found) => found,
+            Err(e) => {
+                println!("skipped {}: {}", path.display(), e);
+                skipped += 1;
+                continue;
+            }
+        };
+        if !dry_run {
+            inserted += insert(&pool, &found).await?;
+        }
+        println!(
+            "{}{} ({} ms)",
+            if dry_run {
+                "would insert "
+            } else {
+                "inserted "
+            },
+            path.display(),
+            found.duration_ms
+        );
+    }
+    println!("{} rows inserted, {} files skipped", inserted, skipped);
+    Ok(())
+}
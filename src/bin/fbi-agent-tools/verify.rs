@@ -0,0 +1,206 @@
+//! `verify`: compare recording rows with the files on disk.
+//!
+//! Reports rows whose file is missing or torn, finished rows whose
+//! `end_ts - start_ts` disagrees with the file's duration, and `.ogg` files
+//! no row points at. Rows held only by a remote backend or already pruned
+//! are not checked against disk.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use fbi_agent::retention::RecordingKind;
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sqlx::{Pool, Postgres};
+
+use crate::{ToolResult, files};
+
+/// Default allowed difference between a row and its file.
+const DEFAULT_TOLERANCE_MS: i64 = 1000;
+
+struct Row {
+    table: &'static str,
+    id: i64,
+    key: RecordingKey,
+    start_ts: i64,
+    end_ts: Option<i64>,
+    sparse: bool,
+}
+
+struct RecordingRow {
+    id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    year: i32,
+    month: i32,
+    file_name: String,
+    start_ts: i64,
+    end_ts: Option<i64>,
+    sparse: bool,
+}
+
+async fn load_rows(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+    guild: Option<i64>,
+) -> Result<Vec<Row>, sqlx::Error> {
+    let rows = match kind {
+        RecordingKind::Track => {
+            sqlx::query_as!(
+                RecordingRow,
+                r#"SELECT id, guild_id, channel_id, year, month, file_name,
+                          start_ts AS "start_ts!", end_ts, storage_mode = 1 AS "sparse!"
+                     FROM audio_files
+                    WHERE storage_backend = 'local' AND pruned_at IS NULL
+                      AND ($1::BIGINT IS NULL OR guild_id = $1)
+                    ORDER BY id"#,
+                guild
+            )
+            .fetch_all(pool)
+            .await?
+        }
+        RecordingKind::Mix => {
+            sqlx::query_as!(
+                RecordingRow,
+                r#"SELECT id, guild_id, channel_id, year, month, file_name,
+                          start_ts, end_ts, FALSE AS "sparse!"
+                     FROM audio_mix_files
+                    WHERE storage_backend = 'local' AND pruned_at IS NULL
+                      AND ($1::BIGINT IS NULL OR guild_id = $1)
+                    ORDER BY id"#,
+                guild
+            )
+            .fetch_all(pool)
+            .await?
+        }
+    };
+    Ok(rows
+        .into_iter()
+        .map(|row| Row {
+            table: kind.table(),
+            id: row.id,
+            key: RecordingKey::new(
+                row.guild_id,
+                row.channel_id,
+                row.year,
+                row.month as u32,
+                row.file_name,
+            ),
+            start_ts: row.start_ts,
+            end_ts: row.end_ts,
+            sparse: row.sparse,
+        })
+        .collect())
+}
+
+/// What is wrong with `row`'s file, if anything.
+fn check(row: &Row, tolerance_ms: i64) -> Option<String> {
+    let path = row.key.recording_path(RECORDING_ROOT);
+    if !path.exists() {
+        return Some("missing".to_string());
+    }
+    let (scan, torn_chunk) = match fbi_agent::repair::scan_file(&path) {
+        Ok(scan) => scan,
+        Err(e) => return Some(format!("unreadable: {}", e)),
+    };
+    // Unfinished rows are either live or waiting for the reaper.
+    let end_ts = row.end_ts?;
+    if scan.needs_repair() || torn_chunk {
+        return Some(format!(
+            "torn: {} of {} bytes valid, eos {}",
+            scan.valid_len, scan.total_len, scan.eos
+        ));
+    }
+    let file_ms = if row.sparse {
+        match fbi_agent::encryption::read_file(&path.with_extension("idx"))
+            .and_then(|bytes| fbi_agent::events::sparse_index::parse_index(&bytes))
+        {
+            Ok(index) => index.logical_frames() as i64 * 20,
+            Err(e) => return Some(format!("index unreadable: {}", e)),
+        }
+    } else {
+        scan.duration_ms()
+    };
+    let row_ms = end_ts - row.start_ts;
+    ((row_ms - file_ms).abs() > tolerance_ms)
+        .then(|| format!("duration: row says {} ms, file has {} ms", row_ms, file_ms))
+}
+
+async fn known_file_names(pool: &Pool<Postgres>) -> Result<HashSet<String>, sqlx::Error> {
+    let names = sqlx::query_scalar!(
+        r#"SELECT file_name AS "file_name!" FROM audio_files
+           UNION ALL SELECT file_name FROM audio_mix_files"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(names.into_iter().collect())
+}
+
+pub async fn run(args: &[String]) -> ToolResult {
+    let mut guild = None;
+    let mut tolerance_ms = DEFAULT_TOLERANCE_MS;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.next()) {
+            ("--guild", Some(id)) => guild = Some(id.parse::<i64>()?),
+            ("--tolerance-ms", Some(ms)) => tolerance_ms = ms.parse()?,
+            _ => return Err("usage: verify [--guild <id>] [--tolerance-ms <ms>]".into()),
+        }
+    }
+
+    let pool = crate::connect().await?;
+    let mut problems = 0;
+    let mut checked = 0;
+    for kind in [RecordingKind::Track, RecordingKind::Mix] {
+        let rows = load_rows(&pool, kind, guild).await?;
+        checked += rows.len();
+        for row in rows {
+            let report = tokio::task::spawn_blocking(move || {
+                let problem = check(&row, tolerance_ms);
+                (row.table, row.id, row.key, problem)
+            })
+            .await?;
+            if let (table, id, key, Some(problem)) = report {
+                problems += 1;
+                println!(
+                    "{} {} {}: {}",
+                    table,
+                    id,
+                    key.recording_path(RECORDING_ROOT).display(),
+                    problem
+                );
+            }
+        }
+    }
+
+    let known = known_file_names(&pool).await?;
+    let root = match guild {
+        Some(guild) => Path::new(RECORDING_ROOT).join(guild.to_string()),
+        None => Path::new(RECORDING_ROOT).to_path_buf(),
+    };
+    let on_disk = if root.exists() {
+        files::recordings(&root)?
+    } else {
+        Vec::new()
+    };
+    for path in &on_disk {
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        if !known.contains(stem) {
+            problems += 1;
+            println!("orphan {}", path.display());
+        }
+    }
+
+    println!(
+        "checked {} rows and {} files, {} problems",
+        checked,
+        on_disk.len(),
+        problems
+    );
+    if problems > 0 {
+        return Err(format!("{} problems found", problems).into());
+    }
+    Ok(())
+}
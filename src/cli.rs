@@ -0,0 +1,149 @@
+//! One-shot maintenance subcommands. `main` runs the bot when none is given.
+//!
+//! ```text
+//! fbi_agent expand-sparse <recording.ogg> <output.ogg>
+//! fbi_agent export-user <user_id> <output.zip>
+//! fbi_agent erase-user <user_id> [--dry-run]
+//! fbi_agent prune-recordings [--dry-run]
+//! fbi_agent rotate-recording-keys [--dry-run]
+//! ```
+//!
+//! The user data and retention commands connect to `DATABASE_URL`.
+
+use std::error::Error;
+use std::path::Path;
+
+type CliResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Run the subcommand named in `args`, or return `None` to start the bot.
+pub async fn run(args: &[String]) -> Option<CliResult> {
+    let command = args.get(1)?;
+    let rest = &args[2..];
+    match command.as_str() {
+        "expand-sparse" => Some(expand_sparse(rest)),
+        "export-user" => Some(export_user(rest).await),
+        "erase-user" => Some(erase_user(rest).await),
+        "prune-recordings" => Some(prune_recordings(rest).await),
+        "rotate-recording-keys" => Some(rotate_recording_keys(rest)),
+        _ => None,
+    }
+}
+
+/// Rebuild the padded timeline of a speech-only recording next to its
+/// `.idx` sidecar.
+fn expand_sparse(args: &[String]) -> CliResult {
+    let [input, output] = args else {
+        return Err("usage: expand-sparse <recording.ogg> <output.ogg>".into());
+    };
+    let input = Path::new(input);
+    let index = crate::events::sparse_index::parse_index(&crate::encryption::read_file(
+        &input.with_extension("idx"),
+    )?)?;
+    let sparse =
+        std::io::BufReader::new(crate::encryption::open_media(std::fs::File::open(input)?)?);
+    let out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let frames = crate::events::sparse_index::expand(sparse, &index, out)?;
+    println!(
+        "wrote {} ({} frames, {} ms from {})",
+        output,
+        frames,
+        frames * 20,
+        index.start_ms
+    );
+    Ok(())
+}
+
+async fn connect() -> Result<sqlx::Pool<sqlx::Postgres>, Box<dyn Error + Send + Sync>> {
+    Ok(sqlx::postgres::PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&crate::config::db_url()?)
+        .await?)
+}
+
+/// Zip every row and recording stored about a user.
+async fn export_user(args: &[String]) -> CliResult {
+    let [user_id, output] = args else {
+        return Err("usage: export-user <user_id> <output.zip>".into());
+    };
+    let user_id: u64 = user_id.parse()?;
+    let storage = crate::storage::Storage::from_env()?;
+    let report =
+        crate::user_data::export(&connect().await?, &storage, user_id, Path::new(output)).await?;
+    for (table, rows) in &report.tables {
+        println!("{:>24} {}", table, rows);
+    }
+    println!(
+        "wrote {} ({} files, {} bytes of recordings)",
+        output, report.files, report.bytes
+    );
+    Ok(())
+}
+
+/// Delete everything stored about a user, or list it with `--dry-run`.
+async fn erase_user(args: &[String]) -> CliResult {
+    let (user_id, dry_run) = match args {
+        [user_id] => (user_id, false),
+        [user_id, flag] if flag == "--dry-run" => (user_id, true),
+        _ => return Err("usage: erase-user <user_id> [--dry-run]".into()),
+    };
+    let user_id: u64 = user_id.parse()?;
+    let storage = crate::storage::Storage::from_env()?;
+    let report =
+        crate::user_data::erase(&connect().await?, &storage, user_id, dry_run, "cli").await?;
+    for (table, rows) in &report.tables {
+        println!("{:>24} {}", table, rows);
+    }
+    for path in &report.files {
+        println!("{}", path.display());
+    }
+    for object in &report.remote_objects {
+        println!("{}", object);
+    }
+    println!("{}", report.summary());
+    Ok(())
+}
+
+/// Run one retention pass now.
+async fn prune_recordings(args: &[String]) -> CliResult {
+    let dry_run = match args {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => return Err("usage: prune-recordings [--dry-run]".into()),
+    };
+    let storage = crate::storage::Storage::from_env()?;
+    let report = crate::retention::prune_once(&connect().await?, &storage, dry_run).await?;
+    println!(
+        "{} {} recordings ({} bytes), {} rows changed",
+        if dry_run { "would prune" } else { "pruned" },
+        report.pruned,
+        report.bytes,
+        report.rows_changed
+    );
+    Ok(())
+}
+
+/// Rewrap every encrypted recording and clip still using a retired key with
+/// `RECORDING_ENCRYPTION_KEY_ID`. Objects already uploaded are not touched.
+fn rotate_recording_keys(args: &[String]) -> CliResult {
+    let dry_run = match args {
+        [] => false,
+        [flag] if flag == "--dry-run" => true,
+        _ => return Err("usage: rotate-recording-keys [--dry-run]".into()),
+    };
+    let keyring = crate::encryption::Keyring::from_env()?;
+    if !keyring.enabled() {
+        return Err("RECORDING_ENCRYPTION_KEYS is not set".into());
+    }
+    for root in [sakiot_paths::RECORDING_ROOT, sakiot_paths::CLIPS_ROOT] {
+        let report = crate::encryption::rotate_tree(Path::new(root), &keyring, dry_run)?;
+        println!(
+            "{}: {} {} of {} files, {} failed",
+            root,
+            if dry_run { "would rewrap" } else { "rewrapped" },
+            report.rotated,
+            report.scanned,
+            report.failed
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! Turn an uploaded audio file into a clip.
+//!
+//! Anything symphonia can demux is accepted: its own codecs are decoded by
+//! symphonia and Opus (which it cannot decode) by libopus. The audio is
+//! brought to 48 kHz stereo (mono duplicated, extra channels dropped,
+//! other rates linearly resampled), gained towards
+//! [`loudness::DEFAULT_TARGET_LUFS`] without passing
+//! [`loudness::DEFAULT_CEILING_DBFS`], and encoded as Ogg/Opus. Blocking.
+
+use std::io::{self, Cursor};
+use std::path::Path;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{CODEC_TYPE_OPUS, Decoder, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use crate::loudness;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+/// Interleaved samples in one 20 ms Opus frame.
+const FRAME_SAMPLES: usize = 960 * CHANNELS;
+/// Largest Opus frame (120 ms) in interleaved stereo samples.
+const MAX_DECODED_SAMPLES: usize = 5760 * CHANNELS;
+
+/// What became of an imported file.
+pub struct Imported {
+    pub duration_ms: i64,
+    /// Integrated loudness before gain; `None` for silence or audio too
+    /// short to measure.
+    pub loudness_lufs: Option<f64>,
+    pub gain_db: f64,
+}
+
+/// Decode `bytes`, normalize it and write it to `path` as an Ogg/Opus clip.
+/// `extension` is the upload's, as a hint for the demuxer. Files longer
+/// than `max_ms` are refused.
+pub fn import(
+    bytes: Vec<u8>,
+    extension: Option<&str>,
+    max_ms: u64,
+    path: &Path,
+    serial: i64,
+) -> io::Result<Imported> {
+    let (samples, rate) = decode(bytes, extension, max_ms)?;
+    let mut samples = to_48k(samples, rate);
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the file has no audio",
+        ));
+    }
+
+    let mut meter = loudness::Meter::new();
+    meter.add(&samples);
+    let measured = meter.measurement();
+    let loudness_lufs = measured.integrated_lufs;
+    let gain_db = measured.gain_db(
+        loudness::DEFAULT_TARGET_LUFS,
+        loudness::DEFAULT_CEILING_DBFS,
+    );
+    let gain = loudness::db_to_linear(gain_db) as f32;
+    for sample in &mut samples {
+        *sample *= gain;
+    }
+
+    let frames = encode(&samples)?;
+    crate::commands::clip::write_clip(path, &frames, serial)?;
+    Ok(Imported {
+        duration_ms: (samples.len() / CHANNELS) as i64 * 1000 / SAMPLE_RATE as i64,
+        loudness_lufs,
+        gain_db,
+    })
+}
+
+/// Loudness of an audio file, decoded as [`import`] would.
+pub fn measure(bytes: Vec<u8>, extension: Option<&str>) -> io::Result<loudness::Measurement> {
+    let (samples, rate) = decode(bytes, extension, u64::MAX)?;
+    let mut meter = loudness::Meter::new();
+    meter.add(&to_48k(samples, rate));
+    Ok(meter.measurement())
+}
+
+fn invalid(err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unsupported audio: {}", err),
+    )
+}
+
+enum TrackDecoder {
+    Symphonia(Box<dyn Decoder>),
+    Opus(opus2::Decoder, Vec<i16>),
+}
+
+/// Decode the default track into interleaved stereo `f32` at its own rate.
+fn decode(bytes: Vec<u8>, extension: Option<&str>, max_ms: u64) -> io::Result<(Vec<f32>, u32)> {
+    let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(invalid)?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| invalid("no audio track"))?;
+    let track_id = track.id;
+    let (rate, mut decoder) = if track.codec_params.codec == CODEC_TYPE_OPUS {
+        let decoder = opus2::Decoder::new(SAMPLE_RATE, opus2::Channels::Stereo)
+            .map_err(|err| io::Error::other(format!("opus decoder init: {}", err)))?;
+        (
+            SAMPLE_RATE,
+            TrackDecoder::Opus(decoder, vec![0; MAX_DECODED_SAMPLES]),
+        )
+    } else {
+        let rate = track
+            .codec_params
+            .sample_rate
+            .ok_or_else(|| invalid("unknown sample rate"))?;
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(invalid)?;
+        (rate, TrackDecoder::Symphonia(decoder))
+    };
+    let max_samples = max_ms.saturating_mul(rate as u64 * CHANNELS as u64) / 1000;
+
+    let mut samples = Vec::new();
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(invalid(e)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        match &mut decoder {
+            TrackDecoder::Symphonia(decoder) => {
+                let decoded = match decoder.decode(&packet) {
+                    Ok(decoded) => decoded,
+                    // A corrupt packet: drop it and carry on.
+                    Err(SymphoniaError::DecodeError(_)) => continue,
+                    Err(e) => return Err(invalid(e)),
+                };
+                let channels = decoded.spec().channels.count();
+                let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                buf.copy_interleaved_ref(decoded);
+                push_stereo(&mut samples, buf.samples(), channels);
+            }
+            TrackDecoder::Opus(decoder, decoded) => {
+                let per_channel = decoder
+                    .decode(&packet.data, decoded, false)
+                    .map_err(invalid)?;
+                samples.extend(
+                    decoded[..per_channel * CHANNELS]
+                        .iter()
+                        .map(|&s| s as f32 / 32_768.0),
+                );
+            }
+        }
+        if samples.len() as u64 > max_samples {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("clips can be at most {} seconds long", max_ms / 1000),
+            ));
+        }
+    }
+    Ok((samples, rate))
+}
+
+/// Append `interleaved` (`channels` wide) to `out` as stereo.
+fn push_stereo(out: &mut Vec<f32>, interleaved: &[f32], channels: usize) {
+    match channels {
+        0 => {}
+        1 => out.extend(interleaved.iter().flat_map(|&s| [s, s])),
+        _ => out.extend(
+            interleaved
+                .chunks_exact(channels)
+                .flat_map(|frame| [frame[0], frame[1]]),
+        ),
+    }
+}
+
+/// Linearly resample interleaved stereo from `rate` to 48 kHz.
+fn to_48k(samples: Vec<f32>, rate: u32) -> Vec<f32> {
+    if rate == SAMPLE_RATE {
+        return samples;
+    }
+    let frames = samples.len() / CHANNELS;
+    let Some(last) = frames.checked_sub(1) else {
+        return samples;
+    };
+    let out_frames = (frames as u64 * SAMPLE_RATE as u64 / rate as u64) as usize;
+    let step = rate as f64 / SAMPLE_RATE as f64;
+    let mut out = Vec::with_capacity(out_frames * CHANNELS);
+    for i in 0..out_frames {
+        let position = i as f64 * step;
+        let at = (position as usize).min(last);
+        let next = (at + 1).min(last);
+        let t = (position - at as f64) as f32;
+        for channel in 0..CHANNELS {
+            let a = samples[at * CHANNELS + channel];
+            let b = samples[next * CHANNELS + channel];
+            out.push(a + (b - a) * t);
+        }
+    }
+    out
+}
+
+/// Encode 48 kHz stereo into 20 ms Opus packets, padding the last frame.
+fn encode(samples: &[f32]) -> io::Result<Vec<Option<Vec<u8>>>> {
+    use opus2::{Application, Channels, Encoder};
+    let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Stereo, Application::Audio)
+        .map_err(|err| io::Error::other(format!("opus encoder init: {}", err)))?;
+    let mut pcm = vec![0i16; FRAME_SAMPLES];
+    let mut out = vec![0u8; 4000];
+    let mut packets = Vec::with_capacity(samples.len().div_ceil(FRAME_SAMPLES));
+    for chunk in samples.chunks(FRAME_SAMPLES) {
+        for (dst, &src) in pcm.iter_mut().zip(chunk) {
+            *dst = (src.clamp(-1.0, 1.0) * 32_767.0) as i16;
+        }
+        pcm[chunk.len()..].fill(0);
+        let n = encoder
+            .encode(&pcm, &mut out)
+            .map_err(|err| io::Error::other(format!("opus encode: {}", err)))?;
+        packets.push(Some(out[..n].to_vec()));
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(36 + data_len).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&channels.to_le_bytes());
+        out.extend_from_slice(&rate.to_le_bytes());
+        out.extend_from_slice(&(rate * channels as u32 * 2).to_le_bytes());
+        out.extend_from_slice(&(channels * 2).to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            out.extend_from_slice(&sample.to_le_bytes());
+        }
+        out
+    }
+
+    fn tone(rate: u32, seconds: u32, amplitude: f64) -> Vec<i16> {
+        (0..rate * seconds)
+            .map(|n| {
+                (amplitude * (2.0 * std::f64::consts::PI * 440.0 * n as f64 / rate as f64).sin())
+                    as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quiet_mono_wav_becomes_a_normalized_clip() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("fbi-clip-import-{}", std::process::id()));
+        let path = dir.join("upload.ogg");
+        let bytes = wav(44_100, 1, &tone(44_100, 2, 1000.0));
+
+        let imported = import(bytes, Some("wav"), 10_000, &path, 1)?;
+        let (scan, torn) = crate::repair::scan_file(&path)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(imported.duration_ms, 2000);
+        assert!(imported.loudness_lufs.is_some_and(|lufs| lufs < -25.0));
+        assert!(imported.gain_db > 5.0);
+        assert!(!torn && !scan.needs_repair());
+        assert_eq!(scan.duration_ms(), 2000);
+        Ok(())
+    }
+
+    #[test]
+    fn long_uploads_are_refused() {
+        let bytes = wav(8_000, 1, &tone(8_000, 3, 1000.0));
+        let path = std::env::temp_dir().join("fbi-clip-import-unused.ogg");
+        assert!(import(bytes, Some("wav"), 2_000, &path, 1).is_err());
+        assert!(!path.exists());
+    }
+}
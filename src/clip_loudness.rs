@@ -0,0 +1,200 @@
+//! Clip loudness, and the gain clips are played with.
+//!
+//! A clip's integrated loudness and sample peak are measured when it is
+//! created ([`measure_file`]) and stored on its `clips` row. Clips that
+//! predate this, were added out of band or failed to measure are picked up
+//! every `CLIP_LOUDNESS_INTERVAL_SECONDS` by the backfill; failures bump
+//! `loudness_attempts` and keep `loudness_error`, and a clip is given up on
+//! after [`MAX_ATTEMPTS`]. Only the instance holding
+//! [`LockKey::ClipLoudness`] backfills.
+//!
+//! [`playback_volume`] turns the measurement into a gain towards the
+//! guild's target loudness, limited so the clip's peak stays below the
+//! guild's ceiling (`guild_playback_settings`, else `CLIP_TARGET_LUFS` and
+//! `CLIP_PEAK_CEILING_DBFS`).
+
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres};
+use tracing::{debug, error, info, warn};
+
+use crate::database::advisory_lock::{LockKey, with_advisory_lock};
+use crate::loudness::{self, Measurement};
+use crate::storage::{ClipSource, Storage};
+
+const MAX_ATTEMPTS: i32 = 3;
+/// Clips per pass.
+const BATCH: i64 = 50;
+/// Volume for clips not measured yet, as every clip used to be played.
+const UNMEASURED_VOLUME: f32 = 0.5;
+
+type LoudnessResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Default)]
+pub struct BackfillReport {
+    pub measured: u64,
+    pub failed: u64,
+}
+
+fn measure_source(source: &ClipSource, saved_file_name: &str) -> std::io::Result<Measurement> {
+    let mut bytes = Vec::new();
+    source.open()?.read_to_end(&mut bytes)?;
+    let extension = Path::new(saved_file_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    crate::clip_import::measure(bytes, extension.as_deref())
+}
+
+async fn store(
+    pool: &Pool<Postgres>,
+    clip_id: &str,
+    measurement: &Measurement,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE clips
+            SET loudness_lufs = $2, peak_dbfs = $3, loudness_measured_at = now(),
+                loudness_error = NULL
+          WHERE clip_id = $1",
+        clip_id,
+        measurement.integrated_lufs,
+        measurement.peak_dbfs
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Measure a clip just written to `path` and store the result. Failures
+/// are logged and left to the backfill.
+pub async fn measure_file(pool: &Pool<Postgres>, clip_id: &str, path: &Path) {
+    let source = ClipSource::File(path.to_path_buf());
+    let saved_file_name = path.to_string_lossy().into_owned();
+    let result: LoudnessResult<()> = async {
+        let measurement =
+            tokio::task::spawn_blocking(move || measure_source(&source, &saved_file_name))
+                .await??;
+        Ok(store(pool, clip_id, &measurement).await?)
+    }
+    .await;
+    if let Err(e) = result {
+        warn!(clip_id, error = %e, "failed to measure clip loudness");
+    }
+}
+
+/// Volume to play `clip_id` at in `guild_id`.
+pub async fn playback_volume(pool: &Pool<Postgres>, guild_id: u64, clip_id: &str) -> f32 {
+    let row = sqlx::query!(
+        "SELECT loudness_lufs, peak_dbfs FROM clips
+          WHERE guild_id = $1 AND clip_id = $2 AND loudness_measured_at IS NOT NULL",
+        guild_id as i64,
+        clip_id
+    )
+    .fetch_optional(pool)
+    .await;
+    let measurement = match row {
+        Ok(Some(row)) => match row.peak_dbfs {
+            Some(peak_dbfs) => Measurement {
+                integrated_lufs: row.loudness_lufs,
+                peak_dbfs,
+            },
+            None => return UNMEASURED_VOLUME,
+        },
+        Ok(None) => return UNMEASURED_VOLUME,
+        Err(e) => {
+            warn!(clip_id, "clip loudness lookup failed: {}", e);
+            return UNMEASURED_VOLUME;
+        }
+    };
+    let settings = crate::database::guild_settings::playback_settings(pool, guild_id).await;
+    let gain_db = measurement.gain_db(settings.target_lufs, settings.ceiling_dbfs);
+    debug!(clip_id, gain_db, "clip playback gain");
+    loudness::db_to_linear(gain_db) as f32
+}
+
+/// Measure one batch of clips without a loudness.
+pub async fn backfill_once(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+) -> LoudnessResult<BackfillReport> {
+    Ok(with_advisory_lock(pool, LockKey::ClipLoudness, || {
+        backfill_locked(pool, storage)
+    })
+    .await?
+    .unwrap_or_default())
+}
+
+async fn backfill_locked(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+) -> LoudnessResult<BackfillReport> {
+    let pending = sqlx::query!(
+        "SELECT clip_id, saved_file_name FROM clips
+          WHERE loudness_measured_at IS NULL AND deleted_at IS NULL
+            AND loudness_attempts < $1
+          ORDER BY clip_id
+          LIMIT $2",
+        MAX_ATTEMPTS,
+        BATCH
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut report = BackfillReport::default();
+    for row in pending {
+        let clip_id = row.clip_id;
+        let saved_file_name = row
+            .saved_file_name
+            .unwrap_or_else(|| format!("{}.ogg", clip_id));
+        match measure_clip(storage, saved_file_name).await {
+            Ok(measurement) => {
+                store(pool, &clip_id, &measurement).await?;
+                report.measured += 1;
+            }
+            Err(e) => {
+                warn!(clip_id = %clip_id, error = %e, "clip loudness backfill failed");
+                report.failed += 1;
+                sqlx::query!(
+                    "UPDATE clips SET loudness_attempts = loudness_attempts + 1, loudness_error = $2
+                      WHERE clip_id = $1",
+                    clip_id,
+                    e.to_string()
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    if report.measured > 0 || report.failed > 0 {
+        info!(
+            measured = report.measured,
+            failed = report.failed,
+            "clip loudness backfill pass done"
+        );
+    }
+    Ok(report)
+}
+
+async fn measure_clip(storage: &Storage, saved_file_name: String) -> LoudnessResult<Measurement> {
+    let source = storage.open_clip(&saved_file_name).await?;
+    Ok(tokio::task::spawn_blocking(move || measure_source(&source, &saved_file_name)).await??)
+}
+
+/// Backfill on `CLIP_LOUDNESS_INTERVAL_SECONDS` for the life of the process.
+pub fn start(pool: Pool<Postgres>, storage: Arc<Storage>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            crate::config::clip_loudness_interval_seconds(),
+        ));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = backfill_once(&pool, &storage).await {
+                error!("clip loudness backfill failed: {}", e);
+            }
+        }
+    });
+}
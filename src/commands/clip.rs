@@ -0,0 +1,580 @@
+//! `/clip`: make playable clips without going through the file system.
+//!
+//! `/clip last` cuts the last few seconds someone said. Audio comes from
+//! the target's live recording via [`crate::events::recent_audio`], so only
+//! people this instance is recording right now can be clipped; silence is
+//! trimmed from both ends and the clip points back at the recording it came
+//! from. `/clip upload` and the "Save as clip" message command turn an
+//! attachment into a clip through [`crate::clip_import`]. Either way the
+//! clip is written to `CLIPS_ROOT` as `<clip_id>.ogg` and gets a `clips`
+//! row naming who made it. `/clip loudness` sets how loud the server's
+//! clips are played; see [`crate::clip_loudness`].
+
+use std::path::Path;
+
+use sakiot_paths::CLIPS_ROOT;
+use serenity::all::{
+    Attachment, CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandType,
+    ResolvedTarget, UserId,
+};
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::client::Context;
+use serenity::model::prelude::{CommandOptionType, GuildId};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::database::guild_settings;
+use crate::events::ogg_opus_writer::OggOpusWriter;
+
+const MIN_SECONDS: i64 = 5;
+const MAX_SECONDS: i64 = 60;
+const MAX_NAME_LEN: u16 = 100;
+
+/// Name of the message context-menu command.
+pub const SAVE_AS_CLIP: &str = "Save as clip";
+
+pub fn register_clip() -> CreateCommand {
+    CreateCommand::new("clip")
+        .description("Make a clip")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "last",
+                "Save the last few seconds someone said as a clip",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::User, "user", "Who to clip")
+                    .required(true),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "seconds",
+                    "How many seconds to go back (5-60)",
+                )
+                .min_int_value(MIN_SECONDS as u64)
+                .max_int_value(MAX_SECONDS as u64)
+                .required(true),
+            )
+            .add_sub_option(name_option()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "upload",
+                "Save an audio file as a clip",
+            )
+            .add_sub_option(name_option())
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Attachment, "file", "The audio file")
+                    .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "loudness",
+                "Show or change how loud clips play in this server (Manage Server to change)",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Number,
+                    "target_lufs",
+                    "Loudness clips are brought to, in LUFS",
+                )
+                .min_number_value(guild_settings::MIN_TARGET_LUFS)
+                .max_number_value(guild_settings::MAX_TARGET_LUFS)
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Number,
+                    "ceiling_dbfs",
+                    "Peak level gain may not push a clip past, in dBFS",
+                )
+                .max_number_value(guild_settings::MAX_CEILING_DBFS)
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "reset",
+                    "Go back to the default loudness",
+                )
+                .required(false),
+            ),
+        )
+}
+
+fn name_option() -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::String, "name", "Name of the clip")
+        .max_length(MAX_NAME_LEN)
+        .required(true)
+}
+
+/// "Save as clip" on a message with an audio attachment.
+pub fn register_save_as_clip() -> CreateCommand {
+    CreateCommand::new(SAVE_AS_CLIP).kind(CommandType::Message)
+}
+
+/// Reply text, and the new clip's id for the Replay button.
+pub async fn handle_clip(
+    application_command: &CommandInteraction,
+    ctx: &Context,
+    pool: &Pool<Postgres>,
+) -> (String, Option<String>) {
+    let Some(guild_id) = application_command.guild_id else {
+        return (
+            "This command can only be used in a server.".to_string(),
+            None,
+        );
+    };
+
+    match application_command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(options),
+            ..
+        }) => match name.as_str() {
+            "last" => handle_last(application_command, ctx, pool, guild_id, options).await,
+            "upload" => handle_upload(application_command, pool, guild_id, options).await,
+            "loudness" => (
+                handle_loudness(application_command, pool, guild_id, options).await,
+                None,
+            ),
+            other => (format!("Unknown /clip subcommand {}.", other), None),
+        },
+        _ => ("Pick a /clip subcommand.".to_string(), None),
+    }
+}
+
+async fn handle_loudness(
+    application_command: &CommandInteraction,
+    pool: &Pool<Postgres>,
+    guild_id: GuildId,
+    options: &[CommandDataOption],
+) -> String {
+    let mut target_lufs = None;
+    let mut ceiling_dbfs = None;
+    let mut reset = false;
+    for opt in options {
+        match (opt.name.as_str(), &opt.value) {
+            ("target_lufs", CommandDataOptionValue::Number(n)) => target_lufs = Some(*n),
+            ("ceiling_dbfs", CommandDataOptionValue::Number(n)) => ceiling_dbfs = Some(*n),
+            ("reset", CommandDataOptionValue::Boolean(b)) => reset = *b,
+            _ => {}
+        }
+    }
+
+    let changing = reset || target_lufs.is_some() || ceiling_dbfs.is_some();
+    if changing && !crate::commands::recording::can_manage(application_command) {
+        return "You need the Manage Server permission to change clip loudness.".to_string();
+    }
+    let result = if reset {
+        guild_settings::reset_playback_settings(pool, guild_id.get()).await
+    } else if changing {
+        guild_settings::set_playback_settings(pool, guild_id.get(), target_lufs, ceiling_dbfs).await
+    } else {
+        Ok(())
+    };
+    if let Err(e) = result {
+        warn!("Failed to update clip loudness: {}", e);
+        return "Failed to update clip loudness.".to_string();
+    }
+    if changing {
+        info!(
+            guild_id = guild_id.get(),
+            ?target_lufs,
+            ?ceiling_dbfs,
+            reset,
+            updated_by = application_command.user.id.get(),
+            "clip loudness updated"
+        );
+    }
+
+    let settings = guild_settings::playback_settings(pool, guild_id.get()).await;
+    format!(
+        "Clips play at {:.1} LUFS, peaking at most {:.1} dBFS.",
+        settings.target_lufs, settings.ceiling_dbfs
+    )
+}
+
+async fn handle_last(
+    application_command: &CommandInteraction,
+    ctx: &Context,
+    pool: &Pool<Postgres>,
+    guild_id: GuildId,
+    options: &[CommandDataOption],
+) -> (String, Option<String>) {
+    let mut target: Option<UserId> = None;
+    let mut seconds: i64 = 0;
+    let mut name = String::new();
+    for opt in options {
+        match (opt.name.as_str(), &opt.value) {
+            ("user", CommandDataOptionValue::User(uid)) => target = Some(*uid),
+            ("seconds", CommandDataOptionValue::Integer(i)) => seconds = *i,
+            ("name", CommandDataOptionValue::String(s)) => name = s.trim().to_string(),
+            _ => {}
+        }
+    }
+    let Some(target) = target else {
+        return ("Missing target user.".to_string(), None);
+    };
+    if !(MIN_SECONDS..=MAX_SECONDS).contains(&seconds) {
+        return (
+            format!("Clips are {} to {} seconds long.", MIN_SECONDS, MAX_SECONDS),
+            None,
+        );
+    }
+    if name.is_empty() {
+        return ("Give the clip a name.".to_string(), None);
+    }
+
+    let invoker = application_command.user.id;
+    let Some(channel_id) = voice_channel(ctx, guild_id, invoker) else {
+        return (
+            "You must be in a voice channel to use /clip last.".to_string(),
+            None,
+        );
+    };
+    if voice_channel(ctx, guild_id, target) != Some(channel_id) {
+        return (
+            format!("<@{}> is not in your voice channel.", target.get()),
+            None,
+        );
+    }
+
+    let ring = match crate::events::recent_audio::from_data(&ctx.data).await {
+        Some(live_audio) => live_audio.get(guild_id.get(), target.get()),
+        None => None,
+    };
+    let Some(ring) = ring else {
+        return (
+            format!("<@{}> is not being recorded right now.", target.get()),
+            None,
+        );
+    };
+    let (frames, source_file_name) = match ring.lock() {
+        Ok(recent) => (
+            recent.last((seconds * 50) as usize),
+            recent.file_name().to_string(),
+        ),
+        Err(_) => return ("Could not read the recording.".to_string(), None),
+    };
+    if frames.is_empty() {
+        return (
+            format!(
+                "<@{}> has not said anything in the last {} seconds.",
+                target.get(),
+                seconds
+            ),
+            None,
+        );
+    }
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let clip_id = new_clip_id(&[
+        guild_id.get() as i64,
+        target.get() as i64,
+        invoker.get() as i64,
+        now_ms,
+    ]);
+    let saved_file_name = format!("{}.ogg", clip_id);
+    let path = Path::new(CLIPS_ROOT).join(&saved_file_name);
+    let duration_ms = frames.len() as i64 * 20;
+    let write_path = path.clone();
+    let written = tokio::task::spawn_blocking(move || write_clip(&write_path, &frames, now_ms))
+        .await
+        .map_err(std::io::Error::other)
+        .and_then(|result| result);
+    if let Err(e) = written {
+        warn!(path = %path.display(), error = %e, "failed to write clip");
+        return ("Failed to save the clip.".to_string(), None);
+    }
+
+    let inserted = sqlx::query!(
+        "INSERT INTO clips
+             (clip_id, guild_id, name, saved_file_name, source_audio_file_id,
+              created_by_user_id, source_user_id)
+         VALUES ($1, $2, $3, $4,
+                 (SELECT id FROM audio_files WHERE file_name = $5),
+                 $6, $7)",
+        clip_id,
+        guild_id.get() as i64,
+        name,
+        saved_file_name,
+        source_file_name,
+        invoker.get() as i64,
+        target.get() as i64
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = inserted {
+        warn!("Failed to insert clip: {}", e);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!(path = %path.display(), error = %e, "failed to remove unsaved clip");
+        }
+        return ("Failed to save the clip.".to_string(), None);
+    }
+    crate::clip_loudness::measure_file(pool, &clip_id, &path).await;
+
+    info!(
+        clip_id = %clip_id,
+        target = target.get(),
+        source = %source_file_name,
+        duration_ms,
+        "clip created"
+    );
+    (
+        format!(
+            "Clipped <@{}>: **{}** ({:.1}s)",
+            target.get(),
+            name,
+            duration_ms as f64 / 1000.0
+        ),
+        Some(clip_id),
+    )
+}
+
+async fn handle_upload(
+    application_command: &CommandInteraction,
+    pool: &Pool<Postgres>,
+    guild_id: GuildId,
+    options: &[CommandDataOption],
+) -> (String, Option<String>) {
+    let mut name = String::new();
+    let mut attachment = None;
+    for opt in options {
+        match (opt.name.as_str(), &opt.value) {
+            ("name", CommandDataOptionValue::String(s)) => name = s.trim().to_string(),
+            ("file", CommandDataOptionValue::Attachment(id)) => {
+                attachment = application_command.data.resolved.attachments.get(id)
+            }
+            _ => {}
+        }
+    }
+    let Some(attachment) = attachment else {
+        return ("Attach an audio file.".to_string(), None);
+    };
+    if name.is_empty() {
+        return ("Give the clip a name.".to_string(), None);
+    }
+    save_upload(
+        pool,
+        guild_id,
+        application_command.user.id,
+        &name,
+        attachment,
+    )
+    .await
+}
+
+/// The "Save as clip" message command: the message's first audio
+/// attachment, named after its file.
+pub async fn handle_save_as_clip(
+    application_command: &CommandInteraction,
+    pool: &Pool<Postgres>,
+) -> (String, Option<String>) {
+    let Some(guild_id) = application_command.guild_id else {
+        return (
+            "This command can only be used in a server.".to_string(),
+            None,
+        );
+    };
+    let Some(ResolvedTarget::Message(message)) = application_command.data.target() else {
+        return ("Use this on a message.".to_string(), None);
+    };
+    let attachment = message
+        .attachments
+        .iter()
+        .find(|a| {
+            a.content_type
+                .as_deref()
+                .is_some_and(|t| t.starts_with("audio/") || t.starts_with("video/"))
+        })
+        .or_else(|| message.attachments.first());
+    let Some(attachment) = attachment else {
+        return ("That message has no attachment.".to_string(), None);
+    };
+    let name: String = Path::new(&attachment.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| attachment.filename.clone())
+        .chars()
+        .take(MAX_NAME_LEN as usize)
+        .collect();
+    save_upload(
+        pool,
+        guild_id,
+        application_command.user.id,
+        &name,
+        attachment,
+    )
+    .await
+}
+
+/// Download `attachment`, import it as clip `name` and record `uploader`
+/// as its author.
+async fn save_upload(
+    pool: &Pool<Postgres>,
+    guild_id: GuildId,
+    uploader: UserId,
+    name: &str,
+    attachment: &Attachment,
+) -> (String, Option<String>) {
+    let max_bytes = crate::config::clip_upload_max_bytes();
+    let too_big = format!(
+        "Uploads can be at most {} MB.",
+        max_bytes.div_ceil(1024 * 1024)
+    );
+    if attachment.size as u64 > max_bytes {
+        return (too_big, None);
+    }
+    let bytes = match attachment.download().await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!(url = %attachment.url, error = %e, "failed to download clip upload");
+            return ("Could not download the attachment.".to_string(), None);
+        }
+    };
+    if bytes.len() as u64 > max_bytes {
+        return (too_big, None);
+    }
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let clip_id = new_clip_id(&[
+        guild_id.get() as i64,
+        uploader.get() as i64,
+        attachment.id.get() as i64,
+        now_ms,
+    ]);
+    let saved_file_name = format!("{}.ogg", clip_id);
+    let path = Path::new(CLIPS_ROOT).join(&saved_file_name);
+    let extension = Path::new(&attachment.filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let max_ms = crate::config::clip_upload_max_ms();
+    let write_path = path.clone();
+    let imported = tokio::task::spawn_blocking(move || {
+        crate::clip_import::import(bytes, extension.as_deref(), max_ms, &write_path, now_ms)
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .and_then(|result| result);
+    let imported = match imported {
+        Ok(imported) => imported,
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            return (
+                format!("Could not use {}: {}", attachment.filename, e),
+                None,
+            );
+        }
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "failed to import clip upload");
+            return ("Failed to save the clip.".to_string(), None);
+        }
+    };
+
+    let inserted = sqlx::query!(
+        "INSERT INTO clips (clip_id, guild_id, name, saved_file_name, created_by_user_id)
+         VALUES ($1, $2, $3, $4, $5)",
+        clip_id,
+        guild_id.get() as i64,
+        name,
+        saved_file_name,
+        uploader.get() as i64
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = inserted {
+        warn!("Failed to insert clip: {}", e);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            warn!(path = %path.display(), error = %e, "failed to remove unsaved clip");
+        }
+        return ("Failed to save the clip.".to_string(), None);
+    }
+    crate::clip_loudness::measure_file(pool, &clip_id, &path).await;
+
+    info!(
+        clip_id = %clip_id,
+        uploader = uploader.get(),
+        file = %attachment.filename,
+        duration_ms = imported.duration_ms,
+        loudness_lufs = imported.loudness_lufs,
+        gain_db = imported.gain_db,
+        "clip uploaded"
+    );
+    (
+        format!(
+            "Saved **{}** ({:.1}s)",
+            name,
+            imported.duration_ms as f64 / 1000.0
+        ),
+        Some(clip_id),
+    )
+}
+
+fn voice_channel(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Option<u64> {
+    let guild = ctx.cache.guild(guild_id)?;
+    let vs = guild.voice_states.get(&user_id)?;
+    vs.channel_id.map(|c| c.get())
+}
+
+/// A fresh clip id: 16 hex digits hashed from `seed`.
+pub fn new_clip_id(seed: &[i64]) -> String {
+    let mut hasher = Sha256::new();
+    for part in seed {
+        hasher.update(part.to_le_bytes());
+    }
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Write `frames` (packets, `None` for silence) as an Ogg/Opus file,
+/// through a temporary file so a half-written clip is never visible.
+pub fn write_clip(path: &Path, frames: &[Option<Vec<u8>>], serial: i64) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let part = path.with_extension("ogg.part");
+    let result =
+        write_ogg(&part, frames, serial as u32).and_then(|()| std::fs::rename(&part, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    result
+}
+
+fn write_ogg(path: &Path, frames: &[Option<Vec<u8>>], serial: u32) -> std::io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    {
+        let mut writer = OggOpusWriter::new(std::io::BufWriter::new(&file), serial, 0)?;
+        for frame in frames {
+            match frame {
+                Some(packet) => writer.write_packet(packet)?,
+                None => writer.write_silence(1)?,
+            }
+        }
+        writer.finish()?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clips_are_whole_ogg_files() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("fbi-clip-test-{}", std::process::id()));
+        let path = dir.join("clip.ogg");
+        let packet = crate::events::ogg_opus_writer::silence_frame_bytes()?;
+        write_clip(&path, &[Some(packet.clone()), None, Some(packet)], 7)?;
+
+        let (scan, torn) = crate::repair::scan_file(&path)?;
+        std::fs::remove_dir_all(&dir)?;
+        assert!(!torn && !scan.needs_repair());
+        assert_eq!(scan.duration_ms(), 60);
+        Ok(())
+    }
+}
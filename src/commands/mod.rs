@@ -1,2 +1,5 @@
+pub mod clip;
+pub mod privacy;
+pub mod recording;
 pub mod stamp;
 pub mod voice_controls;
@@ -0,0 +1,108 @@
+use serenity::all::CommandInteraction;
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::model::prelude::CommandOptionType;
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::database::consent;
+use crate::events::voice_receiver::VoiceEventType;
+
+pub fn register_privacy() -> CreateCommand {
+    CreateCommand::new("privacy")
+        .description("Control whether your voice is recorded")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "optout",
+            "Stop recording your voice in every server",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "optin",
+            "Allow your voice to be recorded again",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "status",
+            "Show your current recording consent",
+        ))
+}
+
+pub async fn handle_privacy(
+    application_command: &CommandInteraction,
+    pool: &Pool<Postgres>,
+) -> String {
+    let user_id = application_command.user.id.get();
+    let Some(subcommand) = application_command.data.options.first() else {
+        return "Missing subcommand.".to_string();
+    };
+
+    match subcommand.name.as_str() {
+        "optout" | "optin" => {
+            let opted_out = subcommand.name == "optout";
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            match consent::set_recording_opt_out(pool, user_id, opted_out, now_ms).await {
+                Ok(changed) => {
+                    if changed {
+                        audit(application_command, pool, opted_out).await;
+                        info!(user_id, opted_out, "recording consent changed");
+                    }
+                    if opted_out {
+                        "You are opted out. Recordings in progress stop within a few seconds \
+                         and no new ones are made. Existing recordings are kept."
+                            .to_string()
+                    } else {
+                        "You are opted in. You will be recorded again from the next time you \
+                         join a recorded channel."
+                            .to_string()
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to update consent for {}: {}", user_id, e);
+                    "Failed to save your choice.".to_string()
+                }
+            }
+        }
+        "status" => match consent::status(pool, user_id).await {
+            Ok(status) => {
+                let state = if status.recording_opt_out {
+                    "opted out"
+                } else {
+                    "opted in"
+                };
+                let mut lines = vec![format!("Recording: {}", state)];
+                if let Some(ts) = status.opted_out_ts {
+                    lines.push(format!("Last opted out: <t:{}:f>", ts / 1000));
+                }
+                if let Some(ts) = status.opted_in_ts {
+                    lines.push(format!("Last opted in: <t:{}:f>", ts / 1000));
+                }
+                lines.join("\n")
+            }
+            Err(e) => {
+                warn!("Failed to load consent for {}: {}", user_id, e);
+                "Failed to load your consent.".to_string()
+            }
+        },
+        other => format!("Unknown subcommand {}", other),
+    }
+}
+
+async fn audit(application_command: &CommandInteraction, pool: &Pool<Postgres>, opted_out: bool) {
+    let (event_type, details) = if opted_out {
+        (VoiceEventType::ConsentOptOut, "Recording consent withdrawn")
+    } else {
+        (VoiceEventType::ConsentOptIn, "Recording consent given")
+    };
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO voice_events_audit (guild_id, user_id, event_type_id, details) VALUES ($1, $2, $3, $4)",
+        application_command.guild_id.map(|g| g.get() as i64),
+        application_command.user.id.get() as i64,
+        event_type as i32,
+        details
+    )
+    .execute(pool)
+    .await
+    {
+        warn!("Failed to audit consent change: {}", e);
+    }
+}
@@ -0,0 +1,350 @@
+use serenity::all::{ChannelType, CommandDataOption, CommandDataOptionValue, CommandInteraction};
+use serenity::builder::{CreateCommand, CreateCommandOption};
+use serenity::client::Context;
+use serenity::model::prelude::{CommandOptionType, GuildId, UserId};
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::database::{guild_settings, recording_policy};
+
+pub fn register_recording() -> CreateCommand {
+    CreateCommand::new("recording")
+        .description("Show or change where this server is recorded")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "status",
+                "Show the recording policy for a voice channel",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Channel,
+                    "channel",
+                    "Voice channel (defaults to the one you are in)",
+                )
+                .channel_types(vec![ChannelType::Voice, ChannelType::Stage])
+                .required(false),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "guild",
+                "Change server-wide recording settings (Manage Server)",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "enabled",
+                    "Record voice in this server at all",
+                )
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "default_channels",
+                    "Record channels without their own setting",
+                )
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "user_opt_out",
+                    "Let members opt out of being recorded",
+                )
+                .required(false),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "channel",
+                "Allow or deny recording in one channel (Manage Server)",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Channel, "channel", "Voice channel")
+                    .channel_types(vec![ChannelType::Voice, ChannelType::Stage])
+                    .required(true),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::String, "mode", "Recording mode")
+                    .add_string_choice("allow", "allow")
+                    .add_string_choice("deny", "deny")
+                    .add_string_choice("server default", "default")
+                    .required(true),
+            ),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "settings",
+                "Change what else is recorded and where stamp clips go (Manage Server)",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "record_bots",
+                    "Record other bots to their own tracks",
+                )
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "record_playback",
+                    "Record clips I play as a track of the session",
+                )
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Channel,
+                    "stamp_clip_channel",
+                    "Text channel to post stamp clips to",
+                )
+                .channel_types(vec![ChannelType::Text])
+                .required(false),
+            )
+            .add_sub_option(
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "stop_stamp_clips",
+                    "Stop posting stamp clips",
+                )
+                .required(false),
+            ),
+        )
+}
+
+pub async fn handle_recording(
+    application_command: &CommandInteraction,
+    ctx: &Context,
+    pool: &Pool<Postgres>,
+) -> String {
+    let Some(guild_id) = application_command.guild_id else {
+        return "This command can only be used in a server.".to_string();
+    };
+    let Some(subcommand) = application_command.data.options.first() else {
+        return "Missing subcommand.".to_string();
+    };
+    let CommandDataOptionValue::SubCommand(options) = &subcommand.value else {
+        return "Missing subcommand.".to_string();
+    };
+
+    match subcommand.name.as_str() {
+        "status" => {
+            let channel_id = channel_option(options, "channel")
+                .or_else(|| user_voice_channel(ctx, guild_id, application_command.user.id));
+            status(pool, guild_id, channel_id, application_command.user.id).await
+        }
+        "guild" | "channel" | "settings" if !can_manage(application_command) => {
+            "You need the Manage Server permission to change the recording policy.".to_string()
+        }
+        "guild" => {
+            let enabled = bool_option(options, "enabled");
+            let default_channels = bool_option(options, "default_channels");
+            let user_opt_out = bool_option(options, "user_opt_out");
+            if enabled.is_none() && default_channels.is_none() && user_opt_out.is_none() {
+                return "Nothing to change.".to_string();
+            }
+            match recording_policy::set_guild_policy(
+                pool,
+                guild_id.get(),
+                enabled,
+                default_channels,
+                user_opt_out,
+                application_command.user.id.get(),
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!(
+                        guild_id = guild_id.get(),
+                        ?enabled,
+                        ?default_channels,
+                        ?user_opt_out,
+                        "guild recording policy updated"
+                    );
+                    "Recording policy updated.".to_string()
+                }
+                Err(e) => {
+                    warn!("Failed to update guild recording policy: {}", e);
+                    "Failed to update the recording policy.".to_string()
+                }
+            }
+        }
+        "channel" => {
+            let Some(channel_id) = channel_option(options, "channel") else {
+                return "Missing channel.".to_string();
+            };
+            let allowed = match string_option(options, "mode") {
+                Some("allow") => Some(true),
+                Some("deny") => Some(false),
+                Some("default") => None,
+                _ => return "Unknown mode.".to_string(),
+            };
+            match recording_policy::set_channel_policy(
+                pool,
+                guild_id.get(),
+                channel_id,
+                allowed,
+                application_command.user.id.get(),
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!(
+                        guild_id = guild_id.get(),
+                        channel_id,
+                        ?allowed,
+                        "channel recording policy updated"
+                    );
+                    format!("Recording policy for <#{}> updated.", channel_id)
+                }
+                Err(e) => {
+                    warn!("Failed to update channel recording policy: {}", e);
+                    "Failed to update the recording policy.".to_string()
+                }
+            }
+        }
+        "settings" => {
+            let record_bots = bool_option(options, "record_bots");
+            let record_playback = bool_option(options, "record_playback");
+            let stamp_clip_channel_id = match (
+                channel_option(options, "stamp_clip_channel"),
+                bool_option(options, "stop_stamp_clips"),
+            ) {
+                (Some(_), Some(true)) => {
+                    return "Pick a stamp clip channel or stop stamp clips, not both.".to_string();
+                }
+                (Some(channel_id), _) => Some(Some(channel_id)),
+                (None, Some(true)) => Some(None),
+                (None, _) => None,
+            };
+            if record_bots.is_none() && record_playback.is_none() && stamp_clip_channel_id.is_none()
+            {
+                return "Nothing to change.".to_string();
+            }
+            match guild_settings::set_recording_settings(
+                pool,
+                guild_id.get(),
+                record_bots,
+                record_playback,
+                stamp_clip_channel_id,
+            )
+            .await
+            {
+                Ok(()) => {
+                    info!(
+                        guild_id = guild_id.get(),
+                        ?record_bots,
+                        ?record_playback,
+                        ?stamp_clip_channel_id,
+                        updated_by = application_command.user.id.get(),
+                        "guild recording settings updated"
+                    );
+                    "Recording settings updated.".to_string()
+                }
+                Err(e) => {
+                    warn!("Failed to update guild recording settings: {}", e);
+                    "Failed to update the recording settings.".to_string()
+                }
+            }
+        }
+        other => format!("Unknown subcommand {}", other),
+    }
+}
+
+async fn status(
+    pool: &Pool<Postgres>,
+    guild_id: GuildId,
+    channel_id: Option<u64>,
+    user_id: UserId,
+) -> String {
+    let policy = match recording_policy::load(pool, guild_id.get(), channel_id.unwrap_or(0)).await {
+        Ok(policy) => policy,
+        Err(e) => {
+            warn!("Failed to load recording policy: {}", e);
+            return "Failed to load the recording policy.".to_string();
+        }
+    };
+
+    let on_off = |b: bool| if b { "on" } else { "off" };
+    let mut lines = vec![
+        format!("Recording in this server: {}", on_off(policy.guild_enabled)),
+        format!(
+            "Channels without their own setting: {}",
+            on_off(policy.channels_default_allowed)
+        ),
+        format!("Member opt-out: {}", on_off(policy.allow_user_opt_out)),
+    ];
+    let settings = guild_settings::recording_settings(pool, guild_id.get()).await;
+    lines.push(format!("Other bots: {}", on_off(settings.record_bots)));
+    lines.push(format!(
+        "Clips I play: {}",
+        on_off(settings.record_playback)
+    ));
+    lines.push(match settings.stamp_clip_channel_id {
+        Some(channel_id) => format!("Stamp clips: posted to <#{}>", channel_id),
+        None => "Stamp clips: not posted".to_string(),
+    });
+    if let Some(channel_id) = channel_id {
+        let source = match policy.channel_override {
+            Some(_) => "channel setting",
+            None => "server default",
+        };
+        lines.push(format!(
+            "<#{}>: {} ({})",
+            channel_id,
+            on_off(policy.allows_channel()),
+            source
+        ));
+        if policy.allows_channel() && !policy.allows_user(user_id.get()) {
+            lines.push("You are opted out and will not be recorded.".to_string());
+        }
+    }
+    lines.join("\n")
+}
+
+pub(crate) fn can_manage(application_command: &CommandInteraction) -> bool {
+    application_command
+        .member
+        .as_ref()
+        .and_then(|m| m.permissions)
+        .is_some_and(|p| p.manage_guild())
+}
+
+fn bool_option(options: &[CommandDataOption], name: &str) -> Option<bool> {
+    options
+        .iter()
+        .find_map(|opt| match (&opt.value, opt.name == name) {
+            (CommandDataOptionValue::Boolean(b), true) => Some(*b),
+            _ => None,
+        })
+}
+
+fn string_option<'a>(options: &'a [CommandDataOption], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find_map(|opt| match (&opt.value, opt.name == name) {
+            (CommandDataOptionValue::String(s), true) => Some(s.as_str()),
+            _ => None,
+        })
+}
+
+fn channel_option(options: &[CommandDataOption], name: &str) -> Option<u64> {
+    options
+        .iter()
+        .find_map(|opt| match (&opt.value, opt.name == name) {
+            (CommandDataOptionValue::Channel(c), true) => Some(c.get()),
+            _ => None,
+        })
+}
+
+fn user_voice_channel(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Option<u64> {
+    let guild = ctx.cache.guild(guild_id)?;
+    let vs = guild.voice_states.get(&user_id)?;
+    vs.channel_id.map(|c| c.get())
+}
@@ -138,8 +138,13 @@ pub async fn handle_stamp(
     let insert = sqlx::query!(
         r#"INSERT INTO stamps
              (guild_id, channel_id, target_user_id, stamper_user_id,
-              stamp_ts, offset_ms, audio_file_id, note)
-           VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+              stamp_ts, offset_ms, audio_file_id, note, session_id)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8,
+                   COALESCE(
+                       (SELECT session_id FROM audio_files WHERE id = $7),
+                       (SELECT id FROM voice_sessions
+                         WHERE guild_id = $1 AND channel_id = $2 AND end_ts IS NULL
+                         ORDER BY start_ts DESC LIMIT 1)))
            RETURNING id"#,
         guild_id.get() as i64,
         channel_id as i64,
@@ -1,15 +1,101 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use serenity::builder::{CreateCommand, CreateCommandOption};
 use serenity::client::Context;
 use serenity::model::id::{ChannelId, UserId};
 use serenity::model::prelude::CommandOptionType;
 
-use crate::events::voice_receiver::CLIPS_FILE_PATH;
+use crate::storage::{ClipSource, Storage};
+use dashmap::DashMap;
 use serenity::model::prelude::GuildId;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
 use songbird::Songbird;
+use songbird::tracks::{PlayMode, Track, TrackHandle, TrackQueue};
 use sqlx::{Pool, Postgres};
 
+/// Songbird input for a clip, streamed from disk or from the bytes fetched
+/// from remote storage, decrypted if needed.
+fn clip_input(clip: &ClipSource) -> std::io::Result<songbird::input::Input> {
+    use songbird::input::{AudioStream, Input, LiveInput};
+    use symphonia::core::probe::Hint;
+
+    let mut hint = Hint::new();
+    hint.with_extension("ogg");
+    Ok(Input::Live(
+        LiveInput::Raw(AudioStream {
+            input: clip.open()?,
+            hint: Some(hint),
+        }),
+        None,
+    ))
+}
+
+/// What a queued track is, attached to its `TrackHandle` by [`play_clip`].
+/// Every track in a queue is enqueued there, so every one carries this.
+pub struct QueuedClip {
+    pub clip_id: String,
+    pub name: String,
+    pub requested_by: i64,
+    /// `None` when the clip's length could not be read.
+    pub duration: Option<Duration>,
+    /// Volume that plays the clip at the guild's target loudness; `/volume`
+    /// scales it.
+    pub gain: f32,
+}
+
+/// `/volume` per guild, in percent of each clip's normalized volume. It
+/// applies to the queue and to every clip queued after it, until restart.
+#[derive(Default)]
+pub struct GuildVolumes(DashMap<GuildId, u32>);
+
+impl GuildVolumes {
+    pub fn percent(&self, guild_id: GuildId) -> u32 {
+        self.0.get(&guild_id).map_or(100, |percent| *percent)
+    }
+
+    /// Track volume for a clip with `gain` in `guild_id`.
+    pub fn track_volume(&self, guild_id: GuildId, gain: f32) -> f32 {
+        gain * self.percent(guild_id) as f32 / 100.0
+    }
+}
+
+pub struct GuildVolumesKey;
+impl TypeMapKey for GuildVolumesKey {
+    type Value = Arc<GuildVolumes>;
+}
+
+/// The shared [`GuildVolumes`], or an empty one when none was registered.
+pub async fn guild_volumes(data: &RwLock<TypeMap>) -> Arc<GuildVolumes> {
+    data.read()
+        .await
+        .get::<GuildVolumesKey>()
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// A queued track as it is right now.
+pub struct QueueEntry {
+    pub clip: Arc<QueuedClip>,
+    pub position: Duration,
+    pub playing: bool,
+    pub volume_percent: u32,
+}
+
+/// Length of an Ogg/Opus clip, from its last granule position.
+fn clip_duration(clip: &ClipSource) -> Option<Duration> {
+    let mut reader = ogg::PacketReader::new(clip.open().ok()?);
+    let mut granule = None;
+    while let Ok(Some(packet)) = reader.read_packet() {
+        granule = Some(packet.absgp_page());
+    }
+    granule.map(|samples| Duration::from_millis(samples * 1000 / 48_000))
+}
+
 pub async fn play_clip(
     pool: &Pool<Postgres>,
+    storage: &Storage,
+    volumes: &GuildVolumes,
     manager: &std::sync::Arc<Songbird>,
     guild_id: GuildId,
     clip_id: &str,
@@ -34,16 +120,47 @@ pub async fn play_clip(
         return Err(format!("Clip with ID '{}' not found in database.", clip_id));
     };
 
-    let result = songbird::input::File::new(format!("{}/{}", CLIPS_FILE_PATH, saved_file_name));
-    let input = songbird::input::Input::from(result);
-
     let handler = match manager.get(guild_id) {
         Some(h) => h,
         None => return Err("I am not currently in a voice channel.".to_string()),
     };
 
-    let handler_lock = handler.lock().await.enqueue(input.into()).await;
-    let _ = handler_lock.set_volume(0.5);
+    let clip = storage
+        .open_clip(&saved_file_name)
+        .await
+        .map_err(|e| format!("Clip '{}' is unavailable: {}", actual_name, e))?;
+    let input = clip_input(&clip)
+        .map_err(|e| format!("Clip '{}' could not be opened: {}", actual_name, e))?;
+
+    let gain = crate::clip_loudness::playback_volume(pool, guild_id.get(), clip_id).await;
+    let duration_source = clip.clone();
+    let duration = tokio::task::spawn_blocking(move || clip_duration(&duration_source))
+        .await
+        .ok()
+        .flatten();
+    let queued = QueuedClip {
+        clip_id: clip_id.to_string(),
+        name: actual_name.clone(),
+        requested_by: user_id,
+        duration,
+        gain,
+    };
+    let track =
+        Track::new_with_data(input, Arc::new(queued)).volume(volumes.track_volume(guild_id, gain));
+    let (handler_lock, connection) = {
+        let mut call = handler.lock().await;
+        let connection = call.current_connection().cloned();
+        (call.enqueue(track).await, connection)
+    };
+    crate::events::playback_track::record_on_play(
+        pool,
+        &handler_lock,
+        connection,
+        guild_id.get(),
+        clip,
+        actual_name.clone(),
+    )
+    .await;
 
     if let Err(e) = sqlx::query!(
         "INSERT INTO jam_invocations (user_id, guild_id, clip_id) VALUES ($1, $2, $3)",
@@ -76,15 +193,206 @@ pub fn register_jam() -> CreateCommand {
         )
 }
 
-pub async fn queue(manager: &std::sync::Arc<Songbird>, guild_id: GuildId) -> String {
-    if let Some(handler) = manager.get(guild_id) {
-        let handler_lock = handler.lock().await;
-        let queue = handler_lock.queue();
-        let queue_len = queue.len();
-        format!("There are {} tracks in the queue.", queue_len)
+async fn track_queue(manager: &Arc<Songbird>, guild_id: GuildId) -> Result<TrackQueue, String> {
+    let handler = manager
+        .get(guild_id)
+        .ok_or_else(|| "Not in a voice channel.".to_string())?;
+    let call = handler.lock().await;
+    Ok(call.queue().clone())
+}
+
+async fn queue_entry(handle: &TrackHandle) -> Option<QueueEntry> {
+    let state = handle.get_info().await.ok()?;
+    let clip = handle.data::<QueuedClip>();
+    Some(QueueEntry {
+        volume_percent: volume_percent(state.volume, clip.gain),
+        clip,
+        position: state.position,
+        playing: matches!(state.playing, PlayMode::Play),
+    })
+}
+
+/// A track's volume as a percentage of its clip's normalized volume.
+fn volume_percent(volume: f32, gain: f32) -> u32 {
+    if gain > 0.0 {
+        (volume / gain * 100.0).round() as u32
     } else {
-        "Not in a voice channel.".to_string()
+        100
+    }
+}
+
+/// The queue, current track first.
+pub async fn queue_entries(
+    manager: &Arc<Songbird>,
+    guild_id: GuildId,
+) -> Result<Vec<QueueEntry>, String> {
+    let queue = track_queue(manager, guild_id).await?;
+    let mut entries = Vec::new();
+    for handle in queue.current_queue() {
+        // A track that ended since the queue was read is skipped.
+        if let Some(entry) = queue_entry(&handle).await {
+            entries.push(entry);
+        }
     }
+    Ok(entries)
+}
+
+/// The track at the head of the queue, if any.
+pub async fn now_playing(
+    manager: &Arc<Songbird>,
+    guild_id: GuildId,
+) -> Result<Option<QueueEntry>, String> {
+    let queue = track_queue(manager, guild_id).await?;
+    match queue.current() {
+        Some(handle) => Ok(queue_entry(&handle).await),
+        None => Ok(None),
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn format_length(clip: &QueuedClip) -> String {
+    clip.duration
+        .map(format_duration)
+        .unwrap_or_else(|| "?:??".to_string())
+}
+
+/// Longest `/queue` listing; the rest is summarized.
+const QUEUE_LISTED: usize = 20;
+
+pub async fn queue(manager: &Arc<Songbird>, guild_id: GuildId) -> String {
+    let entries = match queue_entries(manager, guild_id).await {
+        Ok(entries) => entries,
+        Err(e) => return e,
+    };
+    if entries.is_empty() {
+        return "The queue is empty.".to_string();
+    }
+    let mut lines: Vec<String> = entries
+        .iter()
+        .take(QUEUE_LISTED)
+        .enumerate()
+        .map(|(i, entry)| {
+            let marker = match (i, entry.playing) {
+                (0, true) => "▶ ",
+                (0, false) => "⏸ ",
+                _ => "",
+            };
+            format!(
+                "{}{}. **{}** ({}), requested by <@{}>",
+                marker,
+                i + 1,
+                entry.clip.name,
+                format_length(&entry.clip),
+                entry.clip.requested_by
+            )
+        })
+        .collect();
+    if entries.len() > QUEUE_LISTED {
+        lines.push(format!("…and {} more.", entries.len() - QUEUE_LISTED));
+    }
+    lines.join("\n")
+}
+
+pub async fn now_playing_text(manager: &Arc<Songbird>, guild_id: GuildId) -> String {
+    match now_playing(manager, guild_id).await {
+        Ok(Some(entry)) => format!(
+            "{} **{}** {} / {}, requested by <@{}>, volume {}%",
+            if entry.playing { "Playing" } else { "Paused:" },
+            entry.clip.name,
+            format_duration(entry.position),
+            format_length(&entry.clip),
+            entry.clip.requested_by,
+            entry.volume_percent
+        ),
+        Ok(None) => "Nothing is playing.".to_string(),
+        Err(e) => e,
+    }
+}
+
+pub async fn pause(manager: &Arc<Songbird>, guild_id: GuildId) -> Result<String, String> {
+    let queue = track_queue(manager, guild_id).await?;
+    if queue.is_empty() {
+        return Err("Nothing is playing.".to_string());
+    }
+    queue
+        .pause()
+        .map_err(|e| format!("Could not pause: {}", e))?;
+    Ok("Paused.".to_string())
+}
+
+pub async fn resume(manager: &Arc<Songbird>, guild_id: GuildId) -> Result<String, String> {
+    let queue = track_queue(manager, guild_id).await?;
+    if queue.is_empty() {
+        return Err("Nothing is queued.".to_string());
+    }
+    queue
+        .resume()
+        .map_err(|e| format!("Could not resume: {}", e))?;
+    Ok("Resumed.".to_string())
+}
+
+pub const MAX_VOLUME_PERCENT: u32 = 200;
+
+/// Play the guild's clips at `percent` of their normalized volume: every
+/// queued track now, and clips queued later by [`play_clip`].
+pub async fn set_volume(
+    manager: &Arc<Songbird>,
+    volumes: &GuildVolumes,
+    guild_id: GuildId,
+    percent: u32,
+) -> Result<String, String> {
+    if percent > MAX_VOLUME_PERCENT {
+        return Err(format!("Volume goes from 0 to {}%.", MAX_VOLUME_PERCENT));
+    }
+    volumes.0.insert(guild_id, percent);
+    if let Ok(queue) = track_queue(manager, guild_id).await {
+        for handle in queue.current_queue() {
+            let gain = handle.data::<QueuedClip>().gain;
+            // A track that just ended can no longer be changed; that is fine.
+            let _ = handle.set_volume(volumes.track_volume(guild_id, gain));
+        }
+    }
+    Ok(format!("Volume set to {}%.", percent))
+}
+
+/// Refuse positions past the end of a clip of known length.
+fn check_seek(clip: &QueuedClip, position: Duration) -> Result<(), String> {
+    if clip.duration.is_some_and(|duration| position > duration) {
+        return Err(format!(
+            "**{}** is only {} long.",
+            clip.name,
+            format_length(clip)
+        ));
+    }
+    Ok(())
+}
+
+/// Move the current track to `position`.
+pub async fn seek(
+    manager: &Arc<Songbird>,
+    guild_id: GuildId,
+    position: Duration,
+) -> Result<String, String> {
+    let queue = track_queue(manager, guild_id).await?;
+    let current = queue
+        .current()
+        .ok_or_else(|| "Nothing is playing.".to_string())?;
+    let clip = current.data::<QueuedClip>();
+    check_seek(&clip, position)?;
+    current
+        .seek(position)
+        .result_async()
+        .await
+        .map_err(|e| format!("Could not seek: {}", e))?;
+    Ok(format!(
+        "Moved **{}** to {}.",
+        clip.name,
+        format_duration(position)
+    ))
 }
 
 pub async fn skip(manager: &std::sync::Arc<Songbird>, guild_id: GuildId) -> String {
@@ -139,7 +447,48 @@ fn user_voice_channel(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Opti
 }
 
 pub fn register_queue() -> CreateCommand {
-    CreateCommand::new("queue").description("List how many tracks are in the queue")
+    CreateCommand::new("queue").description("List the clips in the queue")
+}
+
+pub fn register_pause() -> CreateCommand {
+    CreateCommand::new("pause").description("Pause the current track")
+}
+
+pub fn register_resume() -> CreateCommand {
+    CreateCommand::new("resume").description("Resume the paused track")
+}
+
+pub fn register_volume() -> CreateCommand {
+    CreateCommand::new("volume")
+        .description("Set the clip volume for this server")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "percent",
+                "Percent of the normal volume (0-200)",
+            )
+            .min_int_value(0)
+            .max_int_value(MAX_VOLUME_PERCENT as u64)
+            .required(true),
+        )
+}
+
+pub fn register_seek() -> CreateCommand {
+    CreateCommand::new("seek")
+        .description("Jump to a point in the current track")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "seconds",
+                "Seconds from the start",
+            )
+            .min_int_value(0)
+            .required(true),
+        )
+}
+
+pub fn register_nowplaying() -> CreateCommand {
+    CreateCommand::new("nowplaying").description("Show the current track")
 }
 
 pub fn register_skip() -> CreateCommand {
@@ -153,3 +502,56 @@ pub fn register_stop() -> CreateCommand {
 pub fn register_join() -> CreateCommand {
     CreateCommand::new("join").description("Join your current voice channel")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clip(duration: Option<Duration>) -> QueuedClip {
+        QueuedClip {
+            clip_id: "1".to_string(),
+            name: "horn".to_string(),
+            requested_by: 7,
+            duration,
+            gain: 0.5,
+        }
+    }
+
+    #[test]
+    fn durations_format_as_minutes_and_seconds() {
+        assert_eq!(format_duration(Duration::ZERO), "0:00");
+        assert_eq!(format_duration(Duration::from_millis(65_900)), "1:05");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "60:00");
+        assert_eq!(format_length(&clip(None)), "?:??");
+    }
+
+    #[test]
+    fn volume_percent_is_relative_to_the_clip_gain() {
+        assert_eq!(volume_percent(0.5, 0.5), 100);
+        assert_eq!(volume_percent(0.25, 0.5), 50);
+        assert_eq!(volume_percent(1.0, 0.5), 200);
+        assert_eq!(volume_percent(0.3, 0.0), 100);
+    }
+
+    #[test]
+    fn guild_volume_scales_clips_queued_later() {
+        let volumes = GuildVolumes::default();
+        let guild = GuildId::new(1);
+        assert_eq!(volumes.percent(guild), 100);
+        volumes.0.insert(guild, 50);
+        assert_eq!(volumes.track_volume(guild, 1.0), 0.5);
+        assert_eq!(volumes.percent(GuildId::new(2)), 100);
+    }
+
+    #[test]
+    fn seeks_stop_at_the_end_of_the_clip() {
+        let known = clip(Some(Duration::from_secs(30)));
+        assert!(check_seek(&known, Duration::ZERO).is_ok());
+        assert!(check_seek(&known, Duration::from_secs(30)).is_ok());
+        assert_eq!(
+            check_seek(&known, Duration::from_secs(31)),
+            Err("**horn** is only 0:30 long.".to_string())
+        );
+        assert!(check_seek(&clip(None), Duration::from_secs(600)).is_ok());
+    }
+}
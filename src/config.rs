@@ -29,6 +29,236 @@ pub fn application_id_release() -> Result<u64, Box<dyn Error + Send + Sync>> {
     Ok(env::var("APPLICATION_ID_RELEASE")?.parse()?)
 }
 
+/// Write a mixed-down `<session>-mix.ogg` next to the per-user recordings.
+pub fn session_mix_enabled() -> bool {
+    env_flag("RECORD_SESSION_MIX")
+}
+
+/// Rebuild lost voice frames from Opus FEC/PLC instead of writing silence.
+pub fn loss_recovery_enabled() -> bool {
+    env_flag("RECORDING_LOSS_RECOVERY")
+}
+
+/// Store only speech packets plus a `.idx` timeline instead of padding
+/// per-user recordings with silence frames.
+pub fn sparse_recording_enabled() -> bool {
+    env_flag("RECORDING_SPARSE")
+}
+
+/// Pause length that still counts as the same speaking segment.
+pub fn speech_hangover_ms() -> u64 {
+    env_positive_u64("SPEECH_HANGOVER_MS").unwrap_or(300)
+}
+
+/// Speaking segments shorter than this are not stored.
+pub fn speech_min_length_ms() -> u64 {
+    env_positive_u64("SPEECH_MIN_LENGTH_MS").unwrap_or(200)
+}
+
+/// Rotate per-user recordings into a new segment after this many minutes.
+pub fn recording_segment_max_ms() -> Option<u64> {
+    env_positive_u64("RECORDING_SEGMENT_MINUTES").map(|minutes| minutes * 60_000)
+}
+
+/// Rotate per-user recordings into a new segment after this many megabytes.
+pub fn recording_segment_max_bytes() -> Option<u64> {
+    env_positive_u64("RECORDING_SEGMENT_MB").map(|mb| mb * 1024 * 1024)
+}
+
+/// When the recording writer thread syncs files: `never`, `close`, or an
+/// interval in seconds. Defaults to `never`.
+pub fn recording_fsync_policy() -> crate::events::disk_writer::FsyncPolicy {
+    use crate::events::disk_writer::FsyncPolicy;
+    match env::var("RECORDING_FSYNC") {
+        Ok(value) => FsyncPolicy::parse(&value).unwrap_or_else(|| {
+            tracing::warn!("invalid RECORDING_FSYNC {:?}; using never", value);
+            FsyncPolicy::Never
+        }),
+        Err(_) => FsyncPolicy::Never,
+    }
+}
+
+/// Messages the recording writer queue holds before callers buffer locally.
+pub fn recording_write_queue_capacity() -> usize {
+    env_positive_u64("RECORDING_WRITE_QUEUE")
+        .map(|v| v as usize)
+        .unwrap_or(8192)
+}
+
+/// Where the admin API writes user data exports. Defaults to `exports`.
+pub fn user_export_dir() -> std::path::PathBuf {
+    env::var("USER_EXPORT_DIR")
+        .unwrap_or_else(|_| "exports".to_string())
+        .into()
+}
+
+/// Minutes between retention passes. Defaults to hourly.
+pub fn retention_interval_minutes() -> u64 {
+    env_positive_u64("RETENTION_INTERVAL_MINUTES").unwrap_or(60)
+}
+
+/// Log what retention would prune without deleting anything.
+pub fn retention_dry_run() -> bool {
+    env_flag("RETENTION_DRY_RUN")
+}
+
+/// Delete pruned recordings' rows instead of marking them `pruned_at`.
+pub fn retention_delete_rows() -> bool {
+    env_flag("RETENTION_DELETE_ROWS")
+}
+
+/// Free space on the recording disk below which we alert. Defaults to 5 GB.
+pub fn recording_disk_soft_mb() -> u64 {
+    env_positive_u64("RECORDING_DISK_SOFT_MB").unwrap_or(5 * 1024)
+}
+
+/// Free space below which recording stops. Defaults to 1 GB.
+pub fn recording_disk_hard_mb() -> u64 {
+    env_positive_u64("RECORDING_DISK_HARD_MB").unwrap_or(1024)
+}
+
+pub fn recording_disk_check_seconds() -> u64 {
+    env_positive_u64("RECORDING_DISK_CHECK_SECONDS").unwrap_or(30)
+}
+
+/// Discord channel that gets storage guard alerts.
+pub fn recording_disk_alert_channel() -> Option<u64> {
+    env_positive_u64("RECORDING_DISK_ALERT_CHANNEL_ID")
+}
+
+/// `local` (default) keeps finished recordings on disk; `s3` uploads them
+/// to `RECORDING_S3_BUCKET`.
+pub fn recording_storage() -> String {
+    env::var("RECORDING_STORAGE")
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_else(|_| "local".to_string())
+}
+
+pub fn recording_s3_bucket() -> Option<String> {
+    env::var("RECORDING_S3_BUCKET")
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+/// Prefix for every object key, e.g. `fbi/`. Empty by default.
+pub fn recording_s3_prefix() -> String {
+    let prefix = env::var("RECORDING_S3_PREFIX").unwrap_or_default();
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", prefix)
+    }
+}
+
+/// Seconds between upload passes. Defaults to 30.
+pub fn recording_upload_interval_seconds() -> u64 {
+    env_positive_u64("RECORDING_UPLOAD_INTERVAL_SECONDS").unwrap_or(30)
+}
+
+/// Delete local recording files once their upload is verified.
+pub fn recording_upload_delete_local() -> bool {
+    env_flag("RECORDING_UPLOAD_DELETE_LOCAL")
+}
+
+/// Master keys for recording encryption as `id:hex` pairs. Unset leaves
+/// new recordings in plaintext.
+pub fn recording_encryption_keys() -> Option<String> {
+    env::var("RECORDING_ENCRYPTION_KEYS")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// Key new recordings are encrypted with. Defaults to the highest id.
+pub fn recording_encryption_key_id() -> Option<u64> {
+    env_positive_u64("RECORDING_ENCRYPTION_KEY_ID")
+}
+
+/// Longest an encrypted recording holds plaintext before sealing a chunk,
+/// i.e. the most audio a crash can lose. Defaults to one second.
+pub fn recording_encryption_seal_ms() -> u64 {
+    env_positive_u64("RECORDING_ENCRYPTION_SEAL_MS").unwrap_or(1000)
+}
+
+/// Recording exports transcoded at once; more wait their turn. Defaults to 2.
+pub fn export_max_concurrent() -> usize {
+    env_positive_u64("EXPORT_MAX_CONCURRENT")
+        .map(|v| v as usize)
+        .unwrap_or(2)
+}
+
+/// Render a clip for every new stamp.
+pub fn stamp_clips_enabled() -> bool {
+    env_flag("STAMP_CLIPS")
+}
+
+/// Audio kept before a stamp's moment. Defaults to 15 s.
+pub fn stamp_clip_before_ms() -> i64 {
+    env_positive_u64("STAMP_CLIP_BEFORE_SECONDS").unwrap_or(15) as i64 * 1000
+}
+
+/// Audio kept after a stamp's moment. Defaults to 5 s.
+pub fn stamp_clip_after_ms() -> i64 {
+    env_positive_u64("STAMP_CLIP_AFTER_SECONDS").unwrap_or(5) as i64 * 1000
+}
+
+/// Cut stamp clips from the session mixdown (`mix`) instead of the stamped
+/// user's own track (`user`, default).
+pub fn stamp_clip_from_mix() -> bool {
+    env::var("STAMP_CLIP_SOURCE").is_ok_and(|v| v.trim().eq_ignore_ascii_case("mix"))
+}
+
+/// Seconds between stamp clip passes. Defaults to 15.
+pub fn stamp_clip_interval_seconds() -> u64 {
+    env_positive_u64("STAMP_CLIP_INTERVAL_SECONDS").unwrap_or(15)
+}
+
+/// Largest attachment `/clip upload` downloads. Defaults to 8 MB.
+pub fn clip_upload_max_bytes() -> u64 {
+    env_positive_u64("CLIP_UPLOAD_MAX_MB").unwrap_or(8) * 1024 * 1024
+}
+
+/// Longest audio `/clip upload` accepts. Defaults to 60 s.
+pub fn clip_upload_max_ms() -> u64 {
+    env_positive_u64("CLIP_UPLOAD_MAX_SECONDS").unwrap_or(60) * 1000
+}
+
+/// Loudness clips are played at unless the guild sets its own. Defaults
+/// to -16 LUFS.
+pub fn clip_target_lufs() -> f64 {
+    env_f64("CLIP_TARGET_LUFS").unwrap_or(crate::loudness::DEFAULT_TARGET_LUFS)
+}
+
+/// Sample peak playback gain may not push a clip past. Defaults to -1 dBFS.
+pub fn clip_peak_ceiling_dbfs() -> f64 {
+    env_f64("CLIP_PEAK_CEILING_DBFS").unwrap_or(crate::loudness::DEFAULT_CEILING_DBFS)
+}
+
+/// Seconds between clip loudness backfill passes. Defaults to 5 minutes.
+pub fn clip_loudness_interval_seconds() -> u64 {
+    env_positive_u64("CLIP_LOUDNESS_INTERVAL_SECONDS").unwrap_or(300)
+}
+
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+fn env_f64(name: &str) -> Option<f64> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| v.is_finite())
+}
+
+fn env_positive_u64(name: &str) -> Option<u64> {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|v| *v > 0)
+}
+
 pub fn grpc_addr() -> String {
     if let Ok(addr) = env::var("GRPC_ADDR") {
         return addr;
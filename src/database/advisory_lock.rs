@@ -0,0 +1,82 @@
+//! Postgres advisory locks that keep a background job to one instance at a
+//! time. Every job's key is listed in [`LockKey`] so two jobs can never
+//! share one by accident.
+
+use std::future::Future;
+
+use sqlx::{Pool, Postgres};
+use tracing::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKey {
+    Retention,
+    Upload,
+    ClipLoudness,
+    StampClips,
+}
+
+impl LockKey {
+    /// The `pg_try_advisory_lock` key: the job's name in ASCII.
+    fn key(self) -> i64 {
+        match self {
+            Self::Retention => 0x7265_7465_6e74,
+            Self::Upload => 0x7570_6c6f_6164,
+            Self::ClipLoudness => 0x6c6f_7564_6e73,
+            Self::StampClips => 0x7374_616d_7063,
+        }
+    }
+}
+
+/// Run `f` while holding the advisory lock for `key`. Returns `None`
+/// without running it when another instance holds the lock.
+///
+/// The lock is session-level, so it is held on a connection of its own for
+/// as long as `f` runs; `f` is free to use the pool.
+pub async fn with_advisory_lock<T, E, F, Fut>(
+    pool: &Pool<Postgres>,
+    key: LockKey,
+    f: F,
+) -> Result<Option<T>, E>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: From<sqlx::Error>,
+{
+    let mut lock = pool.acquire().await?;
+    let locked = sqlx::query_scalar!(r#"SELECT pg_try_advisory_lock($1) AS "locked!""#, key.key())
+        .fetch_one(&mut *lock)
+        .await?;
+    if !locked {
+        debug!(?key, "another instance holds the lock");
+        return Ok(None);
+    }
+
+    let result = f().await;
+
+    if let Err(e) = sqlx::query_scalar!("SELECT pg_advisory_unlock($1)", key.key())
+        .fetch_one(&mut *lock)
+        .await
+    {
+        warn!(?key, "advisory unlock failed: {}", e);
+    }
+    result.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_keys_are_distinct() {
+        let keys = [
+            LockKey::Retention,
+            LockKey::Upload,
+            LockKey::ClipLoudness,
+            LockKey::StampClips,
+        ]
+        .map(LockKey::key);
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key));
+        }
+    }
+}
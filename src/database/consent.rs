@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+
+use sqlx::{Pool, Postgres};
+
+/// A member's own recording consent from `user_consent`. Users without a
+/// row have never opted out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsentStatus {
+    pub recording_opt_out: bool,
+    pub opted_out_ts: Option<i64>,
+    pub opted_in_ts: Option<i64>,
+}
+
+pub async fn status(pool: &Pool<Postgres>, user_id: u64) -> Result<ConsentStatus, sqlx::Error> {
+    let row = sqlx::query!(
+        "SELECT recording_opt_out, opted_out_ts, opted_in_ts FROM user_consent WHERE user_id = $1",
+        user_id as i64
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row
+        .map(|row| ConsentStatus {
+            recording_opt_out: row.recording_opt_out,
+            opted_out_ts: row.opted_out_ts,
+            opted_in_ts: row.opted_in_ts,
+        })
+        .unwrap_or_default())
+}
+
+/// Record an opt-out or opt-in at `now_ms`. Returns whether the stored
+/// consent actually changed.
+pub async fn set_recording_opt_out(
+    pool: &Pool<Postgres>,
+    user_id: u64,
+    opted_out: bool,
+    now_ms: i64,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
+        "INSERT INTO user_consent (user_id, recording_opt_out, opted_out_ts, opted_in_ts, updated_ts)
+         VALUES ($1, $2,
+                 CASE WHEN $2 THEN $3::BIGINT END,
+                 CASE WHEN $2 THEN NULL ELSE $3::BIGINT END,
+                 $3)
+         ON CONFLICT (user_id) DO UPDATE SET
+            recording_opt_out = $2,
+            opted_out_ts = CASE WHEN $2 THEN $3 ELSE user_consent.opted_out_ts END,
+            opted_in_ts = CASE WHEN $2 THEN user_consent.opted_in_ts ELSE $3 END,
+            updated_ts = $3
+          WHERE user_consent.recording_opt_out <> $2",
+        user_id as i64,
+        opted_out,
+        now_ms
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// The subset of `user_ids` that opted out of recording.
+pub async fn opted_out(
+    pool: &Pool<Postgres>,
+    user_ids: &[u64],
+) -> Result<HashSet<u64>, sqlx::Error> {
+    if user_ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let ids: Vec<i64> = user_ids.iter().map(|id| *id as i64).collect();
+    let rows = sqlx::query_scalar!(
+        "SELECT user_id FROM user_consent WHERE recording_opt_out AND user_id = ANY($1)",
+        &ids[..]
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|id| id as u64).collect())
+}
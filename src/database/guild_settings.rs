@@ -0,0 +1,142 @@
+use sqlx::{Pool, Postgres};
+use tracing::warn;
+
+/// Recording options from `guild_recording_settings`. Guilds without a row
+/// get the defaults (everything off).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GuildRecordingSettings {
+    pub record_bots: bool,
+    pub record_playback: bool,
+    /// Text channel automatic stamp clips are posted to.
+    pub stamp_clip_channel_id: Option<u64>,
+}
+
+pub async fn recording_settings(pool: &Pool<Postgres>, guild_id: u64) -> GuildRecordingSettings {
+    match sqlx::query!(
+        "SELECT record_bots, record_playback, stamp_clip_channel_id
+           FROM guild_recording_settings WHERE guild_id = $1",
+        guild_id as i64
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(Some(row)) => GuildRecordingSettings {
+            record_bots: row.record_bots,
+            record_playback: row.record_playback,
+            stamp_clip_channel_id: row.stamp_clip_channel_id.map(|id| id as u64),
+        },
+        Ok(None) => GuildRecordingSettings::default(),
+        Err(e) => {
+            warn!(
+                "guild_recording_settings lookup failed for {}: {}",
+                guild_id, e
+            );
+            GuildRecordingSettings::default()
+        }
+    }
+}
+
+/// Update a guild's recording options. `None` leaves an option unchanged;
+/// `Some(None)` for the stamp clip channel stops posting stamp clips.
+pub async fn set_recording_settings(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    record_bots: Option<bool>,
+    record_playback: Option<bool>,
+    stamp_clip_channel_id: Option<Option<u64>>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO guild_recording_settings
+            (guild_id, record_bots, record_playback, stamp_clip_channel_id)
+         VALUES ($1, COALESCE($2, FALSE), COALESCE($3, FALSE), $5)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            record_bots = COALESCE($2, guild_recording_settings.record_bots),
+            record_playback = COALESCE($3, guild_recording_settings.record_playback),
+            stamp_clip_channel_id = CASE WHEN $4
+                THEN $5 ELSE guild_recording_settings.stamp_clip_channel_id END,
+            updated_at = now()",
+        guild_id as i64,
+        record_bots,
+        record_playback,
+        stamp_clip_channel_id.is_some(),
+        stamp_clip_channel_id.flatten().map(|id| id as i64),
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Clip playback loudness from `guild_playback_settings`, falling back to
+/// the configured defaults.
+#[derive(Debug, Clone, Copy)]
+pub struct GuildPlaybackSettings {
+    pub target_lufs: f64,
+    pub ceiling_dbfs: f64,
+}
+
+pub async fn playback_settings(pool: &Pool<Postgres>, guild_id: u64) -> GuildPlaybackSettings {
+    let row = match sqlx::query!(
+        "SELECT target_lufs, ceiling_dbfs FROM guild_playback_settings WHERE guild_id = $1",
+        guild_id as i64
+    )
+    .fetch_optional(pool)
+    .await
+    {
+        Ok(row) => row.map(|row| (row.target_lufs, row.ceiling_dbfs)),
+        Err(e) => {
+            warn!(
+                "guild_playback_settings lookup failed for {}: {}",
+                guild_id, e
+            );
+            None
+        }
+    };
+    let (target_lufs, ceiling_dbfs) = row.unwrap_or_default();
+    GuildPlaybackSettings {
+        target_lufs: target_lufs.unwrap_or_else(crate::config::clip_target_lufs),
+        ceiling_dbfs: ceiling_dbfs.unwrap_or_else(crate::config::clip_peak_ceiling_dbfs),
+    }
+}
+
+/// Bounds `guild_playback_settings` accepts.
+pub const MIN_TARGET_LUFS: f64 = -70.0;
+pub const MAX_TARGET_LUFS: f64 = 0.0;
+pub const MAX_CEILING_DBFS: f64 = 0.0;
+
+/// Update a guild's clip playback loudness. `None` leaves a value
+/// unchanged.
+pub async fn set_playback_settings(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    target_lufs: Option<f64>,
+    ceiling_dbfs: Option<f64>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO guild_playback_settings (guild_id, target_lufs, ceiling_dbfs)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            target_lufs = COALESCE($2, guild_playback_settings.target_lufs),
+            ceiling_dbfs = COALESCE($3, guild_playback_settings.ceiling_dbfs),
+            updated_at = now()",
+        guild_id as i64,
+        target_lufs,
+        ceiling_dbfs,
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Drop a guild's playback loudness so the configured defaults apply.
+pub async fn reset_playback_settings(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM guild_playback_settings WHERE guild_id = $1",
+        guild_id as i64
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
@@ -1,4 +1,8 @@
+pub mod advisory_lock;
 pub mod channels;
+pub mod consent;
+pub mod guild_settings;
+pub mod recording_policy;
 pub mod user_names;
 
 use crate::event_handler::Handler;
@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use sqlx::{Pool, Postgres};
+
+/// Who may be recorded in one channel, from the `recording_*_policies`
+/// tables and the guild's user opt-outs. Missing rows mean "record".
+#[derive(Debug, Clone)]
+pub struct RecordingPolicy {
+    pub guild_enabled: bool,
+    pub channels_default_allowed: bool,
+    /// Explicit allow/deny for the channel, if one was set.
+    pub channel_override: Option<bool>,
+    pub allow_user_opt_out: bool,
+    pub opted_out: HashSet<u64>,
+}
+
+impl Default for RecordingPolicy {
+    fn default() -> Self {
+        Self {
+            guild_enabled: true,
+            channels_default_allowed: true,
+            channel_override: None,
+            allow_user_opt_out: true,
+            opted_out: HashSet::new(),
+        }
+    }
+}
+
+impl RecordingPolicy {
+    pub fn allows_channel(&self) -> bool {
+        self.guild_enabled
+            && self
+                .channel_override
+                .unwrap_or(self.channels_default_allowed)
+    }
+
+    pub fn allows_user(&self, user_id: u64) -> bool {
+        self.allows_channel() && !(self.allow_user_opt_out && self.opted_out.contains(&user_id))
+    }
+}
+
+pub async fn load(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    channel_id: u64,
+) -> Result<RecordingPolicy, sqlx::Error> {
+    let mut policy = RecordingPolicy::default();
+
+    if let Some(row) = sqlx::query!(
+        "SELECT enabled, channels_default_allowed, allow_user_opt_out
+           FROM recording_guild_policies WHERE guild_id = $1",
+        guild_id as i64
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        policy.guild_enabled = row.enabled;
+        policy.channels_default_allowed = row.channels_default_allowed;
+        policy.allow_user_opt_out = row.allow_user_opt_out;
+    }
+
+    policy.channel_override = sqlx::query_scalar!(
+        "SELECT allowed FROM recording_channel_policies WHERE channel_id = $1 AND guild_id = $2",
+        channel_id as i64,
+        guild_id as i64
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let opted_out = sqlx::query_scalar!(
+        "SELECT user_id FROM recording_user_opt_outs WHERE guild_id = $1",
+        guild_id as i64
+    )
+    .fetch_all(pool)
+    .await?;
+    policy.opted_out = opted_out.into_iter().map(|id| id as u64).collect();
+
+    Ok(policy)
+}
+
+/// Update the guild-wide settings. `None` leaves a setting unchanged.
+pub async fn set_guild_policy(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    enabled: Option<bool>,
+    channels_default_allowed: Option<bool>,
+    allow_user_opt_out: Option<bool>,
+    updated_by: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO recording_guild_policies
+            (guild_id, enabled, channels_default_allowed, allow_user_opt_out, updated_by)
+         VALUES ($1, COALESCE($2, TRUE), COALESCE($3, TRUE), COALESCE($4, TRUE), $5)
+         ON CONFLICT (guild_id) DO UPDATE SET
+            enabled = COALESCE($2, recording_guild_policies.enabled),
+            channels_default_allowed = COALESCE($3, recording_guild_policies.channels_default_allowed),
+            allow_user_opt_out = COALESCE($4, recording_guild_policies.allow_user_opt_out),
+            updated_by = $5,
+            updated_at = now()",
+        guild_id as i64,
+        enabled,
+        channels_default_allowed,
+        allow_user_opt_out,
+        updated_by as i64
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+/// Allow or deny one channel. `None` removes the override so the guild
+/// default applies again.
+pub async fn set_channel_policy(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    channel_id: u64,
+    allowed: Option<bool>,
+    updated_by: u64,
+) -> Result<(), sqlx::Error> {
+    match allowed {
+        Some(allowed) => sqlx::query!(
+            "INSERT INTO recording_channel_policies (channel_id, guild_id, allowed, updated_by)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (channel_id) DO UPDATE SET
+                guild_id = EXCLUDED.guild_id,
+                allowed = EXCLUDED.allowed,
+                updated_by = EXCLUDED.updated_by,
+                updated_at = now()",
+            channel_id as i64,
+            guild_id as i64,
+            allowed,
+            updated_by as i64
+        )
+        .execute(pool)
+        .await
+        .map(|_| ()),
+        None => sqlx::query!(
+            "DELETE FROM recording_channel_policies WHERE channel_id = $1 AND guild_id = $2",
+            channel_id as i64,
+            guild_id as i64
+        )
+        .execute(pool)
+        .await
+        .map(|_| ()),
+    }
+}
+
+pub async fn set_user_opt_out(
+    pool: &Pool<Postgres>,
+    guild_id: u64,
+    user_id: u64,
+    opted_out: bool,
+) -> Result<(), sqlx::Error> {
+    if opted_out {
+        sqlx::query!(
+            "INSERT INTO recording_user_opt_outs (guild_id, user_id) VALUES ($1, $2)
+             ON CONFLICT (guild_id, user_id) DO NOTHING",
+            guild_id as i64,
+            user_id as i64
+        )
+        .execute(pool)
+        .await
+        .map(|_| ())
+    } else {
+        sqlx::query!(
+            "DELETE FROM recording_user_opt_outs WHERE guild_id = $1 AND user_id = $2",
+            guild_id as i64,
+            user_id as i64
+        )
+        .execute(pool)
+        .await
+        .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_override_beats_guild_default() {
+        let allowlist = RecordingPolicy {
+            channels_default_allowed: false,
+            ..Default::default()
+        };
+        assert!(!allowlist.allows_channel());
+        let allowed = RecordingPolicy {
+            channel_override: Some(true),
+            ..allowlist.clone()
+        };
+        assert!(allowed.allows_channel());
+
+        let disabled = RecordingPolicy {
+            guild_enabled: false,
+            ..allowed
+        };
+        assert!(!disabled.allows_channel());
+    }
+
+    #[test]
+    fn opt_outs_only_count_when_allowed() {
+        let mut policy = RecordingPolicy::default();
+        policy.opted_out.insert(7);
+        assert!(!policy.allows_user(7));
+        assert!(policy.allows_user(8));
+
+        policy.allow_user_opt_out = false;
+        assert!(policy.allows_user(7));
+    }
+}
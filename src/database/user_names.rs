@@ -178,6 +178,15 @@ async fn observe_nickname(
     }
 }
 
+/// Name shown for a user, in the same precedence the name tables are read
+/// back with: guild nickname, then global name, then username.
+pub fn display_name(user: &User, member: Option<&Member>) -> String {
+    member
+        .and_then(|m| m.nick.clone())
+        .or_else(|| user.global_name.clone())
+        .unwrap_or_else(|| user.name.clone())
+}
+
 /// Seed name tables from the member cache of the given guilds. Called once on
 /// cache_ready. Bots are skipped.
 pub async fn seed_from_guilds(pool: &Pool<Postgres>, guilds: &[Guild]) {
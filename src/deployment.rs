@@ -176,6 +176,37 @@ pub async fn mark_instance_stopped(pool: &Pool<Postgres>, runtime: &RuntimeState
         warn!("stopped instance recording cleanup failed: {}", err);
     }
 
+    if let Err(err) = sqlx::query!(
+        "UPDATE audio_mix_files
+            SET end_ts = COALESCE(end_ts, start_ts),
+                reaped = CASE WHEN end_ts IS NULL THEN TRUE ELSE reaped END,
+                recording_heartbeat_at = NULL
+          WHERE recording_owner_instance_id = $1
+            AND end_ts IS NULL",
+        runtime.config().instance_id
+    )
+    .execute(pool)
+    .await
+    {
+        warn!("stopped instance session mix cleanup failed: {}", err);
+    }
+
+    if let Err(err) = sqlx::query!(
+        "UPDATE voice_sessions
+            SET end_ts = COALESCE(
+                    (SELECT MAX(end_ts) FROM audio_files WHERE session_id = voice_sessions.id),
+                    start_ts),
+                reaped = TRUE
+          WHERE recording_owner_instance_id = $1
+            AND end_ts IS NULL",
+        runtime.config().instance_id
+    )
+    .execute(pool)
+    .await
+    {
+        warn!("stopped instance voice session cleanup failed: {}", err);
+    }
+
     if let Err(err) = sqlx::query!(
         "UPDATE bot_instances
             SET state = 'stopped', heartbeat_at = now()
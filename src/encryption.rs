@@ -0,0 +1,719 @@
+//! Optional encryption at rest for recording files.
+//!
+//! With `RECORDING_ENCRYPTION_KEYS` set, every file the recorder creates is
+//! written through a [`ChunkSealer`]: a random per-file data key encrypts
+//! the stream in XChaCha20-Poly1305 chunks, and the data key is wrapped by
+//! the active master key and kept in a fixed-size header:
+//!
+//! ```text
+//! header  magic "FBIENC\0" | version | key id u32 | chunk size u32 |
+//!         nonce prefix [16] | wrap nonce [24] | wrapped data key [48]
+//! chunk   ciphertext length u32 | ciphertext (plaintext + 16-byte tag)
+//! ```
+//!
+//! Chunk `i` uses the nonce `prefix || i` (big endian), so chunks cannot be
+//! reordered or spliced between files. Every chunk authenticates on its own,
+//! which keeps a partial file recoverable: a crash loses only the chunk that
+//! was being written, and [`DecryptingReader`] stops at the last complete
+//! one. The recorder seals a chunk once it is full or every
+//! `RECORDING_ENCRYPTION_SEAL_MS`, whichever comes first.
+//!
+//! Keys are given as `id:hex` pairs, e.g.
+//! `RECORDING_ENCRYPTION_KEYS=1:<64 hex chars>,2:<64 hex chars>`; new files
+//! use `RECORDING_ENCRYPTION_KEY_ID`, else the highest id. Rotation only
+//! rewraps headers in place ([`rotate_tree`]); retired keys can be dropped
+//! once no header references them.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use symphonia::core::io::MediaSource;
+use tracing::{error, warn};
+
+const MAGIC: &[u8; 7] = b"FBIENC\0";
+const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 104;
+/// Header bytes authenticated by the key wrap.
+const WRAP_AAD_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const PREFIX_LEN: usize = 16;
+/// Plaintext bytes per chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn chunk_nonce(prefix: &[u8; PREFIX_LEN], index: u64) -> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+    nonce
+}
+
+/// Whether `bytes` start like an encrypted recording.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.len() > MAGIC.len() && bytes.starts_with(MAGIC)
+}
+
+/// Master keys by id, and the one new files are wrapped with.
+#[derive(Default)]
+pub struct Keyring {
+    active: Option<u32>,
+    keys: HashMap<u32, Key>,
+    seal_interval: Duration,
+}
+
+struct Header {
+    key_id: u32,
+    chunk_size: u32,
+    prefix: [u8; PREFIX_LEN],
+    wrap_nonce: XNonce,
+    wrapped: [u8; 32 + TAG_LEN],
+}
+
+impl Header {
+    fn parse(bytes: &[u8; HEADER_LEN]) -> io::Result<Self> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid("not an encrypted recording"));
+        }
+        if bytes[7] != VERSION {
+            return Err(invalid(format!(
+                "unsupported encryption version {}",
+                bytes[7]
+            )));
+        }
+        let mut prefix = [0; PREFIX_LEN];
+        prefix.copy_from_slice(&bytes[16..32]);
+        let mut wrapped = [0; 32 + TAG_LEN];
+        wrapped.copy_from_slice(&bytes[56..104]);
+        Ok(Self {
+            key_id: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            chunk_size: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            prefix,
+            wrap_nonce: *XNonce::from_slice(&bytes[32..56]),
+            wrapped,
+        })
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..7].copy_from_slice(MAGIC);
+        bytes[7] = VERSION;
+        bytes[8..12].copy_from_slice(&self.key_id.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.chunk_size.to_le_bytes());
+        bytes[16..32].copy_from_slice(&self.prefix);
+        bytes[32..56].copy_from_slice(&self.wrap_nonce);
+        bytes[56..104].copy_from_slice(&self.wrapped);
+        bytes
+    }
+}
+
+impl Keyring {
+    pub fn from_env() -> Result<Self, String> {
+        let Some(keys) = crate::config::recording_encryption_keys() else {
+            return Ok(Self::default());
+        };
+        let mut keyring = Self::parse(
+            &keys,
+            crate::config::recording_encryption_key_id().map(|id| id as u32),
+        )?;
+        keyring.seal_interval =
+            Duration::from_millis(crate::config::recording_encryption_seal_ms());
+        Ok(keyring)
+    }
+
+    /// Parse `id:hex` pairs separated by commas.
+    pub fn parse(keys: &str, active: Option<u32>) -> Result<Self, String> {
+        let mut parsed = HashMap::new();
+        for entry in keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (id, hex) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("encryption key {:?} is not id:hex", entry))?;
+            let id: u32 = id
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid encryption key id {:?}", id))?;
+            let bytes = decode_hex(hex.trim())
+                .filter(|b| b.len() == 32)
+                .ok_or_else(|| format!("encryption key {} must be 64 hex characters", id))?;
+            if parsed.insert(id, *Key::from_slice(&bytes)).is_some() {
+                return Err(format!("encryption key {} given twice", id));
+            }
+        }
+        let active = match active {
+            Some(id) if parsed.contains_key(&id) => Some(id),
+            Some(id) => return Err(format!("active encryption key {} is not configured", id)),
+            None => parsed.keys().max().copied(),
+        };
+        Ok(Self {
+            active,
+            keys: parsed,
+            seal_interval: Duration::from_secs(1),
+        })
+    }
+
+    /// Whether new files are encrypted.
+    pub fn enabled(&self) -> bool {
+        self.active.is_some()
+    }
+
+    fn key(&self, id: u32) -> io::Result<&Key> {
+        self.keys
+            .get(&id)
+            .ok_or_else(|| invalid(format!("encryption key {} is not configured", id)))
+    }
+
+    fn wrap(&self, key_id: u32, mut header: Header, data_key: &Key) -> io::Result<Header> {
+        header.key_id = key_id;
+        header.wrap_nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let aad = header.to_bytes();
+        let wrapped = XChaCha20Poly1305::new(self.key(key_id)?)
+            .encrypt(
+                &header.wrap_nonce,
+                Payload {
+                    msg: data_key,
+                    aad: &aad[..WRAP_AAD_LEN],
+                },
+            )
+            .map_err(|_| io::Error::other("data key wrap failed"))?;
+        header.wrapped.copy_from_slice(&wrapped);
+        Ok(header)
+    }
+
+    fn unwrap(&self, header: &Header) -> io::Result<Key> {
+        let bytes = header.to_bytes();
+        let data_key = XChaCha20Poly1305::new(self.key(header.key_id)?)
+            .decrypt(
+                &header.wrap_nonce,
+                Payload {
+                    msg: &header.wrapped,
+                    aad: &bytes[..WRAP_AAD_LEN],
+                },
+            )
+            .map_err(|_| {
+                invalid(format!(
+                    "data key does not unwrap with key {}",
+                    header.key_id
+                ))
+            })?;
+        Ok(*Key::from_slice(&data_key))
+    }
+
+    /// A sealer for a new file, or `None` when encryption is off.
+    pub fn sealer(&self) -> io::Result<Option<ChunkSealer>> {
+        let Some(active) = self.active else {
+            return Ok(None);
+        };
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut prefix = [0; PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        let header = self.wrap(
+            active,
+            Header {
+                key_id: active,
+                chunk_size: CHUNK_SIZE as u32,
+                prefix,
+                wrap_nonce: XNonce::default(),
+                wrapped: [0; 32 + TAG_LEN],
+            },
+            &data_key,
+        )?;
+        Ok(Some(ChunkSealer {
+            cipher: XChaCha20Poly1305::new(&data_key),
+            header: header.to_bytes(),
+            prefix,
+            index: 0,
+            pending: Vec::with_capacity(CHUNK_SIZE),
+            last_seal: Instant::now(),
+            seal_interval: self.seal_interval,
+        }))
+    }
+
+    /// `header` rewrapped with the active key, or `None` if it already uses
+    /// it.
+    pub fn rewrap(&self, header: &[u8; HEADER_LEN]) -> io::Result<Option<[u8; HEADER_LEN]>> {
+        let Some(active) = self.active else {
+            return Ok(None);
+        };
+        let header = Header::parse(header)?;
+        if header.key_id == active {
+            return Ok(None);
+        }
+        let data_key = self.unwrap(&header)?;
+        Ok(Some(self.wrap(active, header, &data_key)?.to_bytes()))
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Load the keyring from the environment. Call once at startup so a bad
+/// key stops the bot instead of recording in plaintext.
+pub fn init() -> Result<(), String> {
+    let keyring = Keyring::from_env()?;
+    let _ = KEYRING.set(keyring);
+    Ok(())
+}
+
+/// The process keyring; empty (encryption off) if it failed to load.
+pub fn keyring() -> &'static Keyring {
+    KEYRING.get_or_init(|| {
+        Keyring::from_env().unwrap_or_else(|e| {
+            error!("recording encryption disabled: {}", e);
+            Keyring::default()
+        })
+    })
+}
+
+/// Turns a plaintext stream into encrypted chunks.
+pub struct ChunkSealer {
+    cipher: XChaCha20Poly1305,
+    header: [u8; HEADER_LEN],
+    prefix: [u8; PREFIX_LEN],
+    index: u64,
+    pending: Vec<u8>,
+    last_seal: Instant,
+    seal_interval: Duration,
+}
+
+impl ChunkSealer {
+    /// Bytes that must start the file.
+    pub fn header(&self) -> &[u8; HEADER_LEN] {
+        &self.header
+    }
+
+    /// Buffer `data`, appending every chunk it fills to `out`.
+    pub fn push(&mut self, mut data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+        while !data.is_empty() {
+            let take = (CHUNK_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == CHUNK_SIZE {
+                self.seal(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether buffered plaintext has waited longer than the seal interval.
+    pub fn seal_due(&self) -> bool {
+        !self.pending.is_empty() && self.last_seal.elapsed() >= self.seal_interval
+    }
+
+    /// Seal whatever is buffered into a chunk appended to `out`.
+    pub fn seal(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let len = ((self.pending.len() + TAG_LEN) as u32).to_le_bytes();
+        let sealed = self
+            .cipher
+            .encrypt(
+                &chunk_nonce(&self.prefix, self.index),
+                Payload {
+                    msg: &self.pending,
+                    aad: &len,
+                },
+            )
+            .map_err(|_| io::Error::other("chunk encryption failed"))?;
+        out.extend_from_slice(&len);
+        out.extend_from_slice(&sealed);
+        self.pending.clear();
+        self.index += 1;
+        self.last_seal = Instant::now();
+        Ok(())
+    }
+}
+
+/// Encrypting [`Write`] adapter for one-shot files. `flush` seals the
+/// buffered plaintext; dropping seals the rest.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    sealer: ChunkSealer,
+    out: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    pub fn new(inner: W, sealer: ChunkSealer) -> Self {
+        let out = sealer.header().to_vec();
+        Self { inner, sealer, out }
+    }
+
+    fn write_out(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.sealer.push(data, &mut self.out)?;
+        if !self.out.is_empty() {
+            self.write_out()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sealer.seal(&mut self.out)?;
+        self.write_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptingWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("encrypted recording final chunk not written: {}", e);
+        }
+    }
+}
+
+/// `inner`, encrypted when the keyring has an active key.
+pub fn writer<W: Write + Send + 'static>(inner: W) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match keyring().sealer()? {
+        Some(sealer) => Box::new(EncryptingWriter::new(inner, sealer)),
+        None => Box::new(inner),
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChunkRef {
+    /// File offset of the ciphertext.
+    offset: u64,
+    /// Ciphertext length, tag included.
+    len: u32,
+    /// Plaintext offset of the chunk's first byte.
+    start: u64,
+}
+
+impl ChunkRef {
+    fn plain_len(&self) -> u64 {
+        self.len as u64 - TAG_LEN as u64
+    }
+}
+
+/// Seekable plaintext view of an encrypted recording. Chunks are located on
+/// open and decrypted on demand; a torn chunk at the end (a crash while
+/// writing) is left out and reported by [`truncated`](Self::truncated).
+pub struct DecryptingReader<R> {
+    inner: R,
+    cipher: XChaCha20Poly1305,
+    prefix: [u8; PREFIX_LEN],
+    chunks: Vec<ChunkRef>,
+    len: u64,
+    pos: u64,
+    cached: Option<(usize, Vec<u8>)>,
+    truncated: bool,
+}
+
+impl<R: Read + Seek> DecryptingReader<R> {
+    pub fn new(mut inner: R, keyring: &Keyring) -> io::Result<Self> {
+        let file_len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        let mut bytes = [0; HEADER_LEN];
+        inner
+            .read_exact(&mut bytes)
+            .map_err(|_| invalid("encrypted recording header is truncated"))?;
+        let header = Header::parse(&bytes)?;
+        let cipher = XChaCha20Poly1305::new(&keyring.unwrap(&header)?);
+        let max_len = header.chunk_size as u64 + TAG_LEN as u64;
+
+        let mut chunks = Vec::new();
+        let mut offset = HEADER_LEN as u64;
+        let mut start = 0;
+        while offset + 4 <= file_len {
+            let mut len = [0; 4];
+            inner.seek(SeekFrom::Start(offset))?;
+            inner.read_exact(&mut len)?;
+            let len = u32::from_le_bytes(len);
+            if (len as u64) <= TAG_LEN as u64
+                || len as u64 > max_len
+                || offset + 4 + len as u64 > file_len
+            {
+                break;
+            }
+            let chunk = ChunkRef {
+                offset: offset + 4,
+                len,
+                start,
+            };
+            start += chunk.plain_len();
+            offset += 4 + len as u64;
+            chunks.push(chunk);
+        }
+        if offset < file_len {
+            warn!(
+                trailing_bytes = file_len - offset,
+                "encrypted recording ends in a partial chunk"
+            );
+        }
+
+        Ok(Self {
+            inner,
+            cipher,
+            prefix: header.prefix,
+            chunks,
+            len: start,
+            pos: 0,
+            cached: None,
+            truncated: offset < file_len,
+        })
+    }
+
+    /// Plaintext length of the readable chunks.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the file ended in a partial chunk that was skipped.
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    fn load(&mut self, index: usize) -> io::Result<&[u8]> {
+        if self.cached.as_ref().is_none_or(|(i, _)| *i != index) {
+            let chunk = self.chunks[index];
+            let mut sealed = vec![0; chunk.len as usize];
+            self.inner.seek(SeekFrom::Start(chunk.offset))?;
+            self.inner.read_exact(&mut sealed)?;
+            let plain = self
+                .cipher
+                .decrypt(
+                    &chunk_nonce(&self.prefix, index as u64),
+                    Payload {
+                        msg: &sealed,
+                        aad: &chunk.len.to_le_bytes(),
+                    },
+                )
+                .map_err(|_| invalid(format!("chunk {} failed authentication", index)))?;
+            self.cached = Some((index, plain));
+        }
+        Ok(self
+            .cached
+            .as_ref()
+            .map(|(_, p)| p.as_slice())
+            .unwrap_or(&[]))
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let pos = self.pos;
+        let index = self
+            .chunks
+            .partition_point(|c| c.start + c.plain_len() <= pos);
+        let start = self.chunks[index].start;
+        let plain = self.load(index)?;
+        let from = (pos - start) as usize;
+        let n = (plain.len() - from).min(buf.len());
+        buf[..n].copy_from_slice(&plain[from..from + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptingReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek before start of recording",
+            ));
+        };
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+impl<R: MediaSource> MediaSource for DecryptingReader<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        Some(self.len)
+    }
+}
+
+/// Plaintext view of `inner`, decrypting it if it is an encrypted
+/// recording.
+pub fn open_media<R: MediaSource + 'static>(mut inner: R) -> io::Result<Box<dyn MediaSource>> {
+    let mut magic = [0; MAGIC.len() + 1];
+    let n = inner.read(&mut magic)?;
+    inner.seek(SeekFrom::Start(0))?;
+    if is_encrypted(&magic[..n]) {
+        Ok(Box::new(DecryptingReader::new(inner, keyring())?))
+    } else {
+        Ok(Box::new(inner))
+    }
+}
+
+/// Plaintext contents of the file at `path`.
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut plain = Vec::new();
+    open_media(File::open(path)?)?.read_to_end(&mut plain)?;
+    Ok(plain)
+}
+
+#[derive(Debug, Default)]
+pub struct RotationReport {
+    pub scanned: u64,
+    pub rotated: u64,
+    pub failed: u64,
+}
+
+/// Rewrap the header of one file with the active key. Returns whether the
+/// file needed it.
+pub fn rotate_file(path: &Path, keyring: &Keyring, dry_run: bool) -> io::Result<bool> {
+    let mut file = OpenOptions::new().read(true).write(!dry_run).open(path)?;
+    let mut header = [0; HEADER_LEN];
+    if file.read_exact(&mut header).is_err() || !is_encrypted(&header) {
+        return Ok(false);
+    }
+    let Some(rewrapped) = keyring.rewrap(&header)? else {
+        return Ok(false);
+    };
+    if !dry_run {
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&rewrapped)?;
+        file.sync_data()?;
+    }
+    Ok(true)
+}
+
+/// Rewrap every encrypted file below `root` that uses a retired key.
+pub fn rotate_tree(root: &Path, keyring: &Keyring, dry_run: bool) -> io::Result<RotationReport> {
+    let mut report = RotationReport::default();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            report.scanned += 1;
+            match rotate_file(&path, keyring, dry_run) {
+                Ok(true) => report.rotated += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "key rotation failed");
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const KEY_1: &str = "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+    const KEY_2: &str = "2:1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+    fn encrypt(keyring: &Keyring, plain: &[u8], seal_every: usize) -> io::Result<Vec<u8>> {
+        let Some(sealer) = keyring.sealer()? else {
+            return Err(io::Error::other("no active key"));
+        };
+        let mut writer = EncryptingWriter::new(Vec::new(), sealer);
+        for part in plain.chunks(seal_every) {
+            writer.write_all(part)?;
+            writer.flush()?;
+        }
+        writer.flush()?;
+        Ok(std::mem::take(&mut writer.inner))
+    }
+
+    #[test]
+    fn round_trips_and_seeks() -> Result<(), Box<dyn std::error::Error>> {
+        let keyring = Keyring::parse(KEY_1, None)?;
+        let plain: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let sealed = encrypt(&keyring, &plain, 50_000)?;
+        assert!(is_encrypted(&sealed));
+
+        let mut reader = DecryptingReader::new(Cursor::new(&sealed), &keyring)?;
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        assert_eq!(out, plain);
+        assert!(!reader.truncated());
+
+        reader.seek(SeekFrom::Start(123_456))?;
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        assert_eq!(byte[0], plain[123_456]);
+        Ok(())
+    }
+
+    #[test]
+    fn torn_tail_keeps_complete_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let keyring = Keyring::parse(KEY_1, None)?;
+        let plain = vec![7u8; 3000];
+        let mut sealed = encrypt(&keyring, &plain, 1000)?;
+        sealed.truncate(sealed.len() - 10);
+
+        let mut reader = DecryptingReader::new(Cursor::new(&sealed), &keyring)?;
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        assert_eq!(out.len(), 2000);
+        assert!(reader.truncated());
+        Ok(())
+    }
+
+    #[test]
+    fn rewrap_moves_files_to_the_active_key() -> Result<(), Box<dyn std::error::Error>> {
+        let old = Keyring::parse(KEY_1, None)?;
+        let plain = b"opus pages".to_vec();
+        let mut sealed = encrypt(&old, &plain, 4)?;
+
+        let both = Keyring::parse(&format!("{},{}", KEY_1, KEY_2), None)?;
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&sealed[..HEADER_LEN]);
+        let Some(rewrapped) = both.rewrap(&header)? else {
+            return Err("header should need rewrapping".into());
+        };
+        sealed[..HEADER_LEN].copy_from_slice(&rewrapped);
+        assert!(both.rewrap(&rewrapped)?.is_none());
+
+        let new_only = Keyring::parse(KEY_2, None)?;
+        let mut out = Vec::new();
+        DecryptingReader::new(Cursor::new(&sealed), &new_only)?.read_to_end(&mut out)?;
+        assert_eq!(out, plain);
+        assert!(DecryptingReader::new(Cursor::new(&sealed), &old).is_err());
+        Ok(())
+    }
+}
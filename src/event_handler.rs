@@ -346,8 +346,17 @@ impl EventHandler for Handler {
                 crate::commands::voice_controls::register_queue(),
                 crate::commands::voice_controls::register_skip(),
                 crate::commands::voice_controls::register_stop(),
+                crate::commands::voice_controls::register_pause(),
+                crate::commands::voice_controls::register_resume(),
+                crate::commands::voice_controls::register_volume(),
+                crate::commands::voice_controls::register_seek(),
+                crate::commands::voice_controls::register_nowplaying(),
                 crate::commands::voice_controls::register_join(),
                 crate::commands::stamp::register_stamp(),
+                crate::commands::clip::register_clip(),
+                crate::commands::clip::register_save_as_clip(),
+                crate::commands::privacy::register_privacy(),
+                crate::commands::recording::register_recording(),
             ],
         )
         .await
@@ -0,0 +1,748 @@
+//! Dedicated disk writer for recording files.
+//!
+//! Voice handling never touches the filesystem: files are created by the
+//! writer thread, and Ogg pages are buffered in a `QueuedFile` and handed to
+//! that thread over a bounded crossbeam channel. There is one thread per
+//! process. The thread drains whatever is
+//! queued in one batch, writes it, and syncs files according to the
+//! configured `FsyncPolicy`.
+//!
+//! Nothing here blocks the caller. When the channel is full the open and the
+//! pages stay in the `QueuedFile` and are retried on the next flush, so a
+//! slow disk delays data instead of stalling the voice task. A file that
+//! falls `MAX_PENDING_BYTES` behind reports an error on flush so its owner
+//! rotates it; dropping a `QueuedFile` hands whatever the channel cannot take
+//! to a blocking task.
+//!
+//! A file that cannot be created, or whose write fails, is not written
+//! again; its `QueuedFile` reports the failure on the next write or flush so
+//! the owner can close it. A file that is still [`Provisional`] when it is
+//! dropped is deleted instead of closed.
+//!
+//! Files opened with a [`ChunkSealer`] are encrypted before they are queued;
+//! see [`crate::encryption`].
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender, TrySendError};
+use serenity::prelude::TypeMapKey;
+use tracing::{error, info, warn};
+
+use crate::encryption::ChunkSealer;
+
+/// Buffered bytes at which a `QueuedFile` submits without waiting for flush.
+const SUBMIT_THRESHOLD_BYTES: usize = 64 * 1024;
+/// Buffered bytes at which a `QueuedFile` reports that the writer fell behind.
+const MAX_PENDING_BYTES: usize = 16 * 1024 * 1024;
+/// Upper bound on messages handled before the batch is flushed to disk.
+const MAX_BATCH: usize = 1024;
+/// How often the thread wakes up to run interval syncs when idle.
+const IDLE_WAKEUP: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Leave syncing to the OS.
+    Never,
+    /// `fsync` when a file is closed.
+    OnClose,
+    /// `fdatasync` dirty files at this interval, and `fsync` on close.
+    Interval(Duration),
+}
+
+impl FsyncPolicy {
+    /// Parse `never`, `close` or a number of seconds.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "never" | "" => Some(Self::Never),
+            "close" => Some(Self::OnClose),
+            secs => secs
+                .parse::<u64>()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .map(|secs| Self::Interval(Duration::from_secs(secs))),
+        }
+    }
+
+    fn sync_on_close(&self) -> bool {
+        !matches!(self, Self::Never)
+    }
+}
+
+enum WriteOp {
+    Open {
+        id: u64,
+        file: File,
+        failed: Arc<AtomicBool>,
+    },
+    /// Create `path`, and its directory if missing, then treat it as opened.
+    Create {
+        id: u64,
+        path: PathBuf,
+        failed: Arc<AtomicBool>,
+    },
+    Data {
+        id: u64,
+        bytes: Vec<u8>,
+    },
+    Close {
+        id: u64,
+    },
+    /// Close without syncing and delete the file if the thread created it.
+    Discard {
+        id: u64,
+    },
+    /// Flush everything queued before this message, then ack.
+    Drain(Sender<()>),
+}
+
+pub struct DiskWriter {
+    tx: Sender<WriteOp>,
+    next_id: AtomicU64,
+    /// Closing files whose tail is still being handed over.
+    closing: AtomicUsize,
+    metrics: Arc<crate::BotMetrics>,
+}
+
+impl DiskWriter {
+    /// Start the writer thread with the policy and queue size from the
+    /// environment.
+    pub fn start(metrics: Arc<crate::BotMetrics>) -> std::io::Result<Arc<Self>> {
+        Self::start_with(
+            metrics,
+            crate::config::recording_fsync_policy(),
+            crate::config::recording_write_queue_capacity(),
+        )
+    }
+
+    pub fn start_with(
+        metrics: Arc<crate::BotMetrics>,
+        policy: FsyncPolicy,
+        capacity: usize,
+    ) -> std::io::Result<Arc<Self>> {
+        let (tx, rx) = channel::bounded(capacity);
+        let thread_metrics = metrics.clone();
+        std::thread::Builder::new()
+            .name("recording-writer".to_string())
+            .spawn(move || run(rx, policy, thread_metrics))?;
+        info!(?policy, capacity, "recording writer thread started");
+        Ok(Arc::new(Self {
+            tx,
+            next_id: AtomicU64::new(1),
+            closing: AtomicUsize::new(0),
+            metrics,
+        }))
+    }
+
+    /// Hand `file` over to the writer thread.
+    pub fn open(self: &Arc<Self>, file: File) -> std::io::Result<QueuedFile> {
+        self.open_with(file, None)
+    }
+
+    /// Hand `file` over to the writer thread, encrypting what is written to
+    /// it when `sealer` is given.
+    pub fn open_with(
+        self: &Arc<Self>,
+        file: File,
+        sealer: Option<ChunkSealer>,
+    ) -> std::io::Result<QueuedFile> {
+        self.queue_open(sealer, |id, failed| WriteOp::Open { id, file, failed })
+    }
+
+    /// Have the writer thread create `path`, so the caller never waits on
+    /// the filesystem. A failed create is reported by the returned file's
+    /// next write or flush.
+    pub fn create(
+        self: &Arc<Self>,
+        path: impl Into<PathBuf>,
+        sealer: Option<ChunkSealer>,
+    ) -> std::io::Result<QueuedFile> {
+        let path = path.into();
+        self.queue_open(sealer, |id, failed| WriteOp::Create { id, path, failed })
+    }
+
+    fn queue_open(
+        self: &Arc<Self>,
+        sealer: Option<ChunkSealer>,
+        open: impl FnOnce(u64, Arc<AtomicBool>) -> WriteOp,
+    ) -> std::io::Result<QueuedFile> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let failed = Arc::new(AtomicBool::new(false));
+        let unopened = self.try_queue(open(id, failed.clone()))?;
+        Ok(QueuedFile {
+            id,
+            unopened,
+            buf: sealer
+                .as_ref()
+                .map(|sealer| sealer.header().to_vec())
+                .unwrap_or_default(),
+            writer: Arc::clone(self),
+            failed,
+            sealer,
+            discard: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Block until everything queued so far has been written, or `timeout`
+    /// passes. Returns whether the writer caught up.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.closing.load(Ordering::Acquire) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let (ack_tx, ack_rx) = channel::bounded(1);
+        if self
+            .tx
+            .send_timeout(WriteOp::Drain(ack_tx), timeout)
+            .is_err()
+        {
+            return false;
+        }
+        ack_rx.recv_timeout(timeout).is_ok()
+    }
+
+    fn send_blocking(&self, op: WriteOp) -> std::io::Result<()> {
+        self.tx
+            .send(op)
+            .map_err(|_| std::io::Error::other("recording writer thread stopped"))?;
+        self.track_queue_depth();
+        Ok(())
+    }
+
+    /// Queue `op` without blocking. On a full channel it is handed back.
+    fn try_queue(&self, op: WriteOp) -> std::io::Result<Option<WriteOp>> {
+        match self.tx.try_send(op) {
+            Ok(()) => {
+                self.track_queue_depth();
+                Ok(None)
+            }
+            Err(TrySendError::Full(op)) => {
+                self.metrics
+                    .disk_write_backpressure
+                    .fetch_add(1, Ordering::Relaxed);
+                Ok(Some(op))
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                Err(std::io::Error::other("recording writer thread stopped"))
+            }
+        }
+    }
+
+    /// Queue the last messages of a closing file, in order. What the channel
+    /// cannot take now is sent from a blocking task so the caller never
+    /// waits; outside a runtime it is sent inline.
+    fn queue_tail(self: &Arc<Self>, ops: Vec<WriteOp>) {
+        let mut ops = ops.into_iter();
+        let rest = loop {
+            let Some(op) = ops.next() else {
+                return;
+            };
+            match self.try_queue(op) {
+                Ok(None) => {}
+                Ok(Some(op)) => break std::iter::once(op).chain(ops).collect::<Vec<_>>(),
+                Err(e) => {
+                    error!("Failed to queue final recording bytes: {}", e);
+                    return;
+                }
+            }
+        };
+
+        self.closing.fetch_add(1, Ordering::AcqRel);
+        let writer = Arc::clone(self);
+        let send = move || {
+            for op in rest {
+                if let Err(e) = writer.send_blocking(op) {
+                    error!("Failed to queue final recording bytes: {}", e);
+                    break;
+                }
+            }
+            writer.closing.fetch_sub(1, Ordering::AcqRel);
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(send);
+            }
+            Err(_) => send(),
+        }
+    }
+
+    fn track_queue_depth(&self) {
+        self.metrics
+            .disk_write_queue_depth
+            .store(self.tx.len() as u64, Ordering::Relaxed);
+    }
+}
+
+pub struct DiskWriterKey;
+impl TypeMapKey for DiskWriterKey {
+    type Value = Arc<DiskWriter>;
+}
+
+/// A file owned by the writer thread. Writes only buffer; `flush` queues
+/// the buffer for the thread. Dropping closes the file after its remaining
+/// bytes are written.
+pub struct QueuedFile {
+    id: u64,
+    /// The `Open` message, until the channel had room for it.
+    unopened: Option<WriteOp>,
+    buf: Vec<u8>,
+    writer: Arc<DiskWriter>,
+    /// Set by the writer thread once a write to this file failed.
+    failed: Arc<AtomicBool>,
+    /// Present for encrypted files; `buf` then holds sealed chunks only.
+    sealer: Option<ChunkSealer>,
+    /// Set while the file is provisional; drop then deletes the file.
+    discard: Arc<AtomicBool>,
+}
+
+/// Holds a new `QueuedFile` back until whatever it belongs to is set up, also
+/// after the file was moved into a writer. A file dropped before [`keep`] is
+/// deleted.
+///
+/// [`keep`]: Provisional::keep
+pub struct Provisional(Arc<AtomicBool>);
+
+impl Provisional {
+    pub fn keep(self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+impl QueuedFile {
+    /// Make the file provisional until the returned handle is kept.
+    pub fn provisional(&self) -> Provisional {
+        self.discard.store(true, Ordering::Relaxed);
+        Provisional(self.discard.clone())
+    }
+
+    fn check_failed(&self) -> std::io::Result<()> {
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(std::io::Error::other("recording file write failed"));
+        }
+        Ok(())
+    }
+
+    /// Queue the open and the buffer without blocking. Whatever the channel
+    /// has no room for stays here for the next flush.
+    fn submit(&mut self) -> std::io::Result<()> {
+        if let Some(open) = self.unopened.take() {
+            self.unopened = self.writer.try_queue(open)?;
+            if self.unopened.is_some() {
+                return Ok(());
+            }
+        }
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let data = WriteOp::Data {
+            id: self.id,
+            bytes: std::mem::take(&mut self.buf),
+        };
+        if let Some(WriteOp::Data { bytes, .. }) = self.writer.try_queue(data)? {
+            self.buf = bytes;
+        }
+        Ok(())
+    }
+}
+
+impl Write for QueuedFile {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.check_failed()?;
+        match self.sealer.as_mut() {
+            Some(sealer) => sealer.push(data, &mut self.buf)?,
+            None => self.buf.extend_from_slice(data),
+        }
+        if self.buf.len() >= SUBMIT_THRESHOLD_BYTES {
+            self.submit()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.check_failed()?;
+        if let Some(sealer) = self.sealer.as_mut()
+            && sealer.seal_due()
+        {
+            sealer.seal(&mut self.buf)?;
+        }
+        self.submit()?;
+        if self.buf.len() >= MAX_PENDING_BYTES {
+            warn!(
+                file_id = self.id,
+                pending_bytes = self.buf.len(),
+                "recording writer fell behind"
+            );
+            return Err(std::io::Error::other(format!(
+                "recording writer is {} bytes behind",
+                self.buf.len()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for QueuedFile {
+    fn drop(&mut self) {
+        if self.discard.load(Ordering::Relaxed) {
+            // Never queued means never created; nothing to delete.
+            if self.unopened.take().is_none() {
+                self.writer
+                    .queue_tail(vec![WriteOp::Discard { id: self.id }]);
+            }
+            return;
+        }
+        if let Some(sealer) = self.sealer.as_mut()
+            && let Err(e) = sealer.seal(&mut self.buf)
+        {
+            error!("Failed to seal final recording chunk: {}", e);
+        }
+        let mut tail: Vec<WriteOp> = self.unopened.take().into_iter().collect();
+        if !self.buf.is_empty() {
+            tail.push(WriteOp::Data {
+                id: self.id,
+                bytes: std::mem::take(&mut self.buf),
+            });
+        }
+        tail.push(WriteOp::Close { id: self.id });
+        self.writer.queue_tail(tail);
+    }
+}
+
+struct OpenFile {
+    file: File,
+    /// Known for files the thread created itself, so they can be discarded.
+    path: Option<PathBuf>,
+    dirty: bool,
+    failed: Arc<AtomicBool>,
+}
+
+impl OpenFile {
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+fn run(rx: Receiver<WriteOp>, policy: FsyncPolicy, metrics: Arc<crate::BotMetrics>) {
+    let mut files: HashMap<u64, OpenFile> = HashMap::new();
+    let mut last_sync = Instant::now();
+
+    loop {
+        let first = match rx.recv_timeout(IDLE_WAKEUP) {
+            Ok(op) => Some(op),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let mut acks = Vec::new();
+        if let Some(first) = first {
+            let batch = std::iter::once(first).chain(rx.try_iter().take(MAX_BATCH - 1));
+            for op in batch {
+                match op {
+                    WriteOp::Open { id, file, failed } => {
+                        files.insert(
+                            id,
+                            OpenFile {
+                                file,
+                                path: None,
+                                dirty: false,
+                                failed,
+                            },
+                        );
+                    }
+                    WriteOp::Create { id, path, failed } => match create_file(&path) {
+                        Ok(file) => {
+                            files.insert(
+                                id,
+                                OpenFile {
+                                    file,
+                                    path: Some(path),
+                                    dirty: false,
+                                    failed,
+                                },
+                            );
+                        }
+                        Err(e) => {
+                            error!(file_id = id, "cannot create {}: {}", path.display(), e);
+                            failed.store(true, Ordering::Relaxed);
+                            metrics.disk_write_errors.fetch_add(1, Ordering::Relaxed);
+                        }
+                    },
+                    WriteOp::Data { id, bytes } => {
+                        let Some(open) = files.get_mut(&id) else {
+                            continue;
+                        };
+                        if open.failed() {
+                            continue;
+                        }
+                        match open.file.write_all(&bytes) {
+                            Ok(()) => {
+                                open.dirty = true;
+                                metrics
+                                    .disk_write_bytes
+                                    .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+                            }
+                            Err(e) => {
+                                // Later pages would land at the wrong offset;
+                                // stop writing this file instead.
+                                error!(file_id = id, "recording write failed: {}", e);
+                                open.failed.store(true, Ordering::Relaxed);
+                                metrics.disk_write_errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+                    WriteOp::Close { id } => {
+                        if let Some(open) = files.remove(&id)
+                            && policy.sync_on_close()
+                            && !open.failed()
+                        {
+                            sync(&open.file, &metrics, true);
+                        }
+                    }
+                    WriteOp::Discard { id } => {
+                        if let Some(OpenFile {
+                            file,
+                            path: Some(path),
+                            ..
+                        }) = files.remove(&id)
+                        {
+                            drop(file);
+                            if let Err(e) = std::fs::remove_file(&path) {
+                                warn!("Failed to remove {}: {}", path.display(), e);
+                            }
+                        }
+                    }
+                    WriteOp::Drain(ack) => acks.push(ack),
+                }
+            }
+            metrics.disk_write_batches.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let FsyncPolicy::Interval(every) = policy
+            && last_sync.elapsed() >= every
+        {
+            for open in files
+                .values_mut()
+                .filter(|open| open.dirty && !open.failed())
+            {
+                sync(&open.file, &metrics, false);
+                open.dirty = false;
+            }
+            last_sync = Instant::now();
+        }
+
+        metrics
+            .disk_write_queue_depth
+            .store(rx.len() as u64, Ordering::Relaxed);
+        for ack in acks {
+            let _ = ack.send(());
+        }
+    }
+
+    if policy.sync_on_close() {
+        for open in files.values().filter(|open| !open.failed()) {
+            sync(&open.file, &metrics, true);
+        }
+    }
+    info!("recording writer thread stopped");
+}
+
+fn create_file(path: &Path) -> std::io::Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    File::create(path)
+}
+
+fn sync(file: &File, metrics: &crate::BotMetrics, all: bool) {
+    let result = if all {
+        file.sync_all()
+    } else {
+        file.sync_data()
+    };
+    match result {
+        Ok(()) => {
+            metrics.disk_fsyncs.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(e) => {
+            error!("recording fsync failed: {}", e);
+            metrics.disk_write_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fsync_policy_parses_names_and_seconds() {
+        assert_eq!(FsyncPolicy::parse("never"), Some(FsyncPolicy::Never));
+        assert_eq!(FsyncPolicy::parse("Close"), Some(FsyncPolicy::OnClose));
+        assert_eq!(
+            FsyncPolicy::parse("5"),
+            Some(FsyncPolicy::Interval(Duration::from_secs(5)))
+        );
+        assert_eq!(FsyncPolicy::parse("0"), None);
+        assert_eq!(FsyncPolicy::parse("sometimes"), None);
+    }
+
+    #[test]
+    fn failed_file_reports_on_next_flush() -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = Arc::new(crate::BotMetrics::default());
+        let writer = DiskWriter::start_with(metrics.clone(), FsyncPolicy::Never, 16)?;
+        let path = std::env::temp_dir().join(format!(
+            "disk-writer-failed-{}-{}.bin",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        std::fs::write(&path, b"")?;
+
+        // Read-only handle: the thread's write fails.
+        let mut file = writer.open(File::open(&path)?)?;
+        file.write_all(&[1; 10])?;
+        file.flush()?;
+        assert!(writer.drain(Duration::from_secs(5)));
+        assert!(file.flush().is_err());
+        assert!(file.write(&[1]).is_err());
+        assert_eq!(metrics.disk_write_errors.load(Ordering::Relaxed), 1);
+
+        drop(file);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn queued_writes_reach_disk_in_order() -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = Arc::new(crate::BotMetrics::default());
+        // Capacity 1 forces the backpressure path.
+        let writer = DiskWriter::start_with(metrics.clone(), FsyncPolicy::OnClose, 1)?;
+        let path = std::env::temp_dir().join(format!(
+            "disk-writer-test-{}-{}.bin",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+
+        {
+            let mut file = writer.open(File::create(&path)?)?;
+            for i in 0..200u8 {
+                file.write_all(&[i; 100])?;
+                file.flush()?;
+            }
+        }
+        assert!(writer.drain(Duration::from_secs(5)));
+
+        let written = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(written.len(), 200 * 100);
+        assert!(
+            written
+                .chunks(100)
+                .enumerate()
+                .all(|(i, c)| c[0] == i as u8)
+        );
+        assert_eq!(metrics.disk_write_bytes.load(Ordering::Relaxed), 200 * 100);
+        Ok(())
+    }
+
+    #[test]
+    fn created_files_get_their_directory_and_provisional_ones_are_removed()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = Arc::new(crate::BotMetrics::default());
+        let writer = DiskWriter::start_with(metrics, FsyncPolicy::Never, 16)?;
+        let dir = std::env::temp_dir().join(format!(
+            "disk-writer-create-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let kept = dir.join("nested").join("kept.bin");
+        let discarded = dir.join("nested").join("discarded.bin");
+
+        {
+            let mut file = writer.create(&kept, None)?;
+            let provisional = file.provisional();
+            file.write_all(b"kept")?;
+            provisional.keep();
+            let mut other = writer.create(&discarded, None)?;
+            let _provisional = other.provisional();
+            other.write_all(b"discarded")?;
+            other.flush()?;
+        }
+        assert!(writer.drain(Duration::from_secs(5)));
+
+        assert_eq!(std::fs::read(&kept)?, b"kept");
+        assert!(!discarded.exists());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn files_closed_on_a_full_queue_are_written_in_full()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let metrics = Arc::new(crate::BotMetrics::default());
+        let writer = DiskWriter::start_with(metrics, FsyncPolicy::Never, 1)?;
+        let dir = std::env::temp_dir().join(format!(
+            "disk-writer-tail-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        std::fs::create_dir_all(&dir)?;
+
+        for n in 0..20u8 {
+            let mut file = writer.open(File::create(dir.join(n.to_string()))?)?;
+            file.write_all(&[n; 5000])?;
+            let _ = file.flush();
+        }
+        let drain_writer = writer.clone();
+        assert!(
+            tokio::task::spawn_blocking(move || drain_writer.drain(Duration::from_secs(5))).await?
+        );
+
+        for n in 0..20u8 {
+            assert_eq!(std::fs::read(dir.join(n.to_string()))?, vec![n; 5000]);
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sealed_files_decrypt_to_what_was_written() -> Result<(), Box<dyn std::error::Error>> {
+        use std::io::Read;
+
+        let keyring = crate::encryption::Keyring::parse(
+            "1:000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+            None,
+        )?;
+        let metrics = Arc::new(crate::BotMetrics::default());
+        let writer = DiskWriter::start_with(metrics, FsyncPolicy::Never, 16)?;
+        let path = std::env::temp_dir().join(format!(
+            "disk-writer-sealed-{}-{}.bin",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+
+        let plain: Vec<u8> = (0..150_000u32).map(|i| (i % 241) as u8).collect();
+        {
+            let mut file = writer.open_with(File::create(&path)?, keyring.sealer()?)?;
+            for part in plain.chunks(3000) {
+                file.write_all(part)?;
+                file.flush()?;
+            }
+        }
+        assert!(writer.drain(Duration::from_secs(5)));
+
+        let mut reader = crate::encryption::DecryptingReader::new(File::open(&path)?, &keyring)?;
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(out, plain);
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
+use std::time::Duration;
+
 use serenity::{
     all::{ButtonStyle, CommandDataOptionValue, CommandInteraction, Interaction},
     builder::{
         AutocompleteChoice, CreateActionRow, CreateAutocompleteResponse, CreateButton,
-        CreateInteractionResponse, CreateInteractionResponseMessage,
+        CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
     },
     client::Context,
 };
@@ -20,6 +22,33 @@ pub async fn interaction_create(_self: &Handler, ctx: Context, interaction: Inte
         Interaction::Ping(_) => {
             warn!("Unhandled interaction type: Ping");
         }
+        // Clips can take a while to download and transcode, longer than
+        // Discord waits for a reply, so these are acknowledged first.
+        Interaction::Command(application_command)
+            if matches!(
+                application_command.data.name.as_str(),
+                "clip" | crate::commands::clip::SAVE_AS_CLIP
+            ) =>
+        {
+            if let Err(why) = application_command.defer_ephemeral(&ctx.http).await {
+                warn!("Cannot defer clip command: {}", why);
+                return;
+            }
+            let (content, clip_id) = if application_command.data.name == "clip" {
+                crate::commands::clip::handle_clip(&application_command, &ctx, &_self.database)
+                    .await
+            } else {
+                crate::commands::clip::handle_save_as_clip(&application_command, &_self.database)
+                    .await
+            };
+            let mut edit = EditInteractionResponse::new().content(content);
+            if let Some(cid) = clip_id {
+                edit = edit.components(vec![replay_button(&cid)]);
+            }
+            if let Err(why) = application_command.edit_response(&ctx.http, edit).await {
+                warn!("Cannot respond to clip command: {}", why);
+            }
+        }
         Interaction::Command(application_command) => {
             let mut response_msg = CreateInteractionResponseMessage::new().ephemeral(true);
 
@@ -35,11 +64,7 @@ pub async fn interaction_create(_self: &Handler, ctx: Context, interaction: Inte
                     .await;
                     response_msg = response_msg.content(content);
                     if let Some(cid) = clip_id {
-                        let button = CreateButton::new(format!("jam_replay:{}", cid))
-                            .label("Replay")
-                            .style(ButtonStyle::Primary);
-                        response_msg =
-                            response_msg.components(vec![CreateActionRow::Buttons(vec![button])]);
+                        response_msg = response_msg.components(vec![replay_button(&cid)]);
                     }
                 }
                 "queue" => {
@@ -54,6 +79,10 @@ pub async fn interaction_create(_self: &Handler, ctx: Context, interaction: Inte
                     response_msg =
                         response_msg.content(handle_stop(&application_command, &ctx).await)
                 }
+                "pause" | "resume" | "volume" | "seek" | "nowplaying" => {
+                    response_msg =
+                        response_msg.content(handle_playback(&application_command, &ctx).await)
+                }
                 "join" => {
                     response_msg = response_msg
                         .content(handle_join(&application_command, &ctx, &_self.database).await)
@@ -68,6 +97,25 @@ pub async fn interaction_create(_self: &Handler, ctx: Context, interaction: Inte
                         .await,
                     )
                 }
+                "privacy" => {
+                    response_msg = response_msg.content(
+                        crate::commands::privacy::handle_privacy(
+                            &application_command,
+                            &_self.database,
+                        )
+                        .await,
+                    )
+                }
+                "recording" => {
+                    response_msg = response_msg.content(
+                        crate::commands::recording::handle_recording(
+                            &application_command,
+                            &ctx,
+                            &_self.database,
+                        )
+                        .await,
+                    )
+                }
                 other => {
                     response_msg = response_msg.content(format!(
                         "Unknown application_command with the name {}",
@@ -157,6 +205,14 @@ pub async fn interaction_create(_self: &Handler, ctx: Context, interaction: Inte
     }
 }
 
+/// A button that plays `clip_id` again, handled as `jam_replay:<clip_id>`.
+pub fn replay_button(clip_id: &str) -> CreateActionRow {
+    let button = CreateButton::new(format!("jam_replay:{}", clip_id))
+        .label("Replay")
+        .style(ButtonStyle::Primary);
+    CreateActionRow::Buttons(vec![button])
+}
+
 async fn should_skip_interaction(handler: &Handler, interaction: &Interaction) -> bool {
     let guild_id = match interaction {
         Interaction::Command(command) => command.guild_id,
@@ -264,8 +320,12 @@ async fn handle_jam(
             );
         }
     }
-    match crate::commands::voice_controls::play_clip(pool, &manager, guild_id, &clip_name, user_id)
-        .await
+    let storage = crate::storage::from_data(&ctx.data).await;
+    let volumes = crate::commands::voice_controls::guild_volumes(&ctx.data).await;
+    match crate::commands::voice_controls::play_clip(
+        pool, &storage, &volumes, &manager, guild_id, &clip_name, user_id,
+    )
+    .await
     {
         Ok(msg) => (msg, Some(clip_name)),
         Err(e) => {
@@ -303,8 +363,12 @@ async fn replay_clip(
         }
     }
 
-    match crate::commands::voice_controls::play_clip(pool, &manager, guild_id, clip_id, user_id)
-        .await
+    let storage = crate::storage::from_data(&ctx.data).await;
+    let volumes = crate::commands::voice_controls::guild_volumes(&ctx.data).await;
+    match crate::commands::voice_controls::play_clip(
+        pool, &storage, &volumes, &manager, guild_id, clip_id, user_id,
+    )
+    .await
     {
         Ok(msg) => msg,
         Err(e) => e,
@@ -353,6 +417,49 @@ async fn handle_stop(application_command: &CommandInteraction, ctx: &Context) ->
     crate::commands::voice_controls::stop(&manager, guild_id).await
 }
 
+/// `/pause`, `/resume`, `/volume`, `/seek` and `/nowplaying`.
+async fn handle_playback(application_command: &CommandInteraction, ctx: &Context) -> String {
+    use crate::commands::voice_controls;
+
+    let manager = match songbird::get(ctx).await {
+        Some(m) => m,
+        None => return "Voice system is not configured.".to_string(),
+    };
+
+    let guild_id = match application_command.guild_id {
+        Some(id) => id,
+        None => return "This command can only be used in a server.".to_string(),
+    };
+
+    let integer = application_command
+        .data
+        .options
+        .first()
+        .and_then(|o| match o.value {
+            CommandDataOptionValue::Integer(i) => Some(i),
+            _ => None,
+        });
+    let result = match application_command.data.name.as_str() {
+        "pause" => voice_controls::pause(&manager, guild_id).await,
+        "resume" => voice_controls::resume(&manager, guild_id).await,
+        "volume" => match integer.and_then(|i| u32::try_from(i).ok()) {
+            Some(percent) => {
+                let volumes = voice_controls::guild_volumes(&ctx.data).await;
+                voice_controls::set_volume(&manager, &volumes, guild_id, percent).await
+            }
+            None => Err("Please provide a volume.".to_string()),
+        },
+        "seek" => match integer.and_then(|i| u64::try_from(i).ok()) {
+            Some(seconds) => {
+                voice_controls::seek(&manager, guild_id, Duration::from_secs(seconds)).await
+            }
+            None => Err("Please provide a position.".to_string()),
+        },
+        _ => Ok(voice_controls::now_playing_text(&manager, guild_id).await),
+    };
+    result.unwrap_or_else(|e| e)
+}
+
 async fn handle_join(
     application_command: &CommandInteraction,
     ctx: &Context,
@@ -0,0 +1,159 @@
+//! Optional reconstruction of lost voice frames.
+//!
+//! A per-recording Opus decoder follows every packet written to the file.
+//! When the RTP timeline reports a gap, the frame right before the next
+//! packet is rebuilt from that packet's in-band FEC data, and the frames
+//! right after the last good packet come from decoder packet-loss
+//! concealment. Each rebuilt frame is re-encoded and written in place of
+//! the canned silence frame. libopus falls back to PLC by itself when the
+//! next packet carries no FEC data.
+
+use crate::events::ogg_opus_writer::silence_frame_bytes;
+
+/// Interleaved stereo samples in one 20 ms frame at 48 kHz.
+const FRAME_SAMPLES: usize = 960 * 2;
+const MAX_PACKET_BYTES: usize = 4000;
+/// PLC output decays towards silence quickly; past this many frames the
+/// gap is filled with the plain silence frame instead.
+const MAX_PLC_FRAMES: u64 = 5;
+
+pub struct LossRecovery {
+    decoder: opus2::Decoder,
+    encoder: opus2::Encoder,
+    pcm: Vec<i16>,
+    packet: Vec<u8>,
+}
+
+impl LossRecovery {
+    pub fn new() -> std::io::Result<Self> {
+        use opus2::{Application, Channels, Decoder, Encoder};
+        let decoder = Decoder::new(48000, Channels::Stereo)
+            .map_err(|err| std::io::Error::other(format!("opus decoder init: {}", err)))?;
+        let encoder = Encoder::new(48000, Channels::Stereo, Application::Voip)
+            .map_err(|err| std::io::Error::other(format!("opus encoder init: {}", err)))?;
+        Ok(Self {
+            decoder,
+            encoder,
+            pcm: vec![0i16; FRAME_SAMPLES],
+            packet: vec![0u8; MAX_PACKET_BYTES],
+        })
+    }
+
+    /// Feed a packet that is being written so decoder state tracks the stream.
+    pub fn observe(&mut self, packet: &[u8]) -> std::io::Result<()> {
+        self.decoder
+            .decode(packet, &mut self.pcm, false)
+            .map(|_| ())
+            .map_err(|err| std::io::Error::other(format!("opus decode: {}", err)))
+    }
+
+    /// Rebuild `count` frames lost right before `next`, oldest first.
+    pub fn recover(&mut self, count: u64, next: &[u8]) -> std::io::Result<Vec<Vec<u8>>> {
+        let plan = recovery_plan(count);
+        let mut frames = Vec::with_capacity(count as usize);
+
+        for _ in 0..plan.plc {
+            self.decoder
+                .decode(&[], &mut self.pcm, false)
+                .map_err(|err| std::io::Error::other(format!("opus plc: {}", err)))?;
+            frames.push(self.encode_pcm()?);
+        }
+        if plan.silence > 0 {
+            let silence = silence_frame_bytes()?;
+            for _ in 0..plan.silence {
+                frames.push(silence.clone());
+            }
+        }
+        if plan.fec {
+            self.decoder
+                .decode(next, &mut self.pcm, true)
+                .map_err(|err| std::io::Error::other(format!("opus fec: {}", err)))?;
+            frames.push(self.encode_pcm()?);
+        }
+
+        Ok(frames)
+    }
+
+    fn encode_pcm(&mut self) -> std::io::Result<Vec<u8>> {
+        let n = self
+            .encoder
+            .encode(&self.pcm, &mut self.packet)
+            .map_err(|err| std::io::Error::other(format!("encode recovered frame: {}", err)))?;
+        Ok(self.packet[..n].to_vec())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct RecoveryPlan {
+    plc: u64,
+    silence: u64,
+    fec: bool,
+}
+
+impl RecoveryPlan {
+    /// Frames rebuilt from real audio rather than filled with silence.
+    fn recovered(&self) -> u64 {
+        self.plc + u64::from(self.fec)
+    }
+}
+
+fn recovery_plan(count: u64) -> RecoveryPlan {
+    if count == 0 {
+        return RecoveryPlan {
+            plc: 0,
+            silence: 0,
+            fec: false,
+        };
+    }
+    let before_fec = count - 1;
+    let plc = before_fec.min(MAX_PLC_FRAMES);
+    RecoveryPlan {
+        plc,
+        silence: before_fec - plc,
+        fec: true,
+    }
+}
+
+/// How many of `count` lost frames `LossRecovery::recover` rebuilds from
+/// audio; the rest are plain silence.
+pub fn recovered_frames(count: u64) -> u64 {
+    recovery_plan(count).recovered()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_lost_frame_uses_fec_only() {
+        assert_eq!(
+            recovery_plan(1),
+            RecoveryPlan {
+                plc: 0,
+                silence: 0,
+                fec: true
+            }
+        );
+    }
+
+    #[test]
+    fn long_gaps_cap_plc_and_fill_with_silence() {
+        let plan = recovery_plan(20);
+        assert_eq!(plan.plc, MAX_PLC_FRAMES);
+        assert_eq!(plan.silence, 20 - 1 - MAX_PLC_FRAMES);
+        assert!(plan.fec);
+        assert_eq!(recovered_frames(20), MAX_PLC_FRAMES + 1);
+        assert_eq!(recovered_frames(0), 0);
+    }
+
+    #[test]
+    fn recover_returns_one_packet_per_lost_frame() -> Result<(), Box<dyn std::error::Error>> {
+        let mut recovery = LossRecovery::new()?;
+        let next = silence_frame_bytes()?;
+        recovery.observe(&next)?;
+        let frames = recovery.recover(8, &next)?;
+        assert_eq!(frames.len(), 8);
+        assert!(frames.iter().all(|f| !f.is_empty()));
+        Ok(())
+    }
+}
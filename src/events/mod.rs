@@ -1,14 +1,24 @@
 // #![allow(dead_code, unused_variables)]
 
 pub mod channels;
+pub mod disk_writer;
 pub mod emojis;
 pub mod guilds;
 pub mod integrations;
 pub mod interactions;
 pub mod invites;
+pub mod loss_recovery;
 pub mod messages;
 pub mod ogg_opus_writer;
+pub mod playback_track;
 pub mod reactions;
+pub mod recent_audio;
+pub mod recording_tags;
 pub mod roles;
+pub mod rtp_timeline;
+pub mod segment_rotation;
+pub mod session_mix;
+pub mod sparse_index;
+pub mod speech_segments;
 pub mod voice;
 pub mod voice_receiver;
@@ -8,6 +8,8 @@ use std::sync::OnceLock;
 
 use ogg::PacketWriteEndInfo;
 
+use crate::events::sparse_index::SparseIndex;
+
 const OPUS_SAMPLE_RATE: u32 = 48000;
 const OPUS_CHANNELS: u8 = 2;
 const SAMPLES_PER_FRAME: u64 = 960;
@@ -41,10 +43,36 @@ pub fn silence_frame_bytes() -> std::io::Result<Vec<u8>> {
     silence_frame().map(|bytes| bytes.to_vec())
 }
 
+fn opus_tags(comments: &[String]) -> Vec<u8> {
+    let vendor = b"sakiot";
+    let comments_len: usize = comments.iter().map(|c| 4 + c.len()).sum();
+    let mut tags = Vec::with_capacity(8 + 4 + vendor.len() + 4 + comments_len);
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&(comments.len() as u32).to_le_bytes()); // user comment list length
+    for comment in comments {
+        tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        tags.extend_from_slice(comment.as_bytes());
+    }
+    tags
+}
+
+/// Size of a single-packet Ogg page: 27-byte header, lacing table, payload.
+fn page_len(packet_len: usize) -> u64 {
+    (27 + packet_len / 255 + 1 + packet_len) as u64
+}
+
 pub struct OggOpusWriter<W: Write> {
     inner: ogg::PacketWriter<'static, W>,
     serial: u32,
     granule: u64,
+    /// Frames on the recording timeline. Equals `granule / 960` unless the
+    /// writer is sparse, where silence advances only this.
+    frames: u64,
+    bytes_written: u64,
+    /// Present in speech-only mode; silence is then indexed, not written.
+    sparse: Option<SparseIndex<W>>,
     finished: bool,
 }
 
@@ -53,8 +81,16 @@ impl<W: Write> OggOpusWriter<W> {
     /// `pre_skip_samples` should be the encoder pre-skip (typically 0 here
     /// since we are passing through Discord's already-encoded packets).
     pub fn new(writer: W, serial: u32, pre_skip_samples: u16) -> std::io::Result<Self> {
-        let mut pw = ogg::PacketWriter::new(writer);
+        Self::with_comments(writer, serial, pre_skip_samples, &[])
+    }
 
+    /// Same as `new`, with `KEY=value` Vorbis comments in the OpusTags header.
+    pub fn with_comments(
+        writer: W,
+        serial: u32,
+        pre_skip_samples: u16,
+        comments: &[String],
+    ) -> std::io::Result<Self> {
         // OpusHead (RFC 7845 §5.1)
         let mut head = Vec::with_capacity(19);
         head.extend_from_slice(b"OpusHead");
@@ -64,32 +100,55 @@ impl<W: Write> OggOpusWriter<W> {
         head.extend_from_slice(&OPUS_SAMPLE_RATE.to_le_bytes());
         head.extend_from_slice(&0i16.to_le_bytes()); // output gain
         head.push(0); // channel mapping family 0 (mono/stereo)
-        pw.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
 
-        // OpusTags (RFC 7845 §5.2) — minimal: vendor "sakiot", zero comments.
-        let vendor = b"sakiot";
-        let mut tags = Vec::with_capacity(8 + 4 + vendor.len() + 4);
-        tags.extend_from_slice(b"OpusTags");
-        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
-        tags.extend_from_slice(vendor);
-        tags.extend_from_slice(&0u32.to_le_bytes()); // user comment list length
+        // OpusTags (RFC 7845 §5.2) — vendor "sakiot" plus caller comments.
+        Self::from_headers(writer, serial, head, opus_tags(comments))
+    }
+
+    /// Start a stream with already-built OpusHead/OpusTags packets, e.g. ones
+    /// copied from another file.
+    pub fn from_headers(
+        writer: W,
+        serial: u32,
+        head: Vec<u8>,
+        tags: Vec<u8>,
+    ) -> std::io::Result<Self> {
+        let mut pw = ogg::PacketWriter::new(writer);
+        let bytes_written = page_len(head.len()) + page_len(tags.len());
+        pw.write_packet(head, serial, PacketWriteEndInfo::EndPage, 0)?;
         pw.write_packet(tags, serial, PacketWriteEndInfo::EndPage, 0)?;
 
         Ok(Self {
             inner: pw,
             serial,
             granule: 0,
+            frames: 0,
+            bytes_written,
+            sparse: None,
             finished: false,
         })
     }
 
-    /// Append one Opus packet (one 20 ms / 960-sample frame), bump granule,
-    /// flush so the file is byte-current.
+    /// Switch to speech-only storage: silence is recorded in `index` instead
+    /// of being written. Call before any audio is written.
+    pub fn into_sparse(mut self, index: SparseIndex<W>) -> Self {
+        self.sparse = Some(index);
+        self
+    }
+
+    /// Append one Opus packet (one 20 ms / 960-sample frame) and bump
+    /// granule. The page reaches the underlying writer's buffer; call `flush`
+    /// to push it on.
     pub fn write_packet(&mut self, packet: &[u8]) -> std::io::Result<()> {
         if self.finished {
             return Err(std::io::Error::other("writer already finished"));
         }
+        if let Some(index) = self.sparse.as_mut() {
+            index.on_packet(self.frames)?;
+        }
         self.granule += SAMPLES_PER_FRAME;
+        self.frames += 1;
+        self.bytes_written += page_len(packet.len());
         self.inner.write_packet(
             packet.to_vec(),
             self.serial,
@@ -101,6 +160,11 @@ impl<W: Write> OggOpusWriter<W> {
     /// Append `count` silent 20 ms frames in one go (used when a user joins
     /// mid-session and we need to align their file with session-start).
     pub fn write_silence(&mut self, count: u64) -> std::io::Result<()> {
+        if let Some(index) = self.sparse.as_mut() {
+            index.close_run()?;
+            self.frames += count;
+            return Ok(());
+        }
         let bytes = silence_frame_bytes()?;
         for _ in 0..count {
             self.write_packet(&bytes)?;
@@ -108,6 +172,14 @@ impl<W: Write> OggOpusWriter<W> {
         Ok(())
     }
 
+    /// Push buffered pages to the underlying writer.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(index) = self.sparse.as_mut() {
+            index.flush()?;
+        }
+        self.inner.inner_mut().flush()
+    }
+
     /// Write a final EOS-marked page. After this no further writes are allowed.
     pub fn finish(&mut self) -> std::io::Result<()> {
         if self.finished {
@@ -121,13 +193,27 @@ impl<W: Write> OggOpusWriter<W> {
             PacketWriteEndInfo::EndStream,
             self.granule,
         )?;
+        if let Some(index) = self.sparse.as_mut() {
+            index.finish(self.frames)?;
+        }
         self.finished = true;
-        Ok(())
+        self.flush()
     }
 
     pub fn granule(&self) -> u64 {
         self.granule
     }
+
+    /// Bytes written to the underlying writer so far, page headers included.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Number of 20 ms frames on the recording timeline so far, silence
+    /// included even when sparse storage left it out of the file.
+    pub fn frames_written(&self) -> u64 {
+        self.frames
+    }
 }
 
 impl<W: Write> Drop for OggOpusWriter<W> {
@@ -185,6 +271,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn bytes_written_matches_output() -> Result<(), Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        let counted = {
+            let mut w = OggOpusWriter::new(Cursor::new(&mut buf), 7, 0)?;
+            w.write_silence(3)?;
+            w.write_packet(&[0u8; 300])?;
+            w.bytes_written()
+        };
+        // Drop appends the EOS page: 27-byte header plus one lacing byte.
+        assert_eq!(counted + 28, buf.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn opus_tags_carry_comments() -> Result<(), Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        {
+            let comments = vec!["GUILD_ID=1".to_string(), "TITLE=hello".to_string()];
+            let mut w = OggOpusWriter::with_comments(Cursor::new(&mut buf), 1, 0, &comments)?;
+            w.finish()?;
+        }
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(&buf));
+        reader.read_packet_expected()?;
+        let tags = reader.read_packet_expected()?.data;
+        assert_eq!(&tags[..8], b"OpusTags");
+        let vendor_len = u32::from_le_bytes(tags[8..12].try_into()?) as usize;
+        let mut at = 12 + vendor_len;
+        let count = u32::from_le_bytes(tags[at..at + 4].try_into()?);
+        assert_eq!(count, 2);
+        at += 4;
+        let len = u32::from_le_bytes(tags[at..at + 4].try_into()?) as usize;
+        assert_eq!(&tags[at + 4..at + 4 + len], b"GUILD_ID=1");
+        Ok(())
+    }
+
     #[test]
     fn gap_silence_extends_granule() -> Result<(), Box<dyn std::error::Error>> {
         let mut buf = Vec::new();
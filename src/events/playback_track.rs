@@ -0,0 +1,520 @@
+//! Recording of clips we play ourselves.
+//!
+//! Our own output never comes back to us over RTP, so when a guild enables
+//! `record_playback` the clip is written as a bot track of the open session
+//! once songbird is done with it. While it plays, its track state is
+//! sampled on every play, pause and end event and once a second in between;
+//! the resulting [`Span`]s say which stretch of the clip was heard when and
+//! at what volume. A seek or volume change is only noticed on the next
+//! sample, so the track can be up to a second off around one. The heard
+//! audio is decoded and re-encoded into the 20 ms frames every recording
+//! uses, with silence where playback was paused.
+
+use std::io::{Read, Seek, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Datelike;
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use serenity::async_trait;
+use songbird::tracks::PlayMode;
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent};
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+use crate::events::ogg_opus_writer::OggOpusWriter;
+use crate::events::recording_tags::RecordingTags;
+use crate::storage::ClipSource;
+
+/// Interleaved stereo samples in one 20 ms frame at 48 kHz.
+const FRAME_SAMPLES: usize = 960 * 2;
+/// Largest Opus frame is 120 ms.
+const MAX_DECODED_SAMPLES: usize = 5760 * 2;
+const MAX_PACKET_BYTES: usize = 4000;
+/// How often a playing track's state is sampled.
+const SAMPLE_EVERY: Duration = Duration::from_secs(1);
+/// Drift between the expected and reported position taken to be a seek.
+const SEEK_TOLERANCE: Duration = Duration::from_millis(250);
+
+/// A stretch of the clip that was heard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    /// Wall clock (Unix ms) at which the stretch started playing.
+    pub at_ms: i64,
+    /// Clip position it started from.
+    pub from: Duration,
+    /// Clip position it stopped at.
+    pub to: Duration,
+    pub volume: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Playing,
+    Paused,
+    Done,
+}
+
+/// Spans seen so far for one track.
+#[derive(Default)]
+struct PlayLog {
+    /// The span being played: started at, from, volume.
+    open: Option<(i64, Duration, f32)>,
+    spans: Vec<Span>,
+    done: bool,
+}
+
+impl PlayLog {
+    /// Fold a track state sampled at `now_ms` into the log. Returns the
+    /// spans the first time the track is seen done.
+    fn observe(
+        &mut self,
+        mode: Mode,
+        position: Duration,
+        volume: f32,
+        now_ms: i64,
+    ) -> Option<Vec<Span>> {
+        if self.done {
+            return None;
+        }
+        match mode {
+            Mode::Playing => match self.open {
+                None => self.open = Some((now_ms, position, volume)),
+                Some((at_ms, from, open_volume)) => {
+                    let expected = expected_position(at_ms, from, now_ms);
+                    if open_volume != volume || position.abs_diff(expected) > SEEK_TOLERANCE {
+                        self.close(expected, now_ms);
+                        self.open = Some((now_ms, position, volume));
+                    }
+                }
+            },
+            Mode::Paused => self.close(position, now_ms),
+            Mode::Done => {
+                self.close(position, now_ms);
+                self.done = true;
+                return Some(std::mem::take(&mut self.spans));
+            }
+        }
+        None
+    }
+
+    /// End the open span at `position`, or where it should have got to by
+    /// `now_ms` if `position` is not plausible for it.
+    fn close(&mut self, position: Duration, now_ms: i64) {
+        let Some((at_ms, from, volume)) = self.open.take() else {
+            return;
+        };
+        let expected = expected_position(at_ms, from, now_ms);
+        let to = if position.abs_diff(expected) > SEEK_TOLERANCE {
+            expected
+        } else {
+            position
+        };
+        if to > from {
+            self.spans.push(Span {
+                at_ms,
+                from,
+                to,
+                volume,
+            });
+        }
+    }
+}
+
+fn expected_position(at_ms: i64, from: Duration, now_ms: i64) -> Duration {
+    from + Duration::from_millis(now_ms.saturating_sub(at_ms).max(0) as u64)
+}
+
+/// Decode an Ogg/Opus clip into interleaved 48 kHz stereo, dropping its
+/// pre-skip.
+pub fn decode<R: Read + Seek>(clip: R) -> std::io::Result<Vec<i16>> {
+    use opus2::{Channels, Decoder};
+    let mut decoder = Decoder::new(48000, Channels::Stereo)
+        .map_err(|err| std::io::Error::other(format!("opus decoder init: {}", err)))?;
+
+    let mut reader = ogg::PacketReader::new(clip);
+    let head = reader
+        .read_packet_expected()
+        .map_err(std::io::Error::other)?;
+    if head.data.len() < 19 || &head.data[..8] != b"OpusHead" {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "clip is not Ogg/Opus",
+        ));
+    }
+    let mut skip = u16::from_le_bytes([head.data[10], head.data[11]]) as usize * 2;
+    reader
+        .read_packet_expected()
+        .map_err(std::io::Error::other)?; // OpusTags
+
+    let mut decoded = vec![0i16; MAX_DECODED_SAMPLES];
+    let mut pcm = Vec::new();
+    while let Some(p) = reader.read_packet().map_err(std::io::Error::other)? {
+        if p.data.is_empty() {
+            continue;
+        }
+        let per_channel = decoder
+            .decode(&p.data, &mut decoded, false)
+            .map_err(|err| std::io::Error::other(format!("opus decode: {}", err)))?;
+        let samples = &decoded[..per_channel * 2];
+        let dropped = skip.min(samples.len());
+        skip -= dropped;
+        pcm.extend_from_slice(&samples[dropped..]);
+    }
+    Ok(pcm)
+}
+
+/// Encode what was heard of `pcm` into 20 ms stereo frames on `writer`:
+/// each span's stretch at its volume, with silence for the time between
+/// spans. Returns the number of frames written; a partial frame before a
+/// gap or at the end is padded with silence.
+pub fn render<W: Write>(
+    pcm: &[i16],
+    spans: &[Span],
+    writer: &mut OggOpusWriter<W>,
+) -> std::io::Result<u64> {
+    use opus2::{Application, Channels, Encoder};
+    let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio)
+        .map_err(|err| std::io::Error::other(format!("opus encoder init: {}", err)))?;
+    let mut packet = vec![0u8; MAX_PACKET_BYTES];
+    let mut frames = 0u64;
+    let mut encode = |frame: &[i16], writer: &mut OggOpusWriter<W>| -> std::io::Result<()> {
+        let n = encoder
+            .encode(frame, &mut packet)
+            .map_err(|err| std::io::Error::other(format!("opus encode: {}", err)))?;
+        writer.write_packet(&packet[..n])
+    };
+
+    let Some(first) = spans.first() else {
+        return Ok(0);
+    };
+    let mut pending: Vec<i16> = Vec::with_capacity(FRAME_SAMPLES * 2);
+    let mut cursor_ms = first.at_ms;
+    for span in spans {
+        if span.at_ms - cursor_ms >= 20 {
+            if !pending.is_empty() {
+                pending.resize(FRAME_SAMPLES, 0);
+                encode(&pending, writer)?;
+                pending.clear();
+                frames += 1;
+                cursor_ms += 20;
+            }
+            let gap = ((span.at_ms - cursor_ms) / 20).max(0) as u64;
+            if gap > 0 {
+                writer.write_silence(gap)?;
+                frames += gap;
+                cursor_ms += gap as i64 * 20;
+            }
+        }
+
+        let start = sample_at(span.from).min(pcm.len());
+        let end = sample_at(span.to).clamp(start, pcm.len());
+        for &sample in &pcm[start..end] {
+            pending
+                .push((sample as f32 * span.volume).clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            if pending.len() == FRAME_SAMPLES {
+                encode(&pending, writer)?;
+                pending.clear();
+                frames += 1;
+                cursor_ms += 20;
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        pending.resize(FRAME_SAMPLES, 0);
+        encode(&pending, writer)?;
+        frames += 1;
+    }
+    Ok(frames)
+}
+
+/// Index of the first interleaved sample at clip position `at`.
+fn sample_at(at: Duration) -> usize {
+    at.as_millis() as usize * 48 * 2
+}
+
+/// Track event handler registered on a clip we enqueued. Every clone
+/// shares one [`PlayLog`]; the track is written once it is done.
+#[derive(Clone)]
+pub struct PlaybackRecorder {
+    pool: Pool<Postgres>,
+    guild_id: u64,
+    channel_id: u64,
+    bot_user_id: u64,
+    clip: ClipSource,
+    clip_name: String,
+    log: Arc<Mutex<PlayLog>>,
+}
+
+impl PlaybackRecorder {
+    pub fn new(
+        pool: Pool<Postgres>,
+        guild_id: u64,
+        channel_id: u64,
+        bot_user_id: u64,
+        clip: ClipSource,
+        clip_name: String,
+    ) -> Self {
+        Self {
+            pool,
+            guild_id,
+            channel_id,
+            bot_user_id,
+            clip,
+            clip_name,
+            log: Arc::default(),
+        }
+    }
+
+    async fn record(self, spans: Vec<Span>) {
+        // Stopped before a single frame was heard.
+        let Some(first) = spans.first() else {
+            return;
+        };
+        let start_ms = first.at_ms;
+        let Some(start) = chrono::DateTime::from_timestamp_millis(start_ms) else {
+            return;
+        };
+        let session_id = match sqlx::query_scalar!(
+            "SELECT id FROM voice_sessions
+              WHERE guild_id = $1 AND channel_id = $2 AND end_ts IS NULL
+              ORDER BY start_ts DESC LIMIT 1",
+            self.guild_id as i64,
+            self.channel_id as i64
+        )
+        .fetch_optional(&self.pool)
+        .await
+        {
+            Ok(id) => id,
+            Err(err) => {
+                error!("playback track session lookup failed: {}", err);
+                return;
+            }
+        };
+        // Nothing is being recorded in the channel; no track to line up with.
+        let Some(session_id) = session_id else {
+            return;
+        };
+
+        let file_name = RecordingKey::stem_for(start_ms, self.bot_user_id as i64);
+        let key = RecordingKey::new(
+            self.guild_id as i64,
+            self.channel_id as i64,
+            start.year(),
+            start.month(),
+            file_name.clone(),
+        );
+        let dir_path = key.recording_dir(RECORDING_ROOT);
+        let out_path = dir_path.join(format!("{}.ogg", file_name));
+        let tags = RecordingTags {
+            guild_id: self.guild_id,
+            channel_id: self.channel_id,
+            user_id: Some(self.bot_user_id),
+            user_name: Some(format!("playback: {}", self.clip_name)),
+            session_start_ms: start_ms,
+            ..Default::default()
+        };
+        let clip = self.clip.clone();
+
+        let written = tokio::task::spawn_blocking(move || -> std::io::Result<u64> {
+            let pcm = decode(std::io::BufReader::new(clip.open()?))?;
+            std::fs::create_dir_all(&dir_path)?;
+            let out = crate::encryption::writer(std::io::BufWriter::new(std::fs::File::create(
+                &out_path,
+            )?))?;
+            let mut writer =
+                OggOpusWriter::with_comments(out, start_ms as u32, 0, &tags.comments(start))?;
+            let frames = render(&pcm, &spans, &mut writer)?;
+            writer.finish()?;
+            Ok(frames)
+        })
+        .await;
+
+        let frames = match written {
+            Ok(Ok(frames)) => frames,
+            Ok(Err(err)) => {
+                error!(clip = %self.clip_name, "playback track write failed: {}", err);
+                return;
+            }
+            Err(err) => {
+                error!("playback track task failed: {}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = sqlx::query!(
+            "INSERT INTO audio_files
+                (file_name, guild_id, channel_id, user_id, year, month, start_ts, end_ts,
+                 state_enter, state_leave, is_bot_track, session_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, 2, 2, TRUE, $9)",
+            file_name,
+            self.guild_id as i64,
+            self.channel_id as i64,
+            self.bot_user_id as i64,
+            start.year(),
+            start.month() as i32,
+            start_ms,
+            start_ms + frames as i64 * 20,
+            session_id
+        )
+        .execute(&self.pool)
+        .await
+        {
+            error!("playback track insert failed: {}", err);
+            return;
+        }
+        info!(file_name = %file_name, frames, "playback track recorded");
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for PlaybackRecorder {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let (state, _) = tracks.first()?;
+        let mode = match state.playing {
+            PlayMode::Play => Mode::Playing,
+            PlayMode::Pause => Mode::Paused,
+            _ => Mode::Done,
+        };
+        let now_ms = chrono::Utc::now().timestamp_millis();
+        let spans = match self.log.lock() {
+            Ok(mut log) => log.observe(mode, state.position, state.volume, now_ms),
+            Err(_) => return Some(Event::Cancel),
+        };
+        if let Some(spans) = spans {
+            tokio::spawn(self.clone().record(spans));
+        }
+        (mode == Mode::Done).then_some(Event::Cancel)
+    }
+}
+
+/// Hook `track` up for recording if the guild wants its playback recorded.
+pub async fn record_on_play(
+    pool: &Pool<Postgres>,
+    track: &songbird::tracks::TrackHandle,
+    connection: Option<songbird::ConnectionInfo>,
+    guild_id: u64,
+    clip: ClipSource,
+    clip_name: String,
+) {
+    let Some(connection) = connection else {
+        return;
+    };
+    let Some(channel_id) = connection.channel_id else {
+        return;
+    };
+    if !crate::database::guild_settings::recording_settings(pool, guild_id)
+        .await
+        .record_playback
+    {
+        return;
+    }
+
+    let recorder = PlaybackRecorder::new(
+        pool.clone(),
+        guild_id,
+        channel_id.0.get(),
+        connection.user_id.0.get(),
+        clip,
+        clip_name,
+    );
+    let events = [
+        Event::Track(TrackEvent::Play),
+        Event::Track(TrackEvent::Pause),
+        Event::Track(TrackEvent::End),
+        Event::Track(TrackEvent::Error),
+        Event::Periodic(SAMPLE_EVERY, None),
+    ];
+    for event in events {
+        if let Err(err) = track.add_event(event, recorder.clone()) {
+            warn!("could not watch clip playback for recording: {}", err);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn clip(packets: usize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        use opus2::{Application, Channels, Encoder};
+        let mut enc = Encoder::new(48000, Channels::Stereo, Application::Audio)?;
+        let pcm = vec![1000i16; 1920 * 2]; // 40 ms
+        let mut packet = vec![0u8; MAX_PACKET_BYTES];
+
+        let mut clip = Vec::new();
+        {
+            let mut w = OggOpusWriter::new(Cursor::new(&mut clip), 1, 0)?;
+            for _ in 0..packets {
+                let n = enc.encode(&pcm, &mut packet)?;
+                w.write_packet(&packet[..n])?;
+            }
+            w.finish()?;
+        }
+        Ok(clip)
+    }
+
+    fn span(at_ms: i64, from_ms: u64, to_ms: u64) -> Span {
+        Span {
+            at_ms,
+            from: Duration::from_millis(from_ms),
+            to: Duration::from_millis(to_ms),
+            volume: 1.0,
+        }
+    }
+
+    #[test]
+    fn renders_long_packets_as_20ms_frames() -> Result<(), Box<dyn std::error::Error>> {
+        let pcm = decode(Cursor::new(clip(2)?))?;
+        assert_eq!(pcm.len(), 4 * FRAME_SAMPLES);
+
+        let mut out = Vec::new();
+        let mut w = OggOpusWriter::new(Cursor::new(&mut out), 2, 0)?;
+        let frames = render(&pcm, &[span(0, 0, 80)], &mut w)?;
+        assert_eq!(frames, 4);
+        assert_eq!(w.frames_written(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn pauses_become_silence_and_stops_cut_the_clip() -> Result<(), Box<dyn std::error::Error>> {
+        let pcm = decode(Cursor::new(clip(10)?))?;
+        let mut out = Vec::new();
+        let mut w = OggOpusWriter::new(Cursor::new(&mut out), 2, 0)?;
+        // 100 ms heard, 200 ms paused, then 60 ms more before a stop.
+        let frames = render(&pcm, &[span(0, 0, 100), span(300, 100, 160)], &mut w)?;
+        assert_eq!(frames, 5 + 10 + 3);
+        Ok(())
+    }
+
+    #[test]
+    fn play_log_follows_pause_seek_and_stop() {
+        let mut log = PlayLog::default();
+        let second = Duration::from_secs(1);
+        assert!(log.observe(Mode::Playing, Duration::ZERO, 1.0, 0).is_none());
+        assert!(log.observe(Mode::Playing, second, 1.0, 1000).is_none());
+        assert!(log.observe(Mode::Paused, 2 * second, 1.0, 2000).is_none());
+        assert!(log.observe(Mode::Playing, 2 * second, 1.0, 5000).is_none());
+        // Seeked to 10 s somewhere before this sample.
+        assert!(log.observe(Mode::Playing, 10 * second, 0.5, 6000).is_none());
+        let spans = log.observe(Mode::Done, 10 * second + second / 2, 0.5, 6500);
+        assert_eq!(
+            spans,
+            Some(vec![
+                span(0, 0, 2000),
+                span(5000, 2000, 3000),
+                Span {
+                    volume: 0.5,
+                    ..span(6000, 10_000, 10_500)
+                },
+            ])
+        );
+        assert!(log.observe(Mode::Done, second, 1.0, 7000).is_none());
+    }
+}
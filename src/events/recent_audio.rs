@@ -0,0 +1,140 @@
+//! The last minute of every live per-user recording, kept in memory.
+//!
+//! Each `UserRecording` mirrors the frames it writes into a [`RecentAudio`]
+//! ring: Opus packets as received (or rebuilt by loss recovery) and `None`
+//! for silence, so the ring covers wall-clock time even when the file is
+//! speech-only. The rings are registered in [`LiveAudio`] by guild and user
+//! for `/clip last`, and stay there while a recording is paused for a rejoin and
+//! across segment rotation.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use dashmap::DashMap;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
+
+/// 60 seconds of 20 ms frames: the longest `/clip last`.
+pub const MAX_FRAMES: usize = 60 * 50;
+
+pub struct RecentAudio {
+    frames: VecDeque<Option<Vec<u8>>>,
+    /// Stem of the recording file being written, updated on rotation.
+    file_name: String,
+}
+
+impl RecentAudio {
+    pub fn new(file_name: String) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(MAX_FRAMES),
+            file_name,
+        }
+    }
+
+    pub fn push_packet(&mut self, packet: &[u8]) {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(Some(packet.to_vec()));
+    }
+
+    pub fn push_silence(&mut self, frames: u64) {
+        let frames = frames.min(MAX_FRAMES as u64) as usize;
+        let overflow = (self.frames.len() + frames).saturating_sub(MAX_FRAMES);
+        self.frames.drain(..overflow);
+        self.frames.extend(std::iter::repeat_n(None, frames));
+    }
+
+    pub fn set_file_name(&mut self, file_name: String) {
+        self.file_name = file_name;
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// The last `frames` frames, oldest first, with silence at either end
+    /// trimmed off. Empty when nothing was said.
+    pub fn last(&self, frames: usize) -> Vec<Option<Vec<u8>>> {
+        let skip = self.frames.len().saturating_sub(frames);
+        let mut clip: Vec<_> = self.frames.iter().skip(skip).cloned().collect();
+        trim_silence(&mut clip);
+        clip
+    }
+}
+
+/// Drop the silent frames at either end of `frames`.
+pub fn trim_silence(frames: &mut Vec<Option<Vec<u8>>>) {
+    let end = frames
+        .iter()
+        .rposition(Option::is_some)
+        .map_or(0, |i| i + 1);
+    frames.truncate(end);
+    let start = frames.iter().position(Option::is_some).unwrap_or(0);
+    frames.drain(..start);
+}
+
+pub type SharedRecentAudio = Arc<Mutex<RecentAudio>>;
+
+/// Rings of the recordings this process is writing.
+#[derive(Default)]
+pub struct LiveAudio {
+    rings: DashMap<(u64, u64), SharedRecentAudio>,
+}
+
+impl LiveAudio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, guild_id: u64, user_id: u64, ring: SharedRecentAudio) {
+        self.rings.insert((guild_id, user_id), ring);
+    }
+
+    /// Drop `ring` unless a newer recording of the same user replaced it.
+    pub fn unregister(&self, guild_id: u64, user_id: u64, ring: &SharedRecentAudio) {
+        self.rings.remove_if(&(guild_id, user_id), |_, current| {
+            Arc::ptr_eq(current, ring)
+        });
+    }
+
+    pub fn get(&self, guild_id: u64, user_id: u64) -> Option<SharedRecentAudio> {
+        self.rings
+            .get(&(guild_id, user_id))
+            .map(|ring| ring.clone())
+    }
+}
+
+pub struct LiveAudioKey;
+impl TypeMapKey for LiveAudioKey {
+    type Value = Arc<LiveAudio>;
+}
+
+/// The shared [`LiveAudio`], if one was registered.
+pub async fn from_data(data: &RwLock<TypeMap>) -> Option<Arc<LiveAudio>> {
+    data.read().await.get::<LiveAudioKey>().cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_keeps_the_last_minute_and_trims_silence() {
+        let mut ring = RecentAudio::new("1760000000000-42".to_string());
+        ring.push_packet(&[1]);
+        ring.push_silence(MAX_FRAMES as u64 + 10);
+        assert!(ring.last(MAX_FRAMES).is_empty());
+
+        ring.push_packet(&[2]);
+        ring.push_silence(3);
+        ring.push_packet(&[3]);
+        ring.push_silence(5);
+        assert_eq!(ring.frames.len(), MAX_FRAMES);
+
+        let clip = ring.last(10);
+        assert_eq!(clip.len(), 5);
+        assert_eq!(clip.first(), Some(&Some(vec![2])));
+        assert_eq!(clip.last(), Some(&Some(vec![3])));
+        assert_eq!(ring.last(7).len(), 1);
+    }
+}
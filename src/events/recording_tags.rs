@@ -0,0 +1,166 @@
+//! Metadata carried by recordings.
+//!
+//! Identity and provenance go into the OpusTags header as Vorbis comments
+//! when the writer opens. Stamps only exist once the file is already being
+//! written, and the header cannot be rewritten in place, so they are emitted
+//! at finalize as a `<stem>.cue` sidecar next to the `.ogg`.
+
+use std::fmt::Write as _;
+
+/// Vorbis comment values for one recording file.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingTags {
+    pub guild_id: u64,
+    pub guild_name: Option<String>,
+    pub channel_id: u64,
+    pub channel_name: Option<String>,
+    /// Unset for the channel mixdown.
+    pub user_id: Option<u64>,
+    pub user_name: Option<String>,
+    pub session_start_ms: i64,
+    pub instance_id: String,
+}
+
+impl RecordingTags {
+    /// `KEY=value` comments for the OpusTags header of a file started at
+    /// `start_time`.
+    pub fn comments(&self, start_time: chrono::DateTime<chrono::Utc>) -> Vec<String> {
+        let mut comments = Vec::with_capacity(12);
+        let title = match (&self.user_name, &self.channel_name) {
+            (Some(user), Some(channel)) => Some(format!("{} in #{}", user, channel)),
+            (None, Some(channel)) => Some(format!("#{}", channel)),
+            (Some(user), None) => Some(user.clone()),
+            (None, None) => None,
+        };
+        if let Some(title) = title {
+            comments.push(format!("TITLE={}", title));
+        }
+        if let Some(user_name) = &self.user_name {
+            comments.push(format!("ARTIST={}", user_name));
+        }
+        if let Some(guild_name) = &self.guild_name {
+            comments.push(format!("ALBUM={}", guild_name));
+        }
+        comments.push(format!(
+            "DATE={}",
+            start_time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+        ));
+        comments.push(format!("GUILD_ID={}", self.guild_id));
+        if let Some(guild_name) = &self.guild_name {
+            comments.push(format!("GUILD_NAME={}", guild_name));
+        }
+        comments.push(format!("CHANNEL_ID={}", self.channel_id));
+        if let Some(channel_name) = &self.channel_name {
+            comments.push(format!("CHANNEL_NAME={}", channel_name));
+        }
+        if let Some(user_id) = self.user_id {
+            comments.push(format!("USER_ID={}", user_id));
+        }
+        if let Some(user_name) = &self.user_name {
+            comments.push(format!("USER_NAME={}", user_name));
+        }
+        comments.push(format!("SESSION_START={}", self.session_start_ms));
+        comments.push(format!("INSTANCE_ID={}", self.instance_id));
+        comments.push(format!("ENCODER=fbi-agent {}", env!("CARGO_PKG_VERSION")));
+        comments
+    }
+}
+
+/// One stamp positioned inside a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuePoint {
+    pub offset_ms: i64,
+    pub title: String,
+}
+
+/// Position of a stamp relative to the start of the file it landed in.
+pub fn stamp_offset_ms(file_start_ms: i64, stamp_ts: i64, rewind_ms: i32) -> i64 {
+    (stamp_ts + rewind_ms as i64 - file_start_ms).max(0)
+}
+
+/// Render a cue sheet for `audio_file` with one track per stamp. A leading
+/// track covers the audio before the first stamp so track numbers stay
+/// aligned with the file start.
+pub fn render_cue(audio_file: &str, title: &str, points: &[CuePoint]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "TITLE \"{}\"", cue_escape(title));
+    let _ = writeln!(out, "FILE \"{}\" WAVE", cue_escape(audio_file));
+
+    let mut track = 0;
+    if points.first().is_none_or(|p| p.offset_ms > 0) {
+        track += 1;
+        push_track(&mut out, track, "Start", 0);
+    }
+    for point in points {
+        track += 1;
+        push_track(&mut out, track, &point.title, point.offset_ms);
+    }
+    out
+}
+
+fn push_track(out: &mut String, track: usize, title: &str, offset_ms: i64) {
+    let _ = writeln!(out, "  TRACK {:02} AUDIO", track);
+    let _ = writeln!(out, "    TITLE \"{}\"", cue_escape(title));
+    let _ = writeln!(out, "    INDEX 01 {}", cue_time(offset_ms));
+}
+
+/// Cue sheet `mm:ss:ff` where `ff` counts 1/75 s CD frames.
+fn cue_time(offset_ms: i64) -> String {
+    let offset_ms = offset_ms.max(0);
+    let minutes = offset_ms / 60_000;
+    let seconds = (offset_ms / 1000) % 60;
+    let frames = (offset_ms % 1000) * 75 / 1000;
+    format!("{:02}:{:02}:{:02}", minutes, seconds, frames)
+}
+
+fn cue_escape(value: &str) -> String {
+    value.replace('"', "'").replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_include_ids_and_names() -> Result<(), Box<dyn std::error::Error>> {
+        let tags = RecordingTags {
+            guild_id: 1,
+            guild_name: Some("Guild".into()),
+            channel_id: 2,
+            channel_name: Some("general".into()),
+            user_id: Some(3),
+            user_name: Some("alice".into()),
+            session_start_ms: 1_700_000_000_000,
+            instance_id: "bot-1".into(),
+        };
+        let epoch = chrono::DateTime::from_timestamp_millis(0).ok_or("epoch out of range")?;
+        let comments = tags.comments(epoch);
+        assert!(comments.contains(&"TITLE=alice in #general".to_string()));
+        assert!(comments.contains(&"GUILD_ID=1".to_string()));
+        assert!(comments.contains(&"USER_NAME=alice".to_string()));
+        assert!(comments.contains(&"SESSION_START=1700000000000".to_string()));
+        assert!(comments.contains(&"DATE=1970-01-01T00:00:00.000Z".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn stamp_offsets_apply_rewind_and_clamp() {
+        assert_eq!(stamp_offset_ms(1_000, 31_000, -10_000), 20_000);
+        assert_eq!(stamp_offset_ms(1_000, 5_000, -10_000), 0);
+    }
+
+    #[test]
+    fn cue_lists_a_track_per_stamp() {
+        let cue = render_cue(
+            "1-2.ogg",
+            "alice \"in\" #general",
+            &[CuePoint {
+                offset_ms: 61_500,
+                title: "good bit".into(),
+            }],
+        );
+        assert!(cue.starts_with("TITLE \"alice 'in' #general\"\nFILE \"1-2.ogg\" WAVE\n"));
+        assert!(cue.contains("  TRACK 01 AUDIO\n    TITLE \"Start\"\n    INDEX 01 00:00:00\n"));
+        assert!(cue.contains("  TRACK 02 AUDIO\n    TITLE \"good bit\"\n    INDEX 01 01:01:37\n"));
+    }
+}
@@ -0,0 +1,350 @@
+//! Per-SSRC RTP bookkeeping that keeps a recording's granule timeline in step
+//! with the sender's RTP clock instead of with our tick cadence.
+//!
+//! Opus over RTP ticks at 48 kHz, so one 20 ms frame advances the RTP
+//! timestamp by 960. Every frame we write (packet or filler) advances
+//! `next_ts`; an incoming packet whose timestamp lands further ahead tells us
+//! how many frames went missing, and one that lands behind is either a late
+//! reorder (dropped) or a sender clock that stalled while we kept padding
+//! (resynced).
+//!
+//! Packets reach the timeline through a [`JitterBuffer`], which holds the
+//! newest few back so one that arrives out of order is put into its slot
+//! instead of being dropped behind a concealed gap. Ticks where the sender
+//! was speaking but its packet never came are left open for the next
+//! packet's timestamp gap to rebuild; if the sender goes quiet first they
+//! are concealed then, so the file does not fall behind.
+
+use std::collections::VecDeque;
+
+const RTP_TS_PER_FRAME: u32 = 960;
+
+/// What the writer should do with a packet after timeline bookkeeping.
+#[derive(Debug, Eq, PartialEq)]
+pub enum PacketAction {
+    /// Write `conceal_before` filler frames, then the packet itself.
+    Write { conceal_before: u64 },
+    /// Packet is a duplicate or arrived after a newer one was written.
+    Drop,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RtpTimeline {
+    last_seq: Option<u16>,
+    next_ts: Option<u32>,
+    /// Packets the sequence numbers say were sent but never reached us.
+    pub lost_packets: u64,
+    /// Filler frames written in place of missing audio.
+    pub concealed_frames: u64,
+    /// Packets dropped because they were duplicates or arrived too late.
+    pub reordered_packets: u64,
+}
+
+impl RtpTimeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the current RTP stream. Used when the user comes back with a
+    /// new SSRC, which starts fresh sequence/timestamp counters.
+    pub fn reset(&mut self) {
+        self.last_seq = None;
+        self.next_ts = None;
+    }
+
+    /// Zero the per-file counters while keeping the stream position, so a
+    /// rotated segment continues the same RTP stream with fresh statistics.
+    pub fn clear_counters(&mut self) {
+        self.lost_packets = 0;
+        self.concealed_frames = 0;
+        self.reordered_packets = 0;
+    }
+
+    pub fn on_packet(&mut self, seq: u16, ts: u32) -> PacketAction {
+        let (Some(last_seq), Some(next_ts)) = (self.last_seq, self.next_ts) else {
+            self.last_seq = Some(seq);
+            self.next_ts = Some(ts.wrapping_add(RTP_TS_PER_FRAME));
+            return PacketAction::Write { conceal_before: 0 };
+        };
+
+        let seq_delta = seq.wrapping_sub(last_seq) as i16;
+        if seq_delta <= 0 {
+            self.reordered_packets += 1;
+            return PacketAction::Drop;
+        }
+        self.last_seq = Some(seq);
+        self.lost_packets += (seq_delta - 1) as u64;
+
+        let ts_delta = ts.wrapping_sub(next_ts) as i32;
+        self.next_ts = Some(ts.wrapping_add(RTP_TS_PER_FRAME));
+        if ts_delta <= 0 {
+            // Already covered by filler we wrote while the user was silent.
+            return PacketAction::Write { conceal_before: 0 };
+        }
+
+        let gap = ts_delta as u64 / RTP_TS_PER_FRAME as u64;
+        self.concealed_frames += gap;
+        PacketAction::Write {
+            conceal_before: gap,
+        }
+    }
+
+    /// Account for `frames` of silence written outside `on_packet` (idle
+    /// ticks, rejoin/reconnect padding) so the expected timestamp keeps up.
+    pub fn on_silence(&mut self, frames: u64) {
+        if let Some(next_ts) = self.next_ts {
+            let advance = (frames as u32).wrapping_mul(RTP_TS_PER_FRAME);
+            self.next_ts = Some(next_ts.wrapping_add(advance));
+        }
+    }
+
+    /// Count filler frames written for wall-clock catch-up.
+    pub fn on_concealed(&mut self, frames: u64) {
+        self.concealed_frames += frames;
+        self.on_silence(frames);
+    }
+}
+
+/// A received packet waiting in the [`JitterBuffer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldPacket {
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+/// The slots of a stream not written yet: the last `depth` packets, kept in
+/// sequence order until newer ones push them out, and the ticks since the
+/// last packet whose packet never came.
+#[derive(Debug, Clone)]
+pub struct JitterBuffer {
+    depth: usize,
+    held: VecDeque<HeldPacket>,
+    missing: u64,
+}
+
+impl JitterBuffer {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            held: VecDeque::with_capacity(depth + 1),
+            missing: 0,
+        }
+    }
+
+    /// Frames taken but not written: held packets and missing ones.
+    pub fn pending(&self) -> u64 {
+        self.held.len() as u64 + self.missing
+    }
+
+    /// A tick the sender spoke in without its packet arriving.
+    pub fn skip(&mut self) {
+        self.missing += 1;
+    }
+
+    /// Put `packet` in sequence order. Returns false, keeping the held
+    /// copy, for a duplicate.
+    pub fn insert(&mut self, packet: HeldPacket) -> bool {
+        let mut at = self.held.len();
+        while at > 0 {
+            let delta = packet.sequence.wrapping_sub(self.held[at - 1].sequence) as i16;
+            if delta == 0 {
+                return false;
+            }
+            if delta > 0 {
+                break;
+            }
+            at -= 1;
+        }
+        self.held.insert(at, packet);
+        // Whatever went missing before it is now a gap in front of a
+        // packet, which the timeline fills when it is written.
+        self.missing = 0;
+        true
+    }
+
+    /// Packets pushed out of the window, oldest first.
+    pub fn release(&mut self) -> Vec<HeldPacket> {
+        let excess = self.held.len().saturating_sub(self.depth);
+        self.held.drain(..excess).collect()
+    }
+
+    /// Everything held, oldest first, and how many missing slots follow
+    /// the last of it.
+    pub fn drain(&mut self) -> (Vec<HeldPacket>, u64) {
+        (
+            self.held.drain(..).collect(),
+            std::mem::take(&mut self.missing),
+        )
+    }
+}
+
+/// How many 20 ms frames the file is behind wall-clock time, given the frames
+/// already written since `start_ms`.
+pub fn wallclock_deficit_frames(start_ms: i64, now_ms: i64, written_frames: u64) -> u64 {
+    if now_ms <= start_ms {
+        return 0;
+    }
+    let expected = ((now_ms - start_ms) as u64) / 20;
+    expected.saturating_sub(written_frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_order_packets_write_without_filler() {
+        let mut t = RtpTimeline::new();
+        assert_eq!(
+            t.on_packet(10, 1000),
+            PacketAction::Write { conceal_before: 0 }
+        );
+        assert_eq!(
+            t.on_packet(11, 1960),
+            PacketAction::Write { conceal_before: 0 }
+        );
+        assert_eq!(t.lost_packets, 0);
+        assert_eq!(t.concealed_frames, 0);
+    }
+
+    #[test]
+    fn missing_packets_are_filled_from_timestamp_delta() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(10, 0);
+        assert_eq!(
+            t.on_packet(13, 3 * 960),
+            PacketAction::Write { conceal_before: 2 }
+        );
+        assert_eq!(t.lost_packets, 2);
+        assert_eq!(t.concealed_frames, 2);
+    }
+
+    #[test]
+    fn late_and_duplicate_packets_are_dropped() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(10, 0);
+        t.on_packet(12, 2 * 960);
+        assert_eq!(t.on_packet(11, 960), PacketAction::Drop);
+        assert_eq!(t.on_packet(12, 2 * 960), PacketAction::Drop);
+        assert_eq!(t.reordered_packets, 2);
+    }
+
+    #[test]
+    fn silence_ticks_advance_expected_timestamp() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(1, 0);
+        t.on_silence(49);
+        // Sender resumes one second after its first packet: already covered.
+        assert_eq!(
+            t.on_packet(2, 50 * 960),
+            PacketAction::Write { conceal_before: 0 }
+        );
+        assert_eq!(t.concealed_frames, 0);
+    }
+
+    #[test]
+    fn sequence_and_timestamp_wrap_around() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(u16::MAX, u32::MAX - 959);
+        assert_eq!(
+            t.on_packet(1, 960),
+            PacketAction::Write { conceal_before: 1 }
+        );
+        assert_eq!(t.lost_packets, 1);
+    }
+
+    #[test]
+    fn reset_starts_a_fresh_stream() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(500, 100_000);
+        t.reset();
+        assert_eq!(t.on_packet(3, 7), PacketAction::Write { conceal_before: 0 });
+    }
+
+    #[test]
+    fn clearing_counters_keeps_stream_position() {
+        let mut t = RtpTimeline::new();
+        t.on_packet(1, 0);
+        t.on_packet(3, 2 * 960);
+        t.clear_counters();
+        assert_eq!(t.lost_packets, 0);
+        assert_eq!(
+            t.on_packet(4, 3 * 960),
+            PacketAction::Write { conceal_before: 0 }
+        );
+    }
+
+    fn held(sequence: u16) -> HeldPacket {
+        HeldPacket {
+            sequence,
+            timestamp: sequence as u32 * 960,
+            payload: vec![sequence as u8],
+        }
+    }
+
+    fn sequences(packets: &[HeldPacket]) -> Vec<u16> {
+        packets.iter().map(|p| p.sequence).collect()
+    }
+
+    #[test]
+    fn jitter_buffer_puts_a_late_packet_into_its_slot() {
+        let mut buffer = JitterBuffer::new(2);
+        let mut t = RtpTimeline::new();
+        let mut written = Vec::new();
+        for sequence in [10, 12, 11, 13, 14] {
+            assert!(buffer.insert(held(sequence)));
+            for packet in buffer.release() {
+                assert_eq!(
+                    t.on_packet(packet.sequence, packet.timestamp),
+                    PacketAction::Write { conceal_before: 0 }
+                );
+                written.push(packet.sequence);
+            }
+        }
+        assert_eq!(written, [10, 11, 12]);
+        let (rest, missing) = buffer.drain();
+        assert_eq!((sequences(&rest), missing), (vec![13, 14], 0));
+        assert_eq!((t.lost_packets, t.reordered_packets), (0, 0));
+    }
+
+    #[test]
+    fn jitter_buffer_rejects_duplicates_and_orders_across_wrap() {
+        let mut buffer = JitterBuffer::new(3);
+        assert!(buffer.insert(held(1)));
+        assert!(buffer.insert(held(u16::MAX)));
+        assert!(buffer.insert(held(0)));
+        assert!(!buffer.insert(held(0)));
+        assert_eq!(buffer.pending(), 3);
+        assert!(buffer.release().is_empty());
+        assert_eq!(sequences(&buffer.drain().0), [u16::MAX, 0, 1]);
+        assert_eq!(buffer.pending(), 0);
+    }
+
+    #[test]
+    fn missing_slots_wait_for_a_packet_or_the_drain() {
+        let mut buffer = JitterBuffer::new(2);
+        buffer.insert(held(1));
+        buffer.skip();
+        buffer.skip();
+        assert_eq!(buffer.pending(), 3);
+        // A packet after the gap: the timeline rebuilds it from timestamps.
+        buffer.insert(held(4));
+        assert_eq!(buffer.pending(), 2);
+
+        // The sender goes quiet with slots still open: they come back with
+        // the drain so they can be concealed.
+        buffer.skip();
+        let (held, missing) = buffer.drain();
+        assert_eq!((sequences(&held), missing), (vec![1, 4], 1));
+        assert_eq!(buffer.drain().1, 0);
+    }
+
+    #[test]
+    fn wallclock_deficit_counts_missing_frames() {
+        assert_eq!(wallclock_deficit_frames(0, 1000, 50), 0);
+        assert_eq!(wallclock_deficit_frames(0, 1000, 40), 10);
+        assert_eq!(wallclock_deficit_frames(0, 1000, 60), 0);
+        assert_eq!(wallclock_deficit_frames(1000, 0, 0), 0);
+    }
+}
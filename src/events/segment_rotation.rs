@@ -0,0 +1,75 @@
+//! Rotation of long per-user recordings into consecutive segments.
+//!
+//! When a segment reaches the configured length or size, its writer is
+//! finished and a new file continues at the exact sample the old one ended
+//! on: the new segment's `start_ts` is the old start plus its written
+//! frames, so stitching the segments back together reproduces one
+//! continuous timeline. Each new `audio_files` row points at the row it
+//! continues via `previous_file_id`.
+
+/// 20 ms per Opus frame.
+const FRAME_MS: u64 = 20;
+
+/// `audio_files.state_enter` / `state_leave` for a segment boundary.
+pub const STATE_ROTATED: i32 = 4;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SegmentLimits {
+    pub max_ms: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl SegmentLimits {
+    pub fn from_env() -> Self {
+        Self {
+            max_ms: crate::config::recording_segment_max_ms(),
+            max_bytes: crate::config::recording_segment_max_bytes(),
+        }
+    }
+
+    /// Whether a segment with this much audio written should be rotated.
+    pub fn due(&self, frames_written: u64, bytes_written: u64) -> bool {
+        self.max_ms
+            .is_some_and(|max_ms| frames_written * FRAME_MS >= max_ms)
+            || self.max_bytes.is_some_and(|max| bytes_written >= max)
+    }
+}
+
+/// Start of the segment that continues one started at `start_time` after
+/// `frames_written` frames.
+pub fn next_segment_start(
+    start_time: chrono::DateTime<chrono::Utc>,
+    frames_written: u64,
+) -> chrono::DateTime<chrono::Utc> {
+    start_time + chrono::Duration::milliseconds((frames_written * FRAME_MS) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_limits_never_rotate() {
+        let limits = SegmentLimits::default();
+        assert!(!limits.due(u64::MAX / FRAME_MS, u64::MAX));
+    }
+
+    #[test]
+    fn rotates_on_duration_or_size() {
+        let limits = SegmentLimits {
+            max_ms: Some(60 * 60_000),
+            max_bytes: Some(1024),
+        };
+        assert!(!limits.due(10, 100));
+        assert!(limits.due(180_000, 100));
+        assert!(limits.due(10, 1024));
+    }
+
+    #[test]
+    fn next_segment_starts_where_previous_ended() -> Result<(), Box<dyn std::error::Error>> {
+        let start = chrono::DateTime::from_timestamp_millis(1_000).ok_or("out of range")?;
+        let next = next_segment_start(start, 3_000);
+        assert_eq!(next.timestamp_millis(), 61_000);
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Optional per-channel mixdown track written next to the per-user files.
+//!
+//! On every `VoiceTick` the decoded PCM of each recorded speaker is summed
+//! into one stereo frame, re-encoded to Opus and appended to
+//! `<session>-mix.ogg`. Ticks where nobody spoke get the cached silence frame,
+//! so the mix keeps the same 20 ms-per-tick timeline as the per-user files.
+//! A mix opened after the session began starts with silence back to the
+//! session start, which its name and `start_ts` refer to.
+
+use crate::events::disk_writer::QueuedFile;
+use crate::events::ogg_opus_writer::OggOpusWriter;
+
+/// Interleaved stereo samples in one 20 ms frame at 48 kHz.
+const FRAME_SAMPLES: usize = 960 * 2;
+/// Largest Opus packet we ever expect for a 20 ms stereo frame.
+const MAX_PACKET_BYTES: usize = 4000;
+
+/// Stem for the mix file of a session that started at `session_start_ms`.
+/// `reopened` counts earlier mixes of the same session (one closed after a
+/// write error); the first mix is plain `<start>-mix`.
+pub fn stem_for(session_start_ms: i64, reopened: u32) -> String {
+    match reopened {
+        0 => format!("{}-mix", session_start_ms),
+        n => format!("{}-mix-{}", session_start_ms, n + 1),
+    }
+}
+
+/// Ogg stream serial for a session's mix, derived from the channel and
+/// session so mixes of different sessions don't share one. Each reopened mix
+/// of a session gets the next serial.
+pub fn serial_for(channel_id: u64, session_id: i64, reopened: u32) -> u32 {
+    let mixed = channel_id ^ (session_id as u64).rotate_left(32);
+    (mixed as u32 ^ (mixed >> 32) as u32).wrapping_add(reopened)
+}
+
+/// Session start and reopen count of a mix stem, as given to [`stem_for`].
+pub fn parse_stem(stem: &str) -> Option<(i64, u32)> {
+    let (start, rest) = stem.split_once("-mix")?;
+    let session_start_ms = start.parse().ok()?;
+    let reopened = match rest {
+        "" => 0,
+        n => n.strip_prefix('-')?.parse::<u32>().ok()?.checked_sub(1)?,
+    };
+    (stem_for(session_start_ms, reopened) == stem).then_some((session_start_ms, reopened))
+}
+
+pub struct SessionMix {
+    writer: OggOpusWriter<QueuedFile>,
+    encoder: opus2::Encoder,
+    packet: Vec<u8>,
+    pub file_name: String,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+}
+
+impl SessionMix {
+    pub fn new(
+        file: QueuedFile,
+        serial: u32,
+        file_name: String,
+        start_time: chrono::DateTime<chrono::Utc>,
+        comments: &[String],
+    ) -> std::io::Result<Self> {
+        use opus2::{Application, Channels, Encoder};
+        let encoder = Encoder::new(48000, Channels::Stereo, Application::Audio)
+            .map_err(|err| std::io::Error::other(format!("opus encoder init: {}", err)))?;
+        let writer = OggOpusWriter::with_comments(file, serial, 0, comments)?;
+        Ok(Self {
+            writer,
+            encoder,
+            packet: vec![0u8; MAX_PACKET_BYTES],
+            file_name,
+            start_time,
+        })
+    }
+
+    /// Mix one tick worth of decoded speaker frames and append it.
+    pub fn write_tick(&mut self, voices: &[Vec<i16>]) -> std::io::Result<()> {
+        if voices.is_empty() {
+            return self.writer.write_silence(1);
+        }
+        let pcm = mix_frames(voices);
+        let n = self
+            .encoder
+            .encode(&pcm, &mut self.packet)
+            .map_err(|err| std::io::Error::other(format!("encode mix frame: {}", err)))?;
+        self.writer.write_packet(&self.packet[..n])
+    }
+
+    pub fn write_silence(&mut self, count: u64) -> std::io::Result<()> {
+        self.writer.write_silence(count)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.writer.finish()
+    }
+}
+
+/// Sum interleaved stereo frames. When the sum would clip, the whole frame is
+/// scaled down so its peak lands on full scale instead of hard-clipping.
+pub fn mix_frames(voices: &[Vec<i16>]) -> Vec<i16> {
+    let mut acc = vec![0i32; FRAME_SAMPLES];
+    for voice in voices {
+        for (slot, sample) in acc.iter_mut().zip(voice.iter()) {
+            *slot += *sample as i32;
+        }
+    }
+
+    let peak = acc.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    if peak <= i16::MAX as u32 {
+        return acc.into_iter().map(|s| s as i16).collect();
+    }
+
+    let gain = i16::MAX as f32 / peak as f32;
+    acc.into_iter()
+        .map(|s| (s as f32 * gain).round() as i16)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_sums_without_clipping() {
+        let a = vec![100i16; FRAME_SAMPLES];
+        let b = vec![-40i16; FRAME_SAMPLES];
+        let mixed = mix_frames(&[a, b]);
+        assert_eq!(mixed.len(), FRAME_SAMPLES);
+        assert!(mixed.iter().all(|s| *s == 60));
+    }
+
+    #[test]
+    fn mix_scales_frame_instead_of_hard_clipping() {
+        let mut a = vec![0i16; FRAME_SAMPLES];
+        let mut b = vec![0i16; FRAME_SAMPLES];
+        a[0] = 30_000;
+        b[0] = 30_000;
+        a[1] = 1_000;
+        let mixed = mix_frames(&[a, b]);
+        assert_eq!(mixed[0], i16::MAX);
+        // Quieter samples keep their relative level.
+        assert!(mixed[1] > 0 && mixed[1] < 1_000);
+    }
+
+    #[test]
+    fn short_voice_frames_are_zero_padded() {
+        let mixed = mix_frames(&[vec![5i16; 10]]);
+        assert_eq!(mixed.len(), FRAME_SAMPLES);
+        assert_eq!(mixed[9], 5);
+        assert_eq!(mixed[10], 0);
+    }
+
+    #[test]
+    fn mix_stem_is_suffixed() {
+        assert_eq!(stem_for(1700000000000, 0), "1700000000000-mix");
+        assert_eq!(stem_for(1700000000000, 1), "1700000000000-mix-2");
+    }
+
+    #[test]
+    fn mix_serials_follow_the_session() {
+        let channel = 1_234_567_890_123_456_789;
+        assert_eq!(serial_for(channel, 42, 0), serial_for(channel, 42, 0));
+        assert_ne!(serial_for(channel, 42, 0), serial_for(channel, 43, 0));
+        assert_eq!(
+            serial_for(channel, 42, 1),
+            serial_for(channel, 42, 0).wrapping_add(1)
+        );
+    }
+
+    #[test]
+    fn mix_stems_parse_back() {
+        assert_eq!(parse_stem("1700000000000-mix"), Some((1700000000000, 0)));
+        assert_eq!(parse_stem("1700000000000-mix-3"), Some((1700000000000, 2)));
+        assert_eq!(parse_stem("1700000000000-mix-1"), None);
+        assert_eq!(parse_stem("1700000000000-mix-03"), None);
+        assert_eq!(parse_stem("1700000000000-42"), None);
+    }
+}
@@ -0,0 +1,301 @@
+//! Speech-only ("sparse") recording storage.
+//!
+//! In sparse mode the `.ogg` holds only packets that were actually written
+//! as audio; silence never reaches disk. A `<stem>.idx` sidecar records
+//! where on the recording's logical timeline each run of consecutive packets
+//! belongs, so the padded file (and with it the alignment to the session
+//! start shared by every per-user file) can be rebuilt on demand.
+//!
+//! Index layout, little endian:
+//!
+//! ```text
+//! header:  b"SKIX" | version u8 | start_ms i64
+//! record:  logical_start_frame u64 | frames u32     (repeated)
+//! trailer: logical_end_frame u64   | 0u32           (written on finish)
+//! ```
+//!
+//! Records are appended as runs end, so a file cut short by a crash still
+//! has an index covering everything up to its last silence.
+
+use std::io::{Read, Seek, Write};
+
+use crate::events::ogg_opus_writer::OggOpusWriter;
+
+const INDEX_MAGIC: &[u8; 4] = b"SKIX";
+const INDEX_VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 8;
+const RECORD_LEN: usize = 8 + 4;
+
+/// Appends run records for a sparse recording as packets are written.
+pub struct SparseIndex<W: Write> {
+    out: W,
+    run_start: Option<u64>,
+    run_frames: u32,
+}
+
+impl<W: Write> SparseIndex<W> {
+    /// Start an index for a recording whose logical frame 0 is `start_ms`.
+    pub fn new(mut out: W, start_ms: i64) -> std::io::Result<Self> {
+        out.write_all(INDEX_MAGIC)?;
+        out.write_all(&[INDEX_VERSION])?;
+        out.write_all(&start_ms.to_le_bytes())?;
+        Ok(Self {
+            out,
+            run_start: None,
+            run_frames: 0,
+        })
+    }
+
+    /// Note a packet written at `logical_frame`.
+    pub fn on_packet(&mut self, logical_frame: u64) -> std::io::Result<()> {
+        match self.run_start {
+            Some(start) if start + self.run_frames as u64 == logical_frame => {
+                self.run_frames += 1;
+                Ok(())
+            }
+            _ => {
+                self.close_run()?;
+                self.run_start = Some(logical_frame);
+                self.run_frames = 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Write out the current run, if any.
+    pub fn close_run(&mut self) -> std::io::Result<()> {
+        if let Some(start) = self.run_start.take() {
+            self.write_record(start, self.run_frames)?;
+            self.run_frames = 0;
+        }
+        Ok(())
+    }
+
+    /// Close the last run and record the logical length of the recording.
+    pub fn finish(&mut self, logical_end: u64) -> std::io::Result<()> {
+        self.close_run()?;
+        self.write_record(logical_end, 0)?;
+        self.out.flush()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_record(&mut self, logical_start: u64, frames: u32) -> std::io::Result<()> {
+        self.out.write_all(&record(logical_start, frames))
+    }
+}
+
+fn record(logical_start: u64, frames: u32) -> [u8; RECORD_LEN] {
+    let mut out = [0u8; RECORD_LEN];
+    out[..8].copy_from_slice(&logical_start.to_le_bytes());
+    out[8..].copy_from_slice(&frames.to_le_bytes());
+    out
+}
+
+/// The trailer record [`SparseIndex::finish`] writes.
+pub fn trailer(logical_end: u64) -> [u8; RECORD_LEN] {
+    record(logical_end, 0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Run {
+    pub logical_start: u64,
+    pub frames: u32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedIndex {
+    pub start_ms: i64,
+    pub runs: Vec<Run>,
+    /// Missing when the recording was never finished.
+    pub logical_end: Option<u64>,
+}
+
+impl ParsedIndex {
+    /// Logical length in frames; falls back to the end of the last run.
+    pub fn logical_frames(&self) -> u64 {
+        self.logical_end.unwrap_or_else(|| {
+            self.runs
+                .last()
+                .map(|run| run.logical_start + run.frames as u64)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Bytes of the index covered by the header and whole records, trailer
+    /// included when present.
+    pub fn byte_len(&self) -> usize {
+        let records = self.runs.len() + self.logical_end.is_some() as usize;
+        HEADER_LEN + records * RECORD_LEN
+    }
+}
+
+pub fn parse_index(bytes: &[u8]) -> std::io::Result<ParsedIndex> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != INDEX_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a sparse recording index",
+        ));
+    }
+    if bytes[4] != INDEX_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unsupported sparse index version {}", bytes[4]),
+        ));
+    }
+    let start_ms = i64::from_le_bytes(read_array(&bytes[5..HEADER_LEN]));
+
+    let mut runs = Vec::new();
+    let mut logical_end = None;
+    // A trailing partial record is what a crash mid-write leaves; ignore it.
+    for record in bytes[HEADER_LEN..].chunks_exact(RECORD_LEN) {
+        let logical_start = u64::from_le_bytes(read_array(&record[..8]));
+        let frames = u32::from_le_bytes(read_array(&record[8..]));
+        if frames == 0 {
+            logical_end = Some(logical_start);
+            break;
+        }
+        runs.push(Run {
+            logical_start,
+            frames,
+        });
+    }
+
+    Ok(ParsedIndex {
+        start_ms,
+        runs,
+        logical_end,
+    })
+}
+
+fn read_array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut out = [0u8; N];
+    out.copy_from_slice(bytes);
+    out
+}
+
+/// Rebuild the padded recording from a sparse `.ogg` and its index,
+/// writing silence wherever the index has no packets. Returns the number
+/// of frames written.
+pub fn expand<R: Read + Seek, W: Write>(
+    sparse: R,
+    index: &ParsedIndex,
+    out: W,
+) -> std::io::Result<u64> {
+    let mut reader = ogg::PacketReader::new(sparse);
+    let head = reader
+        .read_packet_expected()
+        .map_err(std::io::Error::other)?;
+    let tags = reader
+        .read_packet_expected()
+        .map_err(std::io::Error::other)?;
+    let mut writer = OggOpusWriter::from_headers(out, head.stream_serial(), head.data, tags.data)?;
+
+    'runs: for run in &index.runs {
+        let pad = run.logical_start.saturating_sub(writer.frames_written());
+        writer.write_silence(pad)?;
+        for _ in 0..run.frames {
+            match reader.read_packet().map_err(std::io::Error::other)? {
+                Some(packet) if !packet.data.is_empty() => writer.write_packet(&packet.data)?,
+                // Truncated recording: pad the rest from the index.
+                _ => break 'runs,
+            }
+        }
+    }
+
+    let pad = index
+        .logical_frames()
+        .saturating_sub(writer.frames_written());
+    writer.write_silence(pad)?;
+    writer.finish()?;
+    Ok(writer.frames_written())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn index_records_runs_and_trailer() -> Result<(), Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        {
+            let mut index = SparseIndex::new(&mut buf, 42)?;
+            index.on_packet(3)?;
+            index.on_packet(4)?;
+            index.on_packet(10)?;
+            index.finish(20)?;
+        }
+        let parsed = parse_index(&buf)?;
+        assert_eq!(parsed.start_ms, 42);
+        assert_eq!(
+            parsed.runs,
+            vec![
+                Run {
+                    logical_start: 3,
+                    frames: 2
+                },
+                Run {
+                    logical_start: 10,
+                    frames: 1
+                },
+            ]
+        );
+        assert_eq!(parsed.logical_end, Some(20));
+        Ok(())
+    }
+
+    #[test]
+    fn unfinished_index_ends_at_last_run() -> Result<(), Box<dyn std::error::Error>> {
+        let mut buf = Vec::new();
+        {
+            let mut index = SparseIndex::new(&mut buf, 0)?;
+            index.on_packet(5)?;
+            index.close_run()?;
+        }
+        buf.extend_from_slice(&[1, 2, 3]); // torn record
+        let parsed = parse_index(&buf)?;
+        assert_eq!(parsed.logical_end, None);
+        assert_eq!(parsed.logical_frames(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn expand_restores_padded_timeline() -> Result<(), Box<dyn std::error::Error>> {
+        let mut ogg = Vec::new();
+        let mut idx = Vec::new();
+        {
+            let index = SparseIndex::new(Cursor::new(&mut idx), 0)?;
+            let mut w = OggOpusWriter::new(Cursor::new(&mut ogg), 9, 0)?.into_sparse(index);
+            w.write_silence(50)?;
+            w.write_packet(&[0xAA])?;
+            w.write_packet(&[0xBB])?;
+            w.write_silence(25)?;
+            w.finish()?;
+            assert_eq!(w.frames_written(), 77);
+            assert_eq!(w.granule(), 2 * 960);
+        }
+
+        let index = parse_index(&idx)?;
+        let mut padded = Vec::new();
+        let frames = expand(Cursor::new(&ogg), &index, Cursor::new(&mut padded))?;
+        assert_eq!(frames, 77);
+
+        let mut reader = ogg::PacketReader::new(Cursor::new(&padded));
+        reader.read_packet_expected()?;
+        reader.read_packet_expected()?;
+        let mut packets = Vec::new();
+        while let Some(p) = reader.read_packet()? {
+            if p.data.is_empty() {
+                break;
+            }
+            packets.push(p.data);
+        }
+        assert_eq!(packets.len(), 77);
+        assert_eq!(packets[50], vec![0xAA]);
+        assert_eq!(packets[51], vec![0xBB]);
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! Speaking-activity detection for per-user recordings.
+//!
+//! Every tick tells us whether a user sent audio. Voiced frames are grouped
+//! into segments; a pause shorter than the hangover keeps the segment open,
+//! and segments shorter than the minimum length (coughs, key clicks) are
+//! dropped. Frame numbers are positions on the recording's timeline, so a
+//! segment maps straight onto the file.
+
+/// 20 ms per Opus frame.
+const FRAME_MS: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeechSegment {
+    pub start_frame: u64,
+    /// Exclusive: the first frame after the last voiced one.
+    pub end_frame: u64,
+    /// Highest absolute sample level seen, 0.0–1.0 of full scale.
+    pub peak_level: f32,
+}
+
+impl SpeechSegment {
+    /// Wall-clock bounds for a file whose frame 0 is `file_start_ms`.
+    pub fn wallclock_ms(&self, file_start_ms: i64) -> (i64, i64) {
+        (
+            file_start_ms + (self.start_frame * FRAME_MS) as i64,
+            file_start_ms + (self.end_frame * FRAME_MS) as i64,
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpeechDetector {
+    hangover_frames: u64,
+    min_frames: u64,
+    open: Option<SpeechSegment>,
+}
+
+impl SpeechDetector {
+    pub fn new(hangover_ms: u64, min_ms: u64) -> Self {
+        Self {
+            hangover_frames: hangover_ms.div_ceil(FRAME_MS),
+            min_frames: min_ms.div_ceil(FRAME_MS),
+            open: None,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            crate::config::speech_hangover_ms(),
+            crate::config::speech_min_length_ms(),
+        )
+    }
+
+    /// Feed the frame at `frame`. Returns a segment once the silence after
+    /// it outlasts the hangover.
+    pub fn on_frame(&mut self, frame: u64, voiced: bool, peak_level: f32) -> Option<SpeechSegment> {
+        if voiced {
+            match self.open.as_mut() {
+                Some(segment) => {
+                    segment.end_frame = frame + 1;
+                    segment.peak_level = segment.peak_level.max(peak_level);
+                }
+                None => {
+                    self.open = Some(SpeechSegment {
+                        start_frame: frame,
+                        end_frame: frame + 1,
+                        peak_level,
+                    });
+                }
+            }
+            return None;
+        }
+
+        match self.open {
+            Some(segment) if frame >= segment.end_frame + self.hangover_frames => self.finish(),
+            _ => None,
+        }
+    }
+
+    /// Close the open segment, e.g. when the file ends.
+    pub fn finish(&mut self) -> Option<SpeechSegment> {
+        let segment = self.open.take()?;
+        (segment.end_frame - segment.start_frame >= self.min_frames).then_some(segment)
+    }
+}
+
+/// Peak of an interleaved PCM frame as a fraction of full scale.
+pub fn peak_level(pcm: &[i16]) -> f32 {
+    let peak = pcm.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    peak as f32 / 32768.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_pauses_stay_in_one_segment() {
+        let mut d = SpeechDetector::new(100, 0);
+        for frame in 0..10 {
+            assert_eq!(d.on_frame(frame, true, 0.1), None);
+        }
+        // 3 silent frames < 5-frame hangover.
+        for frame in 10..13 {
+            assert_eq!(d.on_frame(frame, false, 0.0), None);
+        }
+        d.on_frame(13, true, 0.5);
+        for frame in 14..18 {
+            assert_eq!(d.on_frame(frame, false, 0.0), None);
+        }
+        let segment = d.on_frame(19, false, 0.0);
+        assert_eq!(
+            segment,
+            Some(SpeechSegment {
+                start_frame: 0,
+                end_frame: 14,
+                peak_level: 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn segments_below_minimum_length_are_dropped() {
+        let mut d = SpeechDetector::new(20, 200);
+        d.on_frame(0, true, 0.2);
+        d.on_frame(1, true, 0.2);
+        assert_eq!(d.on_frame(5, false, 0.0), None);
+        assert_eq!(d.finish(), None);
+    }
+
+    #[test]
+    fn finish_flushes_open_segment() {
+        let mut d = SpeechDetector::new(300, 20);
+        d.on_frame(50, true, 0.3);
+        let segment = d.finish().map(|s| s.wallclock_ms(1_000));
+        assert_eq!(segment, Some((2_000, 2_020)));
+    }
+
+    #[test]
+    fn peak_level_is_fraction_of_full_scale() {
+        assert_eq!(peak_level(&[]), 0.0);
+        assert_eq!(peak_level(&[0, -16384, 100]), 0.5);
+        assert_eq!(peak_level(&[i16::MIN]), 1.0);
+    }
+}
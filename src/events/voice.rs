@@ -57,8 +57,11 @@ pub(super) async fn insert_voice_event(
     event_type_id: i32,
 ) {
     if let Err(err) = sqlx::query!(
-        "INSERT INTO voice_state_events (guild_id, channel_id, user_id, event_type_id) \
-         VALUES ($1, $2, $3, $4)",
+        "INSERT INTO voice_state_events (guild_id, channel_id, user_id, event_type_id, session_id) \
+         VALUES ($1, $2, $3, $4, \
+                 (SELECT id FROM voice_sessions \
+                   WHERE guild_id = $1 AND channel_id = $2 AND end_ts IS NULL \
+                   ORDER BY start_ts DESC LIMIT 1))",
         guild_id,
         channel_id,
         user_id,
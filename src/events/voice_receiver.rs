@@ -13,19 +13,39 @@ use songbird::{
 };
 use sqlx::{Pool, Postgres};
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::BufWriter;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering};
 use tokio::sync::{Mutex, RwLock};
 use tracing::{debug, error, info, warn};
 
+use crate::database::{consent, recording_policy};
+use crate::events::disk_writer::{DiskWriter, Provisional, QueuedFile};
+use crate::events::loss_recovery::{self, LossRecovery};
 use crate::events::ogg_opus_writer::OggOpusWriter;
+use crate::events::recent_audio::{LiveAudio, LiveAudioKey, RecentAudio, SharedRecentAudio};
+use crate::events::recording_tags::{self, CuePoint, RecordingTags};
+use crate::events::rtp_timeline::{
+    HeldPacket, JitterBuffer, PacketAction, RtpTimeline, wallclock_deficit_frames,
+};
+use crate::events::segment_rotation::{self, SegmentLimits};
+use crate::events::session_mix::{self, SessionMix};
+use crate::events::sparse_index::SparseIndex;
+use crate::events::speech_segments::{self, SpeechDetector};
+use crate::storage_guard::{StorageGuard, StorageGuardKey};
 
 pub const RECORDING_FILE_PATH: &str = RECORDING_ROOT;
 pub const CLIPS_FILE_PATH: &str = CLIPS_ROOT;
 const RECOVERABLE_DISCONNECT_TIMEOUT_MS: u64 = 60_000;
 const USER_REJOIN_RESUME_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+/// How far (in 20 ms frames) a file may fall behind wall-clock time before
+/// the tick handler pads it back into line. Covers late ticks and runtime
+/// stalls without reacting to ordinary scheduling jitter.
+const WALLCLOCK_DRIFT_TOLERANCE_FRAMES: u64 = 10;
+/// One minute of audio between attempts when rotating a segment fails.
+const ROTATION_RETRY_FRAMES: u64 = 50 * 60;
+/// Packets (20 ms each) held back before writing so one that arrives out of
+/// order still lands in its slot.
+const JITTER_FRAMES: usize = 2;
 // Without this only way to test if pray discord randomly disconncts our bot. Need to manually toggle
 // CAVEAT: this includes bot self disconnects
 // TODO: Remote toggle for easier testing. No need to recompile
@@ -38,16 +58,225 @@ pub enum VoiceEventType {
     WriterClose = 2,
     WriterError = 3,
     ZombieReaped = 4,
+    ConsentOptOut = 5,
+    ConsentOptIn = 6,
+    UserDataExported = 7,
+    UserDataErased = 8,
+    DiskFull = 9,
 }
 
 /// One per-user recording: the streaming writer plus the metadata needed to
 /// finalize the audio_files row when the writer closes.
 struct UserRecording {
-    writer: OggOpusWriter<BufWriter<File>>,
+    writer: OggOpusWriter<QueuedFile>,
     file_name: String,
     start_time: chrono::DateTime<chrono::Utc>,
     user_id: u64,
     ssrc: u32,
+    timeline: RtpTimeline,
+    /// Received packets not written yet; see [`JITTER_FRAMES`].
+    jitter: JitterBuffer,
+    /// Present when lost frames are rebuilt via FEC/PLC instead of silence.
+    recovery: Option<LossRecovery>,
+    /// OpusTags values, reused when the recording rotates to a new segment.
+    tags: RecordingTags,
+    /// 0 for the first file of a recording, +1 per rotation.
+    segment_index: i32,
+    /// Frame count before which a failed rotation is not retried.
+    rotation_backoff_until: u64,
+    /// Rows and cue of the last rotation, written off the voice task. Later
+    /// close-outs of this recording wait for it.
+    segment_close: Option<tokio::task::JoinHandle<()>>,
+    speech: SpeechDetector,
+    /// The last minute of this recording for `/clip last`.
+    recent: SharedRecentAudio,
+}
+
+/// A finished speaking segment waiting for the next batch insert.
+struct PendingSpeechSegment {
+    file_name: String,
+    start_ms: i64,
+    end_ms: i64,
+    peak_level: f32,
+}
+
+/// Loss bookkeeping for one written packet, reported to metrics by the caller.
+#[derive(Default)]
+struct PacketOutcome {
+    lost: u64,
+    recovered: u64,
+}
+
+impl UserRecording {
+    /// Take one received packet into the jitter buffer and write whatever
+    /// it pushes out.
+    fn receive_rtp_packet(
+        &mut self,
+        payload: Vec<u8>,
+        sequence: u16,
+        timestamp: u32,
+        now_ms: i64,
+    ) -> std::io::Result<PacketOutcome> {
+        let packet = HeldPacket {
+            sequence,
+            timestamp,
+            payload,
+        };
+        if !self.jitter.insert(packet) {
+            self.timeline.reordered_packets += 1;
+        }
+        let released = self.jitter.release();
+        self.write_held(released, now_ms)
+    }
+
+    /// Write every held packet, and conceal slots whose packet never came,
+    /// before anything that must follow them on the timeline (silence, a
+    /// new stream, the end of the file).
+    fn release_held(&mut self) -> std::io::Result<PacketOutcome> {
+        let (held, missing) = self.jitter.drain();
+        let outcome = self.write_held(held, chrono::Utc::now().timestamp_millis())?;
+        if missing > 0 {
+            self.timeline.on_concealed(missing);
+            self.writer.write_silence(missing)?;
+            self.remember_silence(missing);
+        }
+        Ok(outcome)
+    }
+
+    fn write_held(
+        &mut self,
+        packets: Vec<HeldPacket>,
+        now_ms: i64,
+    ) -> std::io::Result<PacketOutcome> {
+        let mut outcome = PacketOutcome::default();
+        for packet in packets {
+            let written =
+                self.write_rtp_packet(&packet.payload, packet.sequence, packet.timestamp, now_ms)?;
+            outcome.lost += written.lost;
+            outcome.recovered += written.recovered;
+        }
+        Ok(outcome)
+    }
+
+    /// Start over on a new RTP stream (new SSRC) after writing what the
+    /// old one left in the jitter buffer.
+    fn restart_stream(&mut self) -> std::io::Result<()> {
+        let result = self.release_held().map(|_| ());
+        self.timeline.reset();
+        result
+    }
+
+    /// Write one packet at the position its RTP timestamp says it belongs,
+    /// filling any gap first. Filler never pushes the file past wall-clock
+    /// time.
+    fn write_rtp_packet(
+        &mut self,
+        packet: &[u8],
+        sequence: u16,
+        timestamp: u32,
+        now_ms: i64,
+    ) -> std::io::Result<PacketOutcome> {
+        let lost_before = self.timeline.lost_packets;
+        let mut outcome = PacketOutcome::default();
+        if let PacketAction::Write { conceal_before } = self.timeline.on_packet(sequence, timestamp)
+        {
+            let allowed = wallclock_deficit_frames(
+                self.start_time.timestamp_millis(),
+                now_ms,
+                self.writer.frames_written() + 1,
+            );
+            let fill = conceal_before.min(allowed);
+            match self.recovery.as_mut() {
+                Some(recovery) if fill > 0 => {
+                    for frame in recovery.recover(fill, packet)? {
+                        self.writer.write_packet(&frame)?;
+                        self.remember_packet(&frame);
+                    }
+                    outcome.recovered = loss_recovery::recovered_frames(fill);
+                }
+                _ => {
+                    self.writer.write_silence(fill)?;
+                    self.remember_silence(fill);
+                }
+            }
+            if let Some(recovery) = self.recovery.as_mut()
+                && let Err(err) = recovery.observe(packet)
+            {
+                debug!("loss recovery decoder rejected packet: {}", err);
+            }
+            self.writer.write_packet(packet)?;
+            self.remember_packet(packet);
+        }
+        outcome.lost = self.timeline.lost_packets - lost_before;
+        Ok(outcome)
+    }
+
+    /// Silence written for a tick, pause or outage rather than a lost packet.
+    fn write_gap_silence(&mut self, frames: u64) -> std::io::Result<()> {
+        self.release_held()?;
+        self.timeline.on_silence(frames);
+        self.writer.write_silence(frames)?;
+        self.remember_silence(frames);
+        Ok(())
+    }
+
+    /// Feed this tick's speaking state for the frame just taken, written or
+    /// still held.
+    fn track_speech(&mut self, voiced: bool, peak_level: f32) -> Option<PendingSpeechSegment> {
+        let frame = (self.writer.frames_written() + self.jitter.pending()).checked_sub(1)?;
+        let segment = self.speech.on_frame(frame, voiced, peak_level)?;
+        Some(self.pending_speech(segment))
+    }
+
+    /// Close the open speaking segment when the current file ends.
+    fn finish_speech(&mut self) -> Option<PendingSpeechSegment> {
+        let segment = self.speech.finish()?;
+        Some(self.pending_speech(segment))
+    }
+
+    fn pending_speech(&self, segment: speech_segments::SpeechSegment) -> PendingSpeechSegment {
+        let (start_ms, end_ms) = segment.wallclock_ms(self.start_time.timestamp_millis());
+        PendingSpeechSegment {
+            file_name: self.file_name.clone(),
+            start_ms,
+            end_ms,
+            peak_level: segment.peak_level,
+        }
+    }
+
+    /// Pad the file back to wall-clock time when ticks were late or skipped.
+    fn catch_up_to_wallclock(&mut self, now_ms: i64) -> std::io::Result<()> {
+        let deficit = wallclock_deficit_frames(
+            self.start_time.timestamp_millis(),
+            now_ms,
+            self.writer.frames_written(),
+        );
+        if deficit <= WALLCLOCK_DRIFT_TOLERANCE_FRAMES {
+            return Ok(());
+        }
+        self.release_held()?;
+        let deficit = wallclock_deficit_frames(
+            self.start_time.timestamp_millis(),
+            now_ms,
+            self.writer.frames_written(),
+        );
+        self.timeline.on_concealed(deficit);
+        self.writer.write_silence(deficit)?;
+        self.remember_silence(deficit);
+        Ok(())
+    }
+
+    fn remember_packet(&self, packet: &[u8]) {
+        if let Ok(mut recent) = self.recent.lock() {
+            recent.push_packet(packet);
+        }
+    }
+
+    fn remember_silence(&self, frames: u64) {
+        if let Ok(mut recent) = self.recent.lock() {
+            recent.push_silence(frames);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -75,6 +304,8 @@ pub struct InnerReceiver {
     paused_recording_token: AtomicU64,
     bot_ssrcs: Arc<RwLock<HashSet<u32>>>,
     bot_user_id_hashmap: Arc<RwLock<HashMap<u64, u32>>>,
+    /// Users whose SSRC sits in `bot_ssrcs` because they opted out.
+    opted_out_users: RwLock<HashSet<u64>>,
     metrics: Arc<crate::BotMetrics>,
     guild_metrics: Arc<crate::GuildRecordingMetrics>,
     channel_metrics: Arc<crate::GuildRecordingMetrics>,
@@ -84,9 +315,34 @@ pub struct InnerReceiver {
     /// 0 = inactive. Used to pad new joiners' files with leading silence so
     /// every per-user .ogg shares granule-zero = session-start.
     session_start_ms: AtomicI64,
+    /// `voice_sessions.id` of the open session. 0 = none.
+    session_id: AtomicI64,
+    /// Serializes opening and closing the session row.
+    session_lock: Mutex<()>,
     /// Wallclock millisecond when a recoverable driver disconnect began.
     /// 0 = active/no pending resume.
     disconnected_at_ms: AtomicI64,
+    /// Optional mixed-down track of every recorded speaker in this channel.
+    session_mix: Mutex<Option<SessionMix>>,
+    /// Held while a mix is started or finalized, so the tick can keep
+    /// writing the current one meanwhile.
+    session_mix_start: Mutex<()>,
+    /// Mixes opened in the current session, so one reopened after a failure
+    /// gets its own name.
+    session_mixes: AtomicU32,
+    segment_limits: SegmentLimits,
+    /// Keep only speech packets plus a timeline index instead of padding
+    /// files with silence.
+    sparse_storage: bool,
+    /// Speaking segments buffered for the heartbeat's batch insert.
+    pending_speech: Mutex<Vec<PendingSpeechSegment>>,
+    /// `None` only if the writer thread could not be started; recordings
+    /// then fail to open instead of writing on the voice task.
+    disk_writer: Option<Arc<DiskWriter>>,
+    /// `None` when no monitor runs; recording is then never held back.
+    storage_guard: Option<Arc<StorageGuard>>,
+    /// Where `/clip last` finds the recordings' recent audio.
+    live_audio: Option<Arc<LiveAudio>>,
 }
 
 impl Drop for Receiver {
@@ -111,14 +367,28 @@ impl Receiver {
     ) -> Self {
         let guild_metrics = metrics.guild_metrics(guild_id.get());
         let channel_metrics = metrics.channel_metrics(guild_id.get(), channel_id.get());
-        let recording_owner_instance_id = {
+        let (recording_owner_instance_id, disk_writer, storage_guard, live_audio) = {
             let data = ctx.data.read().await;
-            data.get::<crate::runtime::RuntimeStateKey>()
+            let instance_id = data
+                .get::<crate::runtime::RuntimeStateKey>()
                 .map(|runtime| runtime.config().instance_id.clone())
                 .unwrap_or_else(|| {
                     format!("{}-{}", crate::config::SERVICE_NAME, std::process::id())
-                })
+                });
+            (
+                instance_id,
+                data.get::<crate::events::disk_writer::DiskWriterKey>()
+                    .cloned(),
+                data.get::<StorageGuardKey>().cloned(),
+                data.get::<LiveAudioKey>().cloned(),
+            )
         };
+        let disk_writer = disk_writer.or_else(|| {
+            warn!("DiskWriter missing from typemap; starting a receiver-local writer");
+            DiskWriter::start(metrics.clone())
+                .inspect_err(|e| error!("Failed to start recording writer thread: {}", e))
+                .ok()
+        });
         let inner = Arc::new(InnerReceiver {
             pool,
             ctx_main: ctx,
@@ -128,6 +398,7 @@ impl Receiver {
             paused_recording_token: AtomicU64::new(1),
             bot_ssrcs: Arc::new(RwLock::new(HashSet::new())),
             bot_user_id_hashmap: Arc::new(RwLock::new(HashMap::new())),
+            opted_out_users: RwLock::new(HashSet::new()),
             guild_id,
             channel_id,
             metrics,
@@ -136,7 +407,18 @@ impl Receiver {
             recording_owner_instance_id,
             last_voice_packet_time: AtomicI64::new(chrono::Utc::now().timestamp_millis()),
             session_start_ms: AtomicI64::new(0),
+            session_id: AtomicI64::new(0),
+            session_lock: Mutex::new(()),
             disconnected_at_ms: AtomicI64::new(0),
+            session_mix: Mutex::new(None),
+            session_mix_start: Mutex::new(()),
+            session_mixes: AtomicU32::new(0),
+            segment_limits: SegmentLimits::from_env(),
+            sparse_storage: crate::config::sparse_recording_enabled(),
+            pending_speech: Mutex::new(Vec::new()),
+            disk_writer,
+            storage_guard,
+            live_audio,
         });
 
         let heartbeat_inner_weak = Arc::downgrade(&inner);
@@ -148,6 +430,9 @@ impl Receiver {
                     break;
                 };
                 heartbeat_active_recordings(&inner_clone).await;
+                flush_speech_segments(&inner_clone).await;
+                enforce_recording_policy(&inner_clone).await;
+                enforce_storage_guard(&inner_clone).await;
             }
         });
 
@@ -219,207 +504,158 @@ impl VoiceEventHandler for Receiver {
                 ..
             }) => {
                 let _ = async {
-                debug!(
-                    "Speaking state update: user {:?} has SSRC {:?}, using {:?}",
-                    user_id, ssrc, speaking,
-                );
+                    debug!(
+                        "Speaking state update: user {:?} has SSRC {:?}, using {:?}",
+                        user_id, ssrc, speaking,
+                    );
 
-                let Some(user_id) = user_id else {
-                    error!("No user_id in SpeakingStateUpdate");
-                    return None;
-                };
+                    let Some(user_id) = user_id else {
+                        error!("No user_id in SpeakingStateUpdate");
+                        return None;
+                    };
 
-                let previous_bot_ssrc = {
-                    self.inner
-                        .bot_user_id_hashmap
-                        .read()
-                        .await
-                        .get(&user_id.0)
-                        .copied()
-                };
-                if let Some(previous_bot_ssrc) = previous_bot_ssrc {
-                    if previous_bot_ssrc != *ssrc {
-                        let mut bot_ssrcs = self.inner.bot_ssrcs.write().await;
-                        bot_ssrcs.remove(&previous_bot_ssrc);
-                        bot_ssrcs.insert(*ssrc);
+                    let previous_bot_ssrc = {
                         self.inner
                             .bot_user_id_hashmap
-                            .write()
+                            .read()
                             .await
-                            .insert(user_id.0, *ssrc);
-                    }
-                    return None;
-                }
-
-                let (is_channel_empty, previous_ssrc) = {
-                    let users = self.inner.user_id_hashmap.read().await;
-                    (users.is_empty(), users.get(&user_id.0).copied())
-                };
-
-                if let Some(previous_ssrc) = previous_ssrc {
-                    if previous_ssrc == *ssrc {
-                        debug!("Writer already active for ssrc {}", ssrc);
+                            .get(&user_id.0)
+                            .copied()
+                    };
+                    if let Some(previous_bot_ssrc) = previous_bot_ssrc {
+                        if previous_bot_ssrc != *ssrc {
+                            let mut bot_ssrcs = self.inner.bot_ssrcs.write().await;
+                            if bot_ssrcs.remove(&previous_bot_ssrc) {
+                                bot_ssrcs.insert(*ssrc);
+                            } else {
+                                // Recorded bot: its track follows the new SSRC.
+                                remap_writer(&self.inner, previous_bot_ssrc, *ssrc).await;
+                            }
+                            self.inner
+                                .bot_user_id_hashmap
+                                .write()
+                                .await
+                                .insert(user_id.0, *ssrc);
+                        }
                         return None;
                     }
 
-                    let recording = {
-                        let mut writer_map = self.inner.ssrc_writer_hashmap.write().await;
-                        if let Some(recording) = writer_map.remove(&previous_ssrc) {
-                            writer_map.insert(*ssrc, recording.clone());
-                            Some(recording)
-                        } else {
-                            None
-                        }
+                    let (is_channel_empty, previous_ssrc) = {
+                        let users = self.inner.user_id_hashmap.read().await;
+                        (users.is_empty(), users.get(&user_id.0).copied())
                     };
 
-                    if let Some(recording) = recording {
-                        self.inner
-                            .user_id_hashmap
-                            .write()
-                            .await
-                            .insert(user_id.0, *ssrc);
-
-                        let mut rec = recording.lock().await;
-                        rec.ssrc = *ssrc;
-                        info!(
-                            "Remapped active writer for user {} from ssrc {} to {}",
-                            user_id.0, previous_ssrc, ssrc
-                        );
-                        return None;
-                    }
-                }
-
-                let guild = match self.inner.ctx_main.cache.guild(self.inner.guild_id) {
-                    Some(g) => g.to_owned(),
-                    None => {
-                        error!("Guild {} not in cache", self.inner.guild_id);
-                        return None;
-                    }
-                };
+                    if let Some(previous_ssrc) = previous_ssrc {
+                        if previous_ssrc == *ssrc {
+                            debug!("Writer already active for ssrc {}", ssrc);
+                            return None;
+                        }
 
-                let member = match guild
-                    .members
-                    .get(&serenity::model::id::UserId::new(user_id.0))
-                    .cloned()
-                {
-                    Some(m) => m,
-                    None => match guild.member(&self.inner.ctx_main, user_id.0).await {
-                        Ok(m) => m.into_owned(),
-                        Err(e) => {
-                            error!("Failed to get member: {}", e);
+                        if remap_writer(&self.inner, previous_ssrc, *ssrc).await {
+                            self.inner
+                                .user_id_hashmap
+                                .write()
+                                .await
+                                .insert(user_id.0, *ssrc);
                             return None;
                         }
                     }
-                };
 
-                if member.user.bot {
-                    self.inner.bot_ssrcs.write().await.insert(*ssrc);
-                    self.inner
-                        .bot_user_id_hashmap
-                        .write()
-                        .await
-                        .insert(user_id.0, *ssrc);
-                } else {
-                    if resume_paused_recording(&self.inner, user_id.0, *ssrc).await {
-                        return None;
-                    }
+                    let guild = match self.inner.ctx_main.cache.guild(self.inner.guild_id) {
+                        Some(g) => g.to_owned(),
+                        None => {
+                            error!("Guild {} not in cache", self.inner.guild_id);
+                            return None;
+                        }
+                    };
 
+                    let member = match guild
+                        .members
+                        .get(&serenity::model::id::UserId::new(user_id.0))
+                        .cloned()
                     {
+                        Some(m) => m,
+                        None => match guild.member(&self.inner.ctx_main, user_id.0).await {
+                            Ok(m) => m.into_owned(),
+                            Err(e) => {
+                                error!("Failed to get member: {}", e);
+                                return None;
+                            }
+                        },
+                    };
+
+                    if member.user.bot {
                         self.inner
-                            .user_id_hashmap
+                            .bot_user_id_hashmap
                             .write()
                             .await
                             .insert(user_id.0, *ssrc);
-                    }
-
-                    {
-                        // Single write-lock for the check-and-insert to avoid TOCTOU.
-                        let mut writer_map = self.inner.ssrc_writer_hashmap.write().await;
-                        if writer_map.contains_key(ssrc) {
-                            debug!("Writer already active for ssrc {}", ssrc);
-                        } else {
-                            info!("New writer for ssrc {}", ssrc);
-                            let now = chrono::Utc::now();
-                            let now_ms = now.timestamp_millis();
-
-                            let Some(path) = create_path(
-                                self,
-                                now,
-                                user_id.0,
-                                is_channel_empty,
-                            )
-                            .await
-                            else {
-                                error!("Failed to create recording path for ssrc {}", ssrc);
+                        if !should_record_bot(&self.inner).await {
+                            self.inner.bot_ssrcs.write().await.insert(*ssrc);
+                            return None;
+                        }
+                        if !storage_accepting(&self.inner) {
+                            return None;
+                        }
+                        // Bot tracks join an open session but never start one.
+                        let session_id = match self.inner.session_id.load(Ordering::SeqCst) {
+                            0 => None,
+                            id => Some(id),
+                        };
+                        start_user_recording(self, *ssrc, &member, false, session_id, true).await;
+                    } else {
+                        match consent_opted_out(&self.inner, user_id.0).await {
+                            Some(false) => {}
+                            Some(true) => {
+                                debug!("User {} opted out of recording", user_id.0);
+                                treat_as_opted_out(&self.inner, user_id.0, *ssrc).await;
                                 return None;
-                            };
-
-                            let file = match File::create(format!("{}.ogg", path)) {
-                                Ok(f) => f,
-                                Err(e) => {
-                                    error!("Failed to create file for ssrc {}: {}", ssrc, e);
-                                    self.inner
-                                        .metrics
-                                        .track_ffmpeg_spawn_failure(
-                                            &self.inner.guild_metrics,
-                                            &self.inner.channel_metrics,
-                                        );
-                                    return None;
-                                }
-                            };
-
-                            let writer = match OggOpusWriter::new(BufWriter::new(file), *ssrc, 0) {
-                                Ok(w) => w,
-                                Err(e) => {
-                                    error!("Failed to init OggOpusWriter for ssrc {}: {}", ssrc, e);
-                                    return None;
-                                }
-                            };
-
-                            let file_name = RecordingKey::stem_for(now_ms, user_id.0 as i64);
-                            let recording = UserRecording {
-                                writer,
-                                file_name,
-                                start_time: now,
-                                user_id: user_id.0,
-                                ssrc: *ssrc,
-                            };
-                            writer_map.insert(*ssrc, Arc::new(Mutex::new(recording)));
-                            drop(writer_map);
+                            }
+                            None => return None,
+                        }
 
-                            crate::database::user_names::observe(
-                                &self.inner.pool,
-                                self.inner.guild_id.get(),
-                                &member.user,
-                                Some(&member),
-                            )
-                            .await;
+                        if !recording_allowed(&self.inner, user_id.0).await {
+                            return None;
+                        }
 
-                            self.inner.metrics.track_recording_started(
-                                &self.inner.guild_metrics,
-                                &self.inner.channel_metrics,
-                                self.inner.guild_id.get(),
-                                self.inner.channel_id.get(),
-                                user_id.0,
-                            );
+                        if resume_paused_recording(&self.inner, user_id.0, *ssrc).await {
+                            return None;
+                        }
 
-                            let _ = sqlx::query(
-                                "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)"
-                            )
-                            .bind(self.inner.guild_id.get() as i64)
-                            .bind(user_id.0 as i64)
-                            .bind(*ssrc as i64)
-                            .bind(VoiceEventType::WriterOpen as i32)
-                            .bind("Writer opened")
-                            .execute(&self.inner.pool)
-                            .await;
+                        {
+                            self.inner
+                                .user_id_hashmap
+                                .write()
+                                .await
+                                .insert(user_id.0, *ssrc);
+                        }
 
-                            info!("1 file created for ssrc: {}", *ssrc);
+                        // The heartbeat opens the writer once space recovers.
+                        if !storage_accepting(&self.inner) {
+                            debug!("Recording disk full; not recording user {}", user_id.0);
+                            return None;
                         }
+
+                        // Outside the writer-map lock: closing a session takes
+                        // the session lock before reading the maps.
+                        let session_id = ensure_session(&self.inner, chrono::Utc::now()).await;
+
+                        start_user_recording(
+                            self,
+                            *ssrc,
+                            &member,
+                            is_channel_empty,
+                            session_id,
+                            false,
+                        )
+                        .await;
                     }
+                    None::<Event>
                 }
-                None::<Event>
-            }.instrument(tracing::debug_span!("SpeakingStateUpdate", ssrc = %ssrc, user_id = ?user_id)).await;
+                .instrument(
+                    tracing::debug_span!("SpeakingStateUpdate", ssrc = %ssrc, user_id = ?user_id),
+                )
+                .await;
             }
 
             Ctx::RtpPacket(_packet) => {
@@ -436,13 +672,21 @@ impl VoiceEventHandler for Receiver {
                         .collect()
                 };
 
+                let mut mix_voices: Vec<Vec<i16>> = Vec::new();
+                let mut failed = Vec::new();
                 for (ssrc, recording) in active {
                     let speaking_data = tick.speaking.get(&ssrc);
+                    if let Some(voice) = speaking_data.and_then(|d| d.decoded_voice.as_ref()) {
+                        mix_voices.push(voice.clone());
+                    }
 
-                    // Pull the raw Opus payload bytes if the user spoke this tick.
-                    let opus_bytes: Option<Vec<u8>> = speaking_data.and_then(|d| {
-                        d.packet.as_ref().map(|rtp| {
+                    // Pull the raw Opus payload bytes plus RTP position if the
+                    // user spoke this tick.
+                    let opus_packet: Option<(Vec<u8>, u16, u32)> =
+                        speaking_data.and_then(|d| d.packet.as_ref()).map(|rtp| {
                             let view = rtp.rtp();
+                            let sequence = view.get_sequence().0;
+                            let timestamp = view.get_timestamp().0;
                             let payload = view.payload();
                             // NB: in songbird 0.6 VoiceTick, `payload_end_pad` is an
                             // absolute end index into `payload`, not a tail-pad count
@@ -450,7 +694,7 @@ impl VoiceEventHandler for Receiver {
                             let start = rtp.payload_offset.min(payload.len());
                             let end = rtp.payload_end_pad.min(payload.len());
                             if end <= start {
-                                return Vec::new();
+                                return (Vec::new(), sequence, timestamp);
                             }
                             let body = &payload[start..end];
                             // RTP header extension (Discord uses one-byte form) sits
@@ -470,14 +714,24 @@ impl VoiceEventHandler for Receiver {
                             } else {
                                 body
                             };
-                            opus.to_vec()
-                        })
-                    });
+                            (opus.to_vec(), sequence, timestamp)
+                        });
 
+                    let voiced = opus_packet
+                        .as_ref()
+                        .is_some_and(|(bytes, _, _)| !bytes.is_empty());
+                    let peak_level = speaking_data
+                        .and_then(|d| d.decoded_voice.as_deref())
+                        .map(speech_segments::peak_level)
+                        .unwrap_or(0.0);
+
+                    let now = chrono::Utc::now().timestamp_millis();
                     let mut rec = recording.lock().await;
-                    let result = match opus_bytes.as_deref() {
-                        Some(bytes) if !bytes.is_empty() => {
-                            let now = chrono::Utc::now().timestamp_millis();
+                    if let Err(e) = rec.catch_up_to_wallclock(now) {
+                        error!("Writer catch-up error for ssrc {}: {}", ssrc, e);
+                    }
+                    let result = match opus_packet {
+                        Some((bytes, sequence, timestamp)) if !bytes.is_empty() => {
                             self.inner
                                 .last_voice_packet_time
                                 .store(now, Ordering::Relaxed);
@@ -490,13 +744,90 @@ impl VoiceEventHandler for Receiver {
                                 &self.inner.guild_metrics,
                                 &self.inner.channel_metrics,
                             );
-                            rec.writer.write_packet(bytes)
+                            let written = rec.receive_rtp_packet(bytes, sequence, timestamp, now);
+                            if let Ok(outcome) = &written {
+                                if outcome.lost > 0 {
+                                    self.inner.metrics.track_audio_packets_dropped(
+                                        &self.inner.guild_metrics,
+                                        &self.inner.channel_metrics,
+                                        outcome.lost,
+                                    );
+                                }
+                                if outcome.recovered > 0 {
+                                    self.inner.metrics.track_audio_frames_recovered(
+                                        &self.inner.guild_metrics,
+                                        &self.inner.channel_metrics,
+                                        outcome.recovered,
+                                    );
+                                }
+                            }
+                            written.map(|_| ())
                         }
-                        _ => rec.writer.write_silence(1),
+                        // Speaking but the packet was lost: leave the slot
+                        // open so the next packet's RTP gap rebuilds it. If
+                        // the user goes quiet first, the gap silence below
+                        // conceals it.
+                        None if speaking_data.is_some_and(|d| d.packet.is_none()) => {
+                            rec.jitter.skip();
+                            Ok(())
+                        }
+                        _ => rec.write_gap_silence(1),
+                    };
+                    if let Err(e) = result.and_then(|_| rec.writer.flush()) {
+                        error!("Writer error for ssrc {}: {}. Closing writer.", ssrc, e);
+                        failed.push(ssrc);
+                        continue;
+                    }
+                    if let Some(segment) = rec.track_speech(voiced, peak_level) {
+                        self.inner.pending_speech.lock().await.push(segment);
+                    }
+                    rotate_segment_if_due(&self.inner, &mut rec);
+                }
+
+                // Closed instead of retried every tick; the heartbeat opens a
+                // new writer while the user is still here. The close-out
+                // queries run off the tick.
+                if !failed.is_empty() {
+                    let closed: Vec<_> = {
+                        let mut map = self.inner.ssrc_writer_hashmap.write().await;
+                        failed
+                            .into_iter()
+                            .filter_map(|ssrc| map.remove(&ssrc).map(|arc| (ssrc, arc)))
+                            .collect()
                     };
-                    if let Err(e) = result {
-                        error!("Writer error for ssrc {}: {}", ssrc, e);
+                    let inner = self.inner.clone();
+                    let close_time = chrono::Utc::now();
+                    tokio::spawn(async move {
+                        for (ssrc, arc) in closed {
+                            finalize_recording_arc(
+                                &inner,
+                                ssrc,
+                                arc,
+                                VoiceEventType::WriterError,
+                                close_time,
+                            )
+                            .await;
+                        }
+                    });
+                }
+
+                let failed_mix = {
+                    let mut slot = self.inner.session_mix.lock().await;
+                    match slot
+                        .as_mut()
+                        .map(|mix| mix.write_tick(&mix_voices).and_then(|_| mix.flush()))
+                    {
+                        Some(Err(e)) => {
+                            error!("Session mix writer error: {}. Closing mix.", e);
+                            slot.take()
+                        }
+                        _ => None,
                     }
+                };
+                if let Some(mix) = failed_mix {
+                    let inner = self.inner.clone();
+                    let close_time = chrono::Utc::now();
+                    tokio::spawn(async move { close_session_mix(&inner, mix, close_time).await });
                 }
             }
             Ctx::RtcpPacket(_data) => {}
@@ -594,7 +925,11 @@ impl VoiceEventHandler for Receiver {
                         .remove(&user_id.0);
                     if let Some(bot_ssrc) = is_bot_ssrc {
                         warn!("Removed bot with id: {} and ssrc: {}", user_id.0, bot_ssrc);
-                        self.inner.bot_ssrcs.write().await.remove(&bot_ssrc);
+                        self.inner.opted_out_users.write().await.remove(&user_id.0);
+                        if !self.inner.bot_ssrcs.write().await.remove(&bot_ssrc) {
+                            finalize_writer(&self.inner, bot_ssrc, VoiceEventType::WriterClose)
+                                .await;
+                        }
                         return None;
                     }
 
@@ -621,57 +956,416 @@ impl VoiceEventHandler for Receiver {
     }
 }
 
-fn silence_frames_for_gap_ms(gap_ms: i64) -> u64 {
-    if gap_ms <= 0 {
-        0
-    } else {
-        (gap_ms as u64).div_ceil(20)
+/// Move the writer for `previous_ssrc` to `ssrc` after a speaker's SSRC
+/// changed. Returns false if there was no writer to move.
+async fn remap_writer(inner: &Arc<InnerReceiver>, previous_ssrc: u32, ssrc: u32) -> bool {
+    let recording = {
+        let mut writer_map = inner.ssrc_writer_hashmap.write().await;
+        let Some(recording) = writer_map.remove(&previous_ssrc) else {
+            return false;
+        };
+        writer_map.insert(ssrc, recording.clone());
+        recording
+    };
+
+    let mut rec = recording.lock().await;
+    rec.ssrc = ssrc;
+    if let Err(err) = rec.restart_stream().and_then(|_| rec.writer.flush()) {
+        error!(ssrc, "Failed to write held packets before remap: {}", err);
     }
+    info!(
+        "Remapped active writer for user {} from ssrc {} to {}",
+        rec.user_id, previous_ssrc, ssrc
+    );
+    true
 }
 
-fn is_intentional_driver_disconnect(reason: Option<&DisconnectReason>) -> bool {
-    reason.is_none() || matches!(reason, Some(DisconnectReason::Requested))
+/// Whether a bot that started speaking gets its own track: the guild must
+/// opt in, and there has to be a human in the channel to record for.
+async fn should_record_bot(inner: &Arc<InnerReceiver>) -> bool {
+    recording_channel_has_human_members(inner) == Some(true)
+        && crate::database::guild_settings::recording_settings(&inner.pool, inner.guild_id.get())
+            .await
+            .record_bots
+        && recording_policy::load(&inner.pool, inner.guild_id.get(), inner.channel_id.get())
+            .await
+            .is_ok_and(|policy| policy.allows_channel())
 }
 
-fn should_resume_recordings_for_disconnect(
-    reason: Option<&DisconnectReason>,
-    resume_intentional_disconnects: bool,
-) -> bool {
-    resume_intentional_disconnects || !is_intentional_driver_disconnect(reason)
+/// Check the recording policy before opening a writer. A failed lookup
+/// counts as "not allowed".
+async fn recording_allowed(inner: &Arc<InnerReceiver>, user_id: u64) -> bool {
+    match recording_policy::load(&inner.pool, inner.guild_id.get(), inner.channel_id.get()).await {
+        Ok(policy) if policy.allows_user(user_id) => true,
+        Ok(_) => {
+            debug!("Recording policy excludes user {}", user_id);
+            false
+        }
+        Err(err) => {
+            error!("recording policy lookup failed: {}", err);
+            inner
+                .metrics
+                .db_query_errors
+                .fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
 }
 
-fn should_finalize_empty_channel_disconnect(
-    reason: Option<&DisconnectReason>,
-    channel_has_human_members: Option<bool>,
-) -> bool {
-    is_intentional_driver_disconnect(reason) && channel_has_human_members == Some(false)
-}
+/// Close writers the current policy or the users' own consent no longer
+/// allows, e.g. after an admin disabled the channel or a user ran
+/// `/privacy optout` mid-session. Users dropped by policy leave the SSRC
+/// map, so speaking again re-checks it; opted-out users are parked with
+/// the bots until they opt back in.
+async fn enforce_recording_policy(inner: &Arc<InnerReceiver>) {
+    let policy =
+        match recording_policy::load(&inner.pool, inner.guild_id.get(), inner.channel_id.get())
+            .await
+        {
+            Ok(policy) => policy,
+            Err(err) => {
+                warn!("recording policy lookup failed: {}", err);
+                return;
+            }
+        };
 
-fn recording_channel_has_human_members(inner: &InnerReceiver) -> Option<bool> {
-    let guild = inner.ctx_main.cache.guild(inner.guild_id)?;
-    let bot_id = inner.ctx_main.cache.current_user().id;
+    let active: Vec<(u32, Arc<Mutex<UserRecording>>)> = {
+        let map = inner.ssrc_writer_hashmap.read().await;
+        map.iter().map(|(s, w)| (*s, w.clone())).collect()
+    };
+    let mut active_users = Vec::with_capacity(active.len());
+    for (ssrc, recording) in active {
+        active_users.push((ssrc, recording.lock().await.user_id));
+    }
 
-    for (user_id, voice_state) in &guild.voice_states {
-        if voice_state.channel_id != Some(inner.channel_id) {
-            continue;
+    let mut users: Vec<u64> = active_users.iter().map(|(_, user_id)| *user_id).collect();
+    users.extend(inner.paused_recordings.read().await.keys().copied());
+    users.extend(inner.opted_out_users.read().await.iter().copied());
+    let opted_out = match consent::opted_out(&inner.pool, &users).await {
+        Ok(opted_out) => opted_out,
+        Err(err) => {
+            warn!("consent lookup failed: {}", err);
+            return;
         }
+    };
+    let allowed = |user_id: u64| policy.allows_user(user_id) && !opted_out.contains(&user_id);
 
-        if *user_id == bot_id {
+    let paused: Vec<PausedRecording> = {
+        let mut paused = inner.paused_recordings.write().await;
+        let users: Vec<u64> = paused
+            .keys()
+            .filter(|user_id| !allowed(**user_id))
+            .copied()
+            .collect();
+        users.iter().filter_map(|u| paused.remove(u)).collect()
+    };
+
+    for (ssrc, user_id) in active_users {
+        if allowed(user_id) {
             continue;
         }
-
-        let Some(member) = guild.members.get(user_id) else {
-            // Missing member cache for a non-bot user: keep recoverable behavior.
-            return Some(true);
-        };
-
-        if !member.user.bot {
-            return Some(true);
+        info!(user_id, ssrc, "Recording policy changed. Closing writer.");
+        inner.user_id_hashmap.write().await.remove(&user_id);
+        finalize_writer(inner, ssrc, VoiceEventType::WriterClose).await;
+        if opted_out.contains(&user_id) {
+            treat_as_opted_out(inner, user_id, ssrc).await;
         }
     }
+    for paused in paused {
+        finalize_recording_arc(
+            inner,
+            paused.ssrc,
+            paused.recording,
+            VoiceEventType::WriterClose,
+            paused.paused_at,
+        )
+        .await;
+    }
 
-    Some(false)
-}
+    // Opted back in: stop ignoring their SSRC. Recording starts again the
+    // next time Discord reports them speaking.
+    let opted_in: Vec<u64> = inner
+        .opted_out_users
+        .read()
+        .await
+        .iter()
+        .filter(|user_id| !opted_out.contains(user_id))
+        .copied()
+        .collect();
+    for user_id in opted_in {
+        inner.opted_out_users.write().await.remove(&user_id);
+        if let Some(ssrc) = inner.bot_user_id_hashmap.write().await.remove(&user_id) {
+            inner.bot_ssrcs.write().await.remove(&ssrc);
+        }
+        info!(user_id, "User opted back in to recording");
+    }
+}
+
+/// Whether the user opted out via `/privacy`. `None` if the lookup failed.
+async fn consent_opted_out(inner: &Arc<InnerReceiver>, user_id: u64) -> Option<bool> {
+    match consent::opted_out(&inner.pool, &[user_id]).await {
+        Ok(opted_out) => Some(opted_out.contains(&user_id)),
+        Err(err) => {
+            error!("consent lookup failed: {}", err);
+            inner
+                .metrics
+                .db_query_errors
+                .fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+/// Ignore an opted-out user's audio the same way a bot's is ignored: no
+/// writer, no `audio_files` row.
+async fn treat_as_opted_out(inner: &Arc<InnerReceiver>, user_id: u64, ssrc: u32) {
+    inner.opted_out_users.write().await.insert(user_id);
+    inner.bot_ssrcs.write().await.insert(ssrc);
+    inner
+        .bot_user_id_hashmap
+        .write()
+        .await
+        .insert(user_id, ssrc);
+}
+
+fn storage_accepting(inner: &InnerReceiver) -> bool {
+    inner
+        .storage_guard
+        .as_ref()
+        .is_none_or(|guard| guard.accepting_writers())
+}
+
+/// Close every writer while the recording disk is full. Once it has room
+/// again, and after a writer failed, reopen writers for users still in the
+/// channel; this runs on the heartbeat, so a failing disk is retried every
+/// few seconds rather than every tick.
+async fn enforce_storage_guard(inner: &Arc<InnerReceiver>) {
+    if !storage_accepting(inner) {
+        let active = !inner.ssrc_writer_hashmap.read().await.is_empty()
+            || !inner.paused_recordings.read().await.is_empty();
+        if active {
+            warn!("Recording disk full. Closing all writers.");
+            finalize_all_active_recordings(inner, VoiceEventType::DiskFull).await;
+        }
+        return;
+    }
+    if inner.disconnected_at_ms.load(Ordering::SeqCst) > 0 {
+        return;
+    }
+
+    let missing: Vec<(u64, u32)> = {
+        let users = inner.user_id_hashmap.read().await;
+        let writers = inner.ssrc_writer_hashmap.read().await;
+        users
+            .iter()
+            .filter(|(_, ssrc)| !writers.contains_key(ssrc))
+            .map(|(user_id, ssrc)| (*user_id, *ssrc))
+            .collect()
+    };
+    if missing.is_empty() {
+        return;
+    }
+
+    let receiver = Receiver {
+        inner: inner.clone(),
+    };
+    for (user_id, ssrc) in missing {
+        let member = inner
+            .ctx_main
+            .cache
+            .guild(inner.guild_id)
+            .and_then(|guild| {
+                guild
+                    .members
+                    .get(&serenity::model::id::UserId::new(user_id))
+                    .cloned()
+            });
+        let Some(member) = member else {
+            continue;
+        };
+        if consent_opted_out(inner, user_id).await != Some(false)
+            || !recording_allowed(inner, user_id).await
+        {
+            continue;
+        }
+        info!(user_id, ssrc, "Reopening recording writer");
+        let is_channel_empty = inner.ssrc_writer_hashmap.read().await.is_empty();
+        let session_id = ensure_session(inner, chrono::Utc::now()).await;
+        start_user_recording(
+            &receiver,
+            ssrc,
+            &member,
+            is_channel_empty,
+            session_id,
+            false,
+        )
+        .await;
+    }
+}
+
+/// Open the file, `audio_files` row and writer for a newly heard speaker.
+/// No-op if `ssrc` already has a writer.
+async fn start_user_recording(
+    _self: &Receiver,
+    ssrc: u32,
+    member: &serenity::model::guild::Member,
+    is_channel_empty: bool,
+    session_id: Option<i64>,
+    bot_track: bool,
+) {
+    let user_id = member.user.id.get();
+    // Single write-lock for the check-and-insert to avoid TOCTOU.
+    let mut writer_map = _self.inner.ssrc_writer_hashmap.write().await;
+    if writer_map.contains_key(&ssrc) {
+        debug!("Writer already active for ssrc {}", ssrc);
+    } else {
+        info!("New writer for ssrc {}", ssrc);
+        let now = chrono::Utc::now();
+        let now_ms = now.timestamp_millis();
+
+        let Some(path) =
+            create_path(_self, now, user_id, is_channel_empty, session_id, bot_track).await
+        else {
+            error!("Failed to create recording path for ssrc {}", ssrc);
+            return;
+        };
+
+        let ogg_path = std::path::PathBuf::from(format!("{}.ogg", path));
+        let tags = RecordingTags {
+            user_id: Some(user_id),
+            user_name: Some(crate::database::user_names::display_name(
+                &member.user,
+                Some(member),
+            )),
+            ..channel_tags(&_self.inner, now_ms)
+        };
+        let writer =
+            match create_segment_writer(&_self.inner, &ogg_path, ssrc, &tags.comments(now), now_ms)
+            {
+                Ok((writer, provisional)) => {
+                    provisional.into_iter().for_each(Provisional::keep);
+                    writer
+                }
+                Err(e) => {
+                    error!("Failed to create file for ssrc {}: {}", ssrc, e);
+                    _self.inner.metrics.track_ffmpeg_spawn_failure(
+                        &_self.inner.guild_metrics,
+                        &_self.inner.channel_metrics,
+                    );
+                    return;
+                }
+            };
+
+        let file_name = RecordingKey::stem_for(now_ms, user_id as i64);
+        let recent = Arc::new(std::sync::Mutex::new(RecentAudio::new(file_name.clone())));
+        if let Some(live_audio) = _self.inner.live_audio.as_ref() {
+            live_audio.register(_self.inner.guild_id.get(), user_id, recent.clone());
+        }
+        let recording = UserRecording {
+            writer,
+            file_name,
+            start_time: now,
+            user_id,
+            ssrc,
+            timeline: RtpTimeline::new(),
+            jitter: JitterBuffer::new(JITTER_FRAMES),
+            recovery: new_loss_recovery(),
+            tags,
+            segment_index: 0,
+            rotation_backoff_until: 0,
+            segment_close: None,
+            speech: SpeechDetector::from_env(),
+            recent,
+        };
+        writer_map.insert(ssrc, Arc::new(Mutex::new(recording)));
+        drop(writer_map);
+
+        crate::database::user_names::observe(
+            &_self.inner.pool,
+            _self.inner.guild_id.get(),
+            &member.user,
+            Some(member),
+        )
+        .await;
+
+        _self.inner.metrics.track_recording_started(
+            &_self.inner.guild_metrics,
+            &_self.inner.channel_metrics,
+            _self.inner.guild_id.get(),
+            _self.inner.channel_id.get(),
+            user_id,
+        );
+
+        let _ = sqlx::query!(
+            "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)",
+            _self.inner.guild_id.get() as i64,
+            user_id as i64,
+            ssrc as i64,
+            VoiceEventType::WriterOpen as i32,
+            "Writer opened"
+        )
+        .execute(&_self.inner.pool)
+        .await;
+
+        info!("1 file created for ssrc: {}", ssrc);
+
+        refresh_session_participants(&_self.inner).await;
+
+        start_session_mix(&_self.inner, now).await;
+    }
+}
+
+fn silence_frames_for_gap_ms(gap_ms: i64) -> u64 {
+    if gap_ms <= 0 {
+        0
+    } else {
+        (gap_ms as u64).div_ceil(20)
+    }
+}
+
+fn is_intentional_driver_disconnect(reason: Option<&DisconnectReason>) -> bool {
+    reason.is_none() || matches!(reason, Some(DisconnectReason::Requested))
+}
+
+fn should_resume_recordings_for_disconnect(
+    reason: Option<&DisconnectReason>,
+    resume_intentional_disconnects: bool,
+) -> bool {
+    resume_intentional_disconnects || !is_intentional_driver_disconnect(reason)
+}
+
+fn should_finalize_empty_channel_disconnect(
+    reason: Option<&DisconnectReason>,
+    channel_has_human_members: Option<bool>,
+) -> bool {
+    is_intentional_driver_disconnect(reason) && channel_has_human_members == Some(false)
+}
+
+fn recording_channel_has_human_members(inner: &InnerReceiver) -> Option<bool> {
+    let guild = inner.ctx_main.cache.guild(inner.guild_id)?;
+    let bot_id = inner.ctx_main.cache.current_user().id;
+
+    for (user_id, voice_state) in &guild.voice_states {
+        if voice_state.channel_id != Some(inner.channel_id) {
+            continue;
+        }
+
+        if *user_id == bot_id {
+            continue;
+        }
+
+        let Some(member) = guild.members.get(user_id) else {
+            // Missing member cache for a non-bot user: keep recoverable behavior.
+            return Some(true);
+        };
+
+        if !member.user.bot {
+            return Some(true);
+        }
+    }
+
+    Some(false)
+}
 
 fn paused_timeout_matches(current_token: Option<u64>, timeout_token: u64) -> bool {
     current_token == Some(timeout_token)
@@ -752,7 +1446,10 @@ async fn resume_paused_recording(inner: &Arc<InnerReceiver>, user_id: u64, ssrc:
 
     {
         let mut rec = paused.recording.lock().await;
-        if let Err(err) = rec.writer.write_silence(frames) {
+        if let Err(err) = rec
+            .write_gap_silence(frames)
+            .and_then(|_| rec.writer.flush())
+        {
             error!(
                 user_id,
                 old_ssrc = paused.ssrc,
@@ -762,6 +1459,8 @@ async fn resume_paused_recording(inner: &Arc<InnerReceiver>, user_id: u64, ssrc:
             );
         }
         rec.ssrc = ssrc;
+        rec.timeline.reset();
+        rotate_segment_if_due(inner, &mut rec);
     }
 
     inner
@@ -902,12 +1601,25 @@ async fn resume_after_recoverable_disconnect(inner: &Arc<InnerReceiver>) {
 
     for (ssrc, recording) in active {
         let mut rec = recording.lock().await;
-        if let Err(err) = rec.writer.write_silence(frames) {
+        if let Err(err) = rec
+            .write_gap_silence(frames)
+            .and_then(|_| rec.writer.flush())
+        {
             error!(
                 "Failed to write reconnect gap silence for ssrc {}: {}",
                 ssrc, err
             );
         }
+        rotate_segment_if_due(inner, &mut rec);
+    }
+
+    if let Some(mix) = inner.session_mix.lock().await.as_mut()
+        && let Err(err) = mix.write_silence(frames).and_then(|_| mix.flush())
+    {
+        error!(
+            "Failed to write reconnect gap silence for session mix: {}",
+            err
+        );
     }
 
     let mut users_to_remove = Vec::new();
@@ -959,14 +1671,376 @@ async fn finalize_all_active_recordings(inner: &Arc<InnerReceiver>, event_type:
         )
         .await;
     }
+
+    finalize_session_mix(inner, close_time).await;
+    close_session_if_idle(inner, close_time).await;
+}
+
+/// Open the channel mixdown alongside the first per-user writer of a session.
+/// No-op when mixing is disabled or a mix is already running.
+async fn start_session_mix(inner: &Arc<InnerReceiver>, now: chrono::DateTime<chrono::Utc>) {
+    if !crate::config::session_mix_enabled() {
+        return;
+    }
+    let _starting = inner.session_mix_start.lock().await;
+    if inner.session_mix.lock().await.is_some() {
+        return;
+    }
+
+    // Named and filed by the session, like its per-user tracks are grouped,
+    // rather than by when the mixer happened to open.
+    let session_start = match inner.session_start_ms.load(Ordering::SeqCst) {
+        0 => now,
+        start_ms => chrono::DateTime::from_timestamp_millis(start_ms).unwrap_or(now),
+    };
+    let session_id = inner.session_id.load(Ordering::SeqCst);
+    let reopened = inner.session_mixes.fetch_add(1, Ordering::SeqCst);
+    let file_name = session_mix::stem_for(session_start.timestamp_millis(), reopened);
+    let key = RecordingKey::new(
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        session_start.year(),
+        session_start.month(),
+        file_name.clone(),
+    );
+    let path = key
+        .recording_dir(RECORDING_ROOT)
+        .join(format!("{}.ogg", file_name));
+
+    let file = match create_recording_file(inner, &path) {
+        Ok(f) => f,
+        Err(e) => {
+            error!(
+                "Failed to create session mix file {}: {}",
+                path.display(),
+                e
+            );
+            inner
+                .metrics
+                .track_ffmpeg_spawn_failure(&inner.guild_metrics, &inner.channel_metrics);
+            return;
+        }
+    };
+    let provisional = file.provisional();
+    let comments = channel_tags(inner, now.timestamp_millis()).comments(session_start);
+    let serial = session_mix::serial_for(inner.channel_id.get(), session_id, reopened);
+    let mut mix = match SessionMix::new(file, serial, file_name.clone(), session_start, &comments) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Failed to init session mix writer: {}", e);
+            return;
+        }
+    };
+
+    if let Err(err) = sqlx::query!(
+        "INSERT INTO audio_mix_files
+            (file_name, guild_id, channel_id, year, month, start_ts, recording_owner_instance_id, recording_heartbeat_at, session_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, now(), NULLIF($8::BIGINT, 0))",
+        file_name,
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        session_start.year(),
+        session_start.month() as i32,
+        session_start.timestamp_millis(),
+        inner.recording_owner_instance_id,
+        session_id
+    )
+    .execute(&inner.pool)
+    .await
+    {
+        // Dropping the still provisional mix deletes its file again.
+        error!("{}", err);
+        inner.metrics.db_insert_failures.fetch_add(1, Ordering::Relaxed);
+        inner.metrics.db_query_errors.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    provisional.keep();
+
+    // A mix opened after the session began, e.g. one reopened after a write
+    // error, starts with silence up to the session start its name promises.
+    // Counted only now, as ticks during the insert did not reach this mix.
+    let mut slot = inner.session_mix.lock().await;
+    let lead_in = silence_frames_for_gap_ms(
+        chrono::Utc::now()
+            .signed_duration_since(session_start)
+            .num_milliseconds(),
+    );
+    if let Err(e) = mix.write_silence(lead_in) {
+        drop(slot);
+        error!("Session mix writer error: {}. Closing mix.", e);
+        close_session_mix(inner, mix, chrono::Utc::now()).await;
+        return;
+    }
+    info!(file_name = %file_name, lead_in_frames = lead_in, "session mix started");
+    *slot = Some(mix);
+}
+
+/// Guild/channel part of the OpusTags comments. Names come from the cache
+/// and are left out if it has not seen the guild yet.
+fn channel_tags(inner: &InnerReceiver, now_ms: i64) -> RecordingTags {
+    let (guild_name, channel_name) = match inner.ctx_main.cache.guild(inner.guild_id) {
+        Some(guild) => (
+            Some(guild.name.clone()),
+            guild
+                .channels
+                .get(&inner.channel_id)
+                .map(|channel| channel.name.clone()),
+        ),
+        None => (None, None),
+    };
+    let session_start_ms = match inner.session_start_ms.load(Ordering::SeqCst) {
+        0 => now_ms,
+        start => start,
+    };
+    RecordingTags {
+        guild_id: inner.guild_id.get(),
+        guild_name,
+        channel_id: inner.channel_id.get(),
+        channel_name,
+        session_start_ms,
+        instance_id: inner.recording_owner_instance_id.clone(),
+        ..Default::default()
+    }
+}
+
+/// Write `<stem>.cue` next to a finalized recording listing the stamps that
+/// landed in it. Nothing is written for files without stamps.
+async fn write_stamp_cue(
+    inner: &Arc<InnerReceiver>,
+    file_name: &str,
+    start_time: chrono::DateTime<chrono::Utc>,
+) {
+    let stamps = match sqlx::query!(
+        "SELECT s.stamp_ts, s.offset_ms, s.note, COALESCE(u.global_name, u.username) AS stamper
+           FROM stamps s
+           JOIN audio_files a ON a.id = s.audio_file_id
+           LEFT JOIN user_names u ON u.user_id = s.stamper_user_id
+          WHERE a.file_name = $1
+          ORDER BY s.stamp_ts + s.offset_ms",
+        file_name
+    )
+    .fetch_all(&inner.pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(err) => {
+            error!("{}", err);
+            inner
+                .metrics
+                .db_query_errors
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+    };
+    if stamps.is_empty() {
+        return;
+    }
+
+    let start_ms = start_time.timestamp_millis();
+    let points: Vec<CuePoint> = stamps
+        .into_iter()
+        .map(|stamp| CuePoint {
+            offset_ms: recording_tags::stamp_offset_ms(start_ms, stamp.stamp_ts, stamp.offset_ms),
+            title: stamp.note.unwrap_or_else(|| {
+                format!("Stamp by {}", stamp.stamper.as_deref().unwrap_or("unknown"))
+            }),
+        })
+        .collect();
+
+    let key = RecordingKey::new(
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        start_time.year(),
+        start_time.month(),
+        file_name.to_string(),
+    );
+    let dir_path = key.recording_dir(RECORDING_ROOT);
+    let cue = recording_tags::render_cue(&format!("{}.ogg", file_name), file_name, &points);
+    let cue_path = dir_path.join(format!("{}.cue", file_name));
+    if let Err(err) = tokio::fs::write(&cue_path, cue).await {
+        error!("Failed to write cue sheet {}: {}", cue_path.display(), err);
+    }
+}
+
+async fn finalize_session_mix(
+    inner: &Arc<InnerReceiver>,
+    close_time: chrono::DateTime<chrono::Utc>,
+) {
+    let _starting = inner.session_mix_start.lock().await;
+    let Some(mix) = inner.session_mix.lock().await.take() else {
+        return;
+    };
+    close_session_mix(inner, mix, close_time).await;
+}
+
+/// Finish a mix already taken out of its slot and record its length.
+async fn close_session_mix(
+    inner: &Arc<InnerReceiver>,
+    mut mix: SessionMix,
+    close_time: chrono::DateTime<chrono::Utc>,
+) {
+    if let Err(e) = mix.finish() {
+        error!("Failed to finalize session mix {}: {}", mix.file_name, e);
+        inner.metrics.track_recording_finalize_error();
+    }
+
+    let time_elapsed = close_time
+        .signed_duration_since(mix.start_time)
+        .num_milliseconds();
+    if let Err(err) = sqlx::query!(
+        "UPDATE audio_mix_files
+            SET end_ts = start_ts + $1,
+                recording_heartbeat_at = NULL
+          WHERE file_name = $2",
+        time_elapsed,
+        mix.file_name
+    )
+    .execute(&inner.pool)
+    .await
+    {
+        error!("{}", err);
+        inner
+            .metrics
+            .db_query_errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 async fn clear_receiver_state(inner: &Arc<InnerReceiver>) {
     inner.user_id_hashmap.write().await.clear();
-    inner.paused_recordings.write().await.clear();
+    let paused: Vec<_> = inner.paused_recordings.write().await.drain().collect();
+    if let Some(live_audio) = inner.live_audio.as_ref() {
+        for (user_id, paused) in paused {
+            let rec = paused.recording.lock().await;
+            live_audio.unregister(inner.guild_id.get(), user_id, &rec.recent);
+        }
+    }
     inner.bot_ssrcs.write().await.clear();
     inner.bot_user_id_hashmap.write().await.clear();
+    inner.opted_out_users.write().await.clear();
     inner.session_start_ms.store(0, Ordering::SeqCst);
+    inner.session_id.store(0, Ordering::SeqCst);
+    inner.session_mixes.store(0, Ordering::SeqCst);
+}
+
+/// Return the open `voice_sessions` row, creating it for the first human
+/// recorded in the channel. `None` if the insert failed; recordings still
+/// proceed, just without a session.
+async fn ensure_session(
+    inner: &Arc<InnerReceiver>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<i64> {
+    let _guard = inner.session_lock.lock().await;
+    match inner.session_id.load(Ordering::SeqCst) {
+        0 => {}
+        id => return Some(id),
+    }
+
+    let now_ms = now.timestamp_millis();
+    let id = match sqlx::query_scalar!(
+        "INSERT INTO voice_sessions (guild_id, channel_id, start_ts, recording_owner_instance_id)
+         VALUES ($1, $2, $3, $4)
+         RETURNING id",
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        now_ms,
+        inner.recording_owner_instance_id
+    )
+    .fetch_one(&inner.pool)
+    .await
+    {
+        Ok(id) => id,
+        Err(err) => {
+            error!("voice session insert failed: {}", err);
+            inner
+                .metrics
+                .db_insert_failures
+                .fetch_add(1, Ordering::Relaxed);
+            inner
+                .metrics
+                .db_query_errors
+                .fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+    };
+
+    inner.session_id.store(id, Ordering::SeqCst);
+    let _ = inner
+        .session_start_ms
+        .compare_exchange(0, now_ms, Ordering::SeqCst, Ordering::SeqCst);
+    info!(session_id = id, "voice session started");
+    Some(id)
+}
+
+async fn refresh_session_participants(inner: &Arc<InnerReceiver>) {
+    let session_id = inner.session_id.load(Ordering::SeqCst);
+    if session_id == 0 {
+        return;
+    }
+    if let Err(err) = sqlx::query!(
+        "UPDATE voice_sessions
+            SET participant_count = (
+                SELECT COUNT(DISTINCT user_id) FROM audio_files
+                 WHERE session_id = $1 AND NOT is_bot_track
+            )
+          WHERE id = $1",
+        session_id
+    )
+    .execute(&inner.pool)
+    .await
+    {
+        error!("voice session participant update failed: {}", err);
+        inner
+            .metrics
+            .db_query_errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Close the session once nothing in the channel is being recorded. Paused
+/// recordings keep it open so a rejoin continues the same session.
+async fn close_session_if_idle(
+    inner: &Arc<InnerReceiver>,
+    close_time: chrono::DateTime<chrono::Utc>,
+) {
+    let _guard = inner.session_lock.lock().await;
+    let session_id = inner.session_id.load(Ordering::SeqCst);
+    if session_id == 0
+        || !inner.ssrc_writer_hashmap.read().await.is_empty()
+        || !inner.paused_recordings.read().await.is_empty()
+    {
+        return;
+    }
+
+    match sqlx::query!(
+        "UPDATE voice_sessions
+            SET end_ts = $2,
+                participant_count = (
+                    SELECT COUNT(DISTINCT user_id) FROM audio_files
+                 WHERE session_id = $1 AND NOT is_bot_track
+                )
+          WHERE id = $1 AND end_ts IS NULL",
+        session_id,
+        close_time.timestamp_millis()
+    )
+    .execute(&inner.pool)
+    .await
+    {
+        Ok(_) => {
+            inner.session_id.store(0, Ordering::SeqCst);
+            inner.session_start_ms.store(0, Ordering::SeqCst);
+            inner.session_mixes.store(0, Ordering::SeqCst);
+            info!(session_id, "voice session closed");
+        }
+        Err(err) => {
+            error!("voice session close failed: {}", err);
+            inner
+                .metrics
+                .db_query_errors
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
 }
 
 /// Close the writer for `ssrc`, run the audio_files DB update, decrement
@@ -1003,17 +2077,26 @@ async fn finalize_recording_arc(
     // and let the Arc drop naturally after this scope.
     let mut rec = arc.lock().await;
 
+    if let Some(live_audio) = inner.live_audio.as_ref() {
+        live_audio.unregister(inner.guild_id.get(), rec.user_id, &rec.recent);
+    }
+    if let Some(segment) = rec.finish_speech() {
+        inner.pending_speech.lock().await.push(segment);
+    }
+    if let Err(e) = rec.release_held() {
+        error!("Failed to write held packets for ssrc {}: {}", ssrc, e);
+    }
     if let Err(e) = rec.writer.finish() {
         error!("Failed to finalize writer for ssrc {}: {}", ssrc, e);
         inner.metrics.track_recording_finalize_error();
-        let _ = sqlx::query(
-            "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)"
+        let _ = sqlx::query!(
+            "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)",
+            inner.guild_id.get() as i64,
+            rec.user_id as i64,
+            ssrc as i64,
+            VoiceEventType::WriterError as i32,
+            format!("finish: {}", e)
         )
-        .bind(inner.guild_id.get() as i64)
-        .bind(rec.user_id as i64)
-        .bind(ssrc as i64)
-        .bind(VoiceEventType::WriterError as i32)
-        .bind(format!("finish: {}", e))
         .execute(&inner.pool)
         .await;
     }
@@ -1036,17 +2119,59 @@ async fn finalize_recording_arc(
     let file_name = rec.file_name.clone();
     let user_id = rec.user_id;
     let rec_ssrc = rec.ssrc;
+    let start_time = rec.start_time;
+    let timeline = rec.timeline.clone();
+    let segment_close = rec.segment_close.take();
     drop(rec);
 
+    // The current segment's row may still be going in.
+    if let Some(segment_close) = segment_close {
+        let _ = segment_close.await;
+    }
+    update_closed_audio_file(inner, &file_name, time_elapsed, state, &timeline).await;
+    flush_speech_segments(inner).await;
+
+    let _ = sqlx::query!(
+        "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)",
+        inner.guild_id.get() as i64,
+        user_id as i64,
+        rec_ssrc as i64,
+        event_type as i32,
+        "Writer closed"
+    )
+    .execute(&inner.pool)
+    .await;
+
+    write_stamp_cue(inner, &file_name, start_time).await;
+    close_session_if_idle(inner, close_time).await;
+}
+
+/// Record the final length, leave state and RTP counters of a closed file.
+async fn update_closed_audio_file(
+    inner: &Arc<InnerReceiver>,
+    file_name: &str,
+    time_elapsed: i64,
+    state: i32,
+    timeline: &RtpTimeline,
+) {
+    let lost_packets = timeline.lost_packets as i64;
+    let concealed_frames = timeline.concealed_frames as i64;
+    let reordered_packets = timeline.reordered_packets as i64;
     if let Err(err) = sqlx::query!(
         "UPDATE audio_files
             SET end_ts = audio_files.start_ts + $1,
                 state_leave = $2,
-                recording_heartbeat_at = NULL
+                recording_heartbeat_at = NULL,
+                lost_packets = $4,
+                concealed_frames = $5,
+                reordered_packets = $6
             WHERE file_name = $3",
         time_elapsed,
         state,
-        file_name
+        file_name,
+        lost_packets,
+        concealed_frames,
+        reordered_packets
     )
     .execute(&inner.pool)
     .await
@@ -1057,17 +2182,207 @@ async fn finalize_recording_arc(
             .db_query_errors
             .fetch_add(1, Ordering::Relaxed);
     }
+}
+
+/// Once the current segment reaches the configured length or size, finish
+/// it and continue the recording in a new file starting at the sample the
+/// old one ended on. Only the writer swap happens here; the new row, the old
+/// row's close-out and its stamp cue are written by `close_rotated_segment`
+/// off the voice task. A rotation whose files cannot be created keeps
+/// writing to the current file and is retried a minute of audio later.
+fn rotate_segment_if_due(inner: &Arc<InnerReceiver>, rec: &mut UserRecording) {
+    let frames = rec.writer.frames_written();
+    if frames < rec.rotation_backoff_until
+        || !inner.segment_limits.due(frames, rec.writer.bytes_written())
+    {
+        return;
+    }
 
-    let _ = sqlx::query(
-        "INSERT INTO voice_events_audit (guild_id, user_id, ssrc, event_type_id, details) VALUES ($1, $2, $3, $4, $5)"
+    let start = segment_rotation::next_segment_start(rec.start_time, frames);
+    let Some((file_name, writer)) = open_next_segment(inner, rec, start) else {
+        rec.rotation_backoff_until = frames + ROTATION_RETRY_FRAMES;
+        return;
+    };
+
+    let speech = rec.finish_speech();
+    let mut previous_writer = std::mem::replace(&mut rec.writer, writer);
+    if let Err(e) = previous_writer.finish() {
+        error!("Failed to finalize segment {}: {}", rec.file_name, e);
+        inner.metrics.track_recording_finalize_error();
+    }
+    drop(previous_writer);
+
+    if let Ok(mut recent) = rec.recent.lock() {
+        recent.set_file_name(file_name.clone());
+    }
+    let previous = ClosedSegment {
+        file_name: std::mem::replace(&mut rec.file_name, file_name),
+        start_time: std::mem::replace(&mut rec.start_time, start),
+        end_time: start,
+        timeline: rec.timeline.clone(),
+    };
+    rec.timeline.clear_counters();
+    rec.segment_index += 1;
+    rec.rotation_backoff_until = 0;
+
+    info!(
+        user_id = rec.user_id,
+        previous = %previous.file_name,
+        next = %rec.file_name,
+        segment_index = rec.segment_index,
+        "Rotated recording segment"
+    );
+
+    let inner = inner.clone();
+    let next = NextSegment {
+        file_name: rec.file_name.clone(),
+        user_id: rec.user_id,
+        segment_index: rec.segment_index,
+        start_time: start,
+    };
+    let earlier = rec.segment_close.take();
+    rec.segment_close = Some(tokio::spawn(async move {
+        if let Some(earlier) = earlier {
+            let _ = earlier.await;
+        }
+        if let Some(segment) = speech {
+            inner.pending_speech.lock().await.push(segment);
+        }
+        close_rotated_segment(&inner, previous, next).await;
+    }));
+}
+
+/// The segment a rotation finished.
+struct ClosedSegment {
+    file_name: String,
+    start_time: chrono::DateTime<chrono::Utc>,
+    end_time: chrono::DateTime<chrono::Utc>,
+    timeline: RtpTimeline,
+}
+
+/// The segment a rotation continued the recording in.
+struct NextSegment {
+    file_name: String,
+    user_id: u64,
+    segment_index: i32,
+    start_time: chrono::DateTime<chrono::Utc>,
+}
+
+/// Insert the row for `next`, linked to the one it continues, then close
+/// `previous` and write its stamp cue. A segment whose row cannot be inserted
+/// keeps recording; `fbi-agent-tools reindex` adopts its file later.
+async fn close_rotated_segment(
+    inner: &Arc<InnerReceiver>,
+    previous: ClosedSegment,
+    next: NextSegment,
+) {
+    let start = next.start_time;
+    if let Err(err) = sqlx::query!(
+        "INSERT INTO audio_files
+            (file_name, guild_id, channel_id, user_id, year, month, start_ts, state_enter,
+             recording_owner_instance_id, recording_heartbeat_at, previous_file_id, segment_index,
+             storage_mode, session_id, is_bot_track)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now(),
+                 (SELECT id FROM audio_files WHERE file_name = $10), $11, $12,
+                 (SELECT session_id FROM audio_files WHERE file_name = $10),
+                 COALESCE((SELECT is_bot_track FROM audio_files WHERE file_name = $10), FALSE))",
+        next.file_name,
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        next.user_id as i64,
+        start.year(),
+        start.month() as i32,
+        start.timestamp_millis(),
+        segment_rotation::STATE_ROTATED,
+        inner.recording_owner_instance_id,
+        previous.file_name,
+        next.segment_index,
+        storage_mode(inner)
     )
-    .bind(inner.guild_id.get() as i64)
-    .bind(user_id as i64)
-    .bind(rec_ssrc as i64)
-    .bind(event_type as i32)
-    .bind("Writer closed")
     .execute(&inner.pool)
+    .await
+    {
+        error!("Failed to insert segment {}: {}", next.file_name, err);
+        inner
+            .metrics
+            .db_insert_failures
+            .fetch_add(1, Ordering::Relaxed);
+        inner
+            .metrics
+            .db_query_errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    let time_elapsed = previous
+        .end_time
+        .signed_duration_since(previous.start_time)
+        .num_milliseconds();
+    update_closed_audio_file(
+        inner,
+        &previous.file_name,
+        time_elapsed,
+        segment_rotation::STATE_ROTATED,
+        &previous.timeline,
+    )
     .await;
+    write_stamp_cue(inner, &previous.file_name, previous.start_time).await;
+}
+
+/// Have the writer thread create a recording file, and its directory if
+/// missing. Never waits on the filesystem; a failed create surfaces on the
+/// file's next write or flush.
+fn create_recording_file(
+    inner: &InnerReceiver,
+    path: impl AsRef<std::path::Path>,
+) -> std::io::Result<QueuedFile> {
+    let Some(disk_writer) = inner.disk_writer.as_ref() else {
+        return Err(std::io::Error::other("recording writer thread unavailable"));
+    };
+    let sealer = crate::encryption::keyring().sealer()?;
+    disk_writer.create(path.as_ref(), sealer)
+}
+
+/// `audio_files.storage_mode`: 0 = padded, 1 = speech-only with `.idx`.
+fn storage_mode(inner: &InnerReceiver) -> i16 {
+    i16::from(inner.sparse_storage)
+}
+
+/// Create a recording's `.ogg`, and in speech-only mode its `.idx` sidecar,
+/// and start a writer on them. Both files are deleted again unless the
+/// returned handles are kept.
+fn create_segment_writer(
+    inner: &InnerReceiver,
+    ogg_path: &std::path::Path,
+    ssrc: u32,
+    comments: &[String],
+    start_ms: i64,
+) -> std::io::Result<(OggOpusWriter<QueuedFile>, Vec<Provisional>)> {
+    let file = create_recording_file(inner, ogg_path)?;
+    let mut provisional = vec![file.provisional()];
+    let writer = OggOpusWriter::with_comments(file, ssrc, 0, comments)?;
+    if !inner.sparse_storage {
+        return Ok((writer, provisional));
+    }
+    let index_file = create_recording_file(inner, ogg_path.with_extension("idx"))?;
+    provisional.push(index_file.provisional());
+    let index = SparseIndex::new(index_file, start_ms)?;
+    Ok((writer.into_sparse(index), provisional))
+}
+
+fn new_loss_recovery() -> Option<LossRecovery> {
+    if !crate::config::loss_recovery_enabled() {
+        return None;
+    }
+    match LossRecovery::new() {
+        Ok(recovery) => Some(recovery),
+        Err(err) => {
+            warn!(
+                "loss recovery unavailable, falling back to silence: {}",
+                err
+            );
+            None
+        }
+    }
 }
 
 #[tracing::instrument(skip_all, name = "create_path")]
@@ -1076,6 +2391,8 @@ async fn create_path(
     now: chrono::DateTime<chrono::Utc>,
     user_id: u64,
     is_channel_empty: bool,
+    session_id: Option<i64>,
+    bot_track: bool,
 ) -> Option<String> {
     let guild_id = _self.inner.guild_id;
     let channel_id = _self.inner.channel_id;
@@ -1091,7 +2408,7 @@ async fn create_path(
     let dir_path = key.recording_dir(RECORDING_ROOT);
     let combined_path = key.recording_dir(RECORDING_ROOT).join(&file_name);
 
-    if let Err(err) = std::fs::create_dir_all(&dir_path) {
+    if let Err(err) = tokio::fs::create_dir_all(&dir_path).await {
         error!("cannot create path {}: {}", dir_path.display(), err);
         return None;
     };
@@ -1100,8 +2417,8 @@ async fn create_path(
 
     match sqlx::query!(
         "INSERT INTO audio_files
-	(file_name, guild_id, channel_id, user_id, year, month, start_ts, end_ts, state_enter, recording_owner_instance_id, recording_heartbeat_at) VALUES
-	($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now())",
+	(file_name, guild_id, channel_id, user_id, year, month, start_ts, end_ts, state_enter, recording_owner_instance_id, recording_heartbeat_at, storage_mode, session_id, is_bot_track) VALUES
+	($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now(), $11, $12, $13)",
         file_name,
         guild_id.get() as i64,
         channel_id.get() as i64,
@@ -1111,7 +2428,10 @@ async fn create_path(
         now.timestamp_millis(),
         null,
         if is_channel_empty { 1 } else { 2 },
-        _self.inner.recording_owner_instance_id.clone()
+        _self.inner.recording_owner_instance_id.clone(),
+        storage_mode(&_self.inner),
+        session_id,
+        bot_track
     )
     .execute(&_self.inner.pool)
     .await
@@ -1136,6 +2456,45 @@ async fn create_path(
     Some(combined_path.to_string_lossy().into_owned())
 }
 
+/// Create the files for the segment after `rec` and start its writer. Its
+/// `audio_files` row is inserted by `close_rotated_segment`.
+fn open_next_segment(
+    inner: &Arc<InnerReceiver>,
+    rec: &UserRecording,
+    start: chrono::DateTime<chrono::Utc>,
+) -> Option<(String, OggOpusWriter<QueuedFile>)> {
+    let file_name = RecordingKey::stem_for(start.timestamp_millis(), rec.user_id as i64);
+    let key = RecordingKey::new(
+        inner.guild_id.get() as i64,
+        inner.channel_id.get() as i64,
+        start.year(),
+        start.month(),
+        file_name.clone(),
+    );
+    let path = key
+        .recording_dir(RECORDING_ROOT)
+        .join(format!("{}.ogg", file_name));
+    match create_segment_writer(
+        inner,
+        &path,
+        rec.ssrc,
+        &rec.tags.comments(start),
+        start.timestamp_millis(),
+    ) {
+        Ok((writer, provisional)) => {
+            provisional.into_iter().for_each(Provisional::keep);
+            Some((file_name, writer))
+        }
+        Err(e) => {
+            error!("Failed to create segment file {}: {}", path.display(), e);
+            inner
+                .metrics
+                .track_ffmpeg_spawn_failure(&inner.guild_metrics, &inner.channel_metrics);
+            None
+        }
+    }
+}
+
 async fn heartbeat_active_recordings(inner: &Arc<InnerReceiver>) {
     let mut file_names = HashSet::new();
     {
@@ -1153,20 +2512,42 @@ async fn heartbeat_active_recordings(inner: &Arc<InnerReceiver>) {
         }
     }
 
+    let mix_file_name = inner
+        .session_mix
+        .lock()
+        .await
+        .as_ref()
+        .map(|mix| mix.file_name.clone());
+    if let Some(mix_file_name) = mix_file_name
+        && let Err(err) = sqlx::query!(
+            "UPDATE audio_mix_files
+                SET recording_heartbeat_at = now()
+              WHERE file_name = $1
+                AND recording_owner_instance_id = $2
+                AND end_ts IS NULL",
+            mix_file_name,
+            inner.recording_owner_instance_id
+        )
+        .execute(&inner.pool)
+        .await
+    {
+        warn!("session mix heartbeat failed: {}", err);
+    }
+
     if file_names.is_empty() {
         return;
     }
 
     let file_names = file_names.into_iter().collect::<Vec<_>>();
-    if let Err(err) = sqlx::query(
+    if let Err(err) = sqlx::query!(
         "UPDATE audio_files
             SET recording_heartbeat_at = now()
           WHERE file_name = ANY($1)
             AND recording_owner_instance_id = $2
             AND end_ts IS NULL",
+        &file_names[..],
+        inner.recording_owner_instance_id
     )
-    .bind(&file_names)
-    .bind(&inner.recording_owner_instance_id)
     .execute(&inner.pool)
     .await
     {
@@ -1174,6 +2555,53 @@ async fn heartbeat_active_recordings(inner: &Arc<InnerReceiver>) {
     }
 }
 
+/// Insert all buffered speaking segments in one statement.
+async fn flush_speech_segments(inner: &Arc<InnerReceiver>) {
+    let segments = std::mem::take(&mut *inner.pending_speech.lock().await);
+    if segments.is_empty() {
+        return;
+    }
+
+    let mut file_names = Vec::with_capacity(segments.len());
+    let mut starts = Vec::with_capacity(segments.len());
+    let mut ends = Vec::with_capacity(segments.len());
+    let mut peaks = Vec::with_capacity(segments.len());
+    for segment in segments {
+        file_names.push(segment.file_name);
+        starts.push(segment.start_ms);
+        ends.push(segment.end_ms);
+        peaks.push(segment.peak_level);
+    }
+
+    if let Err(err) = sqlx::query!(
+        "INSERT INTO speech_segments (audio_file_id, start_ms, end_ms, peak_level)
+         SELECT a.id, s.start_ms, s.end_ms, s.peak_level
+           FROM UNNEST($1::text[], $2::bigint[], $3::bigint[], $4::real[])
+                AS s(file_name, start_ms, end_ms, peak_level)
+           JOIN audio_files a ON a.file_name = s.file_name",
+        &file_names[..],
+        &starts[..],
+        &ends[..],
+        &peaks[..]
+    )
+    .execute(&inner.pool)
+    .await
+    {
+        error!(
+            dropped = file_names.len(),
+            "speech segment insert failed: {}", err
+        );
+        inner
+            .metrics
+            .db_insert_failures
+            .fetch_add(1, Ordering::Relaxed);
+        inner
+            .metrics
+            .db_query_errors
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,241 @@
+//! Recordings as 20 ms frames of 48 kHz stereo PCM.
+//!
+//! [`Packets`] walks one recording's Opus packets in timeline order,
+//! giving silence where a speech-only recording's index has no run, and a
+//! [`Track`] decodes them. A [`Mixer`] lines several tracks up by start time
+//! and sums them.
+
+use std::collections::VecDeque;
+use std::io;
+
+use symphonia::core::io::MediaSource;
+
+use crate::events::sparse_index::{ParsedIndex, Run};
+use crate::storage::ClipSource;
+
+pub const FRAME_MS: i64 = 20;
+/// Interleaved stereo samples in one frame.
+pub const FRAME_SAMPLES: usize = 960 * 2;
+/// Largest Opus frame (120 ms) in interleaved stereo samples.
+const MAX_DECODED_SAMPLES: usize = 5760 * 2;
+
+/// A recording's Opus packets in timeline order, one per 20 ms frame.
+pub struct Packets {
+    reader: ogg::PacketReader<Box<dyn MediaSource>>,
+    /// Runs still ahead of `frame` in a speech-only recording.
+    runs: Option<VecDeque<Run>>,
+    frame: u64,
+}
+
+impl Packets {
+    /// Open a recording. `index` is the parsed `.idx` of a speech-only one.
+    pub fn open(source: &ClipSource, index: Option<ParsedIndex>) -> io::Result<Self> {
+        let mut reader = ogg::PacketReader::new(source.open()?);
+        let head = reader.read_packet_expected().map_err(io::Error::other)?;
+        if head.data.len() < 19 || &head.data[..8] != b"OpusHead" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "recording is not Ogg/Opus",
+            ));
+        }
+        reader.read_packet_expected().map_err(io::Error::other)?; // OpusTags
+        Ok(Self {
+            reader,
+            runs: index.map(|index| index.runs.into()),
+            frame: 0,
+        })
+    }
+
+    /// The next frame's packet, or `None` where the timeline is silent:
+    /// outside a speech-only recording's runs and past the end of the file.
+    pub fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let frame = self.frame;
+        self.frame += 1;
+        if let Some(runs) = self.runs.as_mut() {
+            while runs
+                .front()
+                .is_some_and(|run| frame >= run.logical_start + run.frames as u64)
+            {
+                runs.pop_front();
+            }
+            if runs.front().is_none_or(|run| frame < run.logical_start) {
+                return Ok(None);
+            }
+        }
+
+        loop {
+            let Some(packet) = self.reader.read_packet().map_err(io::Error::other)? else {
+                return Ok(None);
+            };
+            // The empty packet of the EOS page.
+            if !packet.data.is_empty() {
+                return Ok(Some(packet.data));
+            }
+        }
+    }
+
+    /// Read and drop `frames` frames.
+    pub fn skip(&mut self, frames: u64) -> io::Result<()> {
+        for _ in 0..frames {
+            self.next_packet()?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Track {
+    packets: Packets,
+    decoder: opus2::Decoder,
+    decoded: Vec<i16>,
+}
+
+impl Track {
+    /// Open a recording. `index` is the parsed `.idx` of a speech-only one.
+    pub fn open(source: &ClipSource, index: Option<ParsedIndex>) -> io::Result<Self> {
+        use opus2::{Channels, Decoder};
+        let decoder = Decoder::new(48000, Channels::Stereo)
+            .map_err(|err| io::Error::other(format!("opus decoder init: {}", err)))?;
+        Ok(Self {
+            packets: Packets::open(source, index)?,
+            decoder,
+            decoded: vec![0; MAX_DECODED_SAMPLES],
+        })
+    }
+
+    /// Decode the next frame of the timeline into `out`. Past the end of
+    /// the file this is silence.
+    pub fn next_frame(&mut self, out: &mut [i16]) -> io::Result<()> {
+        let Some(packet) = self.packets.next_packet()? else {
+            out.fill(0);
+            return Ok(());
+        };
+        let per_channel = self
+            .decoder
+            .decode(&packet, &mut self.decoded, false)
+            .map_err(|err| io::Error::other(format!("opus decode: {}", err)))?;
+        let n = (per_channel * 2).min(out.len());
+        out[..n].copy_from_slice(&self.decoded[..n]);
+        out[n..].fill(0);
+        Ok(())
+    }
+
+    /// Skip `frames` frames without decoding them.
+    pub fn skip(&mut self, frames: u64) -> io::Result<()> {
+        self.packets.skip(frames)
+    }
+}
+
+struct Placed {
+    track: Track,
+    /// Output frame the track's frame 0 lands on; negative when the track
+    /// starts before the exported range.
+    offset: i64,
+    frames: u64,
+}
+
+/// Sums tracks placed on a shared timeline.
+pub struct Mixer {
+    tracks: Vec<Placed>,
+    frame: i64,
+    scratch: Vec<i16>,
+    sum: Vec<i32>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            tracks: Vec::new(),
+            frame: 0,
+            scratch: vec![0; FRAME_SAMPLES],
+            sum: vec![0; FRAME_SAMPLES],
+        }
+    }
+
+    /// Add a track `frames` long whose first frame plays at output frame
+    /// `offset`.
+    pub fn add(&mut self, mut track: Track, offset: i64, frames: u64) -> io::Result<()> {
+        if offset < 0 {
+            track.skip((-offset) as u64)?;
+        }
+        self.tracks.push(Placed {
+            track,
+            offset,
+            frames,
+        });
+        Ok(())
+    }
+
+    /// Mix the next output frame into `out`.
+    pub fn next_frame(&mut self, out: &mut [i16]) -> io::Result<()> {
+        let frame = self.frame;
+        self.frame += 1;
+        self.sum.fill(0);
+        for placed in &mut self.tracks {
+            let local = frame - placed.offset;
+            if local < 0 || local as u64 >= placed.frames {
+                continue;
+            }
+            placed.track.next_frame(&mut self.scratch)?;
+            for (sum, sample) in self.sum.iter_mut().zip(&self.scratch) {
+                *sum += *sample as i32;
+            }
+        }
+        for (out, sum) in out.iter_mut().zip(&self.sum) {
+            *out = (*sum).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ogg_opus_writer::OggOpusWriter;
+    use crate::events::sparse_index::SparseIndex;
+    use std::io::Cursor;
+
+    fn tone_packet() -> Result<Vec<u8>, opus2::Error> {
+        use opus2::{Application, Channels, Encoder};
+        let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio)?;
+        let pcm: Vec<i16> = (0..FRAME_SAMPLES)
+            .map(|i| ((i as f32 / 20.0).sin() * 8000.0) as i16)
+            .collect();
+        let mut packet = vec![0; 4000];
+        let n = encoder.encode(&pcm, &mut packet)?;
+        packet.truncate(n);
+        Ok(packet)
+    }
+
+    #[test]
+    fn sparse_tracks_are_silent_outside_their_runs() -> Result<(), Box<dyn std::error::Error>> {
+        let mut ogg = Vec::new();
+        let mut idx = Vec::new();
+        {
+            let index = SparseIndex::new(Cursor::new(&mut idx), 0)?;
+            let mut writer = OggOpusWriter::new(Cursor::new(&mut ogg), 1, 0)?.into_sparse(index);
+            writer.write_silence(3)?;
+            let tone = tone_packet()?;
+            writer.write_packet(&tone)?;
+            writer.write_packet(&tone)?;
+            writer.finish()?;
+        }
+        let index = crate::events::sparse_index::parse_index(&idx)?;
+        assert_eq!(index.logical_frames(), 5);
+
+        let mut track = Track::open(&ClipSource::Memory(ogg.into()), Some(index))?;
+        let mut frame = vec![0; FRAME_SAMPLES];
+        for _ in 0..3 {
+            track.next_frame(&mut frame)?;
+            assert!(frame.iter().all(|s| *s == 0));
+        }
+        track.next_frame(&mut frame)?;
+        assert!(frame.iter().any(|s| *s != 0));
+        Ok(())
+    }
+}
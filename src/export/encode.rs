@@ -0,0 +1,519 @@
+//! Output encoders for 48 kHz 16-bit stereo PCM.
+//!
+//! WAV and FLAC are written here; their headers only need the total length,
+//! which an export knows up front, so both stream straight to the output
+//! without seeking. The FLAC encoder uses fixed blocks with constant,
+//! verbatim or fixed-predictor subframes and Rice-coded residuals: larger
+//! files than libFLAC, but lossless and readable by every decoder. MP3 goes
+//! through LAME.
+
+use std::io::{self, Write};
+
+use super::ExportFormat;
+
+const SAMPLE_RATE: u32 = 48_000;
+const CHANNELS: usize = 2;
+
+/// Interleaved stereo frames in, encoded bytes out.
+pub trait PcmSink {
+    fn write(&mut self, pcm: &[i16]) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// A sink for `format` writing to `out`. `total_samples` is the number of
+/// samples per channel that will be written.
+pub fn sink<'a, W: Write + 'a>(
+    format: ExportFormat,
+    out: W,
+    total_samples: u64,
+) -> io::Result<Box<dyn PcmSink + 'a>> {
+    Ok(match format {
+        ExportFormat::Wav => Box::new(WavSink::new(out, total_samples)?),
+        ExportFormat::Flac => Box::new(FlacSink::new(out, total_samples)?),
+        ExportFormat::Mp3 => Box::new(Mp3Sink::new(out)?),
+    })
+}
+
+pub struct WavSink<W: Write> {
+    out: W,
+    bytes: Vec<u8>,
+}
+
+impl<W: Write> WavSink<W> {
+    pub fn new(mut out: W, total_samples: u64) -> io::Result<Self> {
+        let riff_len = u32::try_from(36 + total_samples * CHANNELS as u64 * 2)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too long for WAV"))?;
+        let data_len = riff_len - 36;
+        let block_align = CHANNELS as u16 * 2;
+        let mut header = Vec::with_capacity(44);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&riff_len.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&(CHANNELS as u16).to_le_bytes());
+        header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        header.extend_from_slice(&(SAMPLE_RATE * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_len.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(Self {
+            out,
+            bytes: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> PcmSink for WavSink<W> {
+    fn write(&mut self, pcm: &[i16]) -> io::Result<()> {
+        self.bytes.clear();
+        self.bytes.extend(pcm.iter().flat_map(|s| s.to_le_bytes()));
+        self.out.write_all(&self.bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Samples per channel in each FLAC frame.
+const FLAC_BLOCK: usize = 4096;
+const RICE_MAX_PARAM: u32 = 14;
+/// Subframe header bytes: zero pad bit, 6-bit type, no wasted bits. The
+/// fixed predictor's order goes in the low bits of its type.
+const SUBFRAME_CONSTANT: u64 = 0x00;
+const SUBFRAME_VERBATIM: u64 = 0x02;
+const SUBFRAME_FIXED: u64 = 0x10;
+
+const fn crc8_table() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC8_TABLE: [u8; 256] = crc8_table();
+static CRC16_TABLE: [u16; 256] = crc16_table();
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter()
+        .fold(0, |crc, &b| CRC8_TABLE[(crc ^ b) as usize])
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &b| {
+        (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ b) as usize]
+    })
+}
+
+/// MSB-first bit packing.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn put_signed(&mut self, value: i32, bits: u32) {
+        self.put(value as u32 as u64, bits);
+    }
+
+    /// `zeros` zero bits and a one.
+    fn put_unary(&mut self, mut zeros: u32) {
+        while zeros >= 32 {
+            self.put(0, 32);
+            zeros -= 32;
+        }
+        self.put(1, zeros + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.put(0, 8 - self.bits);
+        }
+    }
+}
+
+/// FLAC's UTF-8-style coding of the frame number.
+fn put_coded_number(w: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        w.put(n, 8);
+        return;
+    }
+    let mut continuation = 1;
+    while n >= 1u64 << (5 * continuation + 6) {
+        continuation += 1;
+    }
+    let marker = (0xffu64 << (7 - continuation)) & 0xff;
+    w.put(marker | (n >> (6 * continuation)), 8);
+    for i in (0..continuation).rev() {
+        w.put(0x80 | ((n >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+fn zigzag(r: i32) -> u32 {
+    ((r << 1) ^ (r >> 31)) as u32
+}
+
+/// Rice parameter minimising the coded size, with that size in bits.
+fn best_rice(residual: &[i32]) -> (u32, u64) {
+    (0..=RICE_MAX_PARAM)
+        .map(|k| {
+            let bits = residual
+                .iter()
+                .map(|&r| (zigzag(r) >> k) as u64 + 1 + k as u64)
+                .sum::<u64>();
+            (k, bits)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap_or((0, 0))
+}
+
+/// Residual of the order-`order` fixed predictor.
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i32> {
+    samples
+        .windows(order + 1)
+        .map(|w| {
+            let s = |i: usize| w[order - i];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+fn put_subframe(w: &mut BitWriter, samples: &[i32]) {
+    const BPS: u32 = 16;
+    if samples.iter().all(|&s| s == samples[0]) {
+        w.put(SUBFRAME_CONSTANT, 8);
+        w.put_signed(samples[0], BPS);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * BPS as u64;
+    let best = (0..=4usize.min(samples.len() - 1))
+        .map(|order| {
+            let residual = fixed_residual(samples, order);
+            let (k, bits) = best_rice(&residual);
+            // warm-up, coding method, partition order, parameter
+            let total = order as u64 * BPS as u64 + 2 + 4 + 4 + bits;
+            (order, residual, k, total)
+        })
+        .min_by_key(|(_, _, _, total)| *total);
+
+    match best {
+        Some((order, residual, k, total)) if total < verbatim_bits => {
+            w.put(SUBFRAME_FIXED | ((order as u64) << 1), 8);
+            for &s in &samples[..order] {
+                w.put_signed(s, BPS);
+            }
+            w.put(0b00, 2); // Rice, 4-bit parameters
+            w.put(0, 4); // one partition
+            w.put(k as u64, 4);
+            for r in residual {
+                let u = zigzag(r);
+                w.put_unary(u >> k);
+                w.put(u as u64, k);
+            }
+        }
+        _ => {
+            w.put(SUBFRAME_VERBATIM, 8);
+            for &s in samples {
+                w.put_signed(s, BPS);
+            }
+        }
+    }
+}
+
+pub struct FlacSink<W: Write> {
+    out: W,
+    left: Vec<i32>,
+    right: Vec<i32>,
+    frame_number: u64,
+}
+
+impl<W: Write> FlacSink<W> {
+    pub fn new(mut out: W, total_samples: u64) -> io::Result<Self> {
+        let mut w = BitWriter::default();
+        w.put(u32::from_be_bytes(*b"fLaC") as u64, 32);
+        w.put(1, 1); // last metadata block
+        w.put(0, 7); // STREAMINFO
+        w.put(34, 24);
+        w.put(FLAC_BLOCK as u64, 16);
+        w.put(FLAC_BLOCK as u64, 16);
+        w.put(0, 24); // frame sizes unknown
+        w.put(0, 24);
+        w.put(SAMPLE_RATE as u64, 20);
+        w.put(CHANNELS as u64 - 1, 3);
+        w.put(16 - 1, 5);
+        w.put(total_samples >> 32, 4);
+        w.put(total_samples & 0xffff_ffff, 32);
+        for _ in 0..4 {
+            w.put(0, 32); // MD5 not computed
+        }
+        out.write_all(&w.bytes)?;
+        Ok(Self {
+            out,
+            left: Vec::with_capacity(FLAC_BLOCK),
+            right: Vec::with_capacity(FLAC_BLOCK),
+            frame_number: 0,
+        })
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let block = self.left.len();
+        if block == 0 {
+            return Ok(());
+        }
+        let mut w = BitWriter::default();
+        w.put(0b11_1111_1111_1110, 14);
+        w.put(0, 1); // reserved
+        w.put(0, 1); // fixed block size
+        let block_code = if block == FLAC_BLOCK { 0b1100 } else { 0b0111 };
+        w.put(block_code, 4);
+        w.put(0b1010, 4); // 48 kHz
+        w.put(0b0001, 4); // left, right
+        w.put(0b100, 3); // 16 bits
+        w.put(0, 1);
+        put_coded_number(&mut w, self.frame_number);
+        if block_code == 0b0111 {
+            w.put(block as u64 - 1, 16);
+        }
+        let crc = crc8(&w.bytes);
+        w.put(crc as u64, 8);
+
+        put_subframe(&mut w, &self.left);
+        put_subframe(&mut w, &self.right);
+        w.align();
+        let crc = crc16(&w.bytes);
+        w.put(crc as u64, 16);
+
+        self.out.write_all(&w.bytes)?;
+        self.frame_number += 1;
+        self.left.clear();
+        self.right.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> PcmSink for FlacSink<W> {
+    fn write(&mut self, pcm: &[i16]) -> io::Result<()> {
+        for pair in pcm.chunks_exact(CHANNELS) {
+            self.left.push(pair[0] as i32);
+            self.right.push(pair[1] as i32);
+            if self.left.len() == FLAC_BLOCK {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_frame()?;
+        self.out.flush()
+    }
+}
+
+pub struct Mp3Sink<W: Write> {
+    out: W,
+    encoder: mp3lame_encoder::Encoder,
+    buf: Vec<u8>,
+}
+
+fn lame_error(e: impl std::fmt::Debug) -> io::Error {
+    io::Error::other(format!("mp3 encoder: {:?}", e))
+}
+
+impl<W: Write> Mp3Sink<W> {
+    pub fn new(out: W) -> io::Result<Self> {
+        use mp3lame_encoder::{Bitrate, Builder, Quality};
+        let mut builder = Builder::new().ok_or_else(|| lame_error("init failed"))?;
+        builder
+            .set_num_channels(CHANNELS as u8)
+            .map_err(lame_error)?;
+        builder.set_sample_rate(SAMPLE_RATE).map_err(lame_error)?;
+        builder.set_brate(Bitrate::Kbps192).map_err(lame_error)?;
+        builder.set_quality(Quality::Good).map_err(lame_error)?;
+        Ok(Self {
+            out,
+            encoder: builder.build().map_err(lame_error)?,
+            buf: Vec::new(),
+        })
+    }
+}
+
+impl<W: Write> PcmSink for Mp3Sink<W> {
+    fn write(&mut self, pcm: &[i16]) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.reserve(mp3lame_encoder::max_required_buffer_size(
+            pcm.len() / CHANNELS,
+        ));
+        self.encoder
+            .encode_to_vec(mp3lame_encoder::InterleavedPcm(pcm), &mut self.buf)
+            .map_err(lame_error)?;
+        self.out.write_all(&self.buf)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.buf.reserve(7200);
+        self.encoder
+            .flush_to_vec::<mp3lame_encoder::FlushNoGap>(&mut self.buf)
+            .map_err(lame_error)?;
+        self.out.write_all(&self.buf)?;
+        self.out.flush()
+    }
+}
+
+pub fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Wav => "wav",
+        ExportFormat::Flac => "flac",
+        ExportFormat::Mp3 => "mp3",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    /// Decode `bytes` with symphonia into interleaved samples.
+    fn decode(bytes: Vec<u8>, extension: &str) -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+        let stream = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension(extension);
+        let probed = symphonia::default::get_probe().format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?;
+        let mut format = probed.format;
+        let track = format.default_track().ok_or("no track")?;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+        let mut samples = Vec::new();
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(symphonia::core::errors::Error::IoError(e))
+                    if e.kind() == io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let decoded = decoder.decode(&packet)?;
+            let mut buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+            buf.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buf.samples());
+        }
+        Ok(samples)
+    }
+
+    fn test_signal(frames: usize) -> Vec<i16> {
+        (0..frames * CHANNELS)
+            .map(|i| match i / CHANNELS {
+                // A silent stretch, a tone and some noise-like content.
+                n if n < 3000 => 0,
+                n if n < 7000 => ((n as f32 / 11.0).sin() * 12000.0) as i16,
+                n => ((n * 7919 + i * 104_729) % 65_536) as i32 as i16,
+            })
+            .collect()
+    }
+
+    fn roundtrip(format: ExportFormat) -> Result<(), Box<dyn std::error::Error>> {
+        let pcm = test_signal(10_000);
+        let mut out = Vec::new();
+        {
+            let mut sink = sink(format, &mut out, (pcm.len() / CHANNELS) as u64)?;
+            for chunk in pcm.chunks(960 * CHANNELS) {
+                sink.write(chunk)?;
+            }
+            sink.finish()?;
+        }
+        assert_eq!(decode(out, extension(format))?, pcm);
+        Ok(())
+    }
+
+    #[test]
+    fn wav_round_trips() -> Result<(), Box<dyn std::error::Error>> {
+        roundtrip(ExportFormat::Wav)
+    }
+
+    #[test]
+    fn flac_round_trips() -> Result<(), Box<dyn std::error::Error>> {
+        roundtrip(ExportFormat::Flac)
+    }
+
+    #[test]
+    fn coded_numbers_match_utf8() {
+        for n in [0u64, 0x7f, 0x80, 0x7ff, 0x800, 0xffff, 0x1_0000] {
+            let mut w = BitWriter::default();
+            put_coded_number(&mut w, n);
+            let expected = char::from_u32(n as u32).map(|c| c.to_string().into_bytes());
+            assert_eq!(Some(w.bytes), expected, "{n:#x}");
+        }
+    }
+}
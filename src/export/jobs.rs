@@ -0,0 +1,164 @@
+//! Background export jobs.
+//!
+//! A job is keyed by its [`Plan`](super::Plan) id, so asking for the same
+//! export twice while it runs attaches to the running job instead of
+//! starting another. At most `EXPORT_MAX_CONCURRENT` jobs transcode at once;
+//! the rest stay `Queued`. Finished jobs stay visible for
+//! [`FINISHED_RETENTION`] so a client that lost its stream can look up the
+//! result.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
+use tokio::sync::{Semaphore, watch};
+use tracing::{info, warn};
+
+use super::Plan;
+use crate::storage::Storage;
+
+const FINISHED_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub state: ExportState,
+    pub frames_done: u64,
+    pub frames_total: u64,
+    /// Where the export is (or will be) written.
+    pub path: PathBuf,
+    pub error: Option<String>,
+    /// Answered from an earlier export of the same plan.
+    pub cached: bool,
+}
+
+impl Progress {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, ExportState::Done | ExportState::Failed)
+    }
+}
+
+pub struct ExportJobs {
+    jobs: DashMap<String, watch::Receiver<Progress>>,
+    permits: Arc<Semaphore>,
+}
+
+impl ExportJobs {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            jobs: DashMap::new(),
+            permits: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    /// Progress of job `id`, while it runs and for a while after.
+    pub fn watch(&self, id: &str) -> Option<watch::Receiver<Progress>> {
+        self.jobs.get(id).map(|job| job.clone())
+    }
+
+    /// Start `plan`, or attach to it if it is already running. A plan whose
+    /// output is already on disk finishes immediately as `cached`.
+    pub fn start(self: &Arc<Self>, storage: Arc<Storage>, plan: Plan) -> watch::Receiver<Progress> {
+        let mut progress = Progress {
+            state: ExportState::Queued,
+            frames_done: 0,
+            frames_total: plan.frames(),
+            path: plan.path.clone(),
+            error: None,
+            cached: false,
+        };
+        let (tx, rx) = match self.jobs.entry(plan.id.clone()) {
+            Entry::Occupied(job) => return job.get().clone(),
+            Entry::Vacant(slot) => {
+                if plan.path.exists() {
+                    progress.state = ExportState::Done;
+                    progress.frames_done = progress.frames_total;
+                    progress.cached = true;
+                    return watch::channel(progress).1;
+                }
+                let (tx, rx) = watch::channel(progress);
+                slot.insert(rx.clone());
+                (tx, rx)
+            }
+        };
+
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let id = plan.id.clone();
+            let result = jobs.run(&storage, plan, &tx).await;
+            tx.send_modify(|p| match result {
+                Ok(()) => {
+                    p.state = ExportState::Done;
+                    p.frames_done = p.frames_total;
+                }
+                Err(e) => {
+                    warn!(job = %id, error = %e, "export failed");
+                    p.state = ExportState::Failed;
+                    p.error = Some(e.to_string());
+                }
+            });
+            drop(tx);
+            tokio::time::sleep(FINISHED_RETENTION).await;
+            jobs.jobs.remove(&id);
+        });
+        rx
+    }
+
+    async fn run(
+        &self,
+        storage: &Storage,
+        plan: Plan,
+        tx: &watch::Sender<Progress>,
+    ) -> std::io::Result<()> {
+        let _permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(std::io::Error::other)?;
+        tx.send_modify(|p| p.state = ExportState::Running);
+        let started = std::time::Instant::now();
+        let inputs = super::inputs(storage, &plan).await?;
+        let progress = tx.clone();
+        let plan = tokio::task::spawn_blocking(move || {
+            super::render(&plan, inputs, |frames| {
+                progress.send_modify(|p| p.frames_done = frames)
+            })
+            .map(|()| plan)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        info!(
+            job = %plan.id,
+            path = %plan.path.display(),
+            sources = plan.sources.len(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "export finished"
+        );
+        Ok(())
+    }
+}
+
+pub struct ExportJobsKey;
+impl TypeMapKey for ExportJobsKey {
+    type Value = Arc<ExportJobs>;
+}
+
+/// The shared [`ExportJobs`], or an empty one when none was registered.
+pub async fn from_data(data: &RwLock<TypeMap>) -> Arc<ExportJobs> {
+    data.read()
+        .await
+        .get::<ExportJobsKey>()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(ExportJobs::new(crate::config::export_max_concurrent())))
+}
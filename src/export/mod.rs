@@ -0,0 +1,382 @@
+//! Export of recordings to WAV, FLAC or MP3.
+//!
+//! An export is one finished recording, or several (typically the tracks of
+//! a voice session) mixed onto one timeline, optionally trimmed to
+//! `[start_ms, end_ms)`. Opus is decoded in-process ([`decode`]) and
+//! re-encoded ([`encode`]); speech-only recordings are expanded through
+//! their index on the way.
+//!
+//! Output is cached beside the first source recording, as
+//! `<stem>-<hash>.<ext>` for a single recording and `mix-<hash>.<ext>` for a
+//! mix, where the hash covers the sources, the range and the format. A
+//! repeated request is answered from the cache. Exports are encrypted like
+//! the recordings when recording encryption is on, and retention and user
+//! erasure remove the exports beside the recordings they delete
+//! ([`remove_cached`]).
+//!
+//! [`jobs`] runs exports in the background; the `Jammer` gRPC service
+//! starts them and streams their progress.
+
+use std::io::{self, BufWriter, Read};
+use std::path::{Path, PathBuf};
+
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+
+use crate::events::sparse_index::{self, ParsedIndex};
+use crate::storage::{ClipSource, Storage};
+
+pub mod decode;
+pub mod encode;
+pub mod jobs;
+
+pub use jobs::{ExportJobs, ExportJobsKey, ExportState, Progress, from_data};
+
+use decode::{FRAME_MS, FRAME_SAMPLES, Mixer, Track};
+
+pub type ExportResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Wav,
+    Flac,
+    Mp3,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        encode::extension(self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportRequest {
+    /// `audio_files` ids to export; with `session_id`, the subset of the
+    /// session's tracks to keep (empty keeps all).
+    pub audio_file_ids: Vec<i64>,
+    pub session_id: Option<i64>,
+    /// Unix ms; `None` starts at the earliest source.
+    pub start_ms: Option<i64>,
+    /// Unix ms; `None` ends with the latest source.
+    pub end_ms: Option<i64>,
+    pub format: ExportFormat,
+}
+
+impl ExportRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.session_id.is_none() && self.audio_file_ids.is_empty() {
+            return Err("give a session or recordings to export".to_string());
+        }
+        if let (Some(start), Some(end)) = (self.start_ms, self.end_ms)
+            && end <= start
+        {
+            return Err("end_ms must be after start_ms".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One recording going into an export.
+pub struct Source {
+    pub id: i64,
+    pub key: RecordingKey,
+    pub start_ts: i64,
+    pub end_ts: i64,
+    pub sparse: bool,
+    pub storage_backend: String,
+}
+
+/// A resolved export: which recordings, which range, where it goes.
+pub struct Plan {
+    /// Stable for the same sources, range and format.
+    pub id: String,
+    pub sources: Vec<Source>,
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub format: ExportFormat,
+    pub path: PathBuf,
+}
+
+impl Plan {
+    pub fn frames(&self) -> u64 {
+        ((self.end_ms - self.start_ms) / FRAME_MS) as u64
+    }
+}
+
+/// Resolve `request` against `audio_files`. `None` when no finished
+/// recording overlaps the range.
+pub async fn plan(pool: &Pool<Postgres>, request: &ExportRequest) -> ExportResult<Option<Plan>> {
+    request.validate()?;
+    let rows = sqlx::query!(
+        r#"SELECT id, guild_id, channel_id, year, month, file_name,
+                  start_ts AS "start_ts!", end_ts AS "end_ts!",
+                  storage_mode = 1 AS "sparse!", storage_backend
+             FROM audio_files
+            WHERE pruned_at IS NULL AND end_ts IS NOT NULL
+              AND CASE WHEN $1::BIGINT IS NULL THEN id = ANY($2)
+                       ELSE session_id = $1 AND (cardinality($2) = 0 OR id = ANY($2))
+                  END
+            ORDER BY start_ts, id"#,
+        request.session_id,
+        &request.audio_file_ids[..]
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sources: Vec<Source> = rows
+        .into_iter()
+        .map(|row| Source {
+            id: row.id,
+            key: RecordingKey::new(
+                row.guild_id,
+                row.channel_id,
+                row.year,
+                row.month as u32,
+                row.file_name,
+            ),
+            start_ts: row.start_ts,
+            end_ts: row.end_ts,
+            sparse: row.sparse,
+            storage_backend: row.storage_backend,
+        })
+        .collect();
+
+    let (Some(first), Some(last)) = (
+        sources.iter().map(|s| s.start_ts).min(),
+        sources.iter().map(|s| s.end_ts).max(),
+    ) else {
+        return Ok(None);
+    };
+    let start_ms = request.start_ms.unwrap_or(first);
+    let end_ms = request.end_ms.unwrap_or(last);
+    sources.retain(|s| s.start_ts < end_ms && s.end_ts > start_ms);
+    if sources.is_empty() || end_ms - start_ms < FRAME_MS {
+        return Ok(None);
+    }
+
+    let id = plan_id(&sources, start_ms, end_ms, request.format);
+    let dir = sources[0].key.recording_dir(RECORDING_ROOT);
+    let name = if sources.len() == 1 {
+        format!(
+            "{}-{}.{}",
+            stem(&sources[0].key),
+            id,
+            request.format.extension()
+        )
+    } else {
+        format!("mix-{}.{}", id, request.format.extension())
+    };
+    Ok(Some(Plan {
+        id,
+        sources,
+        start_ms,
+        end_ms,
+        format: request.format,
+        path: dir.join(name),
+    }))
+}
+
+/// First 16 hex digits of a hash over the sources, range and format.
+fn plan_id(sources: &[Source], start_ms: i64, end_ms: i64, format: ExportFormat) -> String {
+    let mut hasher = Sha256::new();
+    let mut ids: Vec<i64> = sources.iter().map(|s| s.id).collect();
+    ids.sort_unstable();
+    for id in ids {
+        hasher.update(id.to_le_bytes());
+    }
+    hasher.update(start_ms.to_le_bytes());
+    hasher.update(end_ms.to_le_bytes());
+    hasher.update(format.extension());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+fn stem(key: &RecordingKey) -> String {
+    key.recording_path(RECORDING_ROOT)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Whether `name` is an export cached beside the recording `stem`.
+fn is_cached_export(name: &str, stem: &str) -> bool {
+    let Some((rest, extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    if !["wav", "flac", "mp3"].contains(&extension) {
+        return false;
+    }
+    let hash = rest
+        .strip_prefix(stem)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .or_else(|| rest.strip_prefix("mix-"));
+    hash.is_some_and(|hash| hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Delete the exports cached beside `key`: its own, and every mix in its
+/// directory since a mix there may contain it.
+pub fn remove_cached(key: &RecordingKey) {
+    let dir = key.recording_dir(RECORDING_ROOT);
+    let stem = stem(key);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if let Some(name) = name.to_str()
+            && is_cached_export(name, &stem)
+            && let Err(e) = std::fs::remove_file(entry.path())
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            tracing::warn!(path = %entry.path().display(), error = %e, "cached export delete failed");
+        }
+    }
+}
+
+/// A source's bytes, ready to decode.
+pub struct Input {
+    pub source: ClipSource,
+    pub index: Option<ParsedIndex>,
+    pub start_ts: i64,
+    /// Length on the timeline.
+    pub frames: u64,
+}
+
+fn plaintext(source: &ClipSource) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    source.open()?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+async fn fetch(backend: &dyn crate::storage::StorageBackend, key: &str) -> io::Result<ClipSource> {
+    match backend.local_path(key) {
+        Some(path) => Ok(ClipSource::File(path)),
+        None => Ok(ClipSource::Memory(backend.get(key).await?)),
+    }
+}
+
+/// Locate `source`'s recording (and index) in storage.
+pub async fn input(storage: &Storage, source: &Source) -> io::Result<Input> {
+    let backend = storage.recordings(&source.storage_backend).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("storage backend {} not configured", source.storage_backend),
+        )
+    })?;
+    let recording = fetch(
+        backend,
+        &crate::storage::recording_object_key(&source.key, "ogg"),
+    )
+    .await?;
+    let index = if source.sparse {
+        let index = fetch(
+            backend,
+            &crate::storage::recording_object_key(&source.key, "idx"),
+        )
+        .await?;
+        Some(
+            tokio::task::spawn_blocking(move || sparse_index::parse_index(&plaintext(&index)?))
+                .await
+                .map_err(io::Error::other)??,
+        )
+    } else {
+        None
+    };
+    let frames = match &index {
+        Some(index) => index.logical_frames(),
+        None => ((source.end_ts - source.start_ts) / FRAME_MS) as u64,
+    };
+    Ok(Input {
+        source: recording,
+        index,
+        start_ts: source.start_ts,
+        frames,
+    })
+}
+
+/// [`input`] for every source of `plan`.
+pub async fn inputs(storage: &Storage, plan: &Plan) -> io::Result<Vec<Input>> {
+    let mut inputs = Vec::with_capacity(plan.sources.len());
+    for source in &plan.sources {
+        inputs.push(input(storage, source).await?);
+    }
+    Ok(inputs)
+}
+
+/// Decode, mix and encode `plan` into its cache path, calling `progress`
+/// with the frames done every second of audio. Blocking.
+pub fn render(plan: &Plan, inputs: Vec<Input>, mut progress: impl FnMut(u64)) -> io::Result<()> {
+    let mut mixer = Mixer::new();
+    for input in inputs {
+        let track = Track::open(&input.source, input.index)?;
+        let offset = (input.start_ts - plan.start_ms).div_euclid(FRAME_MS);
+        mixer.add(track, offset, input.frames)?;
+    }
+
+    if let Some(dir) = plan.path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let part = part_path(&plan.path);
+    let result = write_to(&part, plan, &mut mixer, &mut progress)
+        .and_then(|()| std::fs::rename(&part, &plan.path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+    result
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+fn write_to(
+    part: &Path,
+    plan: &Plan,
+    mixer: &mut Mixer,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<()> {
+    let file = std::fs::File::create(part)?;
+    {
+        let out = BufWriter::new(crate::encryption::writer(file.try_clone()?)?);
+        let frames = plan.frames();
+        let mut sink = encode::sink(plan.format, out, frames * (FRAME_SAMPLES / 2) as u64)?;
+        let mut pcm = vec![0; FRAME_SAMPLES];
+        for frame in 0..frames {
+            mixer.next_frame(&mut pcm)?;
+            sink.write(&pcm)?;
+            if frame % 50 == 49 {
+                progress(frame + 1);
+            }
+        }
+        sink.finish()?;
+    }
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_exports_are_recognised_by_name() {
+        let stem = "1760000000000-42";
+        assert!(is_cached_export(
+            "1760000000000-42-0123456789abcdef.wav",
+            stem
+        ));
+        assert!(is_cached_export("mix-0123456789abcdef.flac", stem));
+        assert!(!is_cached_export("1760000000000-42.ogg", stem));
+        assert!(!is_cached_export(
+            "1760000000000-421-0123456789abcdef.wav",
+            stem
+        ));
+        assert!(!is_cached_export(
+            "1760000000000-42-0123456789abcdef.ogg",
+            stem
+        ));
+        assert!(!is_cached_export("1760000000000-42-notahash.mp3", stem));
+    }
+}
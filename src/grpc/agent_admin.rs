@@ -0,0 +1,296 @@
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+use super::MyJammer;
+use super::agent::agent_admin_server::AgentAdmin;
+use super::agent::{
+    EraseUserDataRequest, EraseUserDataResponse, ExportUserDataRequest, ExportUserDataResponse,
+    GuildPlaybackSettingsRequest, GuildPlaybackSettingsResponse, GuildRecordingSettingsRequest,
+    GuildRecordingSettingsResponse, RecordingPolicyRequest, RecordingPolicyResponse,
+    SetChannelRecordingPolicyRequest, SetGuildPlaybackSettingsRequest,
+    SetGuildRecordingPolicyRequest, SetGuildRecordingSettingsRequest,
+    SetUserRecordingOptOutRequest, TableRowCount,
+};
+use crate::database::{guild_settings, recording_policy};
+
+#[tonic::async_trait]
+impl AgentAdmin for MyJammer {
+    async fn get_recording_policy(
+        &self,
+        request: Request<RecordingPolicyRequest>,
+    ) -> Result<Response<RecordingPolicyResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let channel_id = id_arg(req.channel_id, "channel_id")?;
+        Ok(Response::new(self.policy(guild_id, channel_id).await?))
+    }
+
+    async fn set_guild_recording_policy(
+        &self,
+        request: Request<SetGuildRecordingPolicyRequest>,
+    ) -> Result<Response<RecordingPolicyResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let updated_by = id_arg(req.updated_by, "updated_by")?;
+        recording_policy::set_guild_policy(
+            &self.data_cache.pool,
+            guild_id,
+            req.enabled,
+            req.channels_default_allowed,
+            req.allow_user_opt_out,
+            updated_by,
+        )
+        .await
+        .map_err(|err| Status::internal(format!("update guild policy: {err}")))?;
+        info!(guild_id, updated_by, "admin updated guild recording policy");
+        Ok(Response::new(self.policy(guild_id, 0).await?))
+    }
+
+    async fn set_channel_recording_policy(
+        &self,
+        request: Request<SetChannelRecordingPolicyRequest>,
+    ) -> Result<Response<RecordingPolicyResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let channel_id = id_arg(req.channel_id, "channel_id")?;
+        let updated_by = id_arg(req.updated_by, "updated_by")?;
+        recording_policy::set_channel_policy(
+            &self.data_cache.pool,
+            guild_id,
+            channel_id,
+            req.allowed,
+            updated_by,
+        )
+        .await
+        .map_err(|err| Status::internal(format!("update channel policy: {err}")))?;
+        info!(
+            guild_id,
+            channel_id, updated_by, "admin updated channel recording policy"
+        );
+        Ok(Response::new(self.policy(guild_id, channel_id).await?))
+    }
+
+    async fn set_user_recording_opt_out(
+        &self,
+        request: Request<SetUserRecordingOptOutRequest>,
+    ) -> Result<Response<RecordingPolicyResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let user_id = id_arg(req.user_id, "user_id")?;
+        recording_policy::set_user_opt_out(&self.data_cache.pool, guild_id, user_id, req.opted_out)
+            .await
+            .map_err(|err| Status::internal(format!("update opt-out: {err}")))?;
+        info!(
+            guild_id,
+            user_id,
+            opted_out = req.opted_out,
+            "recording opt-out updated"
+        );
+        Ok(Response::new(self.policy(guild_id, 0).await?))
+    }
+
+    async fn get_guild_recording_settings(
+        &self,
+        request: Request<GuildRecordingSettingsRequest>,
+    ) -> Result<Response<GuildRecordingSettingsResponse>, Status> {
+        let guild_id = id_arg(request.into_inner().guild_id, "guild_id")?;
+        Ok(Response::new(self.recording_settings(guild_id).await))
+    }
+
+    async fn set_guild_recording_settings(
+        &self,
+        request: Request<SetGuildRecordingSettingsRequest>,
+    ) -> Result<Response<GuildRecordingSettingsResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let updated_by = id_arg(req.updated_by, "updated_by")?;
+        let stamp_clip_channel_id = req
+            .stamp_clip_channel_id
+            .map(|id| id_arg(id, "stamp_clip_channel_id").map(|id| (id != 0).then_some(id)))
+            .transpose()?;
+        guild_settings::set_recording_settings(
+            &self.data_cache.pool,
+            guild_id,
+            req.record_bots,
+            req.record_playback,
+            stamp_clip_channel_id,
+        )
+        .await
+        .map_err(|err| Status::internal(format!("update recording settings: {err}")))?;
+        info!(
+            guild_id,
+            updated_by, "admin updated guild recording settings"
+        );
+        Ok(Response::new(self.recording_settings(guild_id).await))
+    }
+
+    async fn get_guild_playback_settings(
+        &self,
+        request: Request<GuildPlaybackSettingsRequest>,
+    ) -> Result<Response<GuildPlaybackSettingsResponse>, Status> {
+        let guild_id = id_arg(request.into_inner().guild_id, "guild_id")?;
+        Ok(Response::new(self.playback_settings(guild_id).await))
+    }
+
+    async fn set_guild_playback_settings(
+        &self,
+        request: Request<SetGuildPlaybackSettingsRequest>,
+    ) -> Result<Response<GuildPlaybackSettingsResponse>, Status> {
+        let req = request.into_inner();
+        let guild_id = id_arg(req.guild_id, "guild_id")?;
+        let updated_by = id_arg(req.updated_by, "updated_by")?;
+        if req.target_lufs.is_some_and(|lufs| {
+            !(guild_settings::MIN_TARGET_LUFS..=guild_settings::MAX_TARGET_LUFS).contains(&lufs)
+        }) {
+            return Err(Status::invalid_argument(format!(
+                "target_lufs must be between {} and {}",
+                guild_settings::MIN_TARGET_LUFS,
+                guild_settings::MAX_TARGET_LUFS
+            )));
+        }
+        if req
+            .ceiling_dbfs
+            .is_some_and(|dbfs| dbfs.is_nan() || dbfs > guild_settings::MAX_CEILING_DBFS)
+        {
+            return Err(Status::invalid_argument(format!(
+                "ceiling_dbfs must be at most {}",
+                guild_settings::MAX_CEILING_DBFS
+            )));
+        }
+        let result = if req.reset {
+            guild_settings::reset_playback_settings(&self.data_cache.pool, guild_id).await
+        } else {
+            guild_settings::set_playback_settings(
+                &self.data_cache.pool,
+                guild_id,
+                req.target_lufs,
+                req.ceiling_dbfs,
+            )
+            .await
+        };
+        result.map_err(|err| Status::internal(format!("update playback settings: {err}")))?;
+        info!(
+            guild_id,
+            updated_by,
+            reset = req.reset,
+            "admin updated guild playback settings"
+        );
+        Ok(Response::new(self.playback_settings(guild_id).await))
+    }
+
+    async fn export_user_data(
+        &self,
+        request: Request<ExportUserDataRequest>,
+    ) -> Result<Response<ExportUserDataResponse>, Status> {
+        let req = request.into_inner();
+        let user_id = id_arg(req.user_id, "user_id")?;
+        info!(user_id, requested_by = %req.requested_by, "admin requested user data export");
+        let path = crate::config::user_export_dir().join(format!(
+            "user-{}-{}.zip",
+            user_id,
+            chrono::Utc::now().timestamp_millis()
+        ));
+        let storage = crate::storage::from_data(&self.data_cache.data).await;
+        let report = crate::user_data::export(&self.data_cache.pool, &storage, user_id, &path)
+            .await
+            .map_err(|err| Status::internal(format!("export user data: {err}")))?;
+        Ok(Response::new(ExportUserDataResponse {
+            user_id: req.user_id,
+            path: path.display().to_string(),
+            tables: row_counts(&report.tables),
+            files: report.files as u32,
+            bytes: report.bytes,
+        }))
+    }
+
+    async fn erase_user_data(
+        &self,
+        request: Request<EraseUserDataRequest>,
+    ) -> Result<Response<EraseUserDataResponse>, Status> {
+        let req = request.into_inner();
+        let user_id = id_arg(req.user_id, "user_id")?;
+        if req.requested_by.trim().is_empty() {
+            return Err(Status::invalid_argument("requested_by is required"));
+        }
+        let storage = crate::storage::from_data(&self.data_cache.data).await;
+        let report = crate::user_data::erase(
+            &self.data_cache.pool,
+            &storage,
+            user_id,
+            req.dry_run,
+            &req.requested_by,
+        )
+        .await
+        .map_err(|err| Status::internal(format!("erase user data: {err}")))?;
+        Ok(Response::new(EraseUserDataResponse {
+            user_id: req.user_id,
+            dry_run: report.dry_run,
+            tables: row_counts(&report.tables),
+            files: report
+                .files
+                .iter()
+                .map(|path| path.display().to_string())
+                .chain(report.remote_objects.iter().cloned())
+                .collect(),
+            bytes: report.bytes,
+            skipped_active: report.skipped_active as u32,
+            summary: report.summary(),
+        }))
+    }
+}
+
+fn row_counts(tables: &[(&str, u64)]) -> Vec<TableRowCount> {
+    tables
+        .iter()
+        .map(|(table, rows)| TableRowCount {
+            table: table.to_string(),
+            rows: *rows,
+        })
+        .collect()
+}
+
+fn id_arg(value: i64, field: &str) -> Result<u64, Status> {
+    u64::try_from(value)
+        .map_err(|_| Status::invalid_argument(format!("{field} must be non-negative")))
+}
+
+impl MyJammer {
+    async fn policy(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> Result<RecordingPolicyResponse, Status> {
+        let policy = recording_policy::load(&self.data_cache.pool, guild_id, channel_id)
+            .await
+            .map_err(|err| Status::internal(format!("load recording policy: {err}")))?;
+        Ok(RecordingPolicyResponse {
+            guild_id: guild_id as i64,
+            channel_id: channel_id as i64,
+            guild_enabled: policy.guild_enabled,
+            channels_default_allowed: policy.channels_default_allowed,
+            allow_user_opt_out: policy.allow_user_opt_out,
+            channel_override: policy.channel_override,
+            channel_allowed: policy.allows_channel(),
+            opted_out_user_ids: policy.opted_out.iter().map(|id| *id as i64).collect(),
+        })
+    }
+
+    async fn playback_settings(&self, guild_id: u64) -> GuildPlaybackSettingsResponse {
+        let settings = guild_settings::playback_settings(&self.data_cache.pool, guild_id).await;
+        GuildPlaybackSettingsResponse {
+            guild_id: guild_id as i64,
+            target_lufs: settings.target_lufs,
+            ceiling_dbfs: settings.ceiling_dbfs,
+        }
+    }
+
+    async fn recording_settings(&self, guild_id: u64) -> GuildRecordingSettingsResponse {
+        let settings = guild_settings::recording_settings(&self.data_cache.pool, guild_id).await;
+        GuildRecordingSettingsResponse {
+            guild_id: guild_id as i64,
+            record_bots: settings.record_bots,
+            record_playback: settings.record_playback,
+            stamp_clip_channel_id: settings.stamp_clip_channel_id.map(|id| id as i64),
+        }
+    }
+}
@@ -198,6 +198,7 @@ impl Dashboard for MyJammer {
                                 "ffmpeg_process_crashes": m.ffmpeg_process_crashes.load(Ordering::Relaxed),
                                 "audio_packets_received": m.audio_packets_received.load(Ordering::Relaxed),
                                 "audio_packets_dropped": m.audio_packets_dropped.load(Ordering::Relaxed),
+                                "audio_frames_recovered": m.audio_frames_recovered.load(Ordering::Relaxed),
                                 "last_voice_packet_time": m.last_voice_packet_time.load(Ordering::Relaxed),
                             })
                         });
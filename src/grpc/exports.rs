@@ -0,0 +1,97 @@
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use super::MyJammer;
+use super::agent::exports_server::Exports;
+use super::agent::{
+    ExportFormat as ProtoExportFormat, ExportJobRequest, ExportProgress, ExportRecordingRequest,
+    ExportState as ProtoExportState,
+};
+use crate::export;
+
+#[tonic::async_trait]
+impl Exports for MyJammer {
+    type ExportRecordingStream = ReceiverStream<Result<ExportProgress, Status>>;
+
+    async fn export_recording(
+        &self,
+        request: Request<ExportRecordingRequest>,
+    ) -> Result<Response<Self::ExportRecordingStream>, Status> {
+        let req = request.into_inner();
+        let format = match ProtoExportFormat::try_from(req.format) {
+            Ok(ProtoExportFormat::Wav) => export::ExportFormat::Wav,
+            Ok(ProtoExportFormat::Flac) => export::ExportFormat::Flac,
+            Ok(ProtoExportFormat::Mp3) => export::ExportFormat::Mp3,
+            Err(_) => return Err(Status::invalid_argument("unknown export format")),
+        };
+        let request = export::ExportRequest {
+            audio_file_ids: req.audio_file_ids,
+            session_id: (req.session_id > 0).then_some(req.session_id),
+            start_ms: (req.start_ms > 0).then_some(req.start_ms),
+            end_ms: (req.end_ms > 0).then_some(req.end_ms),
+            format,
+        };
+        request.validate().map_err(Status::invalid_argument)?;
+
+        let plan = export::plan(&self.data_cache.pool, &request)
+            .await
+            .map_err(|err| Status::internal(format!("failed to plan export: {err}")))?
+            .ok_or_else(|| Status::not_found("no finished recordings in that range"))?;
+        let job_id = plan.id.clone();
+        let storage = crate::storage::from_data(&self.data_cache.data).await;
+        let jobs = export::from_data(&self.data_cache.data).await;
+        let progress = jobs.start(storage, plan);
+        Ok(Response::new(progress_stream(job_id, progress)))
+    }
+
+    type WatchExportStream = ReceiverStream<Result<ExportProgress, Status>>;
+
+    async fn watch_export(
+        &self,
+        request: Request<ExportJobRequest>,
+    ) -> Result<Response<Self::WatchExportStream>, Status> {
+        let job_id = request.into_inner().job_id;
+        let jobs = export::from_data(&self.data_cache.data).await;
+        let progress = jobs
+            .watch(&job_id)
+            .ok_or_else(|| Status::not_found(format!("no export job {job_id}")))?;
+        Ok(Response::new(progress_stream(job_id, progress)))
+    }
+}
+
+/// Forward a job's progress until it finishes or the client goes away.
+fn progress_stream(
+    job_id: String,
+    mut progress: watch::Receiver<export::Progress>,
+) -> ReceiverStream<Result<ExportProgress, Status>> {
+    let (tx, rx) = mpsc::channel(8);
+    tokio::spawn(async move {
+        loop {
+            let current = progress.borrow_and_update().clone();
+            let finished = current.is_finished();
+            let state = match current.state {
+                export::ExportState::Queued => ProtoExportState::Queued,
+                export::ExportState::Running => ProtoExportState::Running,
+                export::ExportState::Done => ProtoExportState::Done,
+                export::ExportState::Failed => ProtoExportState::Failed,
+            };
+            let message = ExportProgress {
+                job_id: job_id.clone(),
+                state: state.into(),
+                frames_done: current.frames_done,
+                frames_total: current.frames_total,
+                path: current.path.display().to_string(),
+                error: current.error.unwrap_or_default(),
+                cached: current.cached,
+            };
+            if tx.send(Ok(message)).await.is_err() || finished {
+                break;
+            }
+            if progress.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
@@ -122,9 +122,14 @@ async fn handle_play_audio_to_channel(
             .ok_or_else(|| "Songbird manager missing from typemap".to_string())?
     };
 
+    let storage = crate::storage::from_data(&data).await;
+    let volumes = crate::commands::voice_controls::guild_volumes(&data).await;
+
     let guild_id =
         GuildId::new(u64::try_from(id).map_err(|_| "guild_id must be non-negative".to_string())?);
-    crate::commands::voice_controls::play_clip(&pool, &manager, guild_id, clip_name, user_id)
-        .await
-        .map(|_| ())
+    crate::commands::voice_controls::play_clip(
+        &pool, &storage, &volumes, &manager, guild_id, clip_name, user_id,
+    )
+    .await
+    .map(|_| ())
 }
@@ -4,9 +4,16 @@ pub mod hello_world {
     tonic::include_proto!("helloworld");
 }
 
+pub mod agent {
+    tonic::include_proto!("agent");
+}
+
 mod admin;
+mod agent_admin;
 mod dashboard;
+mod exports;
 mod jammer;
+mod playback;
 mod snapshot;
 
 #[derive(Clone)]
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serenity::model::prelude::GuildId;
+use songbird::{Songbird, SongbirdKey};
+use tonic::{Request, Response, Status};
+
+use super::MyJammer;
+use super::agent::playback_server::Playback;
+use super::agent::{
+    NowPlayingReply, PlaybackQueue, PlaybackReply, PlaybackRequest, QueueEntry as ProtoQueueEntry,
+    SeekRequest, SetVolumeRequest,
+};
+use crate::commands::voice_controls;
+
+#[tonic::async_trait]
+impl Playback for MyJammer {
+    async fn pause_playback(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        Ok(playback_reply(
+            voice_controls::pause(&manager, guild_id).await,
+        ))
+    }
+
+    async fn resume_playback(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        Ok(playback_reply(
+            voice_controls::resume(&manager, guild_id).await,
+        ))
+    }
+
+    async fn skip_track(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        let connected = manager.get(guild_id).is_some();
+        let message = voice_controls::skip(&manager, guild_id).await;
+        Ok(playback_reply(if connected {
+            Ok(message)
+        } else {
+            Err(message)
+        }))
+    }
+
+    async fn stop_playback(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        let connected = manager.get(guild_id).is_some();
+        let message = voice_controls::stop(&manager, guild_id).await;
+        Ok(playback_reply(if connected {
+            Ok(message)
+        } else {
+            Err(message)
+        }))
+    }
+
+    async fn set_volume(
+        &self,
+        request: Request<SetVolumeRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let req = request.into_inner();
+        if req.percent > voice_controls::MAX_VOLUME_PERCENT {
+            return Err(Status::invalid_argument(format!(
+                "percent must be at most {}",
+                voice_controls::MAX_VOLUME_PERCENT
+            )));
+        }
+        let (manager, guild_id) = self.playback_target(req.guild_id).await?;
+        let volumes = voice_controls::guild_volumes(&self.data_cache.data).await;
+        Ok(playback_reply(
+            voice_controls::set_volume(&manager, &volumes, guild_id, req.percent).await,
+        ))
+    }
+
+    async fn seek_playback(
+        &self,
+        request: Request<SeekRequest>,
+    ) -> Result<Response<PlaybackReply>, Status> {
+        let req = request.into_inner();
+        let (manager, guild_id) = self.playback_target(req.guild_id).await?;
+        Ok(playback_reply(
+            voice_controls::seek(&manager, guild_id, Duration::from_millis(req.position_ms)).await,
+        ))
+    }
+
+    async fn now_playing(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<NowPlayingReply>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        let current = voice_controls::now_playing(&manager, guild_id)
+            .await
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(NowPlayingReply {
+            current: current.as_ref().map(queue_entry),
+        }))
+    }
+
+    async fn list_queue(
+        &self,
+        request: Request<PlaybackRequest>,
+    ) -> Result<Response<PlaybackQueue>, Status> {
+        let (manager, guild_id) = self.playback_target(request.into_inner().guild_id).await?;
+        let entries = voice_controls::queue_entries(&manager, guild_id)
+            .await
+            .map_err(Status::failed_precondition)?;
+        Ok(Response::new(PlaybackQueue {
+            entries: entries.iter().map(queue_entry).collect(),
+        }))
+    }
+}
+
+impl MyJammer {
+    /// Songbird and the guild a playback request is for.
+    async fn playback_target(&self, guild_id: i64) -> Result<(Arc<Songbird>, GuildId), Status> {
+        let guild_id = u64::try_from(guild_id)
+            .ok()
+            .filter(|id| *id > 0)
+            .map(GuildId::new)
+            .ok_or_else(|| Status::invalid_argument("guild_id must be positive"))?;
+        let manager = self
+            .data_cache
+            .data
+            .read()
+            .await
+            .get::<SongbirdKey>()
+            .cloned()
+            .ok_or_else(|| Status::unavailable("Songbird manager missing from typemap"))?;
+        Ok((manager, guild_id))
+    }
+}
+
+/// Playback errors such as "Nothing is playing." are answers, not RPC
+/// failures.
+fn playback_reply(result: Result<String, String>) -> Response<PlaybackReply> {
+    let (ok, message) = match result {
+        Ok(message) => (true, message),
+        Err(message) => (false, message),
+    };
+    Response::new(PlaybackReply { ok, message })
+}
+
+fn queue_entry(entry: &voice_controls::QueueEntry) -> ProtoQueueEntry {
+    ProtoQueueEntry {
+        clip_id: entry.clip.clip_id.clone(),
+        name: entry.clip.name.clone(),
+        requested_by: entry.clip.requested_by,
+        duration_ms: entry
+            .clip
+            .duration
+            .map_or(0, |duration| duration.as_millis() as u64),
+        position_ms: entry.position.as_millis() as u64,
+        playing: entry.playing,
+        volume_percent: entry.volume_percent,
+    }
+}
@@ -0,0 +1,93 @@
+//! The bot and its maintenance code. `main.rs` runs the bot and the
+//! one-shot subcommands in [`cli`]; `bin/fbi-agent-tools` holds the offline
+//! recording tools.
+
+use std::{collections::HashMap, sync::Arc};
+
+use serenity::{client::Cache, http::Http, prelude::*};
+use tracing::error;
+
+pub mod metrics;
+pub mod reaper;
+pub mod repair;
+pub mod retention;
+pub use metrics::*;
+
+pub mod cli;
+pub mod clip_import;
+pub mod clip_loudness;
+pub mod commands;
+pub mod config;
+pub mod cooldown;
+mod database;
+pub mod deployment;
+pub mod encryption;
+pub mod event_handler;
+pub mod events;
+pub mod export;
+pub mod grpc;
+pub mod loudness;
+pub mod runtime;
+pub mod stamp_clips;
+pub mod storage;
+pub mod storage_guard;
+pub mod telemetry;
+pub mod user_data;
+
+#[cfg(test)]
+mod tests;
+
+pub struct HasBossMusic;
+impl TypeMapKey for HasBossMusic {
+    type Value = HashMap<u64, Option<String>>;
+}
+
+pub struct HelperStruct;
+impl TypeMapKey for HelperStruct {
+    type Value = Arc<RwLock<HashMap<u64, Option<u64>>>>;
+}
+
+#[derive(Clone)]
+pub struct Custom {
+    cache: Arc<Cache>,
+    _http: Arc<Http>,
+    data: Arc<RwLock<TypeMap>>,
+    pub pool: sqlx::Pool<sqlx::Postgres>,
+    pub jam_cooldown: crate::cooldown::JamCooldown,
+    pub runtime: Arc<crate::runtime::RuntimeState>,
+}
+
+impl Custom {
+    pub fn new(
+        cache: Arc<Cache>,
+        http: Arc<Http>,
+        data: Arc<RwLock<TypeMap>>,
+        pool: sqlx::Pool<sqlx::Postgres>,
+        jam_cooldown: crate::cooldown::JamCooldown,
+        runtime: Arc<crate::runtime::RuntimeState>,
+    ) -> Self {
+        Self {
+            cache,
+            _http: http,
+            data,
+            pool,
+            jam_cooldown,
+            runtime,
+        }
+    }
+}
+
+pub async fn get_lock_read(ctx: &Context) -> Arc<RwLock<HashMap<u64, Option<u64>>>> {
+    if let Some(lock) = {
+        let data_write = ctx.data.read().await;
+        data_write.get::<HelperStruct>().cloned()
+    } {
+        return lock;
+    }
+
+    error!("HelperStruct missing from typemap; recreating it");
+    let lock = Arc::new(RwLock::new(HashMap::new()));
+    let mut data_write = ctx.data.write().await;
+    data_write.insert::<HelperStruct>(lock.clone());
+    lock
+}
@@ -0,0 +1,237 @@
+//! Loudness of 48 kHz stereo audio, per ITU-R BS.1770 / EBU R128.
+//!
+//! [`Meter`] K-weights the signal and collects 400 ms blocks every 100 ms;
+//! [`Meter::integrated`] gates them (absolute at -70 LUFS, relative at
+//! -10 LU) into the programme's integrated loudness. Samples are `f32` in
+//! `[-1, 1]`, interleaved.
+
+/// Loudness clips are brought to unless a guild says otherwise.
+pub const DEFAULT_TARGET_LUFS: f64 = -16.0;
+/// Sample peak no gain may push a clip past.
+pub const DEFAULT_CEILING_DBFS: f64 = -1.0;
+
+const CHANNELS: usize = 2;
+/// 100 ms at 48 kHz: the step between gating blocks.
+const STEP: usize = 4800;
+/// Steps in one 400 ms gating block.
+const STEPS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// One biquad section, direct form II transposed.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    const fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, z: [0.0; 2] }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting pre-filter (high shelf) and RLB high-pass at 48 kHz.
+const fn k_weighting() -> [Biquad; 2] {
+    [
+        Biquad::new(
+            [
+                1.535_124_859_586_97,
+                -2.691_696_189_406_38,
+                1.198_392_810_852_85,
+            ],
+            [-1.690_659_293_182_41, 0.732_480_774_215_85],
+        ),
+        Biquad::new(
+            [1.0, -2.0, 1.0],
+            [-1.990_047_454_833_98, 0.990_072_250_366_21],
+        ),
+    ]
+}
+
+/// A programme's integrated loudness and sample peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    /// `None` for silence or audio too short for one gating block.
+    pub integrated_lufs: Option<f64>,
+    pub peak_dbfs: f64,
+}
+
+impl Measurement {
+    /// Gain in dB towards `target_lufs`, limited by `ceiling_dbfs`. Audio
+    /// without a loudness is only ever turned down, to the ceiling.
+    pub fn gain_db(&self, target_lufs: f64, ceiling_dbfs: f64) -> f64 {
+        match self.integrated_lufs {
+            Some(lufs) => gain_db(lufs, self.peak_dbfs, target_lufs, ceiling_dbfs),
+            None => (ceiling_dbfs - self.peak_dbfs).min(0.0),
+        }
+    }
+}
+
+pub struct Meter {
+    filters: [[Biquad; 2]; CHANNELS],
+    /// Sum of squares of the current step, per channel.
+    step_power: [f64; CHANNELS],
+    step_len: usize,
+    /// Mean square of the last steps, summed over channels.
+    recent: Vec<f64>,
+    /// Mean square of every gating block, summed over channels.
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        Self {
+            filters: [k_weighting(), k_weighting()],
+            step_power: [0.0; CHANNELS],
+            step_len: 0,
+            recent: Vec::with_capacity(STEPS_PER_BLOCK),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Feed interleaved stereo samples.
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                let mut y = sample as f64;
+                for filter in &mut self.filters[channel] {
+                    y = filter.process(y);
+                }
+                self.step_power[channel] += y * y;
+            }
+            self.step_len += 1;
+            if self.step_len == STEP {
+                self.end_step();
+            }
+        }
+    }
+
+    fn end_step(&mut self) {
+        let power = self.step_power.iter().sum::<f64>() / STEP as f64;
+        self.step_power = [0.0; CHANNELS];
+        self.step_len = 0;
+        if self.recent.len() == STEPS_PER_BLOCK {
+            self.recent.remove(0);
+        }
+        self.recent.push(power);
+        if self.recent.len() == STEPS_PER_BLOCK {
+            self.blocks
+                .push(self.recent.iter().sum::<f64>() / STEPS_PER_BLOCK as f64);
+        }
+    }
+
+    /// Integrated loudness in LUFS; `None` for silence or audio too short
+    /// for one gating block.
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|&power| lufs(power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if absolute.is_empty() {
+            return None;
+        }
+        let gate = lufs(mean(&absolute)) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = absolute
+            .into_iter()
+            .filter(|&power| lufs(power) > gate)
+            .collect();
+        Some(lufs(mean(&gated)))
+    }
+
+    pub fn measurement(&self) -> Measurement {
+        Measurement {
+            integrated_lufs: self.integrated(),
+            peak_dbfs: self.peak_dbfs(),
+        }
+    }
+
+    /// Highest sample magnitude seen, in dBFS.
+    pub fn peak_dbfs(&self) -> f64 {
+        20.0 * (self.peak as f64).log10()
+    }
+}
+
+fn lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Gain in dB that brings `loudness` to `target_lufs` without pushing a
+/// `peak_dbfs` peak past `ceiling_dbfs`.
+pub fn gain_db(loudness: f64, peak_dbfs: f64, target_lufs: f64, ceiling_dbfs: f64) -> f64 {
+    (target_lufs - loudness).min(ceiling_dbfs - peak_dbfs)
+}
+
+pub fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, amplitude: f64, seconds: usize) -> Vec<f32> {
+        (0..48_000 * seconds)
+            .flat_map(|n| {
+                let s = (amplitude
+                    * (2.0 * std::f64::consts::PI * frequency * n as f64 / 48_000.0).sin())
+                    as f32;
+                [s, 0.0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_scale_1khz_reads_about_minus_three_lufs() -> Result<(), Box<dyn std::error::Error>> {
+        // BS.1770: a 0 dBFS 1 kHz sine in one channel reads -3.01 LUFS.
+        let mut meter = Meter::new();
+        meter.add(&sine(1000.0, 1.0, 5));
+        let loudness = meter.integrated().ok_or("no loudness")?;
+        assert!((loudness + 3.01).abs() < 0.1, "{loudness}");
+        assert!(meter.peak_dbfs().abs() < 0.01);
+        Ok(())
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut meter = Meter::new();
+        meter.add(&vec![0.0; 48_000 * 2 * 2]);
+        assert_eq!(meter.integrated(), None);
+    }
+
+    #[test]
+    fn gain_stops_at_the_ceiling() {
+        assert_eq!(gain_db(-26.0, -20.0, -16.0, -1.0), 10.0);
+        assert_eq!(gain_db(-26.0, -5.0, -16.0, -1.0), 4.0);
+        assert_eq!(gain_db(-6.0, -0.5, -16.0, -1.0), -10.0);
+
+        let unmeasured = Measurement {
+            integrated_lufs: None,
+            peak_dbfs: -20.0,
+        };
+        assert_eq!(unmeasured.gain_db(-16.0, -1.0), 0.0);
+    }
+}
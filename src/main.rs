@@ -1,78 +1,38 @@
 use std::{collections::HashMap, error::Error, sync::Arc};
 
-use serenity::{all::ApplicationId, client::Cache, http::Http, prelude::*};
+use serenity::{all::ApplicationId, prelude::*};
 use songbird::{Config, SerenityInit, driver::DecodeMode};
 use sqlx::postgres::PgPoolOptions;
 use tonic::transport::Server;
 use tracing::{error, info, warn};
 
-use crate::{
+use fbi_agent::{
+    BotMetrics, BotMetricsKey, Custom, HasBossMusic, HelperStruct, cli, clip_loudness, config,
+    deployment, encryption,
     event_handler::Handler,
+    events, export,
     grpc::{MyJammer, hello_world::jammer_server::JammerServer},
+    reaper, retention, stamp_clips, storage, storage_guard,
 };
 
-pub mod metrics;
-pub mod reaper;
-pub use metrics::*;
-
-pub mod commands;
-pub mod config;
-pub mod cooldown;
-mod database;
-pub mod deployment;
-pub mod event_handler;
-pub mod events;
-pub mod grpc;
-pub mod runtime;
-pub mod telemetry;
-
-#[cfg(test)]
-mod tests;
-
-pub struct HasBossMusic;
-impl TypeMapKey for HasBossMusic {
-    type Value = HashMap<u64, Option<String>>;
-}
-
-pub struct HelperStruct;
-impl TypeMapKey for HelperStruct {
-    type Value = Arc<RwLock<HashMap<u64, Option<u64>>>>;
-}
-
-#[derive(Clone)]
-pub struct Custom {
-    cache: Arc<Cache>,
-    _http: Arc<Http>,
-    data: Arc<RwLock<TypeMap>>,
-    pub pool: sqlx::Pool<sqlx::Postgres>,
-    pub jam_cooldown: crate::cooldown::JamCooldown,
-    pub runtime: Arc<crate::runtime::RuntimeState>,
-}
-
-pub async fn get_lock_read(ctx: &Context) -> Arc<RwLock<HashMap<u64, Option<u64>>>> {
-    if let Some(lock) = {
-        let data_write = ctx.data.read().await;
-        data_write.get::<HelperStruct>().cloned()
-    } {
-        return lock;
-    }
-
-    error!("HelperStruct missing from typemap; recreating it");
-    let lock = Arc::new(RwLock::new(HashMap::new()));
-    let mut data_write = ctx.data.write().await;
-    data_write.insert::<HelperStruct>(lock.clone());
-    lock
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     dotenvy::dotenv().ok();
 
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(result) = cli::run(&args).await {
+        return result;
+    }
+
     rustls::crypto::ring::default_provider()
         .install_default()
         .map_err(|_| "Failed to install rustls crypto provider")?;
 
-    crate::telemetry::init_telemetry()?;
+    fbi_agent::telemetry::init_telemetry()?;
+    encryption::init()?;
+    if encryption::keyring().enabled() {
+        info!("recording encryption enabled");
+    }
 
     if !std::path::Path::new(events::voice_receiver::RECORDING_FILE_PATH).exists() {
         tokio::fs::create_dir_all(events::voice_receiver::RECORDING_FILE_PATH).await?;
@@ -89,7 +49,8 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // let a = conn.exec_map("SELECT * FROM guilds WHERE id IN (:id)", db_param, | id | DBGuild { id });
     // Configure the client with your Discord bot token in the environment.
     let discord_config = config::discord_config()?;
-    let runtime = crate::runtime::RuntimeState::new(crate::runtime::RuntimeConfig::from_env());
+    let runtime =
+        fbi_agent::runtime::RuntimeState::new(fbi_agent::runtime::RuntimeConfig::from_env());
     deployment::upsert_instance(&pool, &runtime).await;
     deployment::start_heartbeat(pool.clone(), runtime.clone());
     info!(
@@ -109,7 +70,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // Create a new instance of the Client, logging in as a bot. This will
     // automatically prepend your bot token with "Bot ", which is a requirement
     // by Discord for bot users.
-    let jam_cooldown = crate::cooldown::JamCooldown::new();
+    let jam_cooldown = fbi_agent::cooldown::JamCooldown::new();
 
     let mut client = Client::builder(discord_config.token, intents)
         .event_handler(Handler {
@@ -121,28 +82,46 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .register_songbird_from_config(songbird_config)
         .application_id(ApplicationId::new(discord_config.application_id))
         .await?;
+    let storage_guard = storage_guard::StorageGuard::new();
+    let recording_storage = Arc::new(storage::Storage::from_env()?);
     {
         let mut data = client.data.write().await;
         // data.insert::<MysqlConnection>(mysql_pool.clone());
         data.insert::<HelperStruct>(Arc::new(RwLock::new(HashMap::new())));
         data.insert::<HasBossMusic>(HashMap::new());
-        data.insert::<BotMetricsKey>(Arc::new(BotMetrics::default()));
-        data.insert::<crate::runtime::RuntimeStateKey>(runtime.clone());
+        let metrics = Arc::new(BotMetrics::default());
+        data.insert::<fbi_agent::events::disk_writer::DiskWriterKey>(
+            fbi_agent::events::disk_writer::DiskWriter::start(metrics.clone())?,
+        );
+        data.insert::<BotMetricsKey>(metrics);
+        data.insert::<fbi_agent::runtime::RuntimeStateKey>(runtime.clone());
+        data.insert::<storage_guard::StorageGuardKey>(storage_guard.clone());
+        data.insert::<storage::StorageKey>(recording_storage.clone());
+        data.insert::<events::recent_audio::LiveAudioKey>(Arc::new(
+            events::recent_audio::LiveAudio::new(),
+        ));
+        data.insert::<fbi_agent::commands::voice_controls::GuildVolumesKey>(Arc::default());
+        data.insert::<export::ExportJobsKey>(Arc::new(export::ExportJobs::new(
+            config::export_max_concurrent(),
+        )));
     }
 
     let http = client.http.clone();
+    let guard_http = http.clone();
+    let stamp_clip_http = http.clone();
     let cache = client.cache.clone();
     let data = client.data.clone();
     let shutdown_data = data.clone();
+    let writer_data = data.clone();
 
-    let custom = Custom {
+    let custom = Custom::new(
         cache,
-        _http: http,
+        http,
         data,
-        pool: pool.clone(),
-        jam_cooldown: jam_cooldown.clone(),
-        runtime: runtime.clone(),
-    };
+        pool.clone(),
+        jam_cooldown.clone(),
+        runtime.clone(),
+    );
 
     // Grab the metrics Arc before moving `client` into the spawn below.
     let process_metrics = {
@@ -163,6 +142,16 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // Background task: sample process health every 15 seconds.
     BotMetrics::start_sysinfo_monitoring(process_metrics.clone());
 
+    retention::start(
+        pool.clone(),
+        recording_storage.clone(),
+        process_metrics.clone(),
+    );
+    clip_loudness::start(pool.clone(), recording_storage.clone());
+    stamp_clips::start(pool.clone(), recording_storage.clone(), stamp_clip_http);
+    storage::uploader::start(pool.clone(), recording_storage, process_metrics.clone());
+    storage_guard::start(storage_guard, process_metrics.clone(), guard_http);
+
     // Register OpenTelemetry metrics.
     BotMetrics::register_otel_metrics(process_metrics, runtime.clone());
 
@@ -170,7 +159,7 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let grpc_shutdown_rx = shutdown_rx.clone();
 
     let grpc_server = tokio::spawn(async move {
-        let addr = match crate::config::grpc_addr().parse() {
+        let addr = match fbi_agent::config::grpc_addr().parse() {
             Ok(addr) => addr,
             Err(err) => {
                 error!("Invalid gRPC address: {}", err);
@@ -184,10 +173,21 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 
         Server::builder()
             .add_service(JammerServer::new(jammer.clone()))
-            .add_service(crate::grpc::hello_world::admin_server::AdminServer::new(
+            .add_service(
+                fbi_agent::grpc::hello_world::admin_server::AdminServer::new(jammer.clone()),
+            )
+            .add_service(
+                fbi_agent::grpc::hello_world::dashboard_server::DashboardServer::new(
+                    jammer.clone(),
+                ),
+            )
+            .add_service(
+                fbi_agent::grpc::agent::agent_admin_server::AgentAdminServer::new(jammer.clone()),
+            )
+            .add_service(fbi_agent::grpc::agent::exports_server::ExportsServer::new(
                 jammer.clone(),
             ))
-            .add_service(crate::grpc::hello_world::dashboard_server::DashboardServer::new(jammer))
+            .add_service(fbi_agent::grpc::agent::playback_server::PlaybackServer::new(jammer))
             .serve_with_shutdown(addr, async move {
                 let mut rx = grpc_shutdown_rx;
                 while !*rx.borrow() {
@@ -249,6 +249,23 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         error!("shutdown task join error: {}", err);
     }
 
+    let disk_writer = {
+        let data_read = writer_data.read().await;
+        data_read
+            .get::<fbi_agent::events::disk_writer::DiskWriterKey>()
+            .cloned()
+    };
+    if let Some(disk_writer) = disk_writer {
+        let drained = tokio::task::spawn_blocking(move || {
+            disk_writer.drain(std::time::Duration::from_secs(30))
+        })
+        .await
+        .unwrap_or(false);
+        if !drained {
+            warn!("recording writer did not drain before shutdown");
+        }
+    }
+
     deployment::mark_instance_stopped(&pool, &runtime).await;
 
     Ok(())
@@ -269,7 +286,7 @@ async fn active_voice_connection_count(data: &Arc<RwLock<TypeMap>>) -> u32 {
         .unwrap_or(0)
 }
 
-async fn wait_for_shutdown_signal_or_drain(runtime: Arc<crate::runtime::RuntimeState>) {
+async fn wait_for_shutdown_signal_or_drain(runtime: Arc<fbi_agent::runtime::RuntimeState>) {
     #[cfg(unix)]
     {
         let mut sigterm =
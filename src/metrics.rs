@@ -12,6 +12,7 @@ pub struct GuildRecordingMetrics {
     pub ffmpeg_process_crashes: AtomicU32,
     pub audio_packets_received: AtomicU64,
     pub audio_packets_dropped: AtomicU64,
+    pub audio_frames_recovered: AtomicU64,
     pub last_voice_packet_time: AtomicI64,
 }
 
@@ -23,6 +24,7 @@ impl GuildRecordingMetrics {
             ffmpeg_process_crashes: AtomicU32::new(0),
             audio_packets_received: AtomicU64::new(0),
             audio_packets_dropped: AtomicU64::new(0),
+            audio_frames_recovered: AtomicU64::new(0),
             last_voice_packet_time: AtomicI64::new(0),
         }
     }
@@ -69,8 +71,29 @@ pub struct BotMetrics {
     pub ffmpeg_process_crashes: AtomicU32,
     pub audio_packets_received: AtomicU64,
     pub audio_packets_dropped: AtomicU64,
+    pub audio_frames_recovered: AtomicU64,
     pub last_voice_packet_time: AtomicI64,
     recording_duration_seconds: Histogram<f64>,
+    // Recording writer thread
+    pub disk_write_queue_depth: AtomicU64,
+    pub disk_write_backpressure: AtomicU64,
+    pub disk_write_batches: AtomicU64,
+    pub disk_write_bytes: AtomicU64,
+    pub disk_write_errors: AtomicU64,
+    pub disk_fsyncs: AtomicU64,
+    // Retention pruning
+    pub retention_runs: AtomicU64,
+    pub retention_recordings_pruned: AtomicU64,
+    pub retention_freed_bytes: AtomicU64,
+    pub retention_prunable_bytes: AtomicU64,
+    // Storage guard
+    pub recording_disk_free_bytes: AtomicU64,
+    pub recording_disk_level: AtomicU64,
+    pub recording_disk_full_events: AtomicU64,
+    // Remote storage uploads
+    pub recording_uploads: AtomicU64,
+    pub recording_upload_bytes: AtomicU64,
+    pub recording_upload_failures: AtomicU64,
     // Voice recording pipeline — per-guild breakdown
     pub guild_recording_metrics: dashmap::DashMap<u64, Arc<GuildRecordingMetrics>>,
     // Voice recording pipeline — per-channel breakdown
@@ -596,6 +619,11 @@ impl BotMetrics {
             "Open file descriptors",
             process_open_fds
         );
+        u64_gauge!(
+            "disk_write_queue_depth",
+            "Messages waiting for the recording writer thread",
+            disk_write_queue_depth
+        );
         u32_gauge!(
             "tokio_active_tasks",
             "Tokio runtime active tasks",
@@ -667,6 +695,82 @@ impl BotMetrics {
             "Total audio packets dropped globally",
             audio_packets_dropped
         );
+        u64_counter!(
+            "audio_frames_recovered",
+            "Total lost audio frames rebuilt via FEC/PLC globally",
+            audio_frames_recovered
+        );
+        u64_counter!(
+            "disk_write_backpressure",
+            "Times a recording write found the writer queue full",
+            disk_write_backpressure
+        );
+        u64_counter!(
+            "disk_write_batches",
+            "Total batches written by the recording writer thread",
+            disk_write_batches
+        );
+        u64_counter!(
+            "disk_write_bytes",
+            "Total recording bytes written to disk",
+            disk_write_bytes
+        );
+        u64_counter!(
+            "disk_write_errors",
+            "Total recording write and fsync failures",
+            disk_write_errors
+        );
+        u64_counter!("disk_fsyncs", "Total recording file syncs", disk_fsyncs);
+        u64_counter!(
+            "retention_runs",
+            "Total completed retention passes",
+            retention_runs
+        );
+        u64_counter!(
+            "retention_recordings_pruned",
+            "Total recordings deleted by retention",
+            retention_recordings_pruned
+        );
+        u64_counter!(
+            "retention_freed_bytes",
+            "Total bytes freed by retention",
+            retention_freed_bytes
+        );
+        u64_gauge!(
+            "retention_prunable_bytes",
+            "Bytes the last dry-run retention pass would free",
+            retention_prunable_bytes
+        );
+        u64_gauge!(
+            "recording_disk_free_bytes",
+            "Free bytes on the recording disk",
+            recording_disk_free_bytes
+        );
+        u64_gauge!(
+            "recording_disk_level",
+            "Recording disk level: 0 ok, 1 below soft threshold, 2 full",
+            recording_disk_level
+        );
+        u64_counter!(
+            "recording_disk_full_events",
+            "Times the recording disk crossed the hard threshold",
+            recording_disk_full_events
+        );
+        u64_counter!(
+            "recording_uploads",
+            "Total recordings uploaded to remote storage",
+            recording_uploads
+        );
+        u64_counter!(
+            "recording_upload_bytes",
+            "Total bytes uploaded to remote storage",
+            recording_upload_bytes
+        );
+        u64_counter!(
+            "recording_upload_failures",
+            "Total failed recording uploads",
+            recording_upload_failures
+        );
         u32_counter!(
             "ffmpeg_spawn_failures",
             "Total ffmpeg/file writer spawn or setup failures",
@@ -732,6 +836,18 @@ impl BotMetrics {
             audio_packets_dropped,
             std::convert::identity
         );
+        guild_counter!(
+            "guild_audio_frames_recovered",
+            "Total lost audio frames rebuilt via FEC/PLC per guild",
+            audio_frames_recovered,
+            std::convert::identity
+        );
+        channel_counter!(
+            "channel_audio_frames_recovered",
+            "Total lost audio frames rebuilt via FEC/PLC per channel",
+            audio_frames_recovered,
+            std::convert::identity
+        );
         guild_counter!(
             "guild_ffmpeg_spawn_failures",
             "Total ffmpeg/file writer spawn or setup failures per guild",
@@ -860,6 +976,38 @@ impl BotMetrics {
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
+    pub fn track_audio_packets_dropped(
+        &self,
+        guild_metrics: &GuildRecordingMetrics,
+        channel_metrics: &GuildRecordingMetrics,
+        count: u64,
+    ) {
+        self.audio_packets_dropped
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+        guild_metrics
+            .audio_packets_dropped
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+        channel_metrics
+            .audio_packets_dropped
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn track_audio_frames_recovered(
+        &self,
+        guild_metrics: &GuildRecordingMetrics,
+        channel_metrics: &GuildRecordingMetrics,
+        count: u64,
+    ) {
+        self.audio_frames_recovered
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+        guild_metrics
+            .audio_frames_recovered
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+        channel_metrics
+            .audio_frames_recovered
+            .fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
     pub fn track_last_voice_packet(
         &self,
         guild_metrics: &GuildRecordingMetrics,
@@ -948,8 +1096,25 @@ impl Default for BotMetrics {
             ffmpeg_process_crashes: AtomicU32::new(0),
             audio_packets_received: AtomicU64::new(0),
             audio_packets_dropped: AtomicU64::new(0),
+            audio_frames_recovered: AtomicU64::new(0),
             last_voice_packet_time: AtomicI64::new(0),
             recording_duration_seconds: Self::recording_duration_histogram(),
+            disk_write_queue_depth: AtomicU64::new(0),
+            disk_write_backpressure: AtomicU64::new(0),
+            disk_write_batches: AtomicU64::new(0),
+            disk_write_bytes: AtomicU64::new(0),
+            disk_write_errors: AtomicU64::new(0),
+            disk_fsyncs: AtomicU64::new(0),
+            retention_runs: AtomicU64::new(0),
+            retention_recordings_pruned: AtomicU64::new(0),
+            retention_freed_bytes: AtomicU64::new(0),
+            retention_prunable_bytes: AtomicU64::new(0),
+            recording_disk_free_bytes: AtomicU64::new(0),
+            recording_disk_level: AtomicU64::new(0),
+            recording_disk_full_events: AtomicU64::new(0),
+            recording_uploads: AtomicU64::new(0),
+            recording_upload_bytes: AtomicU64::new(0),
+            recording_upload_failures: AtomicU64::new(0),
             guild_recording_metrics: dashmap::DashMap::new(),
             channel_recording_metrics: dashmap::DashMap::new(),
             voice_users: dashmap::DashMap::new(),
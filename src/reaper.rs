@@ -7,17 +7,23 @@
 //! only while the row's recording heartbeat is fresh and owned by a fresh,
 //! non-stopped bot instance.
 //!
-//! Default mode: row stays, `end_ts = start_ts`, `reaped = TRUE`. Files on
-//! disk are left alone — they may still be partially playable. Audit them
+//! Default mode: zombie files are repaired first (see [`crate::repair`]):
+//! cut to their last whole Ogg page and closed with an EOS page, and their
+//! rows get `end_ts = start_ts + duration`, `reaped = TRUE`. Rows whose file
+//! is missing or unreadable fall back to `end_ts = start_ts`. Audit them
 //! with:
 //!
 //! ```sql
 //! SELECT * FROM audio_files WHERE reaped = TRUE ORDER BY start_ts DESC;
 //! ```
 //!
-//! Purge mode (`REAPER_PURGE=1`): one-shot wipe — delete the `.ogg` and any
-//! `hls-{stem}/` cache dir, then `DELETE FROM audio_files` for those rows.
-//! Use after a long zombie buildup when you don't want to inspect each one.
+//! Purge mode (`REAPER_PURGE=1`): one-shot wipe — delete the `.ogg`, its
+//! `.idx`/`.cue` sidecars and any `hls-{stem}/` cache dir, then
+//! `DELETE FROM audio_files` for those rows. Use after a long zombie buildup
+//! when you don't want to inspect each one.
+//!
+//! Open `voice_sessions` rows of dead instances are closed at the end of
+//! their last recording and marked `reaped`.
 //!
 //! `bot_reaper_state.last_reap_ts` records when the startup reaper last ran.
 //! We still rescan all unfinished rows because a row can be skipped while its
@@ -124,6 +130,14 @@ pub async fn reap_zombie_recordings(pool: &Pool<Postgres>) {
                     error!(path = %path.display(), error = %e, "reaper: delete failed");
                 }
             }
+            for sidecar in ["idx", "cue"] {
+                let sidecar = path.with_extension(sidecar);
+                if let Err(e) = std::fs::remove_file(&sidecar)
+                    && e.kind() != std::io::ErrorKind::NotFound
+                {
+                    warn!(path = %sidecar.display(), error = %e, "reaper: sidecar delete failed");
+                }
+            }
             let hls = key.live_dir(RECORDING_ROOT);
             if hls.exists()
                 && let Err(e) = std::fs::remove_dir_all(&hls)
@@ -133,6 +147,12 @@ pub async fn reap_zombie_recordings(pool: &Pool<Postgres>) {
         }
     }
 
+    let repaired = if purge {
+        crate::repair::ZombieRepairReport::default()
+    } else {
+        crate::repair::repair_zombies(pool).await
+    };
+
     let rows_changed = if purge {
         match sqlx::query!(
             "DELETE FROM audio_files
@@ -180,6 +200,32 @@ pub async fn reap_zombie_recordings(pool: &Pool<Postgres>) {
         }
     };
 
+    // Sessions whose owner is gone end with their last recording.
+    let sessions_closed = match sqlx::query!(
+        "UPDATE voice_sessions
+            SET end_ts = COALESCE(
+                    (SELECT MAX(end_ts) FROM audio_files WHERE session_id = voice_sessions.id),
+                    start_ts),
+                reaped = TRUE
+          WHERE end_ts IS NULL
+            AND NOT EXISTS (
+                SELECT 1
+                  FROM bot_instances bi
+                 WHERE bi.instance_id = voice_sessions.recording_owner_instance_id
+                   AND bi.heartbeat_at > now() - interval '120 seconds'
+                   AND bi.state <> 'stopped'
+            )"
+    )
+    .execute(pool)
+    .await
+    {
+        Ok(r) => r.rows_affected(),
+        Err(e) => {
+            error!("reaper: voice session close failed: {}", e);
+            0
+        }
+    };
+
     if let Err(e) = sqlx::query!(
         "UPDATE bot_reaper_state SET last_reap_ts = $1 WHERE id = 1",
         now_ms
@@ -194,6 +240,9 @@ pub async fn reap_zombie_recordings(pool: &Pool<Postgres>) {
         purge,
         zombies = zombies.len(),
         rows_changed,
+        repaired = repaired.repaired,
+        repair_failed = repaired.failed,
+        sessions_closed,
         deleted_files,
         missing_files,
         last_reap_ts,
@@ -0,0 +1,676 @@
+//! Repair of recordings cut short by a crash or power loss.
+//!
+//! A recording whose writer never finished ends wherever the disk stopped:
+//! usually mid-page, without an EOS page, and with a speech-only `.idx`
+//! missing its trailer. [`repair_recording`] scans the Ogg pages (checking
+//! each page's CRC), drops everything after the last complete one, appends
+//! an EOS page carrying the last granule and closes the index. Plaintext
+//! files are truncated and appended to in place; encrypted ones are
+//! decrypted up to their last whole chunk and rewritten.
+//!
+//! The reaper runs [`repair_zombies`] on startup so reaped rows get their
+//! real length (`granule / 48000`, or the index's logical end for
+//! speech-only files) as `end_ts` instead of `start_ts`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use ogg::PacketWriteEndInfo;
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::events::sparse_index;
+use crate::retention::RecordingKind;
+
+const PAGE_HEADER_LEN: usize = 27;
+const HEADER_TYPE_EOS: u8 = 0x04;
+/// Opus granules are 48 kHz samples.
+const GRANULES_PER_MS: u64 = 48;
+const SAMPLES_PER_PACKET: u64 = 960;
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+/// Ogg page checksum: CRC-32, polynomial 0x04c11db7, no reflection.
+fn ogg_crc(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize]
+    })
+}
+
+/// What a scan of an Ogg stream found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageScan {
+    pub pages: u64,
+    /// Bytes up to the end of the last page that ends a packet.
+    pub valid_len: u64,
+    /// Granule of that page.
+    pub granule: u64,
+    pub serial: u32,
+    pub last_sequence: u32,
+    /// Whether that page is an EOS page.
+    pub eos: bool,
+    /// Bytes of the stream, valid or not.
+    pub total_len: u64,
+}
+
+impl PageScan {
+    /// Whether the stream needs repair to be a finished recording.
+    pub fn needs_repair(&self) -> bool {
+        !self.eos || self.valid_len < self.total_len
+    }
+
+    pub fn duration_ms(&self) -> i64 {
+        (self.granule / GRANULES_PER_MS) as i64
+    }
+}
+
+/// Fill `buf`; `false` if the stream ends first.
+fn read_exact_or_end<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Walk the pages of an Ogg stream until the first torn or corrupt one.
+pub fn scan_pages<R: Read>(mut reader: R) -> io::Result<PageScan> {
+    let mut scan = PageScan::default();
+    let mut offset = 0u64;
+    let mut header = [0u8; PAGE_HEADER_LEN];
+    let mut lacing = [0u8; 255];
+    let mut body = Vec::new();
+
+    loop {
+        if !read_exact_or_end(&mut reader, &mut header)? {
+            break;
+        }
+        if &header[..4] != b"OggS" || header[4] != 0 {
+            break;
+        }
+        let segments = header[26] as usize;
+        if !read_exact_or_end(&mut reader, &mut lacing[..segments])? {
+            break;
+        }
+        let body_len: usize = lacing[..segments].iter().map(|&l| l as usize).sum();
+        body.resize(body_len, 0);
+        if !read_exact_or_end(&mut reader, &mut body)? {
+            break;
+        }
+
+        let stored_crc = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+        let mut zeroed = header;
+        zeroed[22..26].fill(0);
+        let crc = ogg_crc(ogg_crc(ogg_crc(0, &zeroed), &lacing[..segments]), &body);
+        if crc != stored_crc {
+            break;
+        }
+
+        offset += (PAGE_HEADER_LEN + segments + body_len) as u64;
+        scan.pages += 1;
+        if scan.pages == 1 {
+            scan.serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+        }
+        // A page whose last lacing value is 255 leaves its packet open on the
+        // next page; it is only usable once that packet ends.
+        let ends_packet = segments == 0 || lacing[segments - 1] < 255;
+        if ends_packet {
+            let granule = u64::from_le_bytes([
+                header[6], header[7], header[8], header[9], header[10], header[11], header[12],
+                header[13],
+            ]);
+            if granule != u64::MAX {
+                scan.granule = granule;
+            }
+            scan.valid_len = offset;
+            scan.last_sequence =
+                u32::from_le_bytes([header[18], header[19], header[20], header[21]]);
+            scan.eos = header[5] & HEADER_TYPE_EOS != 0;
+        }
+    }
+
+    // Whatever follows the last readable page counts toward the total.
+    let mut rest = offset;
+    let mut sink = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut sink)?;
+        if n == 0 {
+            break;
+        }
+        rest += n as u64;
+    }
+    scan.total_len = rest;
+    Ok(scan)
+}
+
+/// An EOS page holding one empty packet.
+fn eos_page(serial: u32, sequence: u32, granule: u64) -> Vec<u8> {
+    let mut page = Vec::with_capacity(PAGE_HEADER_LEN + 1);
+    page.extend_from_slice(b"OggS");
+    page.push(0);
+    page.push(HEADER_TYPE_EOS);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]);
+    page.push(1);
+    page.push(0);
+    let crc = ogg_crc(0, &page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+pub fn is_encrypted_file(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 8];
+    let n = File::open(path)?.read(&mut magic)?;
+    Ok(crate::encryption::is_encrypted(&magic[..n]))
+}
+
+/// Plaintext scan of a recording, decrypting it if needed. Also reports
+/// whether an encrypted file ended in a torn chunk.
+pub fn scan_file(path: &Path) -> io::Result<(PageScan, bool)> {
+    if is_encrypted_file(path)? {
+        let reader = crate::encryption::DecryptingReader::new(
+            File::open(path)?,
+            crate::encryption::keyring(),
+        )?;
+        let torn = reader.truncated();
+        Ok((scan_pages(BufReader::new(reader))?, torn))
+    } else {
+        Ok((scan_pages(BufReader::new(File::open(path)?))?, false))
+    }
+}
+
+/// Replace `path` with what `fill` writes, encrypted with the active key if
+/// there is one. The new file is synced before it is renamed into place.
+fn replace_file(
+    path: &Path,
+    fill: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let temp = path.with_extension("repair");
+    {
+        let file = File::create(&temp)?;
+        let mut out = crate::encryption::writer(file.try_clone()?)?;
+        fill(&mut out)?;
+        out.flush()?;
+        drop(out);
+        file.sync_all()?;
+    }
+    std::fs::rename(&temp, path)
+}
+
+/// Replace `path` with the first `keep` plaintext bytes of `source` plus
+/// `tail`.
+fn rewrite<R: Read>(path: &Path, source: R, keep: u64, tail: &[u8]) -> io::Result<()> {
+    replace_file(path, |out| {
+        io::copy(&mut source.take(keep), out)?;
+        out.write_all(tail)
+    })
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    pub scan: PageScan,
+    /// Plaintext bytes dropped after the last complete page.
+    pub dropped_bytes: u64,
+    pub eos_added: bool,
+    /// The speech-only index was missing its trailer.
+    pub index_closed: bool,
+    /// Length of the recording's timeline.
+    pub duration_ms: i64,
+    /// Set when nothing needed fixing or `dry_run` was given.
+    pub unchanged: bool,
+}
+
+/// Repair the Ogg file at `path`, or with `dry_run` only report what would
+/// change.
+pub fn repair_ogg(path: &Path, dry_run: bool) -> io::Result<RepairReport> {
+    let (scan, torn) = scan_file(path)?;
+    if scan.pages == 0 || scan.valid_len == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no complete Ogg page", path.display()),
+        ));
+    }
+    let mut report = RepairReport {
+        dropped_bytes: scan.total_len - scan.valid_len,
+        eos_added: !scan.eos,
+        duration_ms: scan.duration_ms(),
+        unchanged: dry_run || !(scan.needs_repair() || torn),
+        scan,
+    };
+    if report.unchanged {
+        return Ok(report);
+    }
+
+    let tail = if report.scan.eos {
+        Vec::new()
+    } else {
+        eos_page(
+            report.scan.serial,
+            report.scan.last_sequence.wrapping_add(1),
+            report.scan.granule,
+        )
+    };
+    if is_encrypted_file(path)? {
+        let reader = crate::encryption::DecryptingReader::new(
+            File::open(path)?,
+            crate::encryption::keyring(),
+        )?;
+        rewrite(path, reader, report.scan.valid_len, &tail)?;
+    } else {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(report.scan.valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&tail)?;
+        file.sync_all()?;
+    }
+    report.scan.eos = true;
+    report.scan.total_len = report.scan.valid_len + tail.len() as u64;
+    report.scan.valid_len = report.scan.total_len;
+    Ok(report)
+}
+
+/// Close a speech-only index that never got its trailer. Returns the
+/// logical length in frames and whether the trailer was missing.
+///
+/// Runs are only indexed once they end, so packets of a run still open at
+/// the crash stay in the `.ogg` past the last indexed run.
+pub fn repair_index(path: &Path, dry_run: bool) -> io::Result<(u64, bool)> {
+    let encrypted = is_encrypted_file(path)?;
+    let bytes = crate::encryption::read_file(path)?;
+    let parsed = sparse_index::parse_index(&bytes)?;
+    let frames = parsed.logical_frames();
+    if parsed.logical_end.is_some() {
+        return Ok((frames, false));
+    }
+    if dry_run {
+        return Ok((frames, true));
+    }
+    let keep = parsed.byte_len() as u64;
+    let trailer = sparse_index::trailer(frames);
+    if encrypted {
+        rewrite(path, io::Cursor::new(bytes), keep, &trailer)?;
+    } else {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(keep)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&trailer)?;
+        file.sync_all()?;
+    }
+    Ok((frames, true))
+}
+
+/// Repair a recording and, when `sparse`, its `.idx`.
+pub fn repair_recording(ogg: &Path, sparse: bool, dry_run: bool) -> io::Result<RepairReport> {
+    let mut report = repair_ogg(ogg, dry_run)?;
+    if sparse {
+        let index = ogg.with_extension("idx");
+        match repair_index(&index, dry_run) {
+            Ok((frames, closed)) => {
+                report.index_closed = closed;
+                report.duration_ms = frames as i64 * 20;
+            }
+            Err(e) => {
+                warn!(path = %index.display(), error = %e, "repair: index unusable; using granule");
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Rewrite an Ogg Opus stream one packet per page, with fresh page
+/// sequence numbers and granules (960 per audio packet) and a final EOS
+/// page. Reading stops at the first unreadable page; packets of other
+/// logical streams are dropped. Returns the audio packets written.
+pub fn repage<R: Read + Seek, W: Write>(input: R, output: W) -> io::Result<u64> {
+    let mut reader = ogg::PacketReader::new(input);
+    let mut writer = ogg::PacketWriter::new(output);
+    let mut serial = None;
+    let mut headers = 0;
+    let mut packets = 0u64;
+    loop {
+        let packet = match reader.read_packet() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("repage: stopping at unreadable page: {}", e);
+                break;
+            }
+        };
+        let serial = *serial.get_or_insert(packet.stream_serial());
+        if packet.stream_serial() != serial {
+            continue;
+        }
+        // OpusHead and OpusTags keep granule 0.
+        if headers < 2 {
+            writer.write_packet(packet.data, serial, PacketWriteEndInfo::EndPage, 0)?;
+            headers += 1;
+            continue;
+        }
+        // Opus packets are never empty; an empty one is an old EOS marker.
+        if packet.data.is_empty() {
+            continue;
+        }
+        packets += 1;
+        writer.write_packet(
+            packet.data,
+            serial,
+            PacketWriteEndInfo::EndPage,
+            packets * SAMPLES_PER_PACKET,
+        )?;
+    }
+    let Some(serial) = serial.filter(|_| headers == 2) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no Opus headers found",
+        ));
+    };
+    writer.write_packet(
+        Vec::new(),
+        serial,
+        PacketWriteEndInfo::EndStream,
+        packets * SAMPLES_PER_PACKET,
+    )?;
+    writer.inner_mut().flush()?;
+    Ok(packets)
+}
+
+/// [`repage`] the recording at `path` in place.
+pub fn repage_file(path: &Path) -> io::Result<u64> {
+    let input = crate::encryption::open_media(File::open(path)?)?;
+    let mut packets = 0;
+    replace_file(path, |out| {
+        packets = repage(input, out)?;
+        Ok(())
+    })?;
+    Ok(packets)
+}
+
+struct Zombie {
+    id: i64,
+    key: RecordingKey,
+    start_ts: i64,
+    sparse: bool,
+}
+
+impl Zombie {
+    fn path(&self) -> PathBuf {
+        self.key.recording_path(RECORDING_ROOT)
+    }
+}
+
+struct ZombieRow {
+    id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    year: i32,
+    month: i32,
+    file_name: String,
+    start_ts: i64,
+    sparse: bool,
+}
+
+/// Unfinished rows whose owner is gone, as the reaper sees them.
+async fn load_zombies(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+) -> Result<Vec<Zombie>, sqlx::Error> {
+    let rows = match kind {
+        RecordingKind::Track => {
+            sqlx::query_as!(
+                ZombieRow,
+                r#"SELECT t.id, t.guild_id, t.channel_id, t.year, t.month, t.file_name,
+                          t.start_ts AS "start_ts!", t.storage_mode = 1 AS "sparse!"
+                     FROM audio_files t
+                    WHERE t.start_ts IS NOT NULL AND t.end_ts IS NULL
+                      AND NOT EXISTS (
+                          SELECT 1
+                            FROM bot_instances bi
+                           WHERE bi.instance_id = t.recording_owner_instance_id
+                             AND t.recording_heartbeat_at > now() - interval '120 seconds'
+                             AND bi.heartbeat_at > now() - interval '120 seconds'
+                             AND bi.state <> 'stopped'
+                      )"#
+            )
+            .fetch_all(pool)
+            .await?
+        }
+        RecordingKind::Mix => {
+            sqlx::query_as!(
+                ZombieRow,
+                r#"SELECT t.id, t.guild_id, t.channel_id, t.year, t.month, t.file_name,
+                          t.start_ts, FALSE AS "sparse!"
+                     FROM audio_mix_files t
+                    WHERE t.end_ts IS NULL
+                      AND NOT EXISTS (
+                          SELECT 1
+                            FROM bot_instances bi
+                           WHERE bi.instance_id = t.recording_owner_instance_id
+                             AND t.recording_heartbeat_at > now() - interval '120 seconds'
+                             AND bi.heartbeat_at > now() - interval '120 seconds'
+                             AND bi.state <> 'stopped'
+                      )"#
+            )
+            .fetch_all(pool)
+            .await?
+        }
+    };
+    Ok(rows
+        .into_iter()
+        .map(|row| Zombie {
+            id: row.id,
+            key: RecordingKey::new(
+                row.guild_id,
+                row.channel_id,
+                row.year,
+                row.month as u32,
+                row.file_name,
+            ),
+            start_ts: row.start_ts,
+            sparse: row.sparse,
+        })
+        .collect())
+}
+
+async fn close_zombie(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+    id: i64,
+    end_ts: i64,
+) -> Result<(), sqlx::Error> {
+    match kind {
+        RecordingKind::Track => sqlx::query!(
+            "UPDATE audio_files SET end_ts = $2, reaped = TRUE WHERE id = $1 AND end_ts IS NULL",
+            id,
+            end_ts
+        )
+        .execute(pool)
+        .await?,
+        RecordingKind::Mix => {
+            sqlx::query!(
+                "UPDATE audio_mix_files SET end_ts = $2, reaped = TRUE
+                  WHERE id = $1 AND end_ts IS NULL",
+                id,
+                end_ts
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct ZombieRepairReport {
+    pub repaired: u64,
+    pub failed: u64,
+}
+
+/// Repair the files of zombie recordings and close their rows with the
+/// real duration. Rows whose files cannot be repaired are left to the
+/// reaper.
+pub async fn repair_zombies(pool: &Pool<Postgres>) -> ZombieRepairReport {
+    let mut report = ZombieRepairReport::default();
+    for kind in [RecordingKind::Track, RecordingKind::Mix] {
+        let table = kind.table();
+        let zombies = match load_zombies(pool, kind).await {
+            Ok(zombies) => zombies,
+            Err(e) => {
+                warn!(table, "repair: zombie select failed: {}", e);
+                continue;
+            }
+        };
+        for zombie in zombies {
+            let path = zombie.path();
+            let sparse = zombie.sparse;
+            let repaired =
+                tokio::task::spawn_blocking(move || repair_recording(&path, sparse, false)).await;
+            let repaired = match repaired {
+                Ok(Ok(repaired)) => repaired,
+                Ok(Err(e)) => {
+                    warn!(table, id = zombie.id, error = %e, "repair: recording not repaired");
+                    report.failed += 1;
+                    continue;
+                }
+                Err(e) => {
+                    warn!(table, id = zombie.id, "repair task failed: {}", e);
+                    report.failed += 1;
+                    continue;
+                }
+            };
+            match close_zombie(
+                pool,
+                kind,
+                zombie.id,
+                zombie.start_ts + repaired.duration_ms,
+            )
+            .await
+            {
+                Ok(_) => {
+                    report.repaired += 1;
+                    info!(
+                        table,
+                        id = zombie.id,
+                        duration_ms = repaired.duration_ms,
+                        dropped_bytes = repaired.dropped_bytes,
+                        eos_added = repaired.eos_added,
+                        index_closed = repaired.index_closed,
+                        "repair: zombie recording repaired"
+                    );
+                }
+                Err(e) => {
+                    warn!(table, id = zombie.id, "repair: row update failed: {}", e);
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ogg_opus_writer::OggOpusWriter;
+    use std::io::Cursor;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "repair-{}-{}-{}.ogg",
+            name,
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ))
+    }
+
+    #[test]
+    fn torn_recording_is_cut_to_last_page_and_closed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        {
+            let mut writer = OggOpusWriter::new(Cursor::new(&mut bytes), 7, 0)?;
+            writer.write_silence(50)?;
+            writer.flush()?;
+            // Dropping would write EOS; a crash does not.
+            std::mem::forget(writer);
+        }
+        let full = scan_pages(Cursor::new(&bytes))?;
+        assert!(!full.eos);
+        assert_eq!(full.duration_ms(), 50 * 20);
+
+        bytes.truncate(bytes.len() - 5);
+        let path = temp_path("torn");
+        std::fs::write(&path, &bytes)?;
+
+        let report = repair_ogg(&path, false)?;
+        assert!(report.eos_added);
+        assert!(report.dropped_bytes > 0);
+        assert_eq!(report.duration_ms, 49 * 20);
+
+        let after = scan_pages(File::open(&path)?)?;
+        std::fs::remove_file(&path)?;
+        assert!(after.eos);
+        assert!(!after.needs_repair());
+        assert_eq!(after.granule, 49 * 960);
+        Ok(())
+    }
+
+    #[test]
+    fn finished_recording_is_left_alone() -> Result<(), Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        {
+            let mut writer = OggOpusWriter::new(Cursor::new(&mut bytes), 7, 0)?;
+            writer.write_silence(3)?;
+            writer.finish()?;
+        }
+        let path = temp_path("finished");
+        std::fs::write(&path, &bytes)?;
+        let report = repair_ogg(&path, false)?;
+        let after = std::fs::read(&path)?;
+        std::fs::remove_file(&path)?;
+        assert!(report.unchanged);
+        assert_eq!(after, bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn repage_renumbers_and_closes_the_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        {
+            let mut writer = OggOpusWriter::new(Cursor::new(&mut bytes), 7, 0)?;
+            writer.write_silence(4)?;
+            writer.flush()?;
+            std::mem::forget(writer);
+        }
+        let mut out = Vec::new();
+        assert_eq!(repage(Cursor::new(&bytes), &mut out)?, 4);
+        let scan = scan_pages(Cursor::new(&out))?;
+        assert!(scan.eos);
+        assert!(!scan.needs_repair());
+        assert_eq!(scan.granule, 4 * 960);
+        assert_eq!(scan.pages, 2 + 4 + 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,511 @@
+//! Periodic retention pruning for finished recordings.
+//!
+//! Rules live in `recording_retention_rules`; a recording uses the rule of
+//! its channel, else its guild, else the global rule, and is kept forever
+//! when none exists. A rule can:
+//!
+//! - prune recordings older than `max_age_days`,
+//! - cap a guild at `max_guild_bytes`, pruning its oldest recordings first,
+//! - keep recordings a stamp points at (`keep_stamped`) or a clip was cut
+//!   from (`keep_clipped`), whatever their age or the cap.
+//!
+//! Per-user tracks and session mixdowns are both pruned; a mixdown counts as
+//! stamped when any stamp belongs to its session. Pruning deletes the `.ogg`,
+//! its `.idx`/`.cue` sidecars, any `hls-{stem}/` dir and cached exports
+//! beside it (see [`crate::export::remove_cached`]), locally and from
+//! the remote backend the row was uploaded to, then sets `pruned_at` on the
+//! row, or deletes it with `RETENTION_DELETE_ROWS=1`.
+//!
+//! `RETENTION_DRY_RUN=1` only logs what would go. Pruning runs on one
+//! instance at a time, under [`LockKey::Retention`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sqlx::{Pool, Postgres};
+use tracing::{debug, error, info, warn};
+
+use crate::BotMetrics;
+use crate::database::advisory_lock::{LockKey, with_advisory_lock};
+use crate::storage::Storage;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Default)]
+pub struct RetentionRule {
+    pub guild_id: Option<u64>,
+    pub channel_id: Option<u64>,
+    pub max_age_days: Option<u32>,
+    pub max_guild_bytes: Option<u64>,
+    pub keep_stamped: bool,
+    pub keep_clipped: bool,
+}
+
+impl RetentionRule {
+    fn protects(&self, recording: &Candidate) -> bool {
+        (self.keep_stamped && recording.stamped) || (self.keep_clipped && recording.clipped)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Rules(Vec<RetentionRule>);
+
+impl Rules {
+    pub fn new(rules: Vec<RetentionRule>) -> Self {
+        Self(rules)
+    }
+
+    /// The most specific rule covering a channel.
+    fn for_channel(&self, guild_id: u64, channel_id: u64) -> Option<&RetentionRule> {
+        self.find(Some(guild_id), Some(channel_id))
+            .or_else(|| self.for_guild(guild_id))
+    }
+
+    /// The rule holding a guild's byte cap.
+    fn for_guild(&self, guild_id: u64) -> Option<&RetentionRule> {
+        self.find(Some(guild_id), None)
+            .or_else(|| self.find(None, None))
+    }
+
+    fn find(&self, guild_id: Option<u64>, channel_id: Option<u64>) -> Option<&RetentionRule> {
+        self.0
+            .iter()
+            .find(|r| r.guild_id == guild_id && r.channel_id == channel_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingKind {
+    Track,
+    Mix,
+}
+
+impl RecordingKind {
+    /// The table the recording's row lives in.
+    pub fn table(self) -> &'static str {
+        match self {
+            Self::Track => "audio_files",
+            Self::Mix => "audio_mix_files",
+        }
+    }
+}
+
+/// A finished recording retention may prune. `bytes` is filled in from
+/// disk after the rows are loaded, unless the recording was uploaded.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub kind: RecordingKind,
+    pub id: i64,
+    pub guild_id: u64,
+    pub channel_id: u64,
+    pub year: i32,
+    pub month: u32,
+    pub file_name: String,
+    pub start_ts: i64,
+    pub stamped: bool,
+    pub clipped: bool,
+    /// `storage_backend` of the row.
+    pub storage_backend: String,
+    /// Size of the `.ogg` and its sidecars.
+    pub bytes: u64,
+}
+
+impl Candidate {
+    fn key(&self) -> RecordingKey {
+        RecordingKey::new(
+            self.guild_id as i64,
+            self.channel_id as i64,
+            self.year,
+            self.month,
+            self.file_name.clone(),
+        )
+    }
+
+    fn files(&self) -> [std::path::PathBuf; 3] {
+        let path = self.key().recording_path(RECORDING_ROOT);
+        [path.with_extension("idx"), path.with_extension("cue"), path]
+    }
+}
+
+/// Pick the recordings to prune. `candidates` must be ordered oldest first
+/// so byte caps drop the oldest recordings.
+pub fn plan(rules: &Rules, candidates: &[Candidate], now_ms: i64) -> Vec<usize> {
+    let mut prune = vec![false; candidates.len()];
+    let mut guild_bytes: HashMap<u64, u64> = HashMap::new();
+
+    for (i, recording) in candidates.iter().enumerate() {
+        if let Some(rule) = rules.for_channel(recording.guild_id, recording.channel_id)
+            && !rule.protects(recording)
+            && let Some(days) = rule.max_age_days
+            && recording.start_ts < now_ms - days as i64 * DAY_MS
+        {
+            prune[i] = true;
+            continue;
+        }
+        *guild_bytes.entry(recording.guild_id).or_default() += recording.bytes;
+    }
+
+    for (i, recording) in candidates.iter().enumerate() {
+        if prune[i] {
+            continue;
+        }
+        let Some(cap) = rules
+            .for_guild(recording.guild_id)
+            .and_then(|r| r.max_guild_bytes)
+        else {
+            continue;
+        };
+        let total = guild_bytes.entry(recording.guild_id).or_default();
+        if *total <= cap {
+            continue;
+        }
+        if rules
+            .for_channel(recording.guild_id, recording.channel_id)
+            .is_some_and(|r| r.protects(recording))
+        {
+            continue;
+        }
+        prune[i] = true;
+        *total -= recording.bytes;
+    }
+
+    prune
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| p.then_some(i))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    pub pruned: u64,
+    pub bytes: u64,
+    pub rows_changed: u64,
+}
+
+async fn load_rules(pool: &Pool<Postgres>) -> Result<Rules, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT guild_id, channel_id, max_age_days, max_guild_bytes, keep_stamped, keep_clipped
+           FROM recording_retention_rules"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(Rules::new(
+        rows.into_iter()
+            .map(|row| RetentionRule {
+                guild_id: row.guild_id.map(|id| id as u64),
+                channel_id: row.channel_id.map(|id| id as u64),
+                max_age_days: row.max_age_days.map(|d| d as u32),
+                max_guild_bytes: row.max_guild_bytes.map(|b| b as u64),
+                keep_stamped: row.keep_stamped,
+                keep_clipped: row.keep_clipped,
+            })
+            .collect(),
+    ))
+}
+
+async fn load_candidates(pool: &Pool<Postgres>) -> Result<Vec<Candidate>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT FALSE AS "is_mix!", a.id AS "id!", a.guild_id AS "guild_id!",
+                a.channel_id AS "channel_id!", a.year AS "year!", a.month AS "month!",
+                a.file_name AS "file_name!", COALESCE(a.start_ts, 0) AS "start_ts!",
+                EXISTS (SELECT 1 FROM stamps s WHERE s.audio_file_id = a.id) AS "stamped!",
+                EXISTS (SELECT 1 FROM clips c WHERE c.source_audio_file_id = a.id) AS "clipped!",
+                a.storage_backend AS "storage_backend!", a.object_size_bytes
+           FROM audio_files a
+          WHERE a.end_ts IS NOT NULL AND a.pruned_at IS NULL
+         UNION ALL
+         SELECT TRUE, m.id, m.guild_id, m.channel_id, m.year, m.month, m.file_name, m.start_ts,
+                EXISTS (SELECT 1 FROM stamps s WHERE s.session_id = m.session_id),
+                FALSE,
+                m.storage_backend, m.object_size_bytes
+           FROM audio_mix_files m
+          WHERE m.end_ts IS NOT NULL AND m.pruned_at IS NULL
+          ORDER BY 8"#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| Candidate {
+            kind: if row.is_mix {
+                RecordingKind::Mix
+            } else {
+                RecordingKind::Track
+            },
+            id: row.id,
+            guild_id: row.guild_id as u64,
+            channel_id: row.channel_id as u64,
+            year: row.year,
+            month: row.month as u32,
+            file_name: row.file_name,
+            start_ts: row.start_ts,
+            stamped: row.stamped,
+            clipped: row.clipped,
+            storage_backend: row.storage_backend,
+            bytes: row.object_size_bytes.unwrap_or(0) as u64,
+        })
+        .collect())
+}
+
+/// Apply the retention rules once.
+pub async fn prune_once(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    dry_run: bool,
+) -> Result<PruneReport, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(with_advisory_lock(pool, LockKey::Retention, || {
+        prune_locked(pool, storage, dry_run)
+    })
+    .await?
+    .unwrap_or_else(|| PruneReport {
+        dry_run,
+        ..Default::default()
+    }))
+}
+
+async fn prune_locked(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    dry_run: bool,
+) -> Result<PruneReport, Box<dyn std::error::Error + Send + Sync>> {
+    let rules = load_rules(pool).await?;
+    let mut report = PruneReport {
+        dry_run,
+        ..Default::default()
+    };
+    if rules.0.is_empty() {
+        return Ok(report);
+    }
+
+    let mut candidates = load_candidates(pool).await?;
+    let candidates = tokio::task::spawn_blocking(move || {
+        for recording in &mut candidates {
+            if recording.storage_backend != crate::storage::LOCAL {
+                continue;
+            }
+            recording.bytes = recording
+                .files()
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|meta| meta.len())
+                .sum();
+        }
+        candidates
+    })
+    .await?;
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let selected = plan(&rules, &candidates, now_ms);
+
+    let mut tracks = Vec::new();
+    let mut mixes = Vec::new();
+    for &i in &selected {
+        let recording = &candidates[i];
+        report.pruned += 1;
+        report.bytes += recording.bytes;
+        if dry_run {
+            debug!(
+                file_name = %recording.file_name,
+                guild_id = recording.guild_id,
+                bytes = recording.bytes,
+                "retention: would prune"
+            );
+            continue;
+        }
+
+        for path in recording.files() {
+            if let Err(e) = std::fs::remove_file(&path)
+                && e.kind() != std::io::ErrorKind::NotFound
+            {
+                warn!(path = %path.display(), error = %e, "retention: delete failed");
+            }
+        }
+        let hls = recording.key().live_dir(RECORDING_ROOT);
+        if hls.exists()
+            && let Err(e) = std::fs::remove_dir_all(&hls)
+        {
+            warn!(path = %hls.display(), error = %e, "retention: hls cleanup failed");
+        }
+        crate::export::remove_cached(&recording.key());
+        if recording.storage_backend != crate::storage::LOCAL {
+            let Some(backend) = storage.recordings(&recording.storage_backend) else {
+                warn!(
+                    file_name = %recording.file_name,
+                    backend = %recording.storage_backend,
+                    "retention: backend not configured; keeping row"
+                );
+                continue;
+            };
+            let mut deleted = true;
+            for key in crate::storage::recording_object_keys(&recording.key()) {
+                if let Err(e) = backend.delete(&key).await {
+                    warn!(key = %key, error = %e, "retention: remote delete failed");
+                    deleted = false;
+                }
+            }
+            if !deleted {
+                continue;
+            }
+        }
+        match recording.kind {
+            RecordingKind::Track => tracks.push(recording.id),
+            RecordingKind::Mix => mixes.push(recording.id),
+        }
+    }
+
+    if !dry_run {
+        let delete_rows = crate::config::retention_delete_rows();
+        for (kind, ids) in [
+            (RecordingKind::Track, &tracks),
+            (RecordingKind::Mix, &mixes),
+        ] {
+            if ids.is_empty() {
+                continue;
+            }
+            let result = match (kind, delete_rows) {
+                (RecordingKind::Track, true) => {
+                    sqlx::query!("DELETE FROM audio_files WHERE id = ANY($1)", &ids[..])
+                        .execute(pool)
+                        .await
+                }
+                (RecordingKind::Track, false) => {
+                    sqlx::query!(
+                        "UPDATE audio_files SET pruned_at = now() WHERE id = ANY($1)",
+                        &ids[..]
+                    )
+                    .execute(pool)
+                    .await
+                }
+                (RecordingKind::Mix, true) => {
+                    sqlx::query!("DELETE FROM audio_mix_files WHERE id = ANY($1)", &ids[..])
+                        .execute(pool)
+                        .await
+                }
+                (RecordingKind::Mix, false) => {
+                    sqlx::query!(
+                        "UPDATE audio_mix_files SET pruned_at = now() WHERE id = ANY($1)",
+                        &ids[..]
+                    )
+                    .execute(pool)
+                    .await
+                }
+            };
+            match result {
+                Ok(r) => report.rows_changed += r.rows_affected(),
+                Err(e) => error!(table = kind.table(), "retention: row update failed: {}", e),
+            }
+        }
+    }
+
+    info!(
+        dry_run,
+        recordings = candidates.len(),
+        pruned = report.pruned,
+        bytes = report.bytes,
+        rows_changed = report.rows_changed,
+        "retention pass done"
+    );
+    Ok(report)
+}
+
+/// Prune on `RETENTION_INTERVAL_MINUTES` (default hourly) for the life of
+/// the process.
+pub fn start(pool: Pool<Postgres>, storage: Arc<Storage>, metrics: Arc<BotMetrics>) {
+    tokio::spawn(async move {
+        let dry_run = crate::config::retention_dry_run();
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            crate::config::retention_interval_minutes() * 60,
+        ));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match prune_once(&pool, &storage, dry_run).await {
+                Ok(report) => {
+                    metrics.retention_runs.fetch_add(1, Ordering::Relaxed);
+                    if report.dry_run {
+                        metrics
+                            .retention_prunable_bytes
+                            .store(report.bytes, Ordering::Relaxed);
+                    } else {
+                        metrics
+                            .retention_recordings_pruned
+                            .fetch_add(report.pruned, Ordering::Relaxed);
+                        metrics
+                            .retention_freed_bytes
+                            .fetch_add(report.bytes, Ordering::Relaxed);
+                    }
+                }
+                Err(e) => error!("retention pass failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(id: i64, channel_id: u64, start_ts: i64, bytes: u64) -> Candidate {
+        Candidate {
+            kind: RecordingKind::Track,
+            id,
+            guild_id: 1,
+            channel_id,
+            year: 2026,
+            month: 10,
+            file_name: format!("r{}", id),
+            start_ts,
+            stamped: false,
+            clipped: false,
+            storage_backend: crate::storage::LOCAL.to_string(),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn channel_rule_overrides_global_age() {
+        let rules = Rules::new(vec![
+            RetentionRule {
+                max_age_days: Some(30),
+                ..Default::default()
+            },
+            RetentionRule {
+                guild_id: Some(1),
+                channel_id: Some(9),
+                max_age_days: Some(365),
+                ..Default::default()
+            },
+        ]);
+        let now = 400 * DAY_MS;
+        let candidates = [
+            recording(1, 2, now - 40 * DAY_MS, 1),
+            recording(2, 9, now - 40 * DAY_MS, 1),
+            recording(3, 2, now - 10 * DAY_MS, 1),
+        ];
+        assert_eq!(plan(&rules, &candidates, now), vec![0]);
+    }
+
+    #[test]
+    fn byte_cap_drops_oldest_unprotected_first() {
+        let rules = Rules::new(vec![RetentionRule {
+            guild_id: Some(1),
+            max_guild_bytes: Some(250),
+            keep_stamped: true,
+            ..Default::default()
+        }]);
+        let mut stamped = recording(1, 2, 0, 100);
+        stamped.stamped = true;
+        let candidates = [
+            stamped,
+            recording(2, 2, 1, 100),
+            recording(3, 2, 2, 100),
+            recording(4, 2, 3, 100),
+        ];
+        assert_eq!(plan(&rules, &candidates, 10), vec![1, 2]);
+    }
+}
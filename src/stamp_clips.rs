@@ -0,0 +1,448 @@
+//! Automatic clips of stamps.
+//!
+//! With `STAMP_CLIPS=1`, every `STAMP_CLIP_INTERVAL_SECONDS` pending stamps
+//! (`clip_state = 0`) whose window has passed are rendered. The window runs
+//! from `STAMP_CLIP_BEFORE_SECONDS` before the stamp's moment
+//! (`stamp_ts + offset_ms`) to `STAMP_CLIP_AFTER_SECONDS` after it, and is
+//! cut from the stamped user's track or, with `STAMP_CLIP_SOURCE=mix`, from
+//! the channel's session mixdown. Opus packets are copied, not re-encoded,
+//! and silence at either end is trimmed. The clip gets a `clips` row with
+//! `stamp_id` set and is posted with a Replay button to the guild's
+//! `stamp_clip_channel_id`, if it has one.
+//!
+//! A stamp is picked up once its window is [`LIVE_MARGIN_MS`] in the past,
+//! which is enough to cut from a live recording; a live speech-only one
+//! waits until it is finished, since its index is only written then. A
+//! stamp with no recording to cut from, or nothing but silence in its
+//! window, is skipped (`clip_state = 3`). Other failures bump
+//! `clip_attempts`, keep `clip_error` and back off exponentially; after
+//! [`MAX_ATTEMPTS`] the stamp is marked failed (`clip_state = 2`). A clip
+//! that was saved but not posted is posted on retry, not rendered again.
+//! Rendering is kept to one instance by [`LockKey::StampClips`].
+
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sakiot_paths::{CLIPS_ROOT, RecordingKey};
+use serenity::builder::{CreateAllowedMentions, CreateAttachment, CreateMessage};
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
+use sqlx::{Pool, Postgres};
+use tracing::{debug, error, info, warn};
+
+use crate::database::advisory_lock::{LockKey, with_advisory_lock};
+use crate::export::decode::{FRAME_MS, Packets};
+use crate::storage::Storage;
+
+const MAX_ATTEMPTS: i32 = 5;
+/// Stamps per pass.
+const BATCH: i64 = 20;
+/// How far past a window the recording must be before it is cut, so the
+/// window's pages have been sealed and written.
+const LIVE_MARGIN_MS: i64 = 5_000;
+
+const STATE_DONE: i16 = 1;
+const STATE_FAILED: i16 = 2;
+const STATE_SKIPPED: i16 = 3;
+
+type StampClipResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Default)]
+pub struct StampClipReport {
+    pub rendered: u64,
+    pub posted: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+struct PendingStamp {
+    id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    target_user_id: i64,
+    stamper_user_id: i64,
+    /// Unix ms the stamp points at, rewind included.
+    moment_ms: i64,
+    note: Option<String>,
+}
+
+enum Outcome {
+    Done {
+        rendered: bool,
+        message_id: Option<u64>,
+    },
+    Skipped(&'static str),
+    /// The recording is still being written; look again in a minute.
+    NotReady,
+}
+
+async fn load_pending(
+    pool: &Pool<Postgres>,
+    ready_before_ms: i64,
+) -> Result<Vec<PendingStamp>, sqlx::Error> {
+    sqlx::query_as!(
+        PendingStamp,
+        r#"SELECT id, guild_id, channel_id, target_user_id, stamper_user_id,
+                stamp_ts + offset_ms AS "moment_ms!", note
+           FROM stamps
+          WHERE clip_state = 0
+            AND (clip_next_attempt_at IS NULL OR clip_next_attempt_at <= now())
+            AND stamp_ts + offset_ms <= $1
+          ORDER BY id
+          LIMIT $2"#,
+        ready_before_ms,
+        BATCH
+    )
+    .fetch_all(pool)
+    .await
+}
+
+struct SourceRow {
+    id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    year: i32,
+    month: i32,
+    file_name: String,
+    start_ts: i64,
+    end_ts: Option<i64>,
+    sparse: bool,
+    storage_backend: String,
+}
+
+/// The recording covering `stamp`'s moment: the last one to start before
+/// it, or failing that the first overlapping the window.
+async fn find_source(
+    pool: &Pool<Postgres>,
+    stamp: &PendingStamp,
+    window: (i64, i64),
+    from_mix: bool,
+) -> Result<Option<(crate::export::Source, bool)>, sqlx::Error> {
+    let row = if from_mix {
+        sqlx::query_as!(
+            SourceRow,
+            r#"SELECT id, guild_id, channel_id, year, month, file_name, start_ts, end_ts,
+                      FALSE AS "sparse!", storage_backend
+                 FROM audio_mix_files
+                WHERE guild_id = $1 AND channel_id = $2
+                  AND pruned_at IS NULL
+                  AND start_ts < $4 AND (end_ts IS NULL OR end_ts > $3)
+                ORDER BY start_ts <= $5 DESC, start_ts DESC
+                LIMIT 1"#,
+            stamp.guild_id,
+            stamp.channel_id,
+            window.0,
+            window.1,
+            stamp.moment_ms
+        )
+        .fetch_optional(pool)
+        .await?
+    } else {
+        sqlx::query_as!(
+            SourceRow,
+            r#"SELECT id, guild_id, channel_id, year, month, file_name,
+                      start_ts AS "start_ts!", end_ts,
+                      storage_mode = 1 AS "sparse!", storage_backend
+                 FROM audio_files
+                WHERE guild_id = $1 AND channel_id = $2 AND user_id = $3
+                  AND pruned_at IS NULL
+                  AND start_ts < $5 AND (end_ts IS NULL OR end_ts > $4)
+                ORDER BY start_ts <= $6 DESC, start_ts DESC
+                LIMIT 1"#,
+            stamp.guild_id,
+            stamp.channel_id,
+            stamp.target_user_id,
+            window.0,
+            window.1,
+            stamp.moment_ms
+        )
+        .fetch_optional(pool)
+        .await?
+    };
+    Ok(row.map(|row| {
+        (
+            crate::export::Source {
+                id: row.id,
+                key: RecordingKey::new(
+                    row.guild_id,
+                    row.channel_id,
+                    row.year,
+                    row.month as u32,
+                    row.file_name,
+                ),
+                start_ts: row.start_ts,
+                end_ts: row.end_ts.unwrap_or(window.1),
+                sparse: row.sparse,
+                storage_backend: row.storage_backend,
+            },
+            row.end_ts.is_none(),
+        )
+    }))
+}
+
+/// Cut `[first_ms, last_ms)` out of `source`, silence trimmed.
+async fn cut(
+    storage: &Storage,
+    source: &crate::export::Source,
+    first_ms: i64,
+    last_ms: i64,
+) -> StampClipResult<Vec<Option<Vec<u8>>>> {
+    let input = crate::export::input(storage, source).await?;
+    let skip = ((first_ms - input.start_ts).max(0) / FRAME_MS) as u64;
+    let frames = ((last_ms - first_ms.max(input.start_ts)).max(0) / FRAME_MS) as u64;
+    let packets = tokio::task::spawn_blocking(move || -> std::io::Result<_> {
+        let mut packets = Packets::open(&input.source, input.index)?;
+        packets.skip(skip)?;
+        let mut cut = (0..frames)
+            .map(|_| packets.next_packet())
+            .collect::<std::io::Result<Vec<_>>>()?;
+        crate::events::recent_audio::trim_silence(&mut cut);
+        Ok(cut)
+    })
+    .await??;
+    Ok(packets)
+}
+
+async fn render(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    http: &Http,
+    stamp: &PendingStamp,
+) -> StampClipResult<Outcome> {
+    let existing = sqlx::query!(
+        "SELECT clip_id, saved_file_name FROM clips WHERE stamp_id = $1",
+        stamp.id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let (clip_id, saved_file_name, rendered) = match existing {
+        Some(row) => {
+            let saved = row
+                .saved_file_name
+                .unwrap_or_else(|| format!("{}.ogg", row.clip_id));
+            (row.clip_id, saved, false)
+        }
+        None => {
+            let window = (
+                stamp.moment_ms - crate::config::stamp_clip_before_ms(),
+                stamp.moment_ms + crate::config::stamp_clip_after_ms(),
+            );
+            let from_mix = crate::config::stamp_clip_from_mix();
+            let Some((source, live)) = find_source(pool, stamp, window, from_mix).await? else {
+                return Ok(Outcome::Skipped("no recording covers the stamp"));
+            };
+            if live && source.sparse {
+                return Ok(Outcome::NotReady);
+            }
+            let frames = cut(storage, &source, window.0, window.1.min(source.end_ts)).await?;
+            if frames.is_empty() {
+                return Ok(Outcome::Skipped("nothing but silence around the stamp"));
+            }
+
+            let clip_id = crate::commands::clip::new_clip_id(&[stamp.id, stamp.moment_ms]);
+            let saved_file_name = format!("{}.ogg", clip_id);
+            let path = Path::new(CLIPS_ROOT).join(&saved_file_name);
+            let serial = stamp.id;
+            let write_path = path.clone();
+            tokio::task::spawn_blocking(move || {
+                crate::commands::clip::write_clip(&write_path, &frames, serial)
+            })
+            .await??;
+
+            let name = stamp
+                .note
+                .clone()
+                .unwrap_or_else(|| format!("Stamp {}", stamp.id));
+            sqlx::query!(
+                "INSERT INTO clips
+                     (clip_id, guild_id, name, saved_file_name, source_audio_file_id,
+                      created_by_user_id, source_user_id, stamp_id)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                clip_id,
+                stamp.guild_id,
+                name,
+                saved_file_name,
+                (!from_mix).then_some(source.id),
+                stamp.stamper_user_id,
+                stamp.target_user_id,
+                stamp.id
+            )
+            .execute(pool)
+            .await?;
+            crate::clip_loudness::measure_file(pool, &clip_id, &path).await;
+            (clip_id, saved_file_name, true)
+        }
+    };
+
+    let settings =
+        crate::database::guild_settings::recording_settings(pool, stamp.guild_id as u64).await;
+    let message_id = match settings.stamp_clip_channel_id {
+        Some(channel_id) => Some(
+            post(
+                storage,
+                http,
+                ChannelId::new(channel_id),
+                stamp,
+                &clip_id,
+                &saved_file_name,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    Ok(Outcome::Done {
+        rendered,
+        message_id,
+    })
+}
+
+async fn post(
+    storage: &Storage,
+    http: &Http,
+    channel_id: ChannelId,
+    stamp: &PendingStamp,
+    clip_id: &str,
+    saved_file_name: &str,
+) -> StampClipResult<u64> {
+    let clip = storage.open_clip(saved_file_name).await?;
+    let bytes = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        clip.open()?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+    .await??;
+
+    let mut content = format!(
+        "<@{}> at <t:{}:T>, stamped by <@{}>",
+        stamp.target_user_id,
+        stamp.moment_ms / 1000,
+        stamp.stamper_user_id
+    );
+    if let Some(note) = &stamp.note {
+        content.push_str(&format!(": {}", note));
+    }
+    let message = CreateMessage::new()
+        .content(content)
+        .allowed_mentions(CreateAllowedMentions::new())
+        .add_file(CreateAttachment::bytes(bytes, format!("{}.ogg", clip_id)))
+        .components(vec![crate::events::interactions::replay_button(clip_id)]);
+    Ok(channel_id.send_message(http, message).await?.id.get())
+}
+
+/// Render one batch of pending stamps.
+pub async fn render_once(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    http: &Http,
+) -> StampClipResult<StampClipReport> {
+    Ok(with_advisory_lock(pool, LockKey::StampClips, || {
+        render_locked(pool, storage, http)
+    })
+    .await?
+    .unwrap_or_default())
+}
+
+async fn render_locked(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    http: &Http,
+) -> StampClipResult<StampClipReport> {
+    let ready_before_ms = chrono::Utc::now().timestamp_millis()
+        - crate::config::stamp_clip_after_ms()
+        - LIVE_MARGIN_MS;
+    let mut report = StampClipReport::default();
+
+    for stamp in load_pending(pool, ready_before_ms).await? {
+        match render(pool, storage, http, &stamp).await {
+            Ok(Outcome::Done {
+                rendered,
+                message_id,
+            }) => {
+                report.rendered += rendered as u64;
+                report.posted += message_id.is_some() as u64;
+                sqlx::query!(
+                    "UPDATE stamps SET clip_state = $2, clip_error = NULL, clip_message_id = $3
+                      WHERE id = $1",
+                    stamp.id,
+                    STATE_DONE,
+                    message_id.map(|id| id as i64)
+                )
+                .execute(pool)
+                .await?;
+            }
+            Ok(Outcome::Skipped(reason)) => {
+                debug!(stamp_id = stamp.id, reason, "stamp clip skipped");
+                report.skipped += 1;
+                sqlx::query!(
+                    "UPDATE stamps SET clip_state = $2, clip_error = $3 WHERE id = $1",
+                    stamp.id,
+                    STATE_SKIPPED,
+                    reason
+                )
+                .execute(pool)
+                .await?;
+            }
+            Ok(Outcome::NotReady) => {
+                sqlx::query!(
+                    "UPDATE stamps SET clip_next_attempt_at = now() + interval '1 minute'
+                      WHERE id = $1",
+                    stamp.id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Err(e) => {
+                warn!(stamp_id = stamp.id, error = %e, "stamp clip failed");
+                report.failed += 1;
+                sqlx::query!(
+                    "UPDATE stamps
+                        SET clip_attempts = clip_attempts + 1,
+                            clip_error = $2,
+                            clip_next_attempt_at = now() + interval '1 minute' * power(2, clip_attempts),
+                            clip_state = CASE WHEN clip_attempts + 1 >= $3 THEN $4 ELSE clip_state END
+                      WHERE id = $1",
+                    stamp.id,
+                    e.to_string(),
+                    MAX_ATTEMPTS,
+                    STATE_FAILED
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    if report.rendered > 0 || report.skipped > 0 || report.failed > 0 {
+        info!(
+            rendered = report.rendered,
+            posted = report.posted,
+            skipped = report.skipped,
+            failed = report.failed,
+            "stamp clip pass done"
+        );
+    }
+    Ok(report)
+}
+
+/// Render on `STAMP_CLIP_INTERVAL_SECONDS` for the life of the process.
+/// Does nothing unless `STAMP_CLIPS=1`.
+pub fn start(pool: Pool<Postgres>, storage: Arc<Storage>, http: Arc<Http>) {
+    if !crate::config::stamp_clips_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            crate::config::stamp_clip_interval_seconds(),
+        ));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if let Err(e) = render_once(&pool, &storage, &http).await {
+                error!("stamp clip pass failed: {}", e);
+            }
+        }
+    });
+}
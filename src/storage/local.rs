@@ -0,0 +1,71 @@
+//! Storage in a directory on local disk.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use serenity::async_trait;
+
+use super::{LOCAL, StorageBackend, StoredObject};
+
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        LOCAL
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+
+    async fn put_file(&self, key: &str, local: &Path) -> io::Result<StoredObject> {
+        let target = self.path(key);
+        let source = super::sha256_file(local).await?;
+        if target == local {
+            return Ok(source);
+        }
+        if let Some(dir) = target.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::copy(local, &target).await?;
+        let stored = super::sha256_file(&target).await?;
+        if stored != source {
+            let _ = tokio::fs::remove_file(&target).await;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checksum mismatch after copying to {}", target.display()),
+            ));
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        tokio::fs::read(self.path(key)).await.map(Bytes::from)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        tokio::fs::try_exists(self.path(key)).await
+    }
+}
@@ -0,0 +1,239 @@
+//! Where finished recordings and clips live.
+//!
+//! Recordings are always written to `RECORDING_ROOT` first; the disk writer
+//! needs a local file. With `RECORDING_STORAGE=s3` the [`uploader`] copies
+//! every finished recording and its sidecars to an S3-compatible bucket
+//! (AWS, MinIO, ...) and records `storage_backend = 's3'` on the row. Clips
+//! are read from `CLIPS_ROOT` when present there and from the bucket
+//! otherwise.
+//!
+//! Object keys mirror the local layout: a recording at
+//! `RECORDING_ROOT/<guild>/<channel>/.../<stem>.ogg` is stored under
+//! `recordings/<guild>/<channel>/.../<stem>.ogg`, a clip under
+//! `clips/<saved_file_name>`, both below `RECORDING_S3_PREFIX`.
+//!
+//! The bucket client is configured by `RECORDING_S3_BUCKET` plus the usual
+//! `AWS_*` variables (`AWS_ENDPOINT`, `AWS_ALLOW_HTTP`, `AWS_REGION`,
+//! `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`); MinIO needs
+//! `AWS_ENDPOINT=http://host:9000` and `AWS_ALLOW_HTTP=true`.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use sakiot_paths::{CLIPS_ROOT, RECORDING_ROOT, RecordingKey};
+use serenity::async_trait;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
+use sha2::{Digest, Sha256};
+
+pub mod local;
+pub mod s3;
+pub mod uploader;
+
+pub use local::LocalBackend;
+pub use s3::S3Backend;
+
+/// `storage_backend` value of rows whose files are on local disk.
+pub const LOCAL: &str = "local";
+
+/// What a backend holds after [`StorageBackend::put_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the content.
+    pub sha256: String,
+}
+
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Name stored in `storage_backend` for objects held here.
+    fn name(&self) -> &'static str;
+
+    /// Local path of `key` when this backend is a directory on disk.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+
+    /// Store the file at `local` under `key`, verifying the stored size and
+    /// checksum before returning.
+    async fn put_file(&self, key: &str, local: &Path) -> io::Result<StoredObject>;
+
+    async fn get(&self, key: &str) -> io::Result<Bytes>;
+
+    /// Remove `key`. Missing objects are not an error.
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+}
+
+/// Backends for recordings and clips. `remote` is set with
+/// `RECORDING_STORAGE=s3`.
+pub struct Storage {
+    pub local_recordings: LocalBackend,
+    pub local_clips: LocalBackend,
+    pub remote: Option<RemoteBackends>,
+}
+
+pub struct RemoteBackends {
+    pub recordings: S3Backend,
+    pub clips: S3Backend,
+}
+
+/// Where a clip's bytes come from.
+#[derive(Clone)]
+pub enum ClipSource {
+    File(PathBuf),
+    Memory(Bytes),
+}
+
+impl ClipSource {
+    /// Plaintext reader over the clip.
+    pub fn open(&self) -> io::Result<Box<dyn symphonia::core::io::MediaSource>> {
+        match self {
+            Self::File(path) => crate::encryption::open_media(std::fs::File::open(path)?),
+            Self::Memory(bytes) => crate::encryption::open_media(io::Cursor::new(bytes.clone())),
+        }
+    }
+}
+
+impl Storage {
+    pub fn local() -> Self {
+        Self {
+            local_recordings: LocalBackend::new(RECORDING_ROOT),
+            local_clips: LocalBackend::new(CLIPS_ROOT),
+            remote: None,
+        }
+    }
+
+    pub fn from_env() -> io::Result<Self> {
+        let mut storage = Self::local();
+        match crate::config::recording_storage().as_str() {
+            "local" => {}
+            "s3" => {
+                let bucket = crate::config::recording_s3_bucket().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "RECORDING_STORAGE=s3 needs RECORDING_S3_BUCKET",
+                    )
+                })?;
+                let prefix = crate::config::recording_s3_prefix();
+                let client = s3::client(&bucket)?;
+                storage.remote = Some(RemoteBackends {
+                    recordings: S3Backend::new(client.clone(), format!("{}recordings/", prefix)),
+                    clips: S3Backend::new(client, format!("{}clips/", prefix)),
+                });
+            }
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown RECORDING_STORAGE {:?}", other),
+                ));
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Backend holding recordings whose row says `storage_backend = name`.
+    pub fn recordings(&self, name: &str) -> Option<&dyn StorageBackend> {
+        match &self.remote {
+            Some(remote) if remote.recordings.name() == name => Some(&remote.recordings),
+            _ if name == LOCAL => Some(&self.local_recordings),
+            _ => None,
+        }
+    }
+
+    /// The clip stored as `saved_file_name`, from local disk when it is
+    /// there and from the bucket otherwise.
+    pub async fn open_clip(&self, saved_file_name: &str) -> io::Result<ClipSource> {
+        if let Some(path) = self.local_clips.local_path(saved_file_name)
+            && tokio::fs::try_exists(&path).await.unwrap_or(false)
+        {
+            return Ok(ClipSource::File(path));
+        }
+        match &self.remote {
+            Some(remote) => Ok(ClipSource::Memory(remote.clips.get(saved_file_name).await?)),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("clip {} not found", saved_file_name),
+            )),
+        }
+    }
+}
+
+pub struct StorageKey;
+impl TypeMapKey for StorageKey {
+    type Value = Arc<Storage>;
+}
+
+/// The shared [`Storage`], or local-only storage when none was registered.
+pub async fn from_data(data: &RwLock<TypeMap>) -> Arc<Storage> {
+    data.read()
+        .await
+        .get::<StorageKey>()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(Storage::local()))
+}
+
+/// Object key of `key`'s file with `extension`, relative to the recordings
+/// root.
+pub fn recording_object_key(key: &RecordingKey, extension: &str) -> String {
+    let path = key.recording_path(RECORDING_ROOT).with_extension(extension);
+    relative_key(&path, Path::new(RECORDING_ROOT))
+}
+
+/// Keys of a recording and its sidecars.
+pub fn recording_object_keys(key: &RecordingKey) -> [String; 3] {
+    ["ogg", "idx", "cue"].map(|extension| recording_object_key(key, extension))
+}
+
+fn relative_key(path: &Path, root: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .filter_map(|c| match c {
+            std::path::Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    format!("{:x}", hasher.finalize())
+}
+
+/// SHA-256 of a local file, read in the blocking pool.
+pub async fn sha256_file(path: &Path) -> io::Result<StoredObject> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let size = io::copy(&mut file, &mut hasher)?;
+        Ok(StoredObject {
+            size,
+            sha256: hex_digest(hasher),
+        })
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_relative_and_slash_separated() {
+        let root = Path::new("/srv/recordings");
+        let path = root
+            .join("1")
+            .join("2")
+            .join("2026")
+            .join("10")
+            .join("a.ogg");
+        assert_eq!(relative_key(&path, root), "1/2/2026/10/a.ogg");
+        assert_eq!(
+            relative_key(Path::new("/elsewhere/b.ogg"), root),
+            "elsewhere/b.ogg"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Storage in an S3-compatible bucket.
+//!
+//! Files up to [`MULTIPART_THRESHOLD`] go up in a single `PUT`, larger ones
+//! as a multipart upload of [`PART_SIZE`] parts that is aborted on any
+//! error. Every request carries a SHA-256 checksum the server verifies; the
+//! object's size is checked with a `HEAD` afterwards and the local digest is
+//! returned for the database.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use object_store::aws::{AmazonS3, AmazonS3Builder, Checksum};
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, PutPayload};
+use serenity::async_trait;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use super::{StorageBackend, StoredObject};
+
+/// Files larger than this are uploaded in parts.
+pub const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024;
+/// Size of each multipart part. S3 needs at least 5 MiB for all but the last.
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Bucket client from `AWS_*` variables.
+pub fn client(bucket: &str) -> io::Result<Arc<AmazonS3>> {
+    AmazonS3Builder::from_env()
+        .with_bucket_name(bucket)
+        .with_checksum_algorithm(Checksum::SHA256)
+        .build()
+        .map(Arc::new)
+        .map_err(to_io)
+}
+
+fn to_io(e: object_store::Error) -> io::Error {
+    match e {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, e),
+        e => io::Error::other(e),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct S3Backend {
+    store: Arc<AmazonS3>,
+    prefix: String,
+}
+
+impl S3Backend {
+    pub fn new(store: Arc<AmazonS3>, prefix: String) -> Self {
+        Self { store, prefix }
+    }
+
+    fn location(&self, key: &str) -> ObjectPath {
+        ObjectPath::from(format!("{}{}", self.prefix, key))
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &ObjectPath,
+        file: &mut tokio::fs::File,
+        hasher: &mut Sha256,
+    ) -> io::Result<()> {
+        let mut upload = self.store.put_multipart(location).await.map_err(to_io)?;
+        let result = async {
+            loop {
+                let mut part = vec![0u8; PART_SIZE];
+                let n = read_full(file, &mut part).await?;
+                if n == 0 {
+                    break;
+                }
+                part.truncate(n);
+                hasher.update(&part);
+                upload
+                    .put_part(PutPayload::from(part))
+                    .await
+                    .map_err(to_io)?;
+            }
+            upload.complete().await.map_err(to_io)
+        }
+        .await;
+        if let Err(e) = result {
+            if let Err(abort) = upload.abort().await {
+                tracing::warn!(location = %location, "multipart abort failed: {}", abort);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+/// Fill `buf` unless the file ends first; returns the bytes read.
+async fn read_full(file: &mut tokio::fs::File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = file.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[async_trait]
+impl StorageBackend for S3Backend {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    async fn put_file(&self, key: &str, local: &Path) -> io::Result<StoredObject> {
+        let location = self.location(key);
+        let mut file = tokio::fs::File::open(local).await?;
+        let size = file.metadata().await?.len();
+        let mut hasher = Sha256::new();
+
+        if size <= MULTIPART_THRESHOLD {
+            let mut buf = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut buf).await?;
+            hasher.update(&buf);
+            self.store
+                .put(&location, PutPayload::from(buf))
+                .await
+                .map_err(to_io)?;
+        } else {
+            self.put_multipart(&location, &mut file, &mut hasher)
+                .await?;
+        }
+
+        let stored = self.store.head(&location).await.map_err(to_io)?;
+        if stored.size != size {
+            let _ = self.store.delete(&location).await;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} stored as {} bytes, expected {}",
+                    location, stored.size, size
+                ),
+            ));
+        }
+        Ok(StoredObject {
+            size,
+            sha256: super::hex_digest(hasher),
+        })
+    }
+
+    async fn get(&self, key: &str) -> io::Result<Bytes> {
+        let location = self.location(key);
+        self.store
+            .get(&location)
+            .await
+            .map_err(to_io)?
+            .bytes()
+            .await
+            .map_err(to_io)
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        match self.store.delete(&self.location(key)).await {
+            Err(object_store::Error::NotFound { .. }) => Ok(()),
+            other => other.map_err(to_io),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.store.head(&self.location(key)).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::NotFound { .. }) => Ok(false),
+            Err(e) => Err(to_io(e)),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! Background upload of finished recordings to the remote backend.
+//!
+//! Every `RECORDING_UPLOAD_INTERVAL_SECONDS` finished, unpruned recordings
+//! still marked `storage_backend = 'local'` are uploaded with their
+//! sidecars, oldest first. A verified upload sets `storage_backend`,
+//! `object_sha256` and `object_size_bytes`, and with
+//! `RECORDING_UPLOAD_DELETE_LOCAL=1` removes the local files. Failures bump
+//! `upload_attempts` and keep `upload_error`; a recording is given up on
+//! after [`MAX_ATTEMPTS`]. Uploads run on whichever instance holds
+//! [`LockKey::Upload`].
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sqlx::{Pool, Postgres};
+use tracing::{error, info, warn};
+
+use super::{Storage, StorageBackend};
+use crate::BotMetrics;
+use crate::database::advisory_lock::{LockKey, with_advisory_lock};
+use crate::retention::RecordingKind;
+
+const MAX_ATTEMPTS: i32 = 5;
+/// Recordings per table per pass.
+const BATCH: i64 = 50;
+
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    pub uploaded: u64,
+    pub bytes: u64,
+    pub failed: u64,
+}
+
+struct Pending {
+    id: i64,
+    key: RecordingKey,
+}
+
+struct PendingRow {
+    id: i64,
+    guild_id: i64,
+    channel_id: i64,
+    year: i32,
+    month: i32,
+    file_name: String,
+}
+
+async fn load_pending(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+) -> Result<Vec<Pending>, sqlx::Error> {
+    let rows = match kind {
+        RecordingKind::Track => {
+            sqlx::query_as!(
+                PendingRow,
+                "SELECT id, guild_id, channel_id, year, month, file_name
+                   FROM audio_files
+                  WHERE end_ts IS NOT NULL AND pruned_at IS NULL
+                    AND storage_backend = 'local' AND upload_attempts < $1
+                  ORDER BY id
+                  LIMIT $2",
+                MAX_ATTEMPTS,
+                BATCH
+            )
+            .fetch_all(pool)
+            .await?
+        }
+        RecordingKind::Mix => {
+            sqlx::query_as!(
+                PendingRow,
+                "SELECT id, guild_id, channel_id, year, month, file_name
+                   FROM audio_mix_files
+                  WHERE end_ts IS NOT NULL AND pruned_at IS NULL
+                    AND storage_backend = 'local' AND upload_attempts < $1
+                  ORDER BY id
+                  LIMIT $2",
+                MAX_ATTEMPTS,
+                BATCH
+            )
+            .fetch_all(pool)
+            .await?
+        }
+    };
+    Ok(rows
+        .into_iter()
+        .map(|row| Pending {
+            id: row.id,
+            key: RecordingKey::new(
+                row.guild_id,
+                row.channel_id,
+                row.year,
+                row.month as u32,
+                row.file_name,
+            ),
+        })
+        .collect())
+}
+
+async fn mark_uploaded(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+    id: i64,
+    backend: &str,
+    sha256: &str,
+    bytes: u64,
+) -> Result<(), sqlx::Error> {
+    match kind {
+        RecordingKind::Track => {
+            sqlx::query!(
+                "UPDATE audio_files SET storage_backend = $2, object_sha256 = $3,
+                        object_size_bytes = $4, uploaded_at = now(),
+                        upload_error = NULL
+                  WHERE id = $1",
+                id,
+                backend,
+                sha256,
+                bytes as i64
+            )
+            .execute(pool)
+            .await?
+        }
+        RecordingKind::Mix => {
+            sqlx::query!(
+                "UPDATE audio_mix_files SET storage_backend = $2, object_sha256 = $3,
+                        object_size_bytes = $4, uploaded_at = now(),
+                        upload_error = NULL
+                  WHERE id = $1",
+                id,
+                backend,
+                sha256,
+                bytes as i64
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(())
+}
+
+async fn mark_failed(
+    pool: &Pool<Postgres>,
+    kind: RecordingKind,
+    id: i64,
+    error: &str,
+) -> Result<(), sqlx::Error> {
+    match kind {
+        RecordingKind::Track => {
+            sqlx::query!(
+                "UPDATE audio_files SET upload_attempts = upload_attempts + 1, upload_error = $2
+                  WHERE id = $1",
+                id,
+                error
+            )
+            .execute(pool)
+            .await?
+        }
+        RecordingKind::Mix => {
+            sqlx::query!(
+                "UPDATE audio_mix_files SET upload_attempts = upload_attempts + 1, upload_error = $2
+                  WHERE id = $1",
+                id,
+                error
+            )
+            .execute(pool)
+            .await?
+        }
+    };
+    Ok(())
+}
+
+/// Upload the `.ogg` and whichever sidecars exist. Returns the recording's
+/// digest and the total bytes uploaded.
+async fn upload_recording(
+    backend: &dyn StorageBackend,
+    key: &RecordingKey,
+) -> std::io::Result<(String, u64)> {
+    let ogg = key.recording_path(RECORDING_ROOT);
+    let mut total = 0;
+    for extension in ["idx", "cue"] {
+        let path = ogg.with_extension(extension);
+        if !tokio::fs::try_exists(&path).await? {
+            continue;
+        }
+        let stored = backend
+            .put_file(&super::recording_object_key(key, extension), &path)
+            .await?;
+        total += stored.size;
+    }
+    // The `.ogg` goes last so an object listing never shows a recording
+    // without its sidecars.
+    let stored = backend
+        .put_file(&super::recording_object_key(key, "ogg"), &ogg)
+        .await?;
+    Ok((stored.sha256, total + stored.size))
+}
+
+/// Upload one batch of pending recordings.
+pub async fn upload_once(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+) -> Result<UploadReport, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(remote) = &storage.remote else {
+        return Ok(UploadReport::default());
+    };
+    Ok(with_advisory_lock(pool, LockKey::Upload, || {
+        upload_locked(pool, &remote.recordings)
+    })
+    .await?
+    .unwrap_or_default())
+}
+
+async fn upload_locked(
+    pool: &Pool<Postgres>,
+    backend: &dyn StorageBackend,
+) -> Result<UploadReport, Box<dyn std::error::Error + Send + Sync>> {
+    let delete_local = crate::config::recording_upload_delete_local();
+    let mut report = UploadReport::default();
+
+    for kind in [RecordingKind::Track, RecordingKind::Mix] {
+        for pending in load_pending(pool, kind).await? {
+            match upload_recording(backend, &pending.key).await {
+                Ok((sha256, bytes)) => {
+                    mark_uploaded(pool, kind, pending.id, backend.name(), &sha256, bytes).await?;
+                    report.uploaded += 1;
+                    report.bytes += bytes;
+
+                    if delete_local {
+                        let ogg = pending.key.recording_path(RECORDING_ROOT);
+                        for path in [ogg.with_extension("idx"), ogg.with_extension("cue"), ogg] {
+                            if let Err(e) = tokio::fs::remove_file(&path).await
+                                && e.kind() != std::io::ErrorKind::NotFound
+                            {
+                                warn!(path = %path.display(), error = %e, "upload: local delete failed");
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!(table = kind.table(), id = pending.id, error = %e, "upload failed");
+                    report.failed += 1;
+                    mark_failed(pool, kind, pending.id, &e.to_string()).await?;
+                }
+            }
+        }
+    }
+
+    if report.uploaded > 0 || report.failed > 0 {
+        info!(
+            uploaded = report.uploaded,
+            bytes = report.bytes,
+            failed = report.failed,
+            "upload pass done"
+        );
+    }
+    Ok(report)
+}
+
+/// Upload on `RECORDING_UPLOAD_INTERVAL_SECONDS` for the life of the
+/// process. Does nothing without a remote backend.
+pub fn start(pool: Pool<Postgres>, storage: Arc<Storage>, metrics: Arc<BotMetrics>) {
+    if storage.remote.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            crate::config::recording_upload_interval_seconds(),
+        ));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match upload_once(&pool, &storage).await {
+                Ok(report) => {
+                    metrics
+                        .recording_uploads
+                        .fetch_add(report.uploaded, Ordering::Relaxed);
+                    metrics
+                        .recording_upload_bytes
+                        .fetch_add(report.bytes, Ordering::Relaxed);
+                    metrics
+                        .recording_upload_failures
+                        .fetch_add(report.failed, Ordering::Relaxed);
+                }
+                Err(e) => error!("upload pass failed: {}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,209 @@
+//! Free-space monitor for `RECORDING_ROOT`.
+//!
+//! Every `RECORDING_DISK_CHECK_SECONDS` the free space of the filesystem
+//! holding the recordings is sampled and classified:
+//!
+//! - below `RECORDING_DISK_SOFT_MB` the level is `Low`: metrics and an
+//!   optional message to `RECORDING_DISK_ALERT_CHANNEL_ID`, nothing else.
+//! - below `RECORDING_DISK_HARD_MB` the level is `Full`: receivers stop
+//!   opening writers and finalize the ones they have.
+//!
+//! `Full` only clears once free space is back above the midpoint of the two
+//! thresholds, so a recording that frees a little space on close does not
+//! flap the guard. Receivers reopen writers for everyone still in the
+//! channel on their next heartbeat.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::Duration;
+
+use sakiot_paths::RECORDING_ROOT;
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
+use serenity::prelude::TypeMapKey;
+use tracing::{error, info, warn};
+
+use crate::BotMetrics;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageLevel {
+    Ok = 0,
+    Low = 1,
+    Full = 2,
+}
+
+impl StorageLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Low,
+            2 => Self::Full,
+            _ => Self::Ok,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub soft_bytes: u64,
+    pub hard_bytes: u64,
+}
+
+impl Thresholds {
+    pub fn from_env() -> Self {
+        let hard_bytes = crate::config::recording_disk_hard_mb() * 1024 * 1024;
+        let soft_bytes = (crate::config::recording_disk_soft_mb() * 1024 * 1024).max(hard_bytes);
+        Self {
+            soft_bytes,
+            hard_bytes,
+        }
+    }
+
+    /// Level for `free` bytes, given the level of the previous check.
+    pub fn classify(&self, free: u64, previous: StorageLevel) -> StorageLevel {
+        let resume_at = self.hard_bytes + (self.soft_bytes - self.hard_bytes) / 2;
+        if free < self.hard_bytes || (previous == StorageLevel::Full && free < resume_at) {
+            StorageLevel::Full
+        } else if free < self.soft_bytes {
+            StorageLevel::Low
+        } else {
+            StorageLevel::Ok
+        }
+    }
+}
+
+/// Shared verdict of the monitor. Starts at `Ok` so a failed first check
+/// never blocks recording.
+#[derive(Debug, Default)]
+pub struct StorageGuard {
+    level: AtomicU8,
+}
+
+impl StorageGuard {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn level(&self) -> StorageLevel {
+        StorageLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Whether new recording writers may be opened.
+    pub fn accepting_writers(&self) -> bool {
+        self.level() != StorageLevel::Full
+    }
+
+    fn set_level(&self, level: StorageLevel) -> StorageLevel {
+        StorageLevel::from_u8(self.level.swap(level as u8, Ordering::Relaxed))
+    }
+}
+
+pub struct StorageGuardKey;
+impl TypeMapKey for StorageGuardKey {
+    type Value = Arc<StorageGuard>;
+}
+
+/// Available bytes on the filesystem holding `path`: the disk with the
+/// longest mount point that is a prefix of it.
+fn available_space(disks: &sysinfo::Disks, path: &Path) -> Option<u64> {
+    let path = std::fs::canonicalize(path).ok()?;
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+pub fn start(guard: Arc<StorageGuard>, metrics: Arc<BotMetrics>, http: Arc<Http>) {
+    tokio::spawn(async move {
+        let thresholds = Thresholds::from_env();
+        let alert_channel = crate::config::recording_disk_alert_channel().map(ChannelId::new);
+        let mut disks = sysinfo::Disks::new_with_refreshed_list();
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            crate::config::recording_disk_check_seconds(),
+        ));
+        info!(
+            soft_bytes = thresholds.soft_bytes,
+            hard_bytes = thresholds.hard_bytes,
+            "storage guard started"
+        );
+
+        loop {
+            interval.tick().await;
+            disks.refresh(true);
+            let Some(free) = available_space(&disks, Path::new(RECORDING_ROOT)) else {
+                warn!("storage guard: no disk found for {}", RECORDING_ROOT);
+                continue;
+            };
+            metrics
+                .recording_disk_free_bytes
+                .store(free, Ordering::Relaxed);
+
+            let level = thresholds.classify(free, guard.level());
+            let previous = guard.set_level(level);
+            metrics
+                .recording_disk_level
+                .store(level as u64, Ordering::Relaxed);
+            if level == previous {
+                continue;
+            }
+
+            let message = match level {
+                StorageLevel::Full => {
+                    metrics
+                        .recording_disk_full_events
+                        .fetch_add(1, Ordering::Relaxed);
+                    error!(free, "recording disk full; stopping recordings");
+                    format!(
+                        "Recording disk is full ({} MB free). Recordings are stopped until space is freed.",
+                        free / 1024 / 1024
+                    )
+                }
+                _ if previous == StorageLevel::Full => {
+                    info!(free, "recording disk space recovered; recordings resume");
+                    format!(
+                        "Recording disk has {} MB free again. Recordings resume.",
+                        free / 1024 / 1024
+                    )
+                }
+                StorageLevel::Low => {
+                    warn!(free, "recording disk low");
+                    format!("Recording disk is low: {} MB free.", free / 1024 / 1024)
+                }
+                StorageLevel::Ok => {
+                    info!(free, "recording disk space recovered");
+                    format!(
+                        "Recording disk space recovered: {} MB free.",
+                        free / 1024 / 1024
+                    )
+                }
+            };
+            if let Some(channel) = alert_channel
+                && let Err(e) = channel.say(&http, message).await
+            {
+                warn!("storage guard: alert failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_clears_only_past_the_midpoint() {
+        let t = Thresholds {
+            soft_bytes: 1000,
+            hard_bytes: 200,
+        };
+        assert_eq!(t.classify(5000, StorageLevel::Ok), StorageLevel::Ok);
+        assert_eq!(t.classify(900, StorageLevel::Ok), StorageLevel::Low);
+        assert_eq!(t.classify(100, StorageLevel::Low), StorageLevel::Full);
+        assert_eq!(t.classify(500, StorageLevel::Full), StorageLevel::Full);
+        assert_eq!(t.classify(600, StorageLevel::Full), StorageLevel::Low);
+        assert_eq!(t.classify(500, StorageLevel::Low), StorageLevel::Low);
+    }
+}
@@ -0,0 +1,916 @@
+//! Export and erasure of everything stored about one user.
+//!
+//! Rows are matched by user ID in the tables listed in [`TABLES`]. Recordings
+//! are the user's `audio_files` rows, resolved to disk through
+//! [`RecordingKey`] together with their `.idx`/`.cue` sidecars, and to the
+//! remote backend for rows that were uploaded. Clips cut from the user's
+//! voice (`clips.source_user_id`) are theirs too, whoever made them; their
+//! files live in `CLIPS_ROOT` or the remote clip store.
+//!
+//! Export writes a zip with `manifest.json`, one `rows/<table>.json` array
+//! per table, the recordings under `recordings/`, laid out as they are
+//! below `RECORDING_ROOT`, and the clips under `clips/`. Uploaded files are
+//! downloaded next to the zip while it is written, and encrypted ones are
+//! decrypted into it.
+//!
+//! Erasure deletes the rows in one transaction, then the files, their
+//! remote copies, any `hls-{stem}/` cache dir and cached exports. Recordings still being written are skipped and
+//! reported; run the erasure again once the user has left the channel.
+//! `user_consent`, `recording_user_opt_outs` and `voice_events_audit` are
+//! exported but kept, so an opt-out outlives the data and the erasure itself
+//! stays on record. Session mixdowns hold every speaker and are left alone.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use sakiot_paths::{RECORDING_ROOT, RecordingKey};
+use sqlx::{PgConnection, Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::events::voice_receiver::VoiceEventType;
+use crate::storage::{Storage, StorageBackend};
+
+pub type UserDataResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Tables holding rows about a user. Ordered so rows are deleted before the
+/// rows they reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Table {
+    Clips,
+    SpeechSegments,
+    Stamps,
+    AudioFiles,
+    VoiceStateEvents,
+    UserNameHistory,
+    UserNicknames,
+    UserNames,
+    UserRoles,
+    JamInvocations,
+}
+
+const TABLES: &[Table] = &[
+    Table::Clips,
+    Table::SpeechSegments,
+    Table::Stamps,
+    Table::AudioFiles,
+    Table::VoiceStateEvents,
+    Table::UserNameHistory,
+    Table::UserNicknames,
+    Table::UserNames,
+    Table::UserRoles,
+    Table::JamInvocations,
+];
+
+/// Exported, never erased.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeptTable {
+    UserConsent,
+    RecordingUserOptOuts,
+    VoiceEventsAudit,
+}
+
+const KEPT_TABLES: &[KeptTable] = &[
+    KeptTable::UserConsent,
+    KeptTable::RecordingUserOptOuts,
+    KeptTable::VoiceEventsAudit,
+];
+
+impl Table {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Clips => "clips",
+            Self::SpeechSegments => "speech_segments",
+            Self::Stamps => "stamps",
+            Self::AudioFiles => "audio_files",
+            Self::VoiceStateEvents => "voice_state_events",
+            Self::UserNameHistory => "user_name_history",
+            Self::UserNicknames => "user_nicknames",
+            Self::UserNames => "user_names",
+            Self::UserRoles => "user_roles",
+            Self::JamInvocations => "jam_invocations",
+        }
+    }
+
+    /// The user's rows as JSON, recordings still being written included.
+    async fn export(self, pool: &Pool<Postgres>, user_id: i64) -> Result<Vec<String>, sqlx::Error> {
+        match self {
+            Self::Clips => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM clips t WHERE source_user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::SpeechSegments => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM speech_segments t
+                        WHERE audio_file_id IN (SELECT id FROM audio_files WHERE user_id = $1)"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::Stamps => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM stamps t
+                        WHERE target_user_id = $1 OR stamper_user_id = $1
+                           OR audio_file_id IN (SELECT id FROM audio_files WHERE user_id = $1)"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::AudioFiles => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM audio_files t WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::VoiceStateEvents => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM voice_state_events t
+                        WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::UserNameHistory => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM user_name_history t
+                        WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::UserNicknames => sqlx::query_scalar!(
+                r#"SELECT to_jsonb(t)::text AS "row!" FROM user_nicknames t WHERE user_id = $1"#,
+                user_id
+            )
+            .fetch_all(pool)
+            .await,
+            Self::UserNames => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM user_names t WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::UserRoles => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM user_roles t WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::JamInvocations => sqlx::query_scalar!(
+                r#"SELECT to_jsonb(t)::text AS "row!" FROM jam_invocations t WHERE user_id = $1"#,
+                user_id
+            )
+            .fetch_all(pool)
+            .await,
+        }
+    }
+
+    /// How many rows [`Table::erase`] would delete.
+    async fn count(self, pool: &Pool<Postgres>, user_id: i64) -> Result<i64, sqlx::Error> {
+        match self {
+            Self::Clips => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM clips WHERE source_user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::SpeechSegments => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM speech_segments
+                        WHERE audio_file_id IN
+                              (SELECT id FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL)"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::Stamps => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM stamps
+                        WHERE target_user_id = $1 OR stamper_user_id = $1
+                           OR audio_file_id IN
+                              (SELECT id FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL)"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::AudioFiles => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM audio_files
+                        WHERE user_id = $1 AND end_ts IS NOT NULL"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::VoiceStateEvents => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM voice_state_events WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::UserNameHistory => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM user_name_history WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::UserNicknames => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM user_nicknames WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::UserNames => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM user_names WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::UserRoles => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM user_roles WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+            Self::JamInvocations => {
+                sqlx::query_scalar!(
+                    r#"SELECT count(*) AS "count!" FROM jam_invocations WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_one(pool)
+                .await
+            }
+        }
+    }
+
+    /// Delete the user's rows. Recordings still being written are left, as
+    /// are their speech segments.
+    async fn erase(self, conn: &mut PgConnection, user_id: i64) -> Result<u64, sqlx::Error> {
+        let result = match self {
+            Self::Clips => {
+                sqlx::query!("DELETE FROM clips WHERE source_user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::SpeechSegments => {
+                sqlx::query!(
+                    "DELETE FROM speech_segments
+                      WHERE audio_file_id IN
+                            (SELECT id FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL)",
+                    user_id
+                )
+                .execute(conn)
+                .await?
+            }
+            Self::Stamps => {
+                sqlx::query!(
+                    "DELETE FROM stamps
+                      WHERE target_user_id = $1 OR stamper_user_id = $1
+                         OR audio_file_id IN
+                            (SELECT id FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL)",
+                    user_id
+                )
+                .execute(conn)
+                .await?
+            }
+            Self::AudioFiles => {
+                sqlx::query!(
+                    "DELETE FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL",
+                    user_id
+                )
+                .execute(conn)
+                .await?
+            }
+            Self::VoiceStateEvents => {
+                sqlx::query!("DELETE FROM voice_state_events WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::UserNameHistory => {
+                sqlx::query!("DELETE FROM user_name_history WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::UserNicknames => {
+                sqlx::query!("DELETE FROM user_nicknames WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::UserNames => {
+                sqlx::query!("DELETE FROM user_names WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::UserRoles => {
+                sqlx::query!("DELETE FROM user_roles WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+            Self::JamInvocations => {
+                sqlx::query!("DELETE FROM jam_invocations WHERE user_id = $1", user_id)
+                    .execute(conn)
+                    .await?
+            }
+        };
+        Ok(result.rows_affected())
+    }
+}
+
+impl KeptTable {
+    fn name(self) -> &'static str {
+        match self {
+            Self::UserConsent => "user_consent",
+            Self::RecordingUserOptOuts => "recording_user_opt_outs",
+            Self::VoiceEventsAudit => "voice_events_audit",
+        }
+    }
+
+    async fn export(self, pool: &Pool<Postgres>, user_id: i64) -> Result<Vec<String>, sqlx::Error> {
+        match self {
+            Self::UserConsent => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM user_consent t WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::RecordingUserOptOuts => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM recording_user_opt_outs t
+                        WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+            Self::VoiceEventsAudit => {
+                sqlx::query_scalar!(
+                    r#"SELECT to_jsonb(t)::text AS "row!" FROM voice_events_audit t
+                        WHERE user_id = $1"#,
+                    user_id
+                )
+                .fetch_all(pool)
+                .await
+            }
+        }
+    }
+}
+
+/// Archive directory clips are exported under.
+const CLIPS_DIR: &str = "clips";
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportReport {
+    pub user_id: u64,
+    /// Row count per table, in [`TABLES`] order followed by the kept tables.
+    pub tables: Vec<(&'static str, u64)>,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ErasureReport {
+    pub user_id: u64,
+    pub dry_run: bool,
+    pub tables: Vec<(&'static str, u64)>,
+    /// Recording files, sidecars and clips that were (or would be) deleted.
+    pub files: Vec<PathBuf>,
+    /// `<backend>:<key>` of uploaded copies that were (or would be) deleted.
+    pub remote_objects: Vec<String>,
+    pub bytes: u64,
+    /// Recordings left in place because they are still being written.
+    pub skipped_active: u64,
+}
+
+impl ErasureReport {
+    pub fn rows(&self) -> u64 {
+        self.tables.iter().map(|(_, n)| n).sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let verb = if self.dry_run {
+            "would erase"
+        } else {
+            "erased"
+        };
+        let mut line = format!(
+            "user {}: {} {} rows and {} files ({} bytes)",
+            self.user_id,
+            verb,
+            self.rows(),
+            self.files.len() + self.remote_objects.len(),
+            self.bytes
+        );
+        if self.skipped_active > 0 {
+            line.push_str(&format!(
+                ", skipped {} active recordings",
+                self.skipped_active
+            ));
+        }
+        line
+    }
+}
+
+struct RecordingRow {
+    guild_id: i64,
+    channel_id: i64,
+    year: i32,
+    month: i32,
+    file_name: String,
+    storage_backend: String,
+}
+
+impl RecordingRow {
+    fn key(&self) -> RecordingKey {
+        RecordingKey::new(
+            self.guild_id,
+            self.channel_id,
+            self.year,
+            self.month as u32,
+            self.file_name.clone(),
+        )
+    }
+}
+
+/// The `.ogg` and its sidecars that exist on disk for each recording.
+fn recording_files(recordings: &[RecordingRow]) -> Vec<PathBuf> {
+    recordings
+        .iter()
+        .flat_map(|r| {
+            let path = r.key().recording_path(RECORDING_ROOT);
+            ["idx", "cue"]
+                .into_iter()
+                .map(|ext| path.with_extension(ext))
+                .chain(std::iter::once(path.clone()))
+                .collect::<Vec<_>>()
+        })
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// `(backend, key)` of every object uploaded for the recordings.
+fn remote_objects(recordings: &[RecordingRow]) -> Vec<(&str, String)> {
+    recordings
+        .iter()
+        .filter(|r| r.storage_backend != crate::storage::LOCAL)
+        .flat_map(|r| {
+            crate::storage::recording_object_keys(&r.key())
+                .into_iter()
+                .map(|key| (r.storage_backend.as_str(), key))
+        })
+        .collect()
+}
+
+/// Saved file names of the clips holding the user's voice.
+async fn clips(pool: &Pool<Postgres>, user_id: u64) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT COALESCE(saved_file_name, clip_id || '.ogg') AS "name!"
+             FROM clips WHERE source_user_id = $1"#,
+        user_id as i64
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The clips that exist in `CLIPS_ROOT`.
+fn clip_files(storage: &Storage, clips: &[String]) -> Vec<PathBuf> {
+    clips
+        .iter()
+        .filter_map(|name| storage.local_clips.local_path(name))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn total_bytes(files: &[PathBuf]) -> u64 {
+    files
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+async fn recordings(
+    pool: &Pool<Postgres>,
+    user_id: u64,
+    finished_only: bool,
+) -> Result<Vec<RecordingRow>, sqlx::Error> {
+    if finished_only {
+        sqlx::query_as!(
+            RecordingRow,
+            "SELECT guild_id, channel_id, year, month, file_name, storage_backend
+               FROM audio_files WHERE user_id = $1 AND end_ts IS NOT NULL",
+            user_id as i64
+        )
+        .fetch_all(pool)
+        .await
+    } else {
+        sqlx::query_as!(
+            RecordingRow,
+            "SELECT guild_id, channel_id, year, month, file_name, storage_backend
+               FROM audio_files WHERE user_id = $1",
+            user_id as i64
+        )
+        .fetch_all(pool)
+        .await
+    }
+}
+
+/// Write every row and recording about `user_id` to a zip at `out`.
+pub async fn export(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    user_id: u64,
+    out: &Path,
+) -> UserDataResult<ExportReport> {
+    let mut tables = Vec::new();
+    for table in TABLES {
+        let rows = table.export(pool, user_id as i64).await?;
+        tables.push((table.name(), json_rows(&rows)?));
+    }
+    for table in KEPT_TABLES {
+        let rows = table.export(pool, user_id as i64).await?;
+        tables.push((table.name(), json_rows(&rows)?));
+    }
+
+    let recordings = recordings(pool, user_id, false).await?;
+    let mut files: Vec<(String, PathBuf)> = recording_files(&recordings)
+        .into_iter()
+        .map(|path| (archive_name(&path), path))
+        .collect();
+    let staging = out.with_extension("staging");
+    let staged = stage_remote(storage, &recordings, &staging).await;
+    let staged = match staged {
+        Ok(staged) => staged,
+        Err(e) => {
+            let _ = tokio::fs::remove_dir_all(&staging).await;
+            return Err(e);
+        }
+    };
+    files.extend(staged);
+    let clips = clips(pool, user_id).await?;
+    match stage_clips(storage, &clips, &staging).await {
+        Ok(staged) => files.extend(staged),
+        Err(e) => {
+            let _ = tokio::fs::remove_dir_all(&staging).await;
+            return Err(e);
+        }
+    }
+    let paths: Vec<PathBuf> = files.iter().map(|(_, path)| path.clone()).collect();
+    let report = ExportReport {
+        user_id,
+        tables: tables
+            .iter()
+            .map(|(table, rows)| (*table, rows.len() as u64))
+            .collect(),
+        files: files.len() as u64,
+        bytes: total_bytes(&paths),
+    };
+
+    let manifest = serde_json::json!({
+        "user_id": user_id.to_string(),
+        "exported_at_ms": chrono::Utc::now().timestamp_millis(),
+        "tables": report.tables.iter().cloned().collect::<BTreeMap<_, _>>(),
+        "files": files.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+    });
+    let archive_out = out.to_path_buf();
+    let written = tokio::task::spawn_blocking(move || {
+        write_archive(&archive_out, &manifest, &tables, &files)
+    })
+    .await;
+    if tokio::fs::try_exists(&staging).await.unwrap_or(false)
+        && let Err(e) = tokio::fs::remove_dir_all(&staging).await
+    {
+        warn!(path = %staging.display(), error = %e, "user export: staging cleanup failed");
+    }
+    written??;
+
+    audit(
+        pool,
+        user_id,
+        VoiceEventType::UserDataExported,
+        &format!(
+            "Exported {} rows and {} files",
+            report.tables.iter().map(|(_, n)| n).sum::<u64>(),
+            report.files
+        ),
+    )
+    .await;
+    info!(
+        user_id,
+        files = report.files,
+        bytes = report.bytes,
+        "user data exported"
+    );
+    Ok(report)
+}
+
+fn json_rows(rows: &[String]) -> serde_json::Result<Vec<serde_json::Value>> {
+    rows.iter().map(|row| serde_json::from_str(row)).collect()
+}
+
+/// Download the user's uploaded recordings under `staging`, returning their
+/// archive names and staged paths.
+async fn stage_remote(
+    storage: &Storage,
+    recordings: &[RecordingRow],
+    staging: &Path,
+) -> UserDataResult<Vec<(String, PathBuf)>> {
+    let mut staged = Vec::new();
+    for (backend_name, key) in remote_objects(recordings) {
+        let backend = storage
+            .recordings(backend_name)
+            .ok_or_else(|| format!("storage backend {} is not configured", backend_name))?;
+        if !backend.exists(&key).await? {
+            continue;
+        }
+        let path = staging.join(&key);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, backend.get(&key).await?).await?;
+        staged.push((format!("recordings/{}", key), path));
+    }
+    Ok(staged)
+}
+
+/// Archive names and paths of the user's clips: local ones in place,
+/// remote-only ones downloaded under `staging`.
+async fn stage_clips(
+    storage: &Storage,
+    clips: &[String],
+    staging: &Path,
+) -> UserDataResult<Vec<(String, PathBuf)>> {
+    let mut staged = Vec::new();
+    for name in clips {
+        let archive_name = format!("{}/{}", CLIPS_DIR, name);
+        if let Some(path) = storage.local_clips.local_path(name)
+            && path.is_file()
+        {
+            staged.push((archive_name, path));
+            continue;
+        }
+        let Some(remote) = &storage.remote else {
+            continue;
+        };
+        if !remote.clips.exists(name).await? {
+            continue;
+        }
+        let path = staging.join(CLIPS_DIR).join(name);
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, remote.clips.get(name).await?).await?;
+        staged.push((archive_name, path));
+    }
+    Ok(staged)
+}
+
+fn write_archive(
+    out: &Path,
+    manifest: &serde_json::Value,
+    tables: &[(&str, Vec<serde_json::Value>)],
+    files: &[(String, PathBuf)],
+) -> std::io::Result<()> {
+    use zip::CompressionMethod;
+    use zip::write::SimpleFileOptions;
+
+    if let Some(parent) = out.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(out)?);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    // Opus does not compress further.
+    let stored = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+
+    zip.start_file("manifest.json", deflated)
+        .map_err(std::io::Error::other)?;
+    serde_json::to_writer_pretty(&mut zip, manifest)?;
+
+    for (table, rows) in tables {
+        zip.start_file(format!("rows/{}.json", table), deflated)
+            .map_err(std::io::Error::other)?;
+        serde_json::to_writer_pretty(&mut zip, rows)?;
+    }
+
+    for (name, path) in files {
+        zip.start_file(name.as_str(), stored)
+            .map_err(std::io::Error::other)?;
+        std::io::copy(
+            &mut crate::encryption::open_media(std::fs::File::open(path)?)?,
+            &mut zip,
+        )?;
+    }
+
+    zip.finish().map_err(std::io::Error::other)?.flush()
+}
+
+/// Name of a recording inside the export, mirroring its place under
+/// `RECORDING_ROOT`.
+fn archive_name(path: &Path) -> String {
+    let relative = path
+        .strip_prefix(RECORDING_ROOT)
+        .unwrap_or_else(|_| Path::new(path.file_name().unwrap_or_default()));
+    let parts: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    format!("recordings/{}", parts.join("/"))
+}
+
+/// Delete every row and recording about `user_id`, or with `dry_run` only
+/// report what would go.
+pub async fn erase(
+    pool: &Pool<Postgres>,
+    storage: &Storage,
+    user_id: u64,
+    dry_run: bool,
+    requested_by: &str,
+) -> UserDataResult<ErasureReport> {
+    let finished = recordings(pool, user_id, true).await?;
+    let active = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM audio_files WHERE user_id = $1 AND end_ts IS NULL"#,
+        user_id as i64
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let clips = clips(pool, user_id).await?;
+    let mut files = recording_files(&finished);
+    files.extend(clip_files(storage, &clips));
+    let remote = remote_objects(&finished);
+    let mut report = ErasureReport {
+        user_id,
+        dry_run,
+        bytes: total_bytes(&files),
+        files,
+        remote_objects: remote
+            .iter()
+            .map(|(backend, key)| format!("{}:{}", backend, key))
+            .chain(storage.remote.iter().flat_map(|r| {
+                clips
+                    .iter()
+                    .map(|name| format!("{}:{}/{}", r.clips.name(), CLIPS_DIR, name))
+            }))
+            .collect(),
+        skipped_active: active as u64,
+        ..Default::default()
+    };
+
+    if dry_run {
+        for table in TABLES {
+            let count = table.count(pool, user_id as i64).await?;
+            report.tables.push((table.name(), count as u64));
+        }
+        info!(user_id, requested_by, "{}", report.summary());
+        return Ok(report);
+    }
+
+    let mut tx = pool.begin().await?;
+    for table in TABLES {
+        let deleted = table.erase(&mut *tx, user_id as i64).await?;
+        report.tables.push((table.name(), deleted));
+    }
+    tx.commit().await?;
+
+    for path in &report.files {
+        if let Err(e) = std::fs::remove_file(path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!(path = %path.display(), error = %e, "user erasure: delete failed");
+        }
+    }
+    for (backend_name, key) in &remote {
+        let Some(backend) = storage.recordings(backend_name) else {
+            warn!(backend = backend_name, key = %key, "user erasure: backend not configured");
+            continue;
+        };
+        if let Err(e) = backend.delete(key).await {
+            warn!(key = %key, error = %e, "user erasure: remote delete failed");
+        }
+    }
+    if let Some(remote) = &storage.remote {
+        for name in &clips {
+            if let Err(e) = remote.clips.delete(name).await {
+                warn!(clip = %name, error = %e, "user erasure: remote clip delete failed");
+            }
+        }
+    }
+    for recording in &finished {
+        let hls = recording.key().live_dir(RECORDING_ROOT);
+        if hls.exists()
+            && let Err(e) = std::fs::remove_dir_all(&hls)
+        {
+            warn!(path = %hls.display(), error = %e, "user erasure: hls cleanup failed");
+        }
+        crate::export::remove_cached(&recording.key());
+    }
+
+    audit(
+        pool,
+        user_id,
+        VoiceEventType::UserDataErased,
+        &format!("{} (requested by {})", report.summary(), requested_by),
+    )
+    .await;
+    info!(user_id, requested_by, "{}", report.summary());
+    Ok(report)
+}
+
+async fn audit(pool: &Pool<Postgres>, user_id: u64, event_type: VoiceEventType, details: &str) {
+    if let Err(e) = sqlx::query!(
+        "INSERT INTO voice_events_audit (user_id, event_type_id, details) VALUES ($1, $2, $3)",
+        user_id as i64,
+        event_type as i32,
+        details
+    )
+    .execute(pool)
+    .await
+    {
+        warn!("Failed to audit user data request for {}: {}", user_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_names_mirror_recording_root() {
+        let key = RecordingKey::new(1, 2, 2026, 10, "stem".to_string());
+        let path = key.recording_path(RECORDING_ROOT);
+        let name = archive_name(&path);
+        assert!(name.starts_with("recordings/"));
+        assert!(name.ends_with("/stem.ogg"));
+        assert!(name.contains("2026"));
+    }
+
+    #[test]
+    fn rows_are_erased_before_the_recordings_they_point_at() {
+        let position = |table: Table| TABLES.iter().position(|t| *t == table);
+        assert_eq!(position(Table::Clips), Some(0));
+        for table in [Table::SpeechSegments, Table::Stamps] {
+            assert!(position(table) < position(Table::AudioFiles));
+        }
+    }
+
+    #[test]
+    fn table_names_are_distinct() {
+        let names: Vec<_> = TABLES
+            .iter()
+            .map(|t| t.name())
+            .chain(KEPT_TABLES.iter().map(|t| t.name()))
+            .collect();
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[i + 1..].contains(name));
+        }
+    }
+
+    #[test]
+    fn summary_mentions_skipped_recordings() {
+        let report = ErasureReport {
+            user_id: 7,
+            dry_run: true,
+            tables: vec![("stamps", 2), ("audio_files", 3)],
+            files: vec![PathBuf::from("a.ogg")],
+            remote_objects: Vec::new(),
+            bytes: 10,
+            skipped_active: 1,
+        };
+        assert_eq!(
+            report.summary(),
+            "user 7: would erase 5 rows and 1 files (10 bytes), skipped 1 active recordings"
+        );
+    }
+}